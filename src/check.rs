@@ -1,30 +1,118 @@
 //! Directory change detection operations (streaming: same pipeline as index, memory-efficient diff).
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crossbeam_channel::Receiver;
+use log::{info, warn};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
+use rayon::prelude::*;
+
 use crate::engine;
+use crate::engine::progress;
 use crate::pipeline::{
-    PipelineHandles, check_for_initial_error_or_skipped_paths, run_pipeline,
-    shutdown_pipeline_handles,
+    EntryBatch, PipelineHandles, check_for_initial_error_or_skipped_paths, collect_entries,
+    run_pipeline, shutdown_pipeline_handles, walk_was_capped,
 };
+use crate::store::{IndexStore, NefaxStore, StoreBuilder};
+use crate::utils::KeySource;
 use crate::utils::config::DB_INSERT_BATCH_SIZE;
-use crate::{Diff, Entry, Opts, PathMeta};
+use crate::{Diff, Entry, FileKind, ManifestFormat, Nefax, Opts, PathMeta};
+
+/// Error from [`check_dir`] with [`Opts::fail_on_change`] when the tree drifted from its index.
+/// The CLI exits with [`ChangesDetected::EXIT_CODE`] on it.
+#[derive(Debug)]
+pub struct ChangesDetected {
+    /// [`Diff::total`] of the check.
+    pub total: usize,
+}
+
+impl ChangesDetected {
+    /// Process exit status for a detected change (other errors exit with 1).
+    pub const EXIT_CODE: i32 = 2;
+}
+
+impl std::fmt::Display for ChangesDetected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} change(s) detected against the index", self.total)
+    }
+}
 
-/// CLI dry-run: compare directory to existing index, print diff, no index write. Returns nothing.
+impl std::error::Error for ChangesDetected {}
+
+/// CLI dry-run: compare directory to existing index, print diff, no index write. Returns the diff.
+///
+/// When `opts.index_from` is set, the prior index is loaded from that DB instead (e.g. the original
+/// tree's index when checking a copy); that DB is only read, so the probe cache stays in memory.
 ///
 /// # Errors
 ///
-/// Returns [`crate::Error`] when the index database cannot be opened or loaded, the walk pipeline
-/// fails, thread shutdown fails, or [`crate::pipeline::check_for_initial_error_or_skipped_paths`]
-/// reports an error in strict mode.
-pub fn check_dir(root: &Path, opts: &Opts) -> Result<()> {
+/// Returns [`crate::Error`] when the index database cannot be opened or loaded (or `index_from` does
+/// not exist), the walk pipeline fails, thread shutdown fails, or
+/// [`crate::pipeline::check_for_initial_error_or_skipped_paths`] reports an error in strict mode.
+/// With `opts.fail_on_change`, returns [`ChangesDetected`] (after printing) when the diff is not empty.
+pub fn check_dir(root: &Path, opts: &Opts) -> Result<Diff> {
     let db_path = engine::create_db_path(root, opts.db_path.as_deref());
 
-    let (conn, _) = engine::open_db_or_detect_encrypted(db_path.as_path(), root)?;
-    let index = engine::load_index(&conn)?;
+    let (conn, pipeline_conn, passphrase) = match opts.index_from.as_deref() {
+        Some(index_from) => {
+            if !index_from.is_file() {
+                anyhow::bail!(
+                    "index to compare against not found: {}",
+                    index_from.display()
+                );
+            }
+            // Another tree's index: read it, never write or migrate it.
+            let (conn, pass) =
+                engine::open_db_read_only(index_from, root, &KeySource::from_opts(opts))?;
+            (conn, Some(engine::open_db_in_memory()?), pass)
+        }
+        None => {
+            let (conn, pass) = engine::open_db_or_detect_encrypted(
+                db_path.as_path(),
+                root,
+                &KeySource::from_opts(opts),
+            )?;
+            (conn, None, pass)
+        }
+    };
+    let opts = &with_passphrase_hash_key(opts, passphrase.as_deref())?;
+    let mut index = engine::load_index(&conn)?;
+    let extras = engine::load_index_extras(&conn, opts)?;
+    let meta = engine::load_index_meta(&conn)?;
+    if engine::hash_key_matches(&meta, opts) == Some(false) {
+        anyhow::bail!(
+            "index {} was hashed under a different hash key; re-index with the original key or with this one",
+            db_path.display()
+        );
+    }
+    let since_ns = if opts.since_last_index {
+        let since = meta
+            .updated_at
+            .map(|secs| secs.saturating_mul(1_000_000_000));
+        if since.is_none() {
+            warn!("--since: index has no recorded update time; reporting a full diff");
+        }
+        since
+    } else {
+        None
+    };
+    // Follow the index's path mode so absolute-path indexes compare against absolute entries.
+    let stored_absolute = meta.store_absolute;
+    let adopted;
+    let opts = match stored_absolute {
+        Some(abs) if abs != opts.store_absolute => {
+            adopted = Opts {
+                store_absolute: abs,
+                ..opts.clone()
+            };
+            &adopted
+        }
+        _ => opts,
+    };
+    engine::warn_on_index_meta_mismatch(&conn, opts)?;
+    let seeds = engine::seed_keys(root, opts);
+    index.retain(|path, _| engine::under_seeds(path, &seeds));
 
     let PipelineHandles {
         entry_rx,
@@ -33,58 +121,337 @@ pub fn check_dir(root: &Path, opts: &Opts) -> Result<()> {
         worker_handles,
         first_error,
         skipped_paths,
+        tuning,
+        walk_capped,
         ..
-    } = run_pipeline(root, opts, Some(db_path.as_path()), None, &conn)?;
+    } = run_pipeline(
+        root,
+        opts,
+        Some(db_path.as_path()),
+        None,
+        pipeline_conn.as_ref().unwrap_or(&conn),
+    )?;
 
-    let diff = diff_from_stream_diff_only(&entry_rx, &index, root, opts);
+    let opts = &with_effective_mtime_granularity(opts, &tuning);
+    // Check runs have no entry bar, so the hashing bar takes the top line. With `--since` nothing is
+    // hashed.
+    let hashing_bar = (opts.verbose && opts.with_hash && since_ns.is_none()).then(|| {
+        let estimated = index.values().filter(|(_, _, h)| h.is_some()).count();
+        let b = progress::create_hashing_bar(estimated, 0);
+        progress::refresh_bar(&b);
+        b
+    });
+    let on_hash = progress::hash_progress_callback(&hashing_bar);
+    let mut diff = diff_from_stream_diff_only(
+        &entry_rx,
+        &index,
+        Some(&extras),
+        root,
+        opts,
+        since_ns,
+        on_hash.as_deref(),
+    );
+    if let Some(bar) = &hashing_bar {
+        progress::finish_bar_at_count(bar);
+    }
 
-    shutdown_pipeline_handles(walk_handle, worker_handles)?;
+    progress::clear_bars_on_err(
+        shutdown_pipeline_handles(walk_handle, worker_handles),
+        &[&hashing_bar],
+    )?;
+    if walk_was_capped(&walk_capped, opts.max_entries) {
+        // Paths past the cap were never walked, not removed.
+        diff.removed.clear();
+    }
 
     check_for_initial_error_or_skipped_paths(opts, &first_error, &skipped_paths)?;
 
-    engine::print_diff(&diff, true, opts.list_paths, root);
-    Ok(())
+    info!("Nefaxed with {}", tuning.summary());
+    if let Some(ref output) = opts.output {
+        let rebase_dirs = engine::display_rebase_dirs(root, opts.display_relative_to.as_deref());
+        let rebase = rebase_dirs
+            .as_ref()
+            .map(|(root, base)| (root.as_path(), base.as_path()));
+        engine::write_diff_report(&diff, output, rebase)?;
+        engine::print_diff(&diff, true, false, root);
+        info!("Wrote full diff to {}", output.display());
+    } else {
+        engine::print_diff_with_threshold(
+            &diff,
+            true,
+            opts.list_paths,
+            root,
+            opts.list_threshold,
+            opts.display_relative_to.as_deref(),
+        );
+    }
+    if opts.prune_empty_dirs {
+        let emptied = newly_empty_dirs(&diff, index.keys());
+        if !emptied.is_empty() {
+            info!("Directories left empty by removals ({}):", emptied.len());
+            for dir in &emptied {
+                info!("  {}", dir.display());
+            }
+        }
+    }
+    if opts.fail_on_change && !diff.is_empty() {
+        return Err(ChangesDetected {
+            total: diff.total(),
+        }
+        .into());
+    }
+    Ok(diff)
 }
 
-/// Consume stream and build only the Diff (no map). Used by CLI dry-run.
+/// Indexed paths the walk of `root` no longer finds, by on-disk name, sorted: the `removed` half of
+/// [`check_dir`] without add/modify classification or hashing. `db_path` defaults to the index in
+/// `root` (see [`engine::create_db_path`]); it is opened read-only and never migrated, so the probe
+/// cache stays in memory.
+/// A walk stopped by [`Opts::max_entries`] reports nothing, since unreached paths are not gone.
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when the index does not exist or cannot be opened or loaded, the walk
+/// pipeline fails, or strict mode hits an access error.
+pub fn find_removed(root: &Path, db_path: Option<&Path>, opts: &Opts) -> Result<Vec<PathBuf>> {
+    let db_path = engine::create_db_path(root, db_path);
+    if !db_path.is_file() {
+        anyhow::bail!("index not found: {}", db_path.display());
+    }
+    let (conn, _) =
+        engine::open_db_read_only(db_path.as_path(), root, &KeySource::from_opts(opts))?;
+    let mut index = engine::load_index(&conn)?;
+    let meta = engine::load_index_meta(&conn)?;
+    let opts = &Opts {
+        with_hash: false,
+        paranoid: false,
+        store_absolute: meta.store_absolute.unwrap_or(opts.store_absolute),
+        ..opts.clone()
+    };
+    let seeds = engine::seed_keys(root, opts);
+    index.retain(|path, _| engine::under_seeds(path, &seeds));
+
+    let pipeline_conn = engine::open_db_in_memory()?;
+    let PipelineHandles {
+        entry_rx,
+        path_count_rx: _,
+        walk_handle,
+        worker_handles,
+        first_error,
+        skipped_paths,
+        walk_capped,
+        ..
+    } = run_pipeline(root, opts, Some(db_path.as_path()), None, &pipeline_conn)?;
+
+    let mut index_keys_not_seen: HashSet<PathBuf> = index.into_keys().collect();
+    for batch in &entry_rx {
+        for entry in batch {
+            index_keys_not_seen.remove(&entry.path);
+        }
+    }
+
+    shutdown_pipeline_handles(walk_handle, worker_handles)?;
+    check_for_initial_error_or_skipped_paths(opts, &first_error, &skipped_paths)?;
+    if walk_was_capped(&walk_capped, opts.max_entries) {
+        return Ok(Vec::new());
+    }
+    let disk_paths = engine::load_index_disk_paths(&conn)?;
+    let mut removed: Vec<PathBuf> = index_keys_not_seen
+        .into_iter()
+        .filter(|p| engine::matches_focus(p, &opts.focus))
+        .map(|p| disk_paths.get(&p).cloned().unwrap_or(p))
+        .collect();
+    removed.sort();
+    Ok(removed)
+}
+
+/// Directories emptied by `diff`: still present, but every entry they held in `previous` (the
+/// index the diff was taken against) was removed and nothing was added under them. Sorted; only
+/// directories that appear in `previous` are reported.
+pub fn newly_empty_dirs<'a, I>(diff: &Diff, previous: I) -> Vec<PathBuf>
+where
+    I: IntoIterator<Item = &'a PathBuf>,
+{
+    if diff.removed.is_empty() {
+        return Vec::new();
+    }
+    let removed: HashSet<&Path> = diff.removed.iter().map(PathBuf::as_path).collect();
+    let refilled: HashSet<&Path> = diff.added.iter().filter_map(|p| p.parent()).collect();
+    // Candidate dir -> (children in `previous`, of which removed).
+    let mut children: HashMap<&Path, (usize, usize)> = diff
+        .removed
+        .iter()
+        .filter_map(|p| p.parent())
+        .filter(|d| !removed.contains(d) && !refilled.contains(d))
+        .map(|d| (d, (0, 0)))
+        .collect();
+    let mut present = HashSet::new();
+    for path in previous {
+        if children.contains_key(path.as_path()) {
+            present.insert(path.as_path());
+        }
+        if let Some(counts) = path.parent().and_then(|d| children.get_mut(d)) {
+            counts.0 += 1;
+            if removed.contains(path.as_path()) {
+                counts.1 += 1;
+            }
+        }
+    }
+    let mut emptied: Vec<PathBuf> = children
+        .into_iter()
+        .filter(|(d, (total, gone))| total == gone && present.contains(d))
+        .map(|(d, _)| d.to_path_buf())
+        .collect();
+    emptied.sort();
+    emptied
+}
+
+/// `opts` with `hash_key` derived from the index passphrase when `keyed_hash` asks for it.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when `keyed_hash` is set without a `hash_key` and the index is not encrypted.
+pub(crate) fn with_passphrase_hash_key(opts: &Opts, passphrase: Option<&str>) -> Result<Opts> {
+    if !opts.keyed_hash || opts.hash_key.is_some() {
+        return Ok(opts.clone());
+    }
+    let Some(pass) = passphrase else {
+        anyhow::bail!("--keyed-hash needs an encrypted index (or pass --hash-key-file)");
+    };
+    Ok(Opts {
+        hash_key: Some(engine::HashKey::derive(pass)),
+        ..opts.clone()
+    })
+}
+
+/// `opts` with `mtime_granularity_ns` resolved for the drive the pipeline detected.
+pub(crate) fn with_effective_mtime_granularity(
+    opts: &Opts,
+    tuning: &crate::pipeline::PipelineTuning,
+) -> Opts {
+    Opts {
+        mtime_granularity_ns: Some(engine::effective_mtime_granularity_ns(
+            opts.mtime_granularity_ns,
+            tuning.drive_type,
+        )),
+        ..opts.clone()
+    }
+}
+
+/// Fill hashes for a drained chunk on the rayon pool; classification stays sequential in chunk
+/// order afterwards, so the diff is the same as hashing one entry at a time. Entries outside
+/// `focus` (when not empty) are skipped: they are not reported.
+fn hash_chunk(
+    chunk: &mut [Entry],
+    index: &HashMap<PathBuf, engine::StoredMeta>,
+    root: &Path,
+    opts: &Opts,
+    focus: &[String],
+) -> usize {
+    if !opts.with_hash {
+        return 0;
+    }
+    let threshold = engine::hash_size_threshold(opts.hash_small_files);
+    chunk
+        .par_iter_mut()
+        .filter(|entry| engine::matches_focus(&entry.path, focus))
+        .map(|entry| {
+            engine::fill_entry_hash_if_needed(entry, index, root, opts);
+            usize::from(entry.kind == FileKind::File && entry.size >= threshold)
+        })
+        .sum()
+}
+
+/// Consume stream and build only the Diff (no map). Used by CLI dry-run. `on_hash` is called with
+/// the number of files at or above the hashing threshold each chunk settles a hash for.
 fn diff_from_stream_diff_only(
-    entry_rx: &Receiver<Entry>,
+    entry_rx: &Receiver<EntryBatch>,
     index: &HashMap<PathBuf, engine::StoredMeta>,
+    extras: Option<&engine::StoredExtras>,
     root: &Path,
     opts: &Opts,
+    since_ns: Option<i64>,
+    on_hash: Option<&(dyn Fn(usize) + Send)>,
 ) -> Diff {
     let mut index_keys_not_seen: HashSet<PathBuf> = index.keys().cloned().collect();
-    let mut added = Vec::new();
-    let mut modified = Vec::new();
+    let mut diff = Diff::default();
+    let sampler = opts.verify_sample.map(VerifySampler::new);
+    let mut verified = 0usize;
     let mut chunk = Vec::with_capacity(DB_INSERT_BATCH_SIZE);
 
     loop {
         chunk.clear();
-        while let Ok(entry) = entry_rx.try_recv() {
-            chunk.push(entry);
+        while let Ok(batch) = entry_rx.try_recv() {
+            chunk.extend(batch);
             if chunk.len() >= DB_INSERT_BATCH_SIZE {
                 break;
             }
         }
         if chunk.is_empty() {
             match entry_rx.recv() {
-                Ok(entry) => chunk.push(entry),
+                Ok(batch) => chunk.extend(batch),
                 Err(_) => break,
             }
         }
 
-        for mut entry in chunk.drain(..) {
-            engine::fill_entry_hash_if_needed(&mut entry, index, root, opts);
+        if since_ns.is_none() {
+            let hashed = hash_chunk(&mut chunk, index, root, opts, &opts.focus);
+            if let Some(cb) = on_hash
+                && hashed > 0
+            {
+                cb(hashed);
+            }
+        }
+        for entry in chunk.drain(..) {
             index_keys_not_seen.remove(&entry.path);
-            collect_entry_into_diff(entry, index, &mut added, &mut modified, root, opts);
+            if !engine::matches_focus(&entry.path, &opts.focus) {
+                continue;
+            }
+            if let Some(since) = since_ns {
+                // Timestamp mode: newer than the last index run means changed; no row comparison.
+                if entry.mtime_ns > since {
+                    if index.contains_key(&entry.path) {
+                        diff.modified.push(entry.into_fs_path());
+                    } else {
+                        diff.added.push(entry.into_fs_path());
+                    }
+                }
+                continue;
+            }
+            if let Some(ref sampler) = sampler
+                && sampler.picks(&entry.path)
+                && let Some(matches) = verify_stored_hash(&entry, index, root, opts)
+            {
+                verified += 1;
+                if !matches {
+                    diff.corrupted.push(entry.into_fs_path());
+                    continue;
+                }
+            }
+            collect_entry_into_diff(entry, index, extras, &mut diff, root, opts);
         }
     }
 
-    let removed: Vec<PathBuf> = index_keys_not_seen.into_iter().collect();
-    Diff {
-        added,
-        removed,
-        modified,
+    if sampler.is_some() {
+        info!(
+            "Verified {verified} sampled files against stored hashes ({} mismatched).",
+            diff.corrupted.len()
+        );
+    }
+    diff.removed = index_keys_not_seen
+        .into_iter()
+        .filter(|p| engine::matches_focus(p, &opts.focus))
+        .map(|p| removed_disk_path(p, extras))
+        .collect();
+    diff.pair_case_only_renames();
+    diff
+}
+
+/// The on-disk name to report a removed index key under (see [`engine::StoredExtras::disk_path`]).
+fn removed_disk_path(key: PathBuf, extras: Option<&engine::StoredExtras>) -> PathBuf {
+    match extras.and_then(|x| x.disk_paths.get(&key)) {
+        Some(disk) => disk.clone(),
+        None => key,
     }
 }
 
@@ -92,17 +459,19 @@ fn diff_from_stream_diff_only(
 /// Returns (Diff, current index as path → `PathMeta`). Same shape as the DB; available whether or not we write to DB.
 #[must_use]
 pub fn diff_from_stream(
-    entry_rx: &Receiver<Entry>,
+    entry_rx: &Receiver<EntryBatch>,
     index: &HashMap<PathBuf, engine::StoredMeta>,
     root: &Path,
     opts: &Opts,
 ) -> (Diff, HashMap<PathBuf, PathMeta>) {
-    diff_from_stream_impl(entry_rx, index, root, opts, None)
+    let mut store = StoreBuilder::in_memory();
+    let diff = diff_from_stream_impl(entry_rx, index, None, root, opts, None, &mut store);
+    (diff, store.into_map())
 }
 
 /// Like [`diff_from_stream`] but invokes `on_entry` for each entry (after hash fill). Use for streaming progress or forwarding to another stage (e.g. zahir).
 pub fn diff_from_stream_with_callback<F>(
-    entry_rx: &Receiver<Entry>,
+    entry_rx: &Receiver<EntryBatch>,
     index: &HashMap<PathBuf, engine::StoredMeta>,
     root: &Path,
     opts: &Opts,
@@ -111,90 +480,300 @@ pub fn diff_from_stream_with_callback<F>(
 where
     F: FnMut(&Entry),
 {
-    diff_from_stream_impl(entry_rx, index, root, opts, Some(&mut on_entry))
+    let mut store = StoreBuilder::in_memory();
+    let diff = diff_from_stream_impl(
+        entry_rx,
+        index,
+        None,
+        root,
+        opts,
+        Some(&mut on_entry),
+        &mut store,
+    );
+    (diff, store.into_map())
+}
+
+/// Like [`diff_from_stream_with_callback`] but collects the current snapshot into `store`, which
+/// may spill it to disk (see [`crate::NefaxOpts::max_entries_in_memory`]). With `btimes` (stored
+/// creation times), a changed btime also marks a path modified.
+pub(crate) fn diff_from_stream_into_store<F>(
+    entry_rx: &Receiver<EntryBatch>,
+    index: &HashMap<PathBuf, engine::StoredMeta>,
+    extras: Option<&engine::StoredExtras>,
+    root: &Path,
+    opts: &Opts,
+    mut on_entry: F,
+    store: &mut StoreBuilder,
+) -> Diff
+where
+    F: FnMut(&Entry),
+{
+    diff_from_stream_impl(
+        entry_rx,
+        index,
+        extras,
+        root,
+        opts,
+        Some(&mut on_entry),
+        store,
+    )
+}
+
+/// Like [`diff_from_stream_into_store`], against a snapshot read with [`IndexStore::get`] one chunk
+/// of entries at a time, so a spilled previous snapshot is never loaded whole. `removed` is left
+/// empty: fill it with [`removed_from_store`] once `current_index` is finished.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when `existing` cannot be read or holds an invalid row. The stream is
+/// drained either way so the pipeline can shut down.
+pub(crate) fn diff_from_stream_against_store<F>(
+    entry_rx: &Receiver<EntryBatch>,
+    existing: Option<&dyn IndexStore>,
+    root: &Path,
+    opts: &Opts,
+    mut on_entry: F,
+    current_index: &mut StoreBuilder,
+) -> Result<Diff>
+where
+    F: FnMut(&Entry),
+{
+    let mut diff = Diff::default();
+    let mut error = None;
+    let mut chunk = Vec::with_capacity(DB_INSERT_BATCH_SIZE);
+
+    loop {
+        chunk.clear();
+        while let Ok(batch) = entry_rx.try_recv() {
+            chunk.extend(batch);
+            if chunk.len() >= DB_INSERT_BATCH_SIZE {
+                break;
+            }
+        }
+        if chunk.is_empty() {
+            match entry_rx.recv() {
+                Ok(batch) => chunk.extend(batch),
+                Err(_) => break,
+            }
+        }
+        if error.is_some() {
+            continue;
+        }
+        let (prior, extras) = match chunk_prior(&chunk, existing, opts) {
+            Ok(prior) => prior,
+            Err(e) => {
+                error = Some(e);
+                continue;
+            }
+        };
+
+        hash_chunk(&mut chunk, &prior, root, opts, &[]);
+        for entry in chunk.drain(..) {
+            on_entry(&entry);
+            current_index.insert(entry.path.clone(), PathMeta::from(&entry));
+            collect_entry_into_diff(entry, &prior, Some(&extras), &mut diff, root, opts);
+        }
+    }
+
+    error.map_or(Ok(diff), Err)
+}
+
+/// The rows of `existing` for the entries of `chunk`, validated, as the map and extras
+/// [`collect_entry_into_diff`] reads.
+fn chunk_prior(
+    chunk: &[Entry],
+    existing: Option<&dyn IndexStore>,
+    opts: &Opts,
+) -> Result<(HashMap<PathBuf, engine::StoredMeta>, engine::StoredExtras)> {
+    let mut rows = Vec::new();
+    if let Some(existing) = existing {
+        for entry in chunk {
+            if let Some(meta) = existing.get(&entry.path)? {
+                crate::types::validate_path_meta(&entry.path, &meta, opts.store_absolute)?;
+                rows.push((entry.path.clone(), meta));
+            }
+        }
+    }
+    let extras = engine::StoredExtras::from_metas(rows.iter().map(|(p, m)| (p, m)), opts);
+    let prior = rows
+        .into_iter()
+        .map(|(p, m)| (p, (m.mtime_ns, m.size, m.hash.map(|h| h.to_vec()))))
+        .collect();
+    Ok((prior, extras))
+}
+
+/// Rows of `existing` under [`Opts::seeds`] that the finished `current` snapshot does not have,
+/// found by reading `existing` row by row (validating each) and looking every path up in `current`.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when either snapshot cannot be read or a row of `existing` is invalid.
+pub(crate) fn removed_from_store(
+    existing: &dyn IndexStore,
+    current: &NefaxStore,
+    root: &Path,
+    opts: &Opts,
+) -> Result<Vec<PathBuf>> {
+    let seeds = engine::seed_keys(root, opts);
+    let mut removed = Vec::new();
+    for row in existing.iter() {
+        let (path, meta) = row?;
+        if !engine::under_seeds(&path, &seeds) {
+            continue;
+        }
+        crate::types::validate_path_meta(&path, &meta, opts.store_absolute)?;
+        if current.get(&path)?.is_none() {
+            removed.push(path);
+        }
+    }
+    Ok(removed)
 }
 
 fn diff_from_stream_impl(
-    entry_rx: &Receiver<Entry>,
+    entry_rx: &Receiver<EntryBatch>,
     index: &HashMap<PathBuf, engine::StoredMeta>,
+    extras: Option<&engine::StoredExtras>,
     root: &Path,
     opts: &Opts,
     mut on_entry: Option<&mut dyn FnMut(&Entry)>,
-) -> (Diff, HashMap<PathBuf, PathMeta>) {
+    current_index: &mut StoreBuilder,
+) -> Diff {
     let mut index_keys_not_seen: HashSet<PathBuf> = index.keys().cloned().collect();
-    let mut added = Vec::new();
-    let mut modified = Vec::new();
-    let mut current_index = HashMap::new();
+    let mut diff = Diff::default();
     let mut chunk = Vec::with_capacity(DB_INSERT_BATCH_SIZE);
 
     loop {
         chunk.clear();
-        while let Ok(entry) = entry_rx.try_recv() {
-            chunk.push(entry);
+        while let Ok(batch) = entry_rx.try_recv() {
+            chunk.extend(batch);
             if chunk.len() >= DB_INSERT_BATCH_SIZE {
                 break;
             }
         }
         if chunk.is_empty() {
             match entry_rx.recv() {
-                Ok(entry) => chunk.push(entry),
+                Ok(batch) => chunk.extend(batch),
                 Err(_) => break,
             }
         }
 
-        for mut entry in chunk.drain(..) {
-            engine::fill_entry_hash_if_needed(&mut entry, index, root, opts);
+        hash_chunk(&mut chunk, index, root, opts, &[]);
+        for entry in chunk.drain(..) {
             if let Some(ref mut f) = on_entry {
                 f(&entry);
             }
-            current_index.insert(
-                entry.path.clone(),
-                PathMeta {
-                    mtime_ns: entry.mtime_ns,
-                    size: entry.size,
-                    hash: entry.hash,
-                },
-            );
+            current_index.insert(entry.path.clone(), PathMeta::from(&entry));
             index_keys_not_seen.remove(&entry.path);
-            collect_entry_into_diff(entry, index, &mut added, &mut modified, root, opts);
+            collect_entry_into_diff(entry, index, extras, &mut diff, root, opts);
         }
     }
 
-    let removed: Vec<PathBuf> = index_keys_not_seen.into_iter().collect();
-    let diff = Diff {
-        added,
-        removed,
-        modified,
-    };
-    (diff, current_index)
+    diff.removed = index_keys_not_seen
+        .into_iter()
+        .map(|p| removed_disk_path(p, extras))
+        .collect();
+    diff.pair_case_only_renames();
+    diff
+}
+
+/// Picks a pseudo-random subset of paths for [`verify_stored_hash`]: a path is picked when the
+/// blake3 of (per-run seed, path) falls below `rate`. Rates at or above 1.0 pick every path.
+struct VerifySampler {
+    rate: f64,
+    seed: [u8; 16],
+}
+
+impl VerifySampler {
+    fn new(rate: f64) -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            seed: nanos.to_le_bytes(),
+        }
+    }
+
+    fn picks(&self, path: &Path) -> bool {
+        if self.rate >= 1.0 {
+            return true;
+        }
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.seed);
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&hasher.finalize().as_bytes()[..8]);
+        (u64::from_le_bytes(word) as f64 / u64::MAX as f64) < self.rate
+    }
 }
 
-/// Classify entry as added or modified and push into the diff lists.
+/// Re-hash a file whose mtime and size match the index and compare with the stored hash.
+/// Returns `None` when the entry is not eligible (new, metadata changed, or no stored hash).
+fn verify_stored_hash(
+    entry: &Entry,
+    index: &HashMap<PathBuf, engine::StoredMeta>,
+    root: &Path,
+    opts: &Opts,
+) -> Option<bool> {
+    let (old_mtime, old_size, old_hash) = index.get(&entry.path)?;
+    let old_hash = old_hash.as_deref().filter(|h| h.len() == 32)?;
+    if engine::mtime_changed_with_granularity(
+        entry.mtime_ns,
+        *old_mtime,
+        opts.mtime_window_ns,
+        opts.mtime_granularity_ns.unwrap_or(0),
+    ) || entry.size != *old_size
+    {
+        return None;
+    }
+    let rehash = engine::hash_file_keyed(
+        &root.join(entry.fs_path()),
+        entry.size,
+        opts.hash_key.as_ref(),
+    )
+    .ok()
+    .flatten();
+    Some(rehash.is_some_and(|h| h.as_slice() == old_hash))
+}
+
+/// Classify entry as added or modified and push into the diff lists. A creation time or symlink
+/// target that differs from `extras` counts as modified whatever the change policy says.
 fn collect_entry_into_diff(
     entry: Entry,
     index: &HashMap<PathBuf, engine::StoredMeta>,
-    added: &mut Vec<PathBuf>,
-    modified: &mut Vec<PathBuf>,
+    extras: Option<&engine::StoredExtras>,
+    diff: &mut Diff,
     root: &Path,
     opts: &Opts,
 ) {
     match index.get(&entry.path) {
-        None => added.push(entry.path),
-        Some((old_mtime, old_size, old_hash)) => {
-            let same = !engine::mtime_changed(entry.mtime_ns, *old_mtime, opts.mtime_window_ns)
-                && entry.size == *old_size
-                && engine::hash_equals(&entry.hash, old_hash);
-            if same {
+        None => diff.added.push(entry.into_fs_path()),
+        Some(stored) => {
+            let extra_changed = extras.is_some_and(|x| x.changed(&entry));
+            if !extra_changed
+                && !opts.change_policy.is_changed(
+                    &entry,
+                    stored,
+                    opts.mtime_window_ns,
+                    opts.mtime_granularity_ns.unwrap_or(0),
+                )
+            {
                 return;
             }
-            let still_modified = if opts.paranoid
-                && entry.hash.is_some()
+            let old_hash = &stored.2;
+            let hashes_match = entry.hash.is_some()
                 && old_hash.as_ref().is_some_and(|v| v.len() == 32)
-                && engine::hash_equals(&entry.hash, old_hash)
-            {
-                let abs = root.join(&entry.path);
+                && engine::hash_equals(&entry.hash, old_hash);
+            // Same content and size, only mtime moved (e.g. `touch`, or a rewrite with identical bytes).
+            let touched =
+                opts.report_touched && hashes_match && entry.size == stored.1 && !extra_changed;
+            let still_modified = if extra_changed {
+                true
+            } else if opts.paranoid && hashes_match {
+                let abs = root.join(entry.fs_path());
+                let key = opts.hash_key.as_ref();
                 match std::fs::metadata(&abs) {
-                    Ok(meta) if meta.is_file() => engine::hash_file(&abs, meta.len())
+                    Ok(meta) if meta.is_file() => engine::hash_file_keyed(&abs, meta.len(), key)
                         .ok()
                         .flatten()
                         .is_none_or(|rehash: [u8; 32]| {
@@ -205,9 +784,323 @@ fn collect_entry_into_diff(
             } else {
                 true
             };
-            if still_modified {
-                modified.push(entry.path);
+            if touched && !(opts.paranoid && still_modified) {
+                diff.touched.push(entry.into_fs_path());
+            } else if still_modified {
+                diff.modified.push(entry.into_fs_path());
             }
         }
     }
 }
+
+/// Diff two in-memory indexes: paths only in `new` are added, only in `old` removed, and paths in both
+/// are modified when mtime (outside `mtime_window_ns`), size, or hash differ.
+#[must_use]
+pub fn diff_nefax(old: &Nefax, new: &Nefax, mtime_window_ns: i64) -> Diff {
+    let mut added = Vec::new();
+    let mut modified = Vec::new();
+    for (path, meta) in new {
+        match old.get(path) {
+            None => added.push(path.clone()),
+            Some(old_meta) => {
+                if !meta.effectively_equal(old_meta, mtime_window_ns) {
+                    modified.push(path.clone());
+                }
+            }
+        }
+    }
+    let removed = old
+        .keys()
+        .filter(|p| !new.contains_key(*p))
+        .cloned()
+        .collect();
+    let mut diff = Diff {
+        added,
+        removed,
+        modified,
+        ..Default::default()
+    };
+    diff.pair_case_only_renames();
+    diff
+}
+
+/// Check several trees, each against its own index: `roots` holds `(root, db)` pairs, with `db`
+/// replacing `opts.db_path` for that root. Every check runs [`check_dir`] on its own thread (not the
+/// rayon pool, which the parallel walks need); returns the diffs keyed by root.
+///
+/// Encrypted indexes should get their passphrase from `key_file` or the environment, since
+/// concurrent checks cannot share a terminal prompt.
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when a root is listed twice, when `opts.output` is set for more than one
+/// root (every check would write the same file), or for the first root, in `roots` order, whose
+/// check fails (see [`check_dir`]).
+pub fn check_dirs(roots: &[(PathBuf, PathBuf)], opts: &Opts) -> Result<HashMap<PathBuf, Diff>> {
+    if let Some(ref output) = opts.output
+        && roots.len() > 1
+    {
+        anyhow::bail!(
+            "output {} would be written by each of {} roots; check them one at a time to get a report per root",
+            output.display(),
+            roots.len()
+        );
+    }
+    let mut seen = HashSet::new();
+    for (root, _) in roots {
+        if !seen.insert(std::fs::canonicalize(root).unwrap_or_else(|_| root.clone())) {
+            anyhow::bail!("root {} is listed more than once", root.display());
+        }
+    }
+    std::thread::scope(|s| {
+        let handles: Vec<_> = roots
+            .iter()
+            .map(|(root, db)| {
+                let opts = Opts {
+                    db_path: Some(db.clone()),
+                    ..opts.clone()
+                };
+                (root, s.spawn(move || check_dir(root, &opts)))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|(root, handle)| {
+                let diff = handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("check thread panicked for {}", root.display()))?
+                    .with_context(|| format!("check {}", root.display()))?;
+                Ok((root.clone(), diff))
+            })
+            .collect()
+    })
+}
+
+/// Verify `root` against a checksum manifest of `<hex>  <path>` lines as written by `sha256sum` or
+/// `b3sum` (`format` picks the algorithm). A `*` before the path (binary mode) is accepted, blank
+/// and `#` lines are skipped, and paths are taken relative to `root`. Every listed file present in
+/// the tree is hashed whatever its size: a different hash is modified, a listed file not found is
+/// removed, and a file the manifest does not list is added. A listed file that cannot be read is
+/// warned about and put in [`Diff::unreadable`]. Directories are not compared, and the manifest
+/// itself is left out when it lies under `root`.
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when the manifest cannot be read or a line is malformed (the error names
+/// the line), or [`collect_entries`] fails.
+pub fn verify_manifest(
+    root: &Path,
+    manifest_path: &Path,
+    format: ManifestFormat,
+    opts: &Opts,
+) -> Result<Diff> {
+    let expected = parse_manifest(manifest_path, opts)?;
+    let opts = &Opts {
+        store_absolute: false,
+        ..opts.clone()
+    };
+    let conn = engine::open_db_in_memory()?;
+    let db_path = engine::create_db_path(root, opts.db_path.as_deref());
+    let (entries, _) = collect_entries(root, opts, Some(db_path.as_path()), None, &conn)?;
+    // The manifest cannot list its own hash, so under `root` it would always show up as added.
+    let manifest_key = std::fs::canonicalize(manifest_path)
+        .ok()
+        .zip(std::fs::canonicalize(root).ok())
+        .and_then(|(m, r)| m.strip_prefix(&r).map(Path::to_path_buf).ok())
+        .map(|rel| PathBuf::from(engine::path_to_db_key(&rel, opts.path_key)));
+    let files: HashMap<PathBuf, u64> = entries
+        .into_iter()
+        .filter(|e| e.kind == FileKind::File && manifest_key.as_ref() != Some(&e.path))
+        .map(|e| (e.path, e.size))
+        .collect();
+
+    let mut diff = Diff {
+        added: files
+            .keys()
+            .filter(|p| !expected.contains_key(*p))
+            .cloned()
+            .collect(),
+        removed: expected
+            .keys()
+            .filter(|p| !files.contains_key(*p))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+    let listed: Vec<(&PathBuf, &[u8; 32], u64)> = expected
+        .iter()
+        .filter_map(|(p, want)| files.get(p).map(|size| (p, want, *size)))
+        .collect();
+    // `Ok` = content differs (or is no longer a regular file), `Err` = could not be read.
+    let mismatched: Vec<Result<PathBuf, PathBuf>> = listed
+        .par_iter()
+        .filter_map(|(rel, want, size)| {
+            let path = root.join(rel);
+            let got = match format {
+                ManifestFormat::Sha256 => engine::sha256_file(&path),
+                ManifestFormat::Blake3 => engine::hash_file(&path, *size),
+            };
+            match got {
+                Ok(Some(got)) => (got != **want).then(|| Ok((*rel).clone())),
+                Ok(None) => Some(Ok((*rel).clone())),
+                Err(e) => {
+                    warn!("Cannot read {}: {e:#}", path.display());
+                    Some(Err((*rel).clone()))
+                }
+            }
+        })
+        .collect();
+    for result in mismatched {
+        match result {
+            Ok(rel) => diff.modified.push(rel),
+            Err(rel) => diff.unreadable.push(rel),
+        }
+    }
+    diff.added.sort();
+    diff.removed.sort();
+    diff.modified.sort();
+    diff.unreadable.sort();
+    Ok(diff)
+}
+
+/// Expected hash by index key from a `<hex>  <path>` manifest (see [`verify_manifest`]).
+fn parse_manifest(manifest_path: &Path, opts: &Opts) -> Result<HashMap<PathBuf, [u8; 32]>> {
+    let text = std::fs::read_to_string(manifest_path)
+        .with_context(|| format!("read manifest {}", manifest_path.display()))?;
+    let mut expected = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.split_once(' ').and_then(|(hex, rest)| {
+            let hash = <[u8; 32]>::try_from(engine::hex_decode(hex)?).ok()?;
+            let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+            let path = path.strip_prefix("./").unwrap_or(path);
+            (!path.is_empty()).then_some((path, hash))
+        });
+        let Some((path, hash)) = parsed else {
+            anyhow::bail!(
+                "manifest {} line {}: expected `<64 hex digits>  <path>`",
+                manifest_path.display(),
+                i + 1
+            );
+        };
+        let key = engine::path_to_db_key(Path::new(path), opts.path_key);
+        expected.insert(PathBuf::from(key), hash);
+    }
+    Ok(expected)
+}
+
+/// Walk two live trees and diff them with [`diff_nefax`] (`a` = original, `b` = copy). No index is
+/// read or written; each root's own `.nefaxer` is excluded from its walk.
+///
+/// Without `with_hash` the comparison is the usual mtime/size check. With `with_hash` it is a content
+/// check: mtimes are ignored, directories compare by presence only, and only files present in both
+/// trees with equal sizes are hashed (a size mismatch is already a modification). A file that cannot
+/// be hashed on either side is warned about and reported modified, since it was not shown equal.
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when [`collect_entries`] fails for either tree.
+pub fn compare_dirs(a: &Path, b: &Path, opts: &Opts) -> Result<Diff> {
+    // Both trees must be keyed relative to their own roots to line up.
+    let opts = &Opts {
+        store_absolute: false,
+        ..opts.clone()
+    };
+    let conn = engine::open_db_in_memory()?;
+    let db_a = engine::create_db_path(a, None);
+    let db_b = engine::create_db_path(b, None);
+    let (entries_a, _) = collect_entries(a, opts, Some(db_a.as_path()), None, &conn)?;
+    let (entries_b, _) = collect_entries(b, opts, Some(db_b.as_path()), None, &conn)?;
+
+    let files_a: HashSet<PathBuf> = entries_a
+        .iter()
+        .filter(|e| e.kind == FileKind::File)
+        .map(|e| e.path.clone())
+        .collect();
+    let files_b: HashSet<PathBuf> = entries_b
+        .iter()
+        .filter(|e| e.kind == FileKind::File)
+        .map(|e| e.path.clone())
+        .collect();
+    let mut map_a = entries_to_compare_map(entries_a, opts.with_hash);
+    let mut map_b = entries_to_compare_map(entries_b, opts.with_hash);
+
+    if !opts.with_hash {
+        return Ok(diff_nefax(&map_a, &map_b, opts.mtime_window_ns));
+    }
+
+    let candidates: Vec<&PathBuf> = files_a
+        .intersection(&files_b)
+        .filter(|p| map_a.get(*p).map(|m| m.size) == map_b.get(*p).map(|m| m.size))
+        .collect();
+    let hashes: Vec<_> = candidates
+        .par_iter()
+        .map(|rel| {
+            let size = map_a.get(*rel).map_or(0, |m| m.size);
+            let hash = |root: &Path| {
+                let path = root.join(rel);
+                match engine::hash_file(&path, size) {
+                    Ok(Some(h)) => Some(h),
+                    Ok(None) => {
+                        warn!(
+                            "{} is no longer a regular file; cannot compare it",
+                            path.display()
+                        );
+                        None
+                    }
+                    Err(e) => {
+                        warn!("Cannot hash {}: {e:#}", path.display());
+                        None
+                    }
+                }
+            };
+            ((*rel).clone(), hash(a), hash(b))
+        })
+        .collect();
+    let mut unverified = Vec::new();
+    for (rel, hash_a, hash_b) in hashes {
+        let (Some(hash_a), Some(hash_b)) = (hash_a, hash_b) else {
+            unverified.push(rel);
+            continue;
+        };
+        if let Some(m) = map_a.get_mut(&rel) {
+            m.hash = Some(hash_a);
+        }
+        if let Some(m) = map_b.get_mut(&rel) {
+            m.hash = Some(hash_b);
+        }
+    }
+
+    let mut diff = diff_nefax(&map_a, &map_b, i64::MAX);
+    diff.modified.extend(unverified);
+    Ok(diff)
+}
+
+/// Build a comparison map from walked entries. In content mode, directories keep only their presence
+/// (mtime and size zeroed) since both vary with how the copy was made.
+fn entries_to_compare_map(entries: Vec<Entry>, content_mode: bool) -> Nefax {
+    entries
+        .into_iter()
+        .map(|e| {
+            let (mtime_ns, size) = if content_mode && e.kind == FileKind::Dir {
+                (0, 0)
+            } else {
+                (e.mtime_ns, e.size)
+            };
+            (
+                e.path,
+                PathMeta {
+                    mtime_ns,
+                    size,
+                    hash: None,
+                    btime_ns: e.btime_ns,
+                    symlink_target: e.symlink_target,
+                    xattr_hash: e.xattr_hash,
+                },
+            )
+        })
+        .collect()
+}
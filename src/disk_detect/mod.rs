@@ -8,7 +8,7 @@ use std::path::Path;
 
 use rusqlite::Connection;
 
-use crate::utils::config::WorkerThreadLimits;
+use crate::utils::config::{HDD_PARALLEL_WALK_FANOUT, WorkerThreadLimits};
 use crate::utils::fd_limit::determine_threads_given_fd_limit;
 
 // Platform-specific modules
@@ -74,6 +74,18 @@ impl DriveType {
     }
 }
 
+impl std::fmt::Display for DriveType {
+    /// Short name as shown in summaries: `SSD`, `HDD`, `Network`, or `Unknown`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DriveType::SSD => "SSD",
+            DriveType::HDD => "HDD",
+            DriveType::Network => "Network",
+            DriveType::Unknown => "Unknown",
+        })
+    }
+}
+
 /// Detect drive type for the given path (public for callers that need drive type only).
 #[must_use]
 pub fn drive_type_for_path(path: &Path) -> DriveType {
@@ -115,6 +127,16 @@ pub fn channel_cap_for_drive(drive_type: DriveType) -> usize {
     }
 }
 
+/// Walk mode for an HDD root: parallel (jwalk) when `force_parallel_walk` is set or `path` has at
+/// least `fanout` top-level entries ([`HDD_PARALLEL_WALK_FANOUT`] in a run); serial (walkdir)
+/// otherwise, to avoid seek thrashing on deep trees. A `fanout` of 0 never walks in parallel.
+#[must_use]
+pub fn hdd_parallel_walk(path: &Path, force_parallel_walk: bool, fanout: usize) -> bool {
+    force_parallel_walk
+        || (fanout > 0
+            && std::fs::read_dir(path).is_ok_and(|dir| dir.take(fanout).count() >= fanout))
+}
+
 /// Returns `(num_threads, drive_type, use_parallel_walk)` for pipeline tuning.
 ///
 /// - **CLI / pipeline:** pass `Some(conn)` so network probe results can be cached in the DB.
@@ -123,29 +145,41 @@ pub fn channel_cap_for_drive(drive_type: DriveType) -> usize {
 ///
 /// Return value: worker count (FD limit applied), drive type (SSD/HDD/Network/Unknown), and
 /// `use_parallel_walk` (`true` for jwalk, `false` for walkdir). `thread_override` forces the
-/// thread count (still capped by FD limit).
+/// thread count (still capped by FD limit); `Some(0)` means auto-detect, like `None`. The result is
+/// always at least 1. `fds_per_worker` overrides the per-worker FD estimate
+/// used for that cap (`None` = [`FDS_PER_WORKER`](crate::utils::FDS_PER_WORKER)).
+/// `force_parallel_walk` turns on the parallel walk whatever the drive type (see [`hdd_parallel_walk`]
+/// for when an HDD gets it without the override).
 #[must_use]
 pub fn determine_threads_for_drive(
     path: &Path,
     conn: Option<&Connection>,
     available_threads: usize,
     thread_override: Option<usize>,
+    fds_per_worker: Option<usize>,
+    force_parallel_walk: bool,
 ) -> (usize, DriveType, bool) {
     let limits = WorkerThreadLimits::default();
     let drive_type = drive_type_for_path(path);
     let (num_threads, use_parallel_walk) = match drive_type {
         DriveType::SSD => (available_threads, true),
-        DriveType::HDD => (available_threads.min(limits.hdd_max), false),
+        DriveType::HDD => (
+            available_threads.min(limits.hdd_max),
+            hdd_parallel_walk(path, force_parallel_walk, HDD_PARALLEL_WALK_FANOUT),
+        ),
         DriveType::Network => probe::detect_optimal_workers(path, drive_type, conn)
             .unwrap_or((available_threads, false)),
         DriveType::Unknown => (available_threads.min(limits.floor), false),
     };
+    let use_parallel_walk = use_parallel_walk || force_parallel_walk;
 
+    let thread_override = thread_override.filter(|&n| n > 0);
     let num_threads_to_use =
-        determine_threads_given_fd_limit(thread_override.unwrap_or(num_threads));
+        determine_threads_given_fd_limit(thread_override.unwrap_or(num_threads), fds_per_worker)
+            .max(1);
 
     if drive_type != DriveType::Network {
-        debug!("Drive type: {drive_type:?}, using {num_threads_to_use} threads");
+        debug!("Drive type: {drive_type}, using {num_threads_to_use} threads");
     }
 
     (num_threads_to_use, drive_type, use_parallel_walk)
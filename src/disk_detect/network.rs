@@ -11,9 +11,24 @@ pub(crate) fn is_network_fs(fs_type: &str) -> bool {
         || fs.contains("webdav")
 }
 
-/// Check if mount point indicates network path
+/// Strip the Windows extended-length prefix: `\\?\UNC\server\share` → `\\server\share`,
+/// `\\?\C:\dir` → `C:\dir`. Other paths are returned unchanged.
+#[must_use]
+pub fn strip_verbatim_prefix(path: &str) -> std::borrow::Cow<'_, str> {
+    if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        std::borrow::Cow::Owned(format!(r"\\{rest}"))
+    } else if let Some(rest) = path.strip_prefix(r"\\?\") {
+        std::borrow::Cow::Borrowed(rest)
+    } else {
+        std::borrow::Cow::Borrowed(path)
+    }
+}
+
+/// Check if mount point (or path) is a UNC network path (`\\server\share`, `//server/share`, or the
+/// extended-length `\\?\UNC\server\share`). Extended-length local paths (`\\?\C:\`) are not network.
 #[inline]
-#[allow(dead_code)]
-pub(crate) fn is_network_mount(mount: &str) -> bool {
-    mount.starts_with("\\\\") || mount.starts_with("//")
+#[must_use]
+pub fn is_network_mount(mount: &str) -> bool {
+    let mount = strip_verbatim_prefix(mount);
+    (mount.starts_with(r"\\") && !mount.starts_with(r"\\.\")) || mount.starts_with("//")
 }
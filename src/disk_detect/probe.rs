@@ -1,12 +1,12 @@
 //! Performance probing for network mounts and remote disk type detection
 
 use anyhow::{Context, Result};
-use log::{debug, info};
+use log::{debug, info, warn};
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
 use std::io::Write as IoWrite;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use crate::utils::config::{PackagePaths, WorkerThreadLimits};
@@ -19,6 +19,9 @@ struct ProbeConsts;
 impl ProbeConsts {
     pub const NUM_FILES: usize = 50;
     pub const FILE_SIZE: usize = 1024; // 1KB per file
+    pub const MAX_FILES: usize = 1000;
+    pub const MIN_FILE_SIZE: usize = 512;
+    pub const MAX_FILE_SIZE: usize = 1024 * 1024;
     pub const IOPS_HDD_THRESHOLD: f64 = 150.0; // below = HDD, else SSD
     pub const NUM_LATENCY_SAMPLES: usize = 20;
     pub const LATENCY_HIGH_MS: f64 = 10.0;
@@ -43,6 +46,9 @@ pub struct DiskTypeInfo {
     pub drive_type: String,
     /// Random I/O operations per second
     pub random_iops: f64,
+    /// Test files written (and read back) by the probe; 0 in caches from before this was recorded.
+    #[serde(default)]
+    pub probe_files: usize,
     /// When this was tested
     pub tested_at: u64,
 }
@@ -55,6 +61,145 @@ pub struct NetworkInfo {
     pub measured_at: u64,
 }
 
+/// Environment variable overriding how many test files the disk probe writes.
+pub const PROBE_FILES_ENV: &str = "NEFAXER_PROBE_FILES";
+/// Environment variable overriding the size in bytes of each probe test file.
+pub const PROBE_SIZE_ENV: &str = "NEFAXER_PROBE_SIZE";
+
+/// Size of the random I/O probe: fewer files finish sooner on high-latency links, more (or larger)
+/// files give a steadier number on fast NAS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProbeParams {
+    /// Test files to create and read back (1–1000).
+    pub num_files: usize,
+    /// Bytes per test file (512 B – 1 MiB).
+    pub file_size: usize,
+}
+
+impl Default for ProbeParams {
+    fn default() -> Self {
+        Self {
+            num_files: ProbeConsts::NUM_FILES,
+            file_size: ProbeConsts::FILE_SIZE,
+        }
+    }
+}
+
+impl ProbeParams {
+    /// Defaults overridden by [`PROBE_FILES_ENV`] / [`PROBE_SIZE_ENV`] when set; see
+    /// [`Self::from_strs`].
+    #[must_use]
+    pub fn from_env() -> Self {
+        Self::from_strs(
+            std::env::var(PROBE_FILES_ENV).ok().as_deref(),
+            std::env::var(PROBE_SIZE_ENV).ok().as_deref(),
+        )
+    }
+
+    /// Defaults overridden by the raw values of [`PROBE_FILES_ENV`] (`num_files`) and
+    /// [`PROBE_SIZE_ENV`] (`file_size`), then clamped. Unparsable values are ignored with a warning.
+    #[must_use]
+    pub fn from_strs(num_files: Option<&str>, file_size: Option<&str>) -> Self {
+        let defaults = Self::default();
+        Self {
+            num_files: parse_usize(PROBE_FILES_ENV, num_files).unwrap_or(defaults.num_files),
+            file_size: parse_usize(PROBE_SIZE_ENV, file_size).unwrap_or(defaults.file_size),
+        }
+        .clamped()
+    }
+
+    /// Clamp both fields to the supported ranges.
+    #[must_use]
+    pub fn clamped(self) -> Self {
+        Self {
+            num_files: self.num_files.clamp(1, ProbeConsts::MAX_FILES),
+            file_size: self
+                .file_size
+                .clamp(ProbeConsts::MIN_FILE_SIZE, ProbeConsts::MAX_FILE_SIZE),
+        }
+    }
+}
+
+/// Environment variable overriding the IOPS below which a probed share counts as HDD-backed.
+pub const PROBE_HDD_IOPS_ENV: &str = "NEFAXER_PROBE_HDD_IOPS";
+/// Environment variable overriding the latency (ms) above which network workers are cut hardest.
+pub const PROBE_LATENCY_HIGH_ENV: &str = "NEFAXER_PROBE_LATENCY_HIGH_MS";
+/// Environment variable overriding the latency (ms) above which network workers are cut by one step.
+pub const PROBE_LATENCY_MED_ENV: &str = "NEFAXER_PROBE_LATENCY_MED_MS";
+
+/// Cut-offs turning probe measurements into a drive class and worker count. The defaults suit plain
+/// HDD vs SSD shares; NAS with SSD caching can land near them and may need different values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbeThresholds {
+    /// Random IOPS below this classify the share as HDD, otherwise SSD.
+    pub hdd_iops: f64,
+    /// Average latency (ms) above this is high.
+    pub latency_high_ms: f64,
+    /// Average latency (ms) above this (and up to `latency_high_ms`) is medium.
+    pub latency_med_ms: f64,
+}
+
+impl Default for ProbeThresholds {
+    fn default() -> Self {
+        Self {
+            hdd_iops: ProbeConsts::IOPS_HDD_THRESHOLD,
+            latency_high_ms: ProbeConsts::LATENCY_HIGH_MS,
+            latency_med_ms: ProbeConsts::LATENCY_MED_MS,
+        }
+    }
+}
+
+impl ProbeThresholds {
+    /// Defaults overridden by [`PROBE_HDD_IOPS_ENV`], [`PROBE_LATENCY_HIGH_ENV`] and
+    /// [`PROBE_LATENCY_MED_ENV`] when set. Values that are not positive numbers are ignored with a
+    /// warning; a medium latency above the high one is lowered to it.
+    #[must_use]
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let latency_high_ms =
+            positive_f64_from_env(PROBE_LATENCY_HIGH_ENV).unwrap_or(defaults.latency_high_ms);
+        Self {
+            hdd_iops: positive_f64_from_env(PROBE_HDD_IOPS_ENV).unwrap_or(defaults.hdd_iops),
+            latency_high_ms,
+            latency_med_ms: positive_f64_from_env(PROBE_LATENCY_MED_ENV)
+                .unwrap_or(defaults.latency_med_ms)
+                .min(latency_high_ms),
+        }
+    }
+
+    /// Drive class recorded for a share measured at `iops`: `Network+HDD` or `Network+SSD`.
+    #[must_use]
+    pub fn classify(&self, iops: f64) -> &'static str {
+        if iops < self.hdd_iops {
+            "Network+HDD"
+        } else {
+            "Network+SSD"
+        }
+    }
+}
+
+fn positive_f64_from_env(var: &str) -> Option<f64> {
+    let value = std::env::var(var).ok()?;
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v > 0.0)
+        .or_else(|| {
+            warn!("Ignoring {var}={value:?}: expected a positive number");
+            None
+        })
+}
+
+fn parse_usize(var: &str, value: Option<&str>) -> Option<usize> {
+    let value = value?;
+    value
+        .trim()
+        .parse()
+        .map_err(|_| warn!("Ignoring {var}={value:?}: expected a non-negative integer"))
+        .ok()
+}
+
 /// Detect optimal worker count. For network drives optionally uses DB cache (when `conn` is Some).
 /// When `conn` is None, probe still runs for network drives but result is not cached.
 /// Returns (workers, `use_parallel_walk`). `use_parallel_walk` is true when disk type is SSD.
@@ -95,12 +240,14 @@ pub fn detect_optimal_workers(
         None => None,
     };
 
+    let thresholds = ProbeThresholds::from_env();
+
     // Get or probe disk type
     let disk_type_info = if let Some(ref info) = disk_info {
         info.disk_type.clone()
     } else {
         info!("Probing remote disk type (first run)...");
-        probe_disk_type(path)?
+        probe_disk_type(path, ProbeParams::from_env(), &thresholds)?
     };
 
     // Always do quick network latency check
@@ -108,7 +255,7 @@ pub fn detect_optimal_workers(
     let network_info = measure_network_latency(path)?;
 
     // Calculate optimal workers
-    let workers = calculate_workers(&disk_type_info, &network_info);
+    let workers = calculate_workers(&disk_type_info, &network_info, &thresholds);
     let use_parallel_walk = disk_type_info.drive_type.contains("SSD");
     let network_latency_ms = network_info.latency_ms;
 
@@ -134,17 +281,83 @@ pub fn detect_optimal_workers(
     Ok((workers, use_parallel_walk))
 }
 
-/// Probe remote disk type using random I/O test
-fn probe_disk_type(base_path: &Path) -> Result<DiskTypeInfo> {
-    let probe_dir = base_path.join(PackagePaths::get().probe_dir_name());
-    fs::create_dir_all(&probe_dir).context("create probe directory")?;
+/// Run the full probe on `path` (random I/O test plus latency check) and return the measurements,
+/// without reading or writing the DB cache. Runs on any drive type, e.g. to log or display what
+/// [`detect_optimal_workers`] would see on a network mount. Probe size follows [`ProbeParams::from_env`]
+/// and classification [`ProbeThresholds::from_env`].
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the probe files cannot be written or read, or `path` cannot be stat'ed.
+pub fn measure_disk(path: &Path) -> Result<DiskInfo> {
+    let thresholds = ProbeThresholds::from_env();
+    let disk_type = probe_disk_type(path, ProbeParams::from_env(), &thresholds)?;
+    let network = measure_network_latency(path)?;
+    let recommended_workers = calculate_workers(&disk_type, &network, &thresholds);
+    Ok(DiskInfo {
+        disk_type,
+        network: Some(network),
+        recommended_workers,
+    })
+}
+
+/// Scratch directory for [`probe_disk_type`]. Removed on drop, so an early return or a panic mid-probe
+/// does not leave test files on the share.
+pub struct ProbeDirGuard {
+    path: PathBuf,
+}
+
+impl ProbeDirGuard {
+    /// Create the probe directory under `base_path`, first removing one left by an interrupted run.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when a stale probe directory cannot be removed or the new one created.
+    pub fn create(base_path: &Path) -> Result<Self> {
+        let path = base_path.join(PackagePaths::get().probe_dir_name());
+        if path.exists() {
+            debug!("Removing stale probe directory {}", path.display());
+            fs::remove_dir_all(&path).context("remove stale probe directory")?;
+        }
+        fs::create_dir_all(&path).context("create probe directory")?;
+        Ok(Self { path })
+    }
+
+    /// The probe directory.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
 
-    let data = vec![0u8; ProbeConsts::FILE_SIZE];
+impl Drop for ProbeDirGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Probe remote disk type using a random I/O test in a scratch directory under `base_path`
+/// (removed afterwards). `params` is clamped to its supported ranges; the measured IOPS are
+/// classified with `thresholds`.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the probe directory or its test files cannot be written or read.
+pub fn probe_disk_type(
+    base_path: &Path,
+    params: ProbeParams,
+    thresholds: &ProbeThresholds,
+) -> Result<DiskTypeInfo> {
+    let params = params.clamped();
+    let guard = ProbeDirGuard::create(base_path)?;
+    let probe_dir = guard.path();
+
+    let data = vec![0u8; params.file_size];
     let mut files = Vec::new();
 
     // Create test files and measure time
     let start = Instant::now();
-    for i in 0..ProbeConsts::NUM_FILES {
+    for i in 0..params.num_files {
         let file_path = probe_dir.join(format!("test_{i}.dat"));
         let mut file = File::create(&file_path)?;
         file.write_all(&data)?;
@@ -160,25 +373,19 @@ fn probe_disk_type(base_path: &Path) -> Result<DiskTypeInfo> {
         let _ = fs::read(file_path)?;
     }
     let read_time = start.elapsed();
-
-    // Cleanup
-    fs::remove_dir_all(&probe_dir).ok();
+    drop(guard);
 
     // Calculate IOPS
-    // Create + read; fits in u32 → f64 without precision loss (clippy cast_precision_loss).
-    let total_ops = f64::from((ProbeConsts::NUM_FILES * 2) as u32);
+    // Create + read; at most 2 * MAX_FILES, so u32 → f64 is exact (clippy cast_precision_loss).
+    let total_ops = f64::from((params.num_files * 2) as u32);
     let total_time_secs = (create_time + read_time).as_secs_f64();
     let iops = total_ops / total_time_secs;
 
-    let drive_type = if iops < ProbeConsts::IOPS_HDD_THRESHOLD {
-        "Network+HDD"
-    } else {
-        "Network+SSD"
-    };
+    let drive_type = thresholds.classify(iops);
 
     debug!(
         "Disk probe: {} files in {:.2}s = {:.0} IOPS → {}",
-        ProbeConsts::NUM_FILES * 2,
+        params.num_files * 2,
         total_time_secs,
         iops,
         drive_type
@@ -187,6 +394,7 @@ fn probe_disk_type(base_path: &Path) -> Result<DiskTypeInfo> {
     Ok(DiskTypeInfo {
         drive_type: drive_type.to_string(),
         random_iops: iops,
+        probe_files: params.num_files,
         tested_at: SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -219,23 +427,31 @@ fn measure_network_latency(path: &Path) -> Result<NetworkInfo> {
 
 /// Calculate optimal worker count based on disk type and network conditions.
 /// Decision matrix: HDD+high latency → floor; HDD+low → `hdd_max`; SSD+high → `hdd_max`; SSD+low → `network_max`.
-fn calculate_workers(disk_type: &DiskTypeInfo, network: &NetworkInfo) -> usize {
+fn calculate_workers(
+    disk_type: &DiskTypeInfo,
+    network: &NetworkInfo,
+    thresholds: &ProbeThresholds,
+) -> usize {
     let limits = WorkerThreadLimits::current();
     let is_hdd = DriveType::from_disk_type_str(&disk_type.drive_type).is_hdd();
     let latency = network.latency_ms;
 
     match (is_hdd, latency) {
-        (true, l) if l > ProbeConsts::LATENCY_HIGH_MS => limits.floor,
-        (true, l) if l > ProbeConsts::LATENCY_MED_MS => limits.hdd_max.saturating_sub(1),
+        (true, l) if l > thresholds.latency_high_ms => limits.floor,
+        (true, l) if l > thresholds.latency_med_ms => limits.hdd_max.saturating_sub(1),
         (true, _) => limits.hdd_max,
-        (false, l) if l > ProbeConsts::LATENCY_HIGH_MS => limits.hdd_max,
-        (false, l) if l > ProbeConsts::LATENCY_MED_MS => limits.unknown_max,
+        (false, l) if l > thresholds.latency_high_ms => limits.hdd_max,
+        (false, l) if l > thresholds.latency_med_ms => limits.unknown_max,
         (false, _) => limits.network_max,
     }
 }
 
 /// Load cached disk info from the diskinfo table.
-fn load_cache_from_db(conn: &Connection, root_path: &str) -> Result<Option<DiskInfo>> {
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails or the cached JSON cannot be parsed.
+pub fn load_cache_from_db(conn: &Connection, root_path: &str) -> Result<Option<DiskInfo>> {
     let s: String = match conn.query_row(
         "SELECT data FROM diskinfo WHERE root_path = ?1",
         [root_path],
@@ -250,7 +466,11 @@ fn load_cache_from_db(conn: &Connection, root_path: &str) -> Result<Option<DiskI
 }
 
 /// Save disk info to the diskinfo table.
-fn save_cache_to_db(conn: &Connection, root_path: &str, info: &DiskInfo) -> Result<()> {
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when serializing or writing the row fails.
+pub fn save_cache_to_db(conn: &Connection, root_path: &str, info: &DiskInfo) -> Result<()> {
     let json = serde_json::to_string(info).context("serialize cache")?;
     conn.execute(
         "INSERT OR REPLACE INTO diskinfo (root_path, data) VALUES (?1, ?2)",
@@ -258,3 +478,51 @@ fn save_cache_to_db(conn: &Connection, root_path: &str, info: &DiskInfo) -> Resu
     )?;
     Ok(())
 }
+
+/// Delete the cached probe result for `root` (e.g. after moving hardware) so the next run re-detects.
+/// `root` should be the canonical root, as used for the cache key during detection.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the delete fails.
+pub fn clear_disk_cache(conn: &Connection, root: &Path) -> Result<()> {
+    let removed = conn
+        .execute(
+            "DELETE FROM diskinfo WHERE root_path = ?1",
+            [root.to_string_lossy().as_ref()],
+        )
+        .context("clear diskinfo cache")?;
+    debug!(
+        "Cleared {removed} cached probe row(s) for {}",
+        root.display()
+    );
+    Ok(())
+}
+
+/// Delete cached probe results for every root not in `keep` (e.g. old or unmounted shares that an
+/// index probed at some point). Roots are matched like [`clear_disk_cache`]. Returns rows removed.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when reading or deleting rows fails.
+pub fn prune_disk_cache(conn: &Connection, keep: &[&Path]) -> Result<usize> {
+    let keep: std::collections::HashSet<String> = keep
+        .iter()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    let roots: Vec<String> = conn
+        .prepare("SELECT root_path FROM diskinfo")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()
+        })
+        .context("read diskinfo roots")?;
+    let mut removed = 0;
+    for root in roots.iter().filter(|r| !keep.contains(*r)) {
+        removed += conn
+            .execute("DELETE FROM diskinfo WHERE root_path = ?1", [root])
+            .context("prune diskinfo cache")?;
+        debug!("Pruned cached probe for {root}");
+    }
+    Ok(removed)
+}
@@ -1,7 +1,7 @@
 //! Windows-specific disk type detection using sysinfo WMI
 
 use super::DriveType;
-use super::network::{is_network_fs, is_network_mount};
+use super::network::{is_network_fs, is_network_mount, strip_verbatim_prefix};
 use log::debug;
 use std::path::Path;
 use sysinfo::Disks;
@@ -19,8 +19,13 @@ pub fn detect(path: &Path) -> DriveType {
         );
     }
 
-    // Find disk containing this path
-    let path_str = path.to_string_lossy();
+    // Canonical roots carry the extended-length prefix (`\\?\C:\`, `\\?\UNC\`); sysinfo mounts do not.
+    let path_lossy = path.to_string_lossy();
+    let path_str = strip_verbatim_prefix(&path_lossy);
+    if is_network_mount(&path_str) {
+        debug!("UNC path {}: network", path.display());
+        return DriveType::Network;
+    }
     let disk = disks
         .iter()
         .filter(|d| {
@@ -5,6 +5,23 @@ use crate::utils::config::PackagePaths;
 
 struct DefaultArgs;
 
+/// Patterns read from an `--exclude-from` file.
+#[derive(Clone, Debug)]
+pub struct ExcludeFile(pub Vec<String>);
+
+/// Read `--exclude-from`: one pattern per line; blank lines and lines starting with `#` are skipped.
+/// An unreadable file is a usage error (like git's `--exclude-from`), not silently ignored.
+fn parse_exclude_file(path: &str) -> Result<ExcludeFile, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    Ok(ExcludeFile(
+        text.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_string)
+            .collect(),
+    ))
+}
+
 impl DefaultArgs {
     pub const DIR: &'static str = ".";
 }
@@ -22,34 +39,200 @@ pub struct Cli {
     #[arg(long, short)]
     pub db: Option<PathBuf>,
 
+    /// Apply the `[profiles.NAME]` table of `.nefaxer.toml` over its `[settings]` (e.g. `quick`, `deep`).
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
     /// Compare to index and report added/removed/modified; do not write to the index.
     #[arg(long)]
     pub dry_run: bool,
 
+    /// With --dry-run: compare DIR against this index (e.g. the original tree's `.nefaxer` when verifying a copy).
+    #[arg(long, value_name = "DB", requires = "dry_run")]
+    pub index_from: Option<PathBuf>,
+
+    /// With --dry-run: re-hash this fraction (0.0–1.0) of unchanged hashed files to detect silent corruption.
+    #[arg(long, value_name = "RATE", requires = "dry_run", value_parser = parse_sample_rate)]
+    pub verify_sample: Option<f64>,
+
+    /// Forget the cached drive probe for DIR (stored in the index) so this run re-detects the drive.
+    #[arg(long)]
+    pub clear_cache: bool,
+
+    /// Drop cached drive probes stored in the index for every root other than DIR (e.g. old mounts).
+    #[arg(long)]
+    pub prune_cache: bool,
+
+    /// With --dry-run: write every added/removed/modified path to this file (one section each), regardless of count.
+    #[arg(long, short = 'o', value_name = "FILE", requires = "dry_run")]
+    pub output: Option<PathBuf>,
+
+    /// List changed paths relative to this directory (e.g. `.` for the current one) instead of DIR;
+    /// applies to the terminal list, --output, and --print0 (unless --absolute).
+    #[arg(long, value_name = "DIR")]
+    pub display_relative_to: Option<PathBuf>,
+
+    /// With --dry-run: only report changes for paths matching this glob (whole path or any component,
+    /// e.g. `*.rs`, `src`). Repeatable.
+    #[arg(long, value_name = "GLOB", requires = "dry_run")]
+    pub focus: Vec<String>,
+
+    /// With --dry-run: only report paths whose mtime is newer than the index's last update (removals
+    /// are still reported). Faster to read than a full diff, but misses files copied in with old mtimes.
+    #[arg(long, requires = "dry_run")]
+    pub since: bool,
+
+    /// With --dry-run: exit with status 2 when any change is found (after printing the diff), for CI drift checks.
+    #[arg(long, requires = "dry_run", conflicts_with = "print0")]
+    pub fail_on_change: bool,
+
+    /// With --dry-run: also list directories left empty because all their contents were removed.
+    #[arg(long, requires = "dry_run")]
+    pub prune_empty_dirs: bool,
+
+    /// Profile the tree (file/dir counts, total bytes, size histogram); do not read or write the index.
+    #[arg(long, conflicts_with = "dry_run")]
+    pub stats: bool,
+
+    /// Stream one JSON object per entry (path, size, mtime_ns, hash) to stdout; do not read or write the index.
+    #[arg(long, conflicts_with_all = ["dry_run", "stats"])]
+    pub jsonl: bool,
+
+    /// Print every indexed path to stdout, sorted, from the existing index; do not walk the tree.
+    #[arg(long, conflicts_with_all = ["dry_run", "stats", "jsonl"])]
+    pub ls: bool,
+
+    /// Index into a throwaway copy of the index and report what the write would change (rows written,
+    /// rows deleted, hashing included); the index on disk is not modified.
+    #[arg(long, conflicts_with_all = ["dry_run", "stats", "jsonl", "ls", "clear_cache", "prune_cache"])]
+    pub preview: bool,
+
+    /// Print a blake3 digest of DIR's index file (to verify a copied `.nefaxer`); do not walk the tree.
+    #[arg(long, conflicts_with_all = ["dry_run", "stats", "jsonl", "ls", "preview", "print0"])]
+    pub print_digest: bool,
+
+    /// With --ls: plain (one path per line, default) or tsv (`path<TAB>size<TAB>mtime_ns`).
+    #[arg(long, value_name = "FORMAT", requires = "ls", value_parser = clap::value_parser!(crate::ListFormat))]
+    pub format: Option<crate::ListFormat>,
+
+    /// Print added and modified paths to stdout separated by NUL bytes (for `xargs -0`), relative to DIR.
+    #[arg(long, conflicts_with_all = ["stats", "jsonl", "list", "ls"])]
+    pub print0: bool,
+
+    /// With --print0: print absolute paths instead of DIR-relative ones.
+    #[arg(long, requires = "print0")]
+    pub absolute: bool,
+
+    /// With --print0: leave out directories (e.g. a new folder), keeping only files and other entries.
+    #[arg(long, requires = "print0")]
+    pub files_only: bool,
+
     /// List each changed path. If total changes exceed threshold, write to nefaxer.results instead of stdout.
     #[arg(long, short = 'l', num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
     pub list: Option<bool>,
 
+    /// With --list: most changes printed to stdout before writing nefaxer.results instead (default 100; 0 = always the file).
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(usize))]
+    pub list_threshold: Option<usize>,
+
     /// Verbose output.
     #[arg(long, short = 'v', num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
     pub verbose: Option<bool>,
 
+    /// Only print warnings and errors (no summary lines); listed paths and --output files are unaffected.
+    #[arg(long, short = 'q', num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool), conflicts_with = "verbose")]
+    pub quiet: Option<bool>,
+
+    /// Log format: pretty (colored text, default) or json (one JSON object per line, for log aggregation).
+    #[arg(long, value_name = "FORMAT", value_parser = clap::value_parser!(crate::LogFormat))]
+    pub log_format: Option<crate::LogFormat>,
+
     /// Compute blake3 hash for files (slower but more accurate).
     #[arg(long, short = 'c', num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
     pub check_hash: Option<bool>,
 
+    /// With -c: also hash files under 4 KB (normally compared by mtime/size only).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub hash_small_files: Option<bool>,
+
     /// Follow symbolic links.
     #[arg(long, short = 'f', num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
     pub follow_links: Option<bool>,
 
+    /// Follow only symlinks at most N levels below DIR (1 = top level); deeper links are not descended. Implies --follow-links.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(usize))]
+    pub follow_depth: Option<usize>,
+
+    /// Skip all dotfiles and dot-directories (e.g. .git/, .venv/).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub skip_dotfiles: Option<bool>,
+
+    /// With an external --db: index in-tree files named like nefaxer's own (results file, probe dir).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub index_self: Option<bool>,
+
+    /// Do not descend into subdirectories that hold their own index (nested .nefaxer).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub prune_nested: Option<bool>,
+
+    /// Record each entry's creation time (btime) where the platform reports one.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub track_btime: Option<bool>,
+
+    /// Count a changed creation time as a modification (implies --track-btime).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub btime_is_change: Option<bool>,
+
+    /// Hash each entry's extended attributes and count a change to them as a modification (Linux/macOS).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub track_xattrs: Option<bool>,
+
+    /// Store absolute paths in the index instead of paths relative to DIR.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub store_absolute: Option<bool>,
+
+    /// Canonical form of index keys: as-is, lowercase, nfc, or nfd (nfc/nfd make both Unicode spellings of a name match).
+    /// Recorded in the index; use the same value on every run. `--unicode-normalize FORM` is an alias.
+    #[arg(long, alias = "unicode-normalize", value_name = "POLICY", value_parser = clap::value_parser!(crate::PathKeyPolicy))]
+    pub path_key: Option<crate::PathKeyPolicy>,
+
     /// Mtime tolerance window in seconds. Files within this window are considered unchanged.
     #[arg(long, short = 'm', value_parser = clap::value_parser!(i64))]
     pub mtime_window: Option<i64>,
 
+    /// Mtime resolution in seconds (e.g. 2 for FAT/SMB copies); smaller differences are not changes.
+    /// Default: 2 on network drives, else exact (0).
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(i64))]
+    pub mtime_granularity: Option<i64>,
+
+    /// What counts as a change: mtime-size (default), size-only, or hash-only (implies --check-hash).
+    #[arg(long, value_name = "POLICY", value_parser = clap::value_parser!(crate::ChangePolicy))]
+    pub change_policy: Option<crate::ChangePolicy>,
+
+    /// Estimated file descriptors per worker for the FD-limit thread cap (default 10). Raise for deep trees.
+    #[arg(long, value_parser = clap::value_parser!(usize))]
+    pub fds_per_worker: Option<usize>,
+
+    /// Walk in parallel even on an HDD (by default only wide, shallow HDD roots are walked in parallel).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub force_parallel_walk: Option<bool>,
+
+    /// Walk only this subtree of DIR (relative path); repeatable. Rows outside the seeds are kept as they are.
+    #[arg(long = "seed", value_name = "PATH")]
+    pub seeds: Vec<PathBuf>,
+
     /// Exclude patterns (glob syntax). Can specify multiple: -e pattern1 pattern2 pattern3
     #[arg(long, short = 'e', num_args = 1..)]
     pub exclude: Vec<String>,
 
+    /// Read exclude patterns from FILE, one per line (blank lines and `#` comments ignored). Added to the other excludes; repeatable.
+    #[arg(long, value_name = "FILE", value_parser = parse_exclude_file)]
+    pub exclude_from: Vec<ExcludeFile>,
+
+    /// Also exclude common VCS and build directories (.git, .hg, .svn, node_modules, target, .venv, __pycache__, ...).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub exclude_vcs: Option<bool>,
+
     /// Strict mode: fail on first permission error instead of skipping.
     #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
     pub strict: Option<bool>,
@@ -58,9 +241,72 @@ pub struct Cli {
     #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
     pub paranoid: Option<bool>,
 
+    /// With --dry-run and --check-hash: list files whose content is unchanged and only mtime moved as touched, not modified.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool), requires = "dry_run")]
+    pub touched: Option<bool>,
+
     /// Encrypt the index database with `SQLCipher`. Prompts for passphrase (or use `NEFAXER_DB_KEY` / .env).
     #[arg(long, short = 'x', num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
     pub encrypt: Option<bool>,
+
+    /// Read the `SQLCipher` passphrase from this file instead of `NEFAXER_DB_KEY` / .env / prompt.
+    #[arg(long, value_name = "PATH")]
+    pub key_file: Option<PathBuf>,
+
+    /// Keep the passphrase in the OS keyring: prompt once, then read it from there (needs the `keyring` feature).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub keyring: Option<bool>,
+
+    /// Hash files with a key derived from the secret in this file (tamper-evident hashes; implies --check-hash).
+    /// Falls back to the NEFAXER_HASH_KEY environment variable.
+    #[arg(long, value_name = "PATH")]
+    pub hash_key_file: Option<PathBuf>,
+
+    /// Derive the hash key from the index passphrase (needs --encrypt or an encrypted index; implies --check-hash).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub keyed_hash: Option<bool>,
+
+    /// Store index paths zstd-compressed to shrink the DB (needs the `compress` feature).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub compress_paths: Option<bool>,
+
+    /// Keep index rows for paths removed from disk (append-only audit trail); removals are still reported.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub append_only: Option<bool>,
+
+    /// Compute a digest of the whole index, log it, and store it in the index (unchanged digest = no change).
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_parser = clap::value_parser!(bool))]
+    pub digest: Option<bool>,
+
+    /// Index DB crash safety: fast (synchronous=OFF), normal (default), or safe (synchronous=FULL).
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(crate::DbDurability))]
+    pub durability: Option<crate::DbDurability>,
+
+    /// Stop indexing after this many seconds and flush a partial index (the run then exits with an error).
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64))]
+    pub deadline: Option<u64>,
+
+    /// Stop the walk after N paths (quick probe of a huge tree). The index is partial: unreached paths keep their rows.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(usize))]
+    pub max_entries: Option<usize>,
+
+    /// Skip (and list) paths longer than N bytes relative to the root.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(usize))]
+    pub max_path_len: Option<usize>,
+
+    /// Seconds to wait for another process's lock on the index before failing (default 5).
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64))]
+    pub busy_timeout: Option<u64>,
+}
+
+/// Parse a sampling rate in `0.0..=1.0`.
+fn parse_sample_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("{rate} is not between 0.0 and 1.0"))
+    }
 }
 
 impl Cli {
@@ -5,10 +5,16 @@ use log::{debug, warn};
 
 use crate::Opts;
 use crate::check::check_dir;
+use crate::disk_detect::probe::{clear_disk_cache, prune_disk_cache};
 use crate::engine::arg_parser::Cli;
-use crate::engine::running_as_root;
-use crate::index::nefax_dir_with_opts;
-use crate::utils::nefaxer_toml::{apply_file_to_opts, load_nefaxer_toml};
+use crate::engine::{
+    check_root_and_canonicalize, create_db_path, display_rebase_dirs, hex_encode,
+    index_file_digest, list_index, open_db_or_detect_encrypted, open_db_read_only, running_as_root,
+    write_changed_paths_nul,
+};
+use crate::index::{nefax_dir_jsonl, nefax_dir_with_opts};
+use crate::stats::{print_profile, profile_dir};
+use crate::utils::nefaxer_toml::load_nefaxer_toml;
 use crate::utils::setup_logging;
 
 /// Overwrite opts field with CLI value only when user passed the flag.
@@ -20,49 +26,303 @@ macro_rules! apply_cli_opt {
     };
 }
 
-/// Setup options: load .nefaxer.toml into opts, then overwrite with CLI only when user passed a flag.
-fn setup_opts(cli: &Cli) -> Opts {
+/// Environment variable that sets the worker thread count (e.g. to cap parallelism in CI).
+pub const THREADS_ENV: &str = "NEFAXER_THREADS";
+
+/// Thread override from [`THREADS_ENV`] (see [`threads_from_str`]).
+fn threads_from_env() -> Option<usize> {
+    threads_from_str(std::env::var(THREADS_ENV).ok().as_deref())
+}
+
+/// Thread override from a [`THREADS_ENV`] value: a positive integer, or `0` / unset for auto-detect;
+/// anything else is ignored with a warning. Still capped by the FD limit during tuning.
+#[must_use]
+pub fn threads_from_str(value: Option<&str>) -> Option<usize> {
+    let value = value?;
+    match value.trim().parse::<usize>() {
+        Ok(0) => None,
+        Ok(n) => Some(n),
+        Err(_) => {
+            warn!("Ignoring {THREADS_ENV}={value:?}: expected a non-negative integer (0 = auto)");
+            None
+        }
+    }
+}
+
+/// Environment variable holding the secret for keyed hashes when `--hash-key-file` is not given.
+pub const HASH_KEY_ENV: &str = "NEFAXER_HASH_KEY";
+
+/// Secret for keyed hashes: `--hash-key-file` first, then [`HASH_KEY_ENV`]. Never taken on argv, where
+/// other users can read it from the process list.
+fn hash_key_secret(cli: &Cli) -> Result<Option<String>> {
+    if let Some(ref path) = cli.hash_key_file {
+        return crate::utils::passphrase::read_key_file(path).map(Some);
+    }
+    Ok(std::env::var(HASH_KEY_ENV)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty()))
+}
+
+/// Setup options: load .nefaxer.toml into opts (with the `--profile` table over its base settings),
+/// then overwrite with CLI only when user passed a flag. `num_threads` comes from [`THREADS_ENV`] when set.
+/// Only builds the options: [`handle_run`] installs the logger and logs their consistency warnings.
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when `--profile` names a profile the directory's `.nefaxer.toml` does not define,
+/// or when `--hash-key-file` cannot be read or is empty.
+pub fn setup_opts(cli: &Cli) -> Result<Opts> {
     let mut opts = Opts::default();
-    if let Some(file) = load_nefaxer_toml(&cli.dir) {
-        apply_file_to_opts(&file, &mut opts);
+    let file = load_nefaxer_toml(&cli.dir)?;
+    match (&cli.profile, file) {
+        (Some(profile), Some(file)) => file.apply_profile_to(profile, &mut opts)?,
+        (Some(profile), None) => anyhow::bail!(
+            "profile {profile:?} requested but {} has no .nefaxer.toml",
+            cli.dir.display()
+        ),
+        (None, Some(file)) => file.apply_to(&mut opts)?,
+        (None, None) => {}
     }
     opts.db_path = cli.db.clone().or(opts.db_path);
-    opts.num_threads = None;
+    opts.index_from.clone_from(&cli.index_from);
+    opts.verify_sample = cli.verify_sample;
+    opts.output.clone_from(&cli.output);
+    opts.display_relative_to
+        .clone_from(&cli.display_relative_to);
+    opts.focus.clone_from(&cli.focus);
+    opts.since_last_index = cli.since;
+    opts.fail_on_change = cli.fail_on_change;
+    opts.prune_empty_dirs = cli.prune_empty_dirs;
+    opts.key_file.clone_from(&cli.key_file);
+    opts.num_threads = threads_from_env();
+    opts.fds_per_worker = cli.fds_per_worker.or(opts.fds_per_worker);
+    apply_cli_opt!(cli, opts, force_parallel_walk => force_parallel_walk);
     apply_cli_opt!(cli, opts, check_hash => with_hash);
+    apply_cli_opt!(cli, opts, hash_small_files => hash_small_files);
     apply_cli_opt!(cli, opts, follow_links => follow_links);
+    opts.follow_depth = cli.follow_depth.or(opts.follow_depth);
+    apply_cli_opt!(cli, opts, skip_dotfiles => skip_dotfiles);
+    apply_cli_opt!(cli, opts, index_self => index_self);
+    apply_cli_opt!(cli, opts, prune_nested => prune_nested);
+    apply_cli_opt!(cli, opts, store_absolute => store_absolute);
+    apply_cli_opt!(cli, opts, track_btime => track_btime);
+    apply_cli_opt!(cli, opts, btime_is_change => btime_is_change);
+    opts.track_btime |= opts.btime_is_change;
+    apply_cli_opt!(cli, opts, track_xattrs => track_xattrs);
+    if let Some(policy) = cli.path_key {
+        opts.path_key = policy;
+    }
+    if !cli.seeds.is_empty() {
+        opts.seeds.clone_from(&cli.seeds);
+    }
     if !cli.exclude.is_empty() {
         opts.exclude.clone_from(&cli.exclude);
     }
+    for file in &cli.exclude_from {
+        opts.exclude.extend(file.0.iter().cloned());
+    }
+    apply_cli_opt!(cli, opts, exclude_vcs => exclude_vcs);
     apply_cli_opt!(cli, opts, verbose => verbose);
+    apply_cli_opt!(cli, opts, quiet => quiet);
+    if opts.quiet {
+        // Quiet wins over a verbose default from .nefaxer.toml: no progress bars or debug output.
+        opts.verbose = false;
+    }
     if let Some(secs) = cli.mtime_window {
         opts.mtime_window_ns = secs * 1_000_000_000;
     }
+    if let Some(secs) = cli.mtime_granularity {
+        opts.mtime_granularity_ns = Some(secs * 1_000_000_000);
+    }
+    if let Some(policy) = cli.change_policy {
+        opts.change_policy = policy;
+    }
+    if matches!(opts.change_policy, crate::ChangePolicy::HashOnly) {
+        opts.with_hash = true;
+    }
     apply_cli_opt!(cli, opts, strict => strict);
     apply_cli_opt!(cli, opts, paranoid => paranoid);
+    apply_cli_opt!(cli, opts, touched => report_touched);
     apply_cli_opt!(cli, opts, encrypt => encrypt);
+    apply_cli_opt!(cli, opts, keyring => keyring);
+    apply_cli_opt!(cli, opts, keyed_hash => keyed_hash);
+    if let Some(secret) = hash_key_secret(cli)? {
+        opts.hash_key = Some(crate::engine::HashKey::derive(&secret));
+    }
+    if opts.keyed_hash || opts.hash_key.is_some() {
+        opts.with_hash = true;
+    }
+    apply_cli_opt!(cli, opts, compress_paths => compress_paths);
+    apply_cli_opt!(cli, opts, append_only => append_only);
+    apply_cli_opt!(cli, opts, digest => tree_digest);
+    if let Some(log_format) = cli.log_format {
+        opts.log_format = log_format;
+    }
+    if let Some(durability) = cli.durability {
+        opts.durability = durability;
+    }
     apply_cli_opt!(cli, opts, list => list_paths);
+    if let Some(secs) = cli.deadline {
+        opts.deadline = Some(std::time::Duration::from_secs(secs));
+    }
+    if cli.max_entries.is_some() {
+        opts.max_entries = cli.max_entries;
+    }
+    if cli.max_path_len.is_some() {
+        opts.max_path_len = cli.max_path_len;
+    }
+    if cli.list_threshold.is_some() {
+        opts.list_threshold = cli.list_threshold;
+    }
+    if let Some(secs) = cli.busy_timeout {
+        opts.busy_timeout = Some(std::time::Duration::from_secs(secs));
+    }
+    if cli.print0 {
+        // stdout carries the NUL list; a `list = true` from .nefaxer.toml must not mix into it.
+        opts.list_paths = false;
+    }
     opts.write_to_db = !cli.dry_run;
-    setup_logging(opts.verbose);
-    opts
+    if !cli.dry_run {
+        // Like --touched (which requires --dry-run), `touched = true` from .nefaxer.toml only
+        // applies to dry runs; an index run records the touch as a modification.
+        opts.report_touched = false;
+    }
+    opts.preview = cli.preview;
+    Ok(opts)
+}
+
+/// Remove the stored drive probe for `dir` from its index, if the index exists.
+fn clear_cached_probe(dir: &std::path::Path, opts: &Opts) -> Result<()> {
+    let db_path = create_db_path(dir, opts.db_path.as_deref());
+    if !db_path.exists() {
+        debug!("No index at {}; no probe cache to clear", db_path.display());
+        return Ok(());
+    }
+    let root = check_root_and_canonicalize(dir)?;
+    let (conn, _) =
+        open_db_or_detect_encrypted(&db_path, dir, &crate::utils::KeySource::from_opts(opts))?;
+    clear_disk_cache(&conn, &root)?;
+    log::info!("Cleared cached drive probe for {}", root.display());
+    Ok(())
 }
 
-/// Run index (default) or compare-only when --dry-run. Does not write to index when `dry_run`.
+/// Remove stored drive probes for every root except `dir` from its index, if the index exists.
+fn prune_cached_probes(dir: &std::path::Path, opts: &Opts) -> Result<()> {
+    let db_path = create_db_path(dir, opts.db_path.as_deref());
+    if !db_path.exists() {
+        debug!("No index at {}; no probe cache to prune", db_path.display());
+        return Ok(());
+    }
+    let root = check_root_and_canonicalize(dir)?;
+    let (conn, _) =
+        open_db_or_detect_encrypted(&db_path, dir, &crate::utils::KeySource::from_opts(opts))?;
+    let removed = prune_disk_cache(&conn, &[root.as_path()])?;
+    log::info!("Pruned {removed} cached drive probe(s) for other roots");
+    Ok(())
+}
+
+/// Run index (default), compare-only when --dry-run, a walk-only profile when --stats, a JSON-lines
+/// export to stdout when --jsonl, a listing of the stored index when --ls, or the index file's digest
+/// when --print-digest. Does not write to index when `dry_run`, `stats`, `jsonl`, `ls`, or `print_digest`.
 ///
 /// # Errors
 ///
-/// Returns [`crate::Error`] from [`crate::check::check_dir`] (dry-run) or
-/// [`crate::index::nefax_dir_with_opts`] (index).
+/// Returns [`crate::Error`] from [`crate::check::check_dir`] (dry-run),
+/// [`crate::stats::profile_dir`] (stats), [`crate::index::nefax_dir_jsonl`] (jsonl),
+/// [`crate::engine::list_index`] (ls), or [`crate::index::nefax_dir_with_opts`] (index).
 pub fn handle_run(cli: &Cli) -> Result<()> {
-    let opts = setup_opts(cli);
+    let opts = setup_opts(cli)?;
+    setup_logging(opts.verbose, opts.quiet, opts.log_format);
+    for warning in opts.consistency_warnings() {
+        warn!("{warning}");
+    }
     if running_as_root() && !opts.encrypt {
         log::info!("Running as root. Consider using -x or --encrypt to protect the index.");
     }
-    if cli.dry_run {
-        warn!("RUNNING IN DRY-RUN MODE. NO CHANGES WILL BE APPLIED TO THE INDEX.");
-        check_dir(&cli.dir, &opts)?;
+    if cli.clear_cache {
+        clear_cached_probe(&cli.dir, &opts)?;
+    }
+    if cli.prune_cache {
+        prune_cached_probes(&cli.dir, &opts)?;
+    }
+    if cli.stats {
+        debug!("Profiling directory...");
+        print_profile(&profile_dir(&cli.dir, &opts)?);
+    } else if cli.ls {
+        list_indexed_paths(cli, &opts)?;
+    } else if cli.print_digest {
+        print_index_digest(cli, &opts)?;
+    } else if cli.jsonl {
+        let out = std::io::BufWriter::new(std::io::stdout().lock());
+        let lines = nefax_dir_jsonl(&cli.dir, &opts, out)?;
+        debug!("Wrote {lines} JSON lines");
+    } else if cli.dry_run {
+        // The banner is a reminder for interactive use; --quiet callers asked for a dry run explicitly.
+        if !opts.quiet {
+            warn!("RUNNING IN DRY-RUN MODE. NO CHANGES WILL BE APPLIED TO THE INDEX.");
+        }
+        let diff = check_dir(&cli.dir, &opts)?;
+        print0_changed_paths(cli, &opts, &diff)?;
     } else {
         debug!("Nefaxing directory...");
-        let _ = nefax_dir_with_opts(&cli.dir, &opts, None)?;
+        let (_, diff, report) = nefax_dir_with_opts(&cli.dir, &opts, None)?;
+        print0_changed_paths(cli, &opts, &diff)?;
+        // Scheduled jobs rely on a non-zero exit to notice an incomplete index; a --max-entries
+        // probe asked for one.
+        if report.status == crate::CompletionStatus::Partial && opts.max_entries.is_none() {
+            anyhow::bail!("Nefaxing stopped early; the index is partial");
+        }
+    }
+    Ok(())
+}
+
+/// With --ls, print the paths stored in DIR's index (no walk).
+fn list_indexed_paths(cli: &Cli, opts: &Opts) -> Result<()> {
+    let db_path = create_db_path(&cli.dir, opts.db_path.as_deref());
+    if !db_path.exists() {
+        anyhow::bail!(
+            "No index at {}; run nefaxer on the directory first",
+            db_path.display()
+        );
+    }
+    let (conn, _) = open_db_read_only(
+        &db_path,
+        &cli.dir,
+        &crate::utils::KeySource::from_opts(opts),
+    )?;
+    let with_meta = cli.format.unwrap_or_default() == crate::ListFormat::Tsv;
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let lines = list_index(&conn, with_meta, &mut out)?;
+    debug!("Listed {lines} indexed paths");
+    Ok(())
+}
+
+/// With --print-digest, print `<blake3 hex>  <index path>` for DIR's index (no walk).
+fn print_index_digest(cli: &Cli, opts: &Opts) -> Result<()> {
+    let db_path = create_db_path(&cli.dir, opts.db_path.as_deref());
+    if !db_path.exists() {
+        anyhow::bail!(
+            "No index at {}; run nefaxer on the directory first",
+            db_path.display()
+        );
+    }
+    let digest = index_file_digest(&db_path)?;
+    println!("{}  {}", hex_encode(&digest), db_path.display());
+    Ok(())
+}
+
+/// With --print0, write the diff's changed paths to stdout NUL-separated.
+fn print0_changed_paths(cli: &Cli, opts: &Opts, diff: &crate::Diff) -> Result<()> {
+    if !cli.print0 {
+        return Ok(());
     }
+    let root = check_root_and_canonicalize(&cli.dir)?;
+    let rebase_dirs = display_rebase_dirs(&root, opts.display_relative_to.as_deref());
+    let rebase = rebase_dirs
+        .as_ref()
+        .map(|(root, base)| (root.as_path(), base.as_path()));
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    write_changed_paths_nul(&mut out, diff, &root, cli.absolute, !cli.files_only, rebase)?;
     Ok(())
 }
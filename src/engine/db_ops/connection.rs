@@ -1,13 +1,23 @@
 //! Open, backup, and load index database.
 
 use anyhow::{Context, Result};
-use rusqlite::Connection;
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
 use std::collections::HashMap;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 
-use crate::utils::get_passphrase;
+use crate::utils::config::DB_BUSY_TIMEOUT;
+use crate::utils::{KeySource, get_passphrase_for_db, remember_passphrase};
+use crate::{DbDurability, PathMeta};
 
-use super::{SCHEMA, StoredMeta, WAL_PRAGMAS};
+use super::{
+    SCHEMA, StoredBtimes, StoredDiskPaths, StoredExtras, StoredMeta, StoredSymlinkTargets,
+    StoredXattrHashes, wal_pragmas,
+};
 
 /// Enable WAL and apply schema to an open connection (idempotent).
 /// `db_path` is used in error messages when present.
@@ -17,55 +27,337 @@ fn apply_wal_and_schema(conn: &Connection, db_path: Option<&Path>) -> Result<()>
         .unwrap_or_default();
     conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
         .with_context(|| format!("enable WAL{path_ctx}"))?;
-    conn.execute_batch(WAL_PRAGMAS)
+    apply_durability(conn, DbDurability::default())
         .with_context(|| format!("set WAL pragmas{path_ctx}"))?;
     conn.execute_batch(SCHEMA)
         .with_context(|| format!("create schema{path_ctx}"))?;
+    migrate_schema(conn).with_context(|| format!("migrate schema{path_ctx}"))?;
+    Ok(())
+}
+
+/// Open a connection that waits up to [`DB_BUSY_TIMEOUT`] for another process's lock.
+fn open_connection(path: &Path) -> Result<Connection> {
+    let conn =
+        Connection::open(path).with_context(|| format!("open database at {}", path.display()))?;
+    conn.busy_timeout(DB_BUSY_TIMEOUT)
+        .with_context(|| format!("set busy timeout for {}", path.display()))?;
+    Ok(conn)
+}
+
+/// True when `err` comes from `SQLite` giving up on a lock held by another connection
+/// (`SQLITE_BUSY` / `SQLITE_LOCKED`).
+#[must_use]
+pub fn is_db_locked(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<rusqlite::Error>(),
+            Some(rusqlite::Error::SqliteFailure(e, _))
+                if matches!(
+                    e.code,
+                    rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+                )
+        )
+    })
+}
+
+/// Add a clear explanation to a lock error (see [`is_db_locked`]) on the index at `db_path` after
+/// waiting `waited`; other errors are returned unchanged.
+#[must_use]
+pub fn explain_db_locked(err: anyhow::Error, db_path: &Path, waited: Duration) -> anyhow::Error {
+    if !is_db_locked(&err) {
+        return err;
+    }
+    err.context(format!(
+        "index at {} is locked by another process (still busy after {:.1}s); wait for the other run to finish or raise --busy-timeout",
+        db_path.display(),
+        waited.as_secs_f64()
+    ))
+}
+
+/// Set the `synchronous` level and WAL checkpointing of `conn` for `durability`. [`open_db`] applies
+/// [`DbDurability::Normal`]; call this on the writing connection to pick another level. The pragmas
+/// last for the connection only; nothing is recorded in the index.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when a pragma cannot be set.
+pub fn apply_durability(conn: &Connection, durability: DbDurability) -> Result<()> {
+    conn.execute_batch(wal_pragmas(durability))
+        .with_context(|| format!("set {durability:?} durability pragmas"))
+}
+
+/// `paths` columns added after the first release, with their types; see [`migrate_schema`].
+const MIGRATED_COLUMNS: [(&str, &str); 5] = [
+    ("last_seen", "INTEGER"),
+    ("btime_ns", "INTEGER"),
+    ("symlink_target", "TEXT"),
+    ("xattr_hash", "BLOB"),
+    ("disk_path", "TEXT"),
+];
+
+/// True when `conn` already has every table and column of [`SCHEMA`], i.e. [`migrate_schema`]
+/// would change nothing.
+fn schema_is_current(conn: &Connection) -> Result<bool> {
+    let tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name IN ('paths', 'diskinfo', 'meta')",
+        [],
+        |row| row.get(0),
+    )?;
+    let columns: i64 = conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM pragma_table_info('paths') WHERE name IN ({})",
+            MIGRATED_COLUMNS.map(|(c, _)| format!("'{c}'")).join(", ")
+        ),
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(tables == 3 && columns == MIGRATED_COLUMNS.len() as i64)
+}
+
+/// Bring an index created by an older version up to [`SCHEMA`]: add `paths.last_seen` (NULL for
+/// existing rows until a run sees them), `paths.btime_ns` and `paths.xattr_hash` (NULL until a
+/// `track_btime` / `track_xattrs` run rewrites the row), `paths.symlink_target`, and
+/// `paths.disk_path`.
+fn migrate_schema(conn: &Connection) -> Result<()> {
+    for (column, ty) in MIGRATED_COLUMNS {
+        let has_column: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('paths') WHERE name = ?1",
+            [column],
+            |row| row.get(0),
+        )?;
+        if !has_column {
+            conn.execute_batch(&format!("ALTER TABLE paths ADD COLUMN {column} {ty}"))?;
+        }
+    }
     Ok(())
 }
 
 /// Open or create the index DB and ensure schema + WAL with optimizations.
-/// If `passphrase` is Some, set `SQLCipher` PRAGMA key before any other operations.
+/// If `passphrase` is Some, set `SQLCipher` PRAGMA key before any other operations and check it
+/// can read the schema, so a wrong key fails here instead of on the first real query.
 ///
 /// # Errors
 ///
-/// Returns [`anyhow::Error`] when `SQLite` open fails, the key cannot be set, or schema/WAL setup fails.
+/// Returns [`anyhow::Error`] when `SQLite` open fails, the key cannot be set or is wrong for an
+/// existing encrypted index, or schema/WAL setup fails.
 pub fn open_db(path: &Path, passphrase: Option<&str>) -> Result<Connection> {
-    let conn =
-        Connection::open(path).with_context(|| format!("open database at {}", path.display()))?;
+    try_open_db(path, passphrase).map_err(|e| explain_db_locked(e, path, DB_BUSY_TIMEOUT))
+}
+
+fn try_open_db(path: &Path, passphrase: Option<&str>) -> Result<Connection> {
+    if passphrase.is_some() {
+        ensure_sqlcipher().with_context(|| format!("open {}", path.display()))?;
+    }
+    let conn = open_connection(path)?;
 
     if let Some(key) = passphrase {
-        conn.pragma_update(None, "key", key)
-            .with_context(|| format!("set SQLCipher key for {}", path.display()))?;
+        set_key(&conn, path, key)?;
     }
 
     apply_wal_and_schema(&conn, Some(path))?;
     Ok(conn)
 }
 
+/// Set the `SQLCipher` key on `conn` and check it can read the schema.
+fn set_key(conn: &Connection, path: &Path, key: &str) -> Result<()> {
+    conn.pragma_update(None, "key", key)
+        .with_context(|| format!("set SQLCipher key for {}", path.display()))?;
+    // SQLCipher only decrypts on first read; without this a wrong key surfaces later as
+    // "file is not a database" from an unrelated statement.
+    if let Err(e) = conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())) {
+        if e.sqlite_error_code() == Some(rusqlite::ErrorCode::DatabaseBusy) {
+            return Err(e).with_context(|| format!("read {}", path.display()));
+        }
+        anyhow::bail!(
+            "wrong passphrase for encrypted index at {} ({e})",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Open the index like [`open_db`], then take an exclusive lock on it for maintenance (vacuum, rekey,
+/// migration) so no index run can write or read it meanwhile. Waits at most `timeout` for other
+/// connections to close (in WAL mode any open connection holds the lock off, not only a writer).
+/// The lock is held until the returned connection is dropped.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when [`open_db`] fails, or the lock is not granted within `timeout`
+/// (see [`is_db_locked`]; the error names the index and says another process holds it).
+pub fn open_db_exclusive(
+    path: &Path,
+    passphrase: Option<&str>,
+    timeout: Duration,
+) -> Result<Connection> {
+    let conn = open_db(path, passphrase)?;
+    conn.busy_timeout(timeout)
+        .with_context(|| format!("set busy timeout for {}", path.display()))?;
+    conn.pragma_update(None, "locking_mode", "EXCLUSIVE")
+        .with_context(|| format!("set exclusive locking mode for {}", path.display()))?;
+    // The exclusive lock is taken on the first write transaction and kept from then on.
+    conn.execute_batch("BEGIN EXCLUSIVE; COMMIT;")
+        .with_context(|| format!("lock {} for maintenance", path.display()))
+        .map_err(|e| explain_db_locked(e, path, timeout))?;
+    Ok(conn)
+}
+
+/// True when the linked `SQLite` is SQLCipher (`PRAGMA cipher_version` answers). Plain `SQLite`
+/// accepts `PRAGMA key` and ignores it, so without this check an "encrypted" index is plaintext.
+/// Checked once per process.
+#[must_use]
+pub fn sqlcipher_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Connection::open_in_memory().is_ok_and(|conn| {
+            conn.query_row("PRAGMA cipher_version", [], |row| row.get::<_, String>(0))
+                .is_ok_and(|v| !v.is_empty())
+        })
+    })
+}
+
+/// Error unless [`sqlcipher_available`]; called before any key is set or asked for.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when this build has no SQLCipher support.
+pub fn ensure_sqlcipher() -> Result<()> {
+    if !sqlcipher_available() {
+        anyhow::bail!(
+            "encryption requested, but this build of nefaxer has no SQLCipher support (built without the `sqlcipher` feature); refusing to write an unencrypted index"
+        );
+    }
+    Ok(())
+}
+
 /// Open existing DB, detecting if it is encrypted: try without key first; if read fails, load
-/// passphrase (env → .env in dir → prompt) and open with key. Returns (connection, `passphrase_used`).
+/// passphrase (`keys.key_file` → env → .env in dir → `keys.store` → prompt) and open with key. A
+/// passphrase that opens the index is saved to `keys.store`. Returns (connection, `passphrase_used`).
 ///
 /// # Errors
 ///
 /// Returns [`anyhow::Error`] when opening the database fails, passphrase loading fails, or
-/// [`open_db`] fails after decryption.
+/// [`open_db`] fails after decryption (including a wrong passphrase). Without SQLCipher support an
+/// unreadable index is an error before any passphrase is asked for.
 pub fn open_db_or_detect_encrypted(
     path: &Path,
     dir: &Path,
+    keys: &KeySource<'_>,
 ) -> Result<(Connection, Option<String>)> {
-    let conn =
-        Connection::open(path).with_context(|| format!("open database at {}", path.display()))?;
+    let conn = open_connection(path)?;
     if conn.query_row("SELECT 1", [], |_| Ok(())).is_ok() {
-        apply_wal_and_schema(&conn, Some(path))?;
+        apply_wal_and_schema(&conn, Some(path))
+            .map_err(|e| explain_db_locked(e, path, DB_BUSY_TIMEOUT))?;
         return Ok((conn, None));
     }
     drop(conn);
-    let pass = get_passphrase(dir, false)?;
+    if !sqlcipher_available() {
+        anyhow::bail!(
+            "cannot read {}; it may be an encrypted index, which this build (no SQLCipher support) cannot open",
+            path.display()
+        );
+    }
+    let pass = get_passphrase_for_db(Some(path), dir, false, keys)?;
     let conn = open_db(path, Some(pass.as_str()))?;
+    remember_passphrase(path, keys, &pass);
     Ok((conn, Some(pass)))
 }
 
+/// Open an existing index for reading only (`SQLITE_OPEN_READ_ONLY`), for comparisons and listings
+/// that must leave it untouched: no WAL switch, schema creation, or migration. Encryption is
+/// detected and the passphrase loaded as in [`open_db_or_detect_encrypted`]. A plaintext index
+/// from an older version is copied into memory and migrated there; an older encrypted index is an
+/// error. Returns (connection, `passphrase_used`).
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the index does not exist or cannot be opened, the passphrase is
+/// wrong or cannot be loaded, or an encrypted index predates the current schema.
+pub fn open_db_read_only(
+    path: &Path,
+    dir: &Path,
+    keys: &KeySource<'_>,
+) -> Result<(Connection, Option<String>)> {
+    let open = || -> Result<Connection> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .with_context(|| format!("open database at {} read-only", path.display()))?;
+        conn.busy_timeout(DB_BUSY_TIMEOUT)
+            .with_context(|| format!("set busy timeout for {}", path.display()))?;
+        Ok(conn)
+    };
+    let mut conn = open()?;
+    let mut passphrase = None;
+    if conn
+        .query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .is_err()
+    {
+        if !sqlcipher_available() {
+            anyhow::bail!(
+                "cannot read {}; it may be an encrypted index, which this build (no SQLCipher support) cannot open",
+                path.display()
+            );
+        }
+        let pass = get_passphrase_for_db(Some(path), dir, false, keys)?;
+        conn = open()?;
+        set_key(&conn, path, &pass).map_err(|e| explain_db_locked(e, path, DB_BUSY_TIMEOUT))?;
+        remember_passphrase(path, keys, &pass);
+        passphrase = Some(pass);
+    }
+    if schema_is_current(&conn).with_context(|| format!("read schema of {}", path.display()))? {
+        return Ok((conn, passphrase));
+    }
+    if passphrase.is_some() {
+        anyhow::bail!(
+            "encrypted index at {} was written by an older nefaxer; run nefaxer on its directory once to upgrade it",
+            path.display()
+        );
+    }
+    let mut copy = Connection::open_in_memory().context("open in-memory copy of index")?;
+    // A step of -1 copies every page at once.
+    let step = Backup::new(&conn, &mut copy)
+        .and_then(|backup| backup.step(-1))
+        .with_context(|| format!("copy {} into memory", path.display()))?;
+    if step != StepResult::Done {
+        anyhow::bail!(
+            "copy {} into memory: index is busy ({step:?})",
+            path.display()
+        );
+    }
+    copy.execute_batch(SCHEMA)
+        .with_context(|| format!("create schema in copy of {}", path.display()))?;
+    migrate_schema(&copy).with_context(|| format!("migrate copy of {}", path.display()))?;
+    Ok((copy, None))
+}
+
+/// blake3 of the index file's bytes, to check that a copy of `.nefaxer` arrived intact. Unlike the
+/// tree digest ([`crate::Opts::tree_digest`]) this covers the file itself, so any write changes
+/// it. Changes still in a `-wal` file beside it are not covered (a warning is logged when one exists).
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when `path` is not a regular file or cannot be read.
+pub fn index_file_digest(path: &Path) -> Result<[u8; 32]> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("read metadata of {}", path.display()))?
+        .len();
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    if std::fs::metadata(&wal).is_ok_and(|m| m.len() > 0) {
+        log::warn!(
+            "{} has uncheckpointed changes in its WAL; the digest covers the main file only",
+            path.display()
+        );
+    }
+    crate::engine::hash_file(path, size)
+        .with_context(|| format!("hash {}", path.display()))?
+        .with_context(|| format!("{} is not a regular file", path.display()))
+}
+
 /// Open an in-memory DB with the same schema (for small-index path; no WAL pragmas needed).
 ///
 /// # Errors
@@ -74,6 +366,7 @@ pub fn open_db_or_detect_encrypted(
 pub fn open_db_in_memory() -> Result<Connection> {
     let conn = Connection::open_in_memory().context("open in-memory database")?;
     conn.execute_batch(SCHEMA).context("create schema")?;
+    migrate_schema(&conn).context("migrate schema")?;
     Ok(conn)
 }
 
@@ -84,28 +377,283 @@ pub fn path_count_from_db(conn: &Connection) -> Option<usize> {
         .map(|n| n.max(0) as usize)
 }
 
-/// Load existing index from DB into a map: path -> (`mtime_ns`, size, hash).
+/// Log a warning for each recorded index option that conflicts with `opts` (see [`super::index_meta_warnings`]).
 ///
 /// # Errors
 ///
-/// Returns [`anyhow::Error`] when preparing or executing the query, or reading a row fails.
+/// Returns [`anyhow::Error`] when reading the `meta` table fails.
+pub fn warn_on_index_meta_mismatch(conn: &Connection, opts: &crate::Opts) -> Result<()> {
+    let stored = super::load_index_meta(conn)?;
+    for msg in super::index_meta_warnings(&stored, opts) {
+        log::warn!("{msg}");
+    }
+    Ok(())
+}
+
+/// Load existing index from DB into a map: path -> (`mtime_ns`, size, hash). Compressed paths
+/// (see [`super::compress_index_paths`]) are decoded with the recorded codec.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when preparing or executing the query, reading a row, or decoding a
+/// compressed path fails.
 pub fn load_index(conn: &Connection) -> Result<HashMap<PathBuf, StoredMeta>> {
+    let codec = super::load_path_codec(conn)?;
     let mut stmt = conn
         .prepare("SELECT path, mtime_ns, size, hash FROM paths")
         .context("prepare load_index query")?;
     let rows = stmt
         .query_map([], |row| {
-            let path: String = row.get(0)?;
+            let path: Value = row.get(0)?;
             let mtime_ns: i64 = row.get(1)?;
             let size: i64 = row.get(2)?;
             let hash: Option<Vec<u8>> = row.get(3)?;
-            Ok((PathBuf::from(path), (mtime_ns, size.max(0) as u64, hash)))
+            Ok((path, (mtime_ns, size.max(0) as u64, hash)))
         })
         .context("query paths table")?;
     let mut map = HashMap::new();
     for row in rows {
         let (path, rest) = row.context("read path row")?;
-        map.insert(path, rest);
+        let path = super::path_codec::path_from_db_value(codec.as_ref(), path)?;
+        map.insert(PathBuf::from(path), rest);
+    }
+    Ok(map)
+}
+
+/// Stored metadata for one `path` (a single lookup; no full [`load_index`]), or `None` when the
+/// index has no such row. `path` is relative to the indexed root like the stored keys; backslashes
+/// are normalized to `/`, the index's recorded [`crate::PathKeyPolicy`] is applied, and compressed paths
+/// are encoded with the recorded codec.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails or the path cannot be encoded.
+pub fn get_path_meta(conn: &Connection, path: &Path) -> Result<Option<PathMeta>> {
+    let codec = super::load_path_codec(conn)?;
+    let key = crate::engine::path_to_db_key(path, super::stored_path_key(conn)?);
+    let key = super::path_codec::path_to_db_value(codec.as_ref(), Path::new(&key))?;
+    conn.query_row(
+        "SELECT mtime_ns, size, hash, btime_ns, symlink_target, xattr_hash FROM paths WHERE path = ?1",
+        [key],
+        |row| {
+            let hash: Option<Vec<u8>> = row.get(2)?;
+            Ok(PathMeta {
+                mtime_ns: row.get(0)?,
+                size: row.get::<_, i64>(1)?.max(0) as u64,
+                hash: hash.as_deref().and_then(|h| h.try_into().ok()),
+                btime_ns: row.get(3)?,
+                symlink_target: row.get::<_, Option<String>>(4)?.map(PathBuf::from),
+                xattr_hash: row
+                    .get::<_, Option<Vec<u8>>>(5)?
+                    .and_then(|h| h.try_into().ok()),
+            })
+        },
+    )
+    .optional()
+    .with_context(|| format!("look up {} in index", path.display()))
+}
+
+/// Stored creation times (path -> `btime_ns`) of rows that have one; see [`crate::Opts::track_btime`].
+/// Loaded separately from [`load_index`] because only `btime_is_change` runs compare them.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails or a compressed path cannot be decoded.
+pub fn load_index_btimes(conn: &Connection) -> Result<StoredBtimes> {
+    let codec = super::load_path_codec(conn)?;
+    let mut stmt = conn
+        .prepare("SELECT path, btime_ns FROM paths WHERE btime_ns IS NOT NULL")
+        .context("prepare btime query")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, Value>(0)?, row.get::<_, i64>(1)?))
+        })
+        .context("query btimes")?;
+    let mut map = HashMap::new();
+    for row in rows {
+        let (path, btime_ns) = row.context("read btime row")?;
+        let path = super::path_codec::path_from_db_value(codec.as_ref(), path)?;
+        map.insert(PathBuf::from(path), btime_ns);
     }
     Ok(map)
 }
+
+/// Stored symlink targets by path (rows written while following links), for
+/// [`StoredExtras::symlink_targets`](super::StoredExtras::symlink_targets).
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails or a compressed path cannot be decoded.
+pub fn load_index_symlink_targets(conn: &Connection) -> Result<StoredSymlinkTargets> {
+    let codec = super::load_path_codec(conn)?;
+    let mut stmt = conn
+        .prepare("SELECT path, symlink_target FROM paths WHERE symlink_target IS NOT NULL")
+        .context("prepare symlink target query")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, Value>(0)?, row.get::<_, String>(1)?))
+        })
+        .context("query symlink targets")?;
+    let mut map = HashMap::new();
+    for row in rows {
+        let (path, target) = row.context("read symlink target row")?;
+        let path = super::path_codec::path_from_db_value(codec.as_ref(), path)?;
+        map.insert(PathBuf::from(path), PathBuf::from(target));
+    }
+    Ok(map)
+}
+
+/// Stored extended-attribute hashes (path -> `xattr_hash`) of rows that have one, for
+/// [`StoredExtras::xattr_hashes`](super::StoredExtras::xattr_hashes). Values that are not 32 bytes
+/// are skipped.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails or a compressed path cannot be decoded.
+pub fn load_index_xattr_hashes(conn: &Connection) -> Result<StoredXattrHashes> {
+    let codec = super::load_path_codec(conn)?;
+    let mut stmt = conn
+        .prepare("SELECT path, xattr_hash FROM paths WHERE xattr_hash IS NOT NULL")
+        .context("prepare xattr hash query")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, Value>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })
+        .context("query xattr hashes")?;
+    let mut map = HashMap::new();
+    for row in rows {
+        let (path, hash) = row.context("read xattr hash row")?;
+        let Ok(hash) = <[u8; 32]>::try_from(hash) else {
+            continue;
+        };
+        let path = super::path_codec::path_from_db_value(codec.as_ref(), path)?;
+        map.insert(PathBuf::from(path), hash);
+    }
+    Ok(map)
+}
+
+/// On-disk names of rows whose key differs from them (see [`StoredDiskPaths`]), for
+/// [`StoredExtras::disk_paths`](super::StoredExtras::disk_paths).
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails or a compressed path cannot be decoded.
+pub fn load_index_disk_paths(conn: &Connection) -> Result<StoredDiskPaths> {
+    let codec = super::load_path_codec(conn)?;
+    let mut stmt = conn
+        .prepare("SELECT path, disk_path FROM paths WHERE disk_path IS NOT NULL")
+        .context("prepare disk path query")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, Value>(0)?, row.get::<_, String>(1)?))
+        })
+        .context("query disk paths")?;
+    let mut map = HashMap::new();
+    for row in rows {
+        let (path, disk_path) = row.context("read disk path row")?;
+        let path = super::path_codec::path_from_db_value(codec.as_ref(), path)?;
+        map.insert(PathBuf::from(path), PathBuf::from(disk_path));
+    }
+    Ok(map)
+}
+
+/// The [`StoredExtras`] a run with `opts` compares: creation times with `track_btime`, symlink
+/// targets when following links, xattr hashes with `track_xattrs`. On-disk names are always loaded.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when a query fails or a compressed path cannot be decoded.
+pub fn load_index_extras(conn: &Connection, opts: &crate::Opts) -> Result<StoredExtras> {
+    Ok(StoredExtras {
+        btimes: if opts.track_btime {
+            Some(load_index_btimes(conn)?)
+        } else {
+            None
+        },
+        btime_is_change: opts.btime_is_change,
+        symlink_targets: if opts.follow_links || opts.follow_depth.is_some() {
+            Some(load_index_symlink_targets(conn)?)
+        } else {
+            None
+        },
+        xattr_hashes: if opts.track_xattrs {
+            Some(load_index_xattr_hashes(conn)?)
+        } else {
+            None
+        },
+        disk_paths: load_index_disk_paths(conn)?,
+    })
+}
+
+/// Paths whose `last_seen` is before `older_than` (unix seconds), or unknown (rows never seen since
+/// the column was added). In an append-only index (see [`crate::Opts::append_only`]) these are the
+/// candidates for stale-file cleanup; pass e.g. the `updated_at` of an earlier run.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails or a compressed path cannot be decoded.
+pub fn query_stale(conn: &Connection, older_than: i64) -> Result<Vec<PathBuf>> {
+    let codec = super::load_path_codec(conn)?;
+    let mut stmt = conn
+        .prepare("SELECT path FROM paths WHERE last_seen IS NULL OR last_seen < ?1")
+        .context("prepare stale query")?;
+    let rows = stmt
+        .query_map([older_than], |row| row.get::<_, Value>(0))
+        .context("query stale paths")?;
+    let mut paths = Vec::new();
+    for row in rows {
+        let path = row.context("read path row")?;
+        paths.push(PathBuf::from(super::path_codec::path_from_db_value(
+            codec.as_ref(),
+            path,
+        )?));
+    }
+    Ok(paths)
+}
+
+/// Write every indexed path to `out`, one per line and sorted by path bytes, without walking the tree
+/// or loading the index into memory. With `with_meta`, each line is `path\tsize\tmtime_ns` instead.
+/// Returns the number of lines written.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when querying the index, decoding a compressed path, or writing to
+/// `out` fails.
+pub fn list_index(conn: &Connection, with_meta: bool, out: &mut impl Write) -> Result<usize> {
+    let codec = super::load_path_codec(conn)?;
+    // Plain paths come out of SQL sorted; compressed ones only sort once decoded, so those lines
+    // are collected and sorted before writing.
+    let sql = format!(
+        "SELECT path{} FROM paths{}",
+        if with_meta { ", size, mtime_ns" } else { "" },
+        if codec.is_some() {
+            ""
+        } else {
+            " ORDER BY path"
+        }
+    );
+    let mut stmt = conn.prepare(&sql).context("prepare list_index query")?;
+    let mut rows = stmt.query([]).context("query paths table")?;
+    let mut buffered = Vec::new();
+    let mut lines = 0;
+    while let Some(row) = rows.next().context("read path row")? {
+        let mut line = super::path_codec::path_from_db_value(codec.as_ref(), row.get(0)?)?;
+        if with_meta {
+            let size: i64 = row.get(1)?;
+            let mtime_ns: i64 = row.get(2)?;
+            line = format!("{line}\t{}\t{mtime_ns}", size.max(0));
+        }
+        if codec.is_some() {
+            buffered.push(line);
+        } else {
+            writeln!(out, "{line}").context("write index listing")?;
+        }
+        lines += 1;
+    }
+    buffered.sort_unstable();
+    for line in &buffered {
+        writeln!(out, "{line}").context("write index listing")?;
+    }
+    out.flush().context("flush index listing")?;
+    Ok(lines)
+}
@@ -2,6 +2,7 @@
 
 use anyhow::{Context, Result};
 use crossbeam_channel::Receiver;
+use rusqlite::types::Value;
 use rusqlite::{Connection, Statement};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
@@ -9,12 +10,17 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
-use crate::engine::hashing::{hash_equals, hash_file};
-use crate::engine::tools::{mtime_changed, path_to_db_string};
-use crate::utils::config::{DB_INSERT_BATCH_SIZE, SMALL_FILE_THRESHOLD};
-use crate::{Diff, Entry};
+use crate::engine::hashing::{HashKey, hash_file_keyed, hash_size_threshold, may_reuse_hash};
+use crate::engine::tools::{mtime_changed_with_granularity, path_to_db_string};
+use crate::pipeline::EntryBatch;
+use crate::utils::config::{DB_DELETE_BATCH_SIZE, DB_INSERT_BATCH_SIZE};
+use crate::{ChangePolicy, Diff, Entry, FileKind};
 
-use super::{INSERT_PATH_SQL, StoredMeta};
+use super::path_codec::path_to_db_value;
+use super::{
+    INSERT_PATH_SQL, PathCodec, StoredBtimes, StoredDiskPaths, StoredMeta, StoredSymlinkTargets,
+    StoredXattrHashes,
+};
 
 /// True if the entry is new or its mtime/size/hash differ from existing (within `mtime_window_ns`).
 #[must_use]
@@ -23,41 +29,250 @@ pub fn entry_needs_update(
     existing: &HashMap<PathBuf, StoredMeta>,
     mtime_window_ns: i64,
 ) -> bool {
-    match existing.get(&entry.path) {
-        None => true,
-        Some((old_mtime, old_size, old_hash)) => {
-            mtime_changed(entry.mtime_ns, *old_mtime, mtime_window_ns)
-                || *old_size != entry.size
-                || !hash_equals(&entry.hash, old_hash)
+    entry_needs_update_with_policy(entry, existing, mtime_window_ns, 0, ChangePolicy::MtimeSize)
+}
+
+/// Like [`entry_needs_update`], with `policy` deciding whether a known path changed and mtimes
+/// compared at `mtime_granularity_ns` resolution.
+#[must_use]
+pub fn entry_needs_update_with_policy(
+    entry: &Entry,
+    existing: &HashMap<PathBuf, StoredMeta>,
+    mtime_window_ns: i64,
+    mtime_granularity_ns: i64,
+    policy: ChangePolicy,
+) -> bool {
+    existing.get(&entry.path).is_none_or(|stored| {
+        policy.is_changed(entry, stored, mtime_window_ns, mtime_granularity_ns)
+    })
+}
+
+/// True when `entry`'s creation time differs from its stored one in `stored_btimes`. False without
+/// a map, or when either side has no btime (untracked, unsupported, or a row from before tracking).
+#[must_use]
+pub fn btime_changed(entry: &Entry, stored_btimes: Option<&StoredBtimes>) -> bool {
+    match (
+        entry.btime_ns,
+        stored_btimes.and_then(|m| m.get(&entry.path)),
+    ) {
+        (Some(now), Some(&before)) => now != before,
+        _ => false,
+    }
+}
+
+/// True when `entry`'s path was stored as a symlink in `stored_targets` and now points elsewhere
+/// (or is no longer a symlink). False without a map, or for paths not stored as symlinks, so rows
+/// written before targets were recorded do not all show up as changed.
+#[must_use]
+pub fn symlink_target_changed(
+    entry: &Entry,
+    stored_targets: Option<&StoredSymlinkTargets>,
+) -> bool {
+    stored_targets
+        .and_then(|m| m.get(&entry.path))
+        .is_some_and(|before| entry.symlink_target.as_ref() != Some(before))
+}
+
+/// True when `entry`'s extended-attribute hash differs from its stored one in `stored_xattrs`. False
+/// without a map, or when either side has no hash (untracked, unsupported, or a row from before
+/// tracking).
+#[must_use]
+pub fn xattr_changed(entry: &Entry, stored_xattrs: Option<&StoredXattrHashes>) -> bool {
+    match (
+        entry.xattr_hash,
+        stored_xattrs.and_then(|m| m.get(&entry.path)),
+    ) {
+        (Some(now), Some(before)) => &now != before,
+        _ => false,
+    }
+}
+
+/// Stored per-path values a run compares on top of its [`ChangePolicy`], each loaded only when its
+/// option is on (see [`super::load_index_extras`]). A difference in any of them marks the path modified.
+#[derive(Debug, Default)]
+pub struct StoredExtras {
+    /// Creation times ([`crate::Opts::track_btime`]).
+    pub btimes: Option<StoredBtimes>,
+    /// Whether a changed creation time counts as modified ([`crate::Opts::btime_is_change`]);
+    /// otherwise `btimes` is only used to fill in missing ones.
+    pub btime_is_change: bool,
+    /// Symlink targets (when following links).
+    pub symlink_targets: Option<StoredSymlinkTargets>,
+    /// Extended-attribute hashes ([`crate::Opts::track_xattrs`]).
+    pub xattr_hashes: Option<StoredXattrHashes>,
+    /// On-disk names of rows whose key was rewritten; not compared, only used to report removals.
+    pub disk_paths: StoredDiskPaths,
+}
+
+impl StoredExtras {
+    /// True when `entry` differs from a stored creation time, symlink target, or xattr hash.
+    #[must_use]
+    pub fn changed(&self, entry: &Entry) -> bool {
+        (self.btime_is_change && btime_changed(entry, self.btimes.as_ref()))
+            || symlink_target_changed(entry, self.symlink_targets.as_ref())
+            || xattr_changed(entry, self.xattr_hashes.as_ref())
+    }
+
+    /// True when `entry` has a creation time, symlink target, or xattr hash that its stored row lacks
+    /// (NULL in a row written before it was tracked). Not a change, but the row is rewritten to fill
+    /// it in.
+    #[must_use]
+    pub fn needs_backfill(&self, entry: &Entry) -> bool {
+        let missing = |known: bool, stored: Option<bool>| known && stored == Some(false);
+        missing(
+            entry.btime_ns.is_some(),
+            self.btimes.as_ref().map(|m| m.contains_key(&entry.path)),
+        ) || missing(
+            entry.symlink_target.is_some(),
+            self.symlink_targets
+                .as_ref()
+                .map(|m| m.contains_key(&entry.path)),
+        ) || missing(
+            entry.xattr_hash.is_some(),
+            self.xattr_hashes
+                .as_ref()
+                .map(|m| m.contains_key(&entry.path)),
+        )
+    }
+
+    /// The extras a run with `opts` compares, taken from snapshot rows instead of the index DB.
+    pub(crate) fn from_metas<'a>(
+        rows: impl IntoIterator<Item = (&'a PathBuf, &'a crate::PathMeta)>,
+        opts: &crate::Opts,
+    ) -> Self {
+        let mut extras = Self {
+            btimes: opts.track_btime.then(HashMap::new),
+            btime_is_change: opts.btime_is_change,
+            symlink_targets: (opts.follow_links || opts.follow_depth.is_some()).then(HashMap::new),
+            xattr_hashes: opts.track_xattrs.then(HashMap::new),
+            disk_paths: HashMap::new(),
+        };
+        for (path, meta) in rows {
+            if let (Some(map), Some(btime)) = (extras.btimes.as_mut(), meta.btime_ns) {
+                map.insert(path.clone(), btime);
+            }
+            if let (Some(map), Some(target)) = (
+                extras.symlink_targets.as_mut(),
+                meta.symlink_target.as_ref(),
+            ) {
+                map.insert(path.clone(), target.clone());
+            }
+            if let (Some(map), Some(hash)) = (extras.xattr_hashes.as_mut(), meta.xattr_hash) {
+                map.insert(path.clone(), hash);
+            }
         }
+        extras
+    }
+
+    /// The on-disk name of the stored row `key` (the key itself unless it was rewritten).
+    #[must_use]
+    pub fn disk_path(&self, key: &Path) -> PathBuf {
+        self.disk_paths
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| key.to_path_buf())
     }
 }
 
 /// Delete from the paths table every key in `existing` that is not in `current_paths`.
+/// Uses `DELETE ... WHERE path IN (...)` in chunks of `DB_DELETE_BATCH_SIZE`, all in one transaction,
+/// so removing a large subtree is not one statement per path.
 fn delete_removed_paths(
-    conn: &Connection,
+    conn: &mut Connection,
     existing: &HashMap<PathBuf, StoredMeta>,
     current_paths: &HashSet<PathBuf>,
+    codec: Option<&PathCodec>,
+) -> Result<usize> {
+    let removed = existing
+        .keys()
+        .filter(|p| !current_paths.contains(*p))
+        .map(|p| path_to_db_value(codec, p))
+        .collect::<Result<Vec<_>>>()?;
+    if removed.is_empty() {
+        return Ok(0);
+    }
+    let mut deleted = 0;
+    let tx = conn.transaction().context("begin delete transaction")?;
+    {
+        let full_sql = delete_in_sql(DB_DELETE_BATCH_SIZE);
+        let mut full_stmt = tx.prepare(&full_sql).context("prepare delete")?;
+        for chunk in removed.chunks(DB_DELETE_BATCH_SIZE) {
+            deleted += if chunk.len() == DB_DELETE_BATCH_SIZE {
+                full_stmt
+                    .execute(rusqlite::params_from_iter(chunk))
+                    .context("delete paths")?
+            } else {
+                tx.execute(
+                    &delete_in_sql(chunk.len()),
+                    rusqlite::params_from_iter(chunk),
+                )
+                .context("delete paths")?
+            };
+        }
+    }
+    tx.commit().context("commit delete transaction")?;
+    Ok(deleted)
+}
+
+/// Set `last_seen` to `seen_at` for every path of `existing` received this run. New and changed rows
+/// already got it on insert; this covers unchanged ones, in `DB_DELETE_BATCH_SIZE` chunks. Skipped by
+/// stopped runs, which did not see the whole tree.
+fn touch_seen_paths(
+    conn: &mut Connection,
+    existing: &HashMap<PathBuf, StoredMeta>,
+    current_paths: &HashSet<PathBuf>,
+    seen_at: i64,
+    codec: Option<&PathCodec>,
 ) -> Result<()> {
-    let mut stmt = conn
-        .prepare("DELETE FROM paths WHERE path = ?1")
-        .context("prepare delete")?;
-    for old_path in existing.keys() {
-        if !current_paths.contains(old_path) {
-            stmt.execute([path_to_db_string(old_path).as_str()])
-                .context("delete path")?;
+    let tx = conn.transaction().context("begin last_seen transaction")?;
+    let touch = |chunk: &[Value]| -> Result<()> {
+        let placeholders = vec!["?"; chunk.len()].join(", ");
+        let sql = format!("UPDATE paths SET last_seen = ? WHERE path IN ({placeholders})");
+        let values = std::iter::once(Value::Integer(seen_at)).chain(chunk.iter().cloned());
+        tx.prepare_cached(&sql)
+            .context("prepare last_seen update")?
+            .execute(rusqlite::params_from_iter(values))
+            .context("update last_seen")?;
+        Ok(())
+    };
+    let mut chunk = Vec::with_capacity(DB_DELETE_BATCH_SIZE);
+    for path in current_paths.iter().filter(|p| existing.contains_key(*p)) {
+        chunk.push(path_to_db_value(codec, path)?);
+        if chunk.len() == DB_DELETE_BATCH_SIZE {
+            touch(&chunk)?;
+            chunk.clear();
         }
     }
+    if !chunk.is_empty() {
+        touch(&chunk)?;
+    }
+    tx.commit().context("commit last_seen transaction")?;
     Ok(())
 }
 
+/// `DELETE FROM paths WHERE path IN (?, ?, ...)` with `n` placeholders.
+fn delete_in_sql(n: usize) -> String {
+    let placeholders = vec!["?"; n].join(", ");
+    format!("DELETE FROM paths WHERE path IN ({placeholders})")
+}
+
 /// Execute one path insert for an entry (used by `flush_batch`).
-fn execute_insert_entry(stmt: &mut Statement<'_>, e: &Entry) -> Result<()> {
+fn execute_insert_entry(
+    stmt: &mut Statement<'_>,
+    e: &Entry,
+    seen_at: i64,
+    codec: Option<&PathCodec>,
+) -> Result<()> {
     stmt.execute((
-        path_to_db_string(&e.path).as_str(),
+        path_to_db_value(codec, &e.path)?,
         e.mtime_ns,
         e.size as i64,
         e.hash.as_ref().map(|h| h.as_slice()),
+        seen_at,
+        e.btime_ns,
+        e.symlink_target.as_deref().map(path_to_db_string),
+        e.xattr_hash.as_ref().map(|h| h.as_slice()),
+        e.disk_path.as_deref().map(path_to_db_string),
     ))
     .context("insert path")?;
     Ok(())
@@ -67,12 +282,14 @@ fn execute_insert_entry(stmt: &mut Statement<'_>, e: &Entry) -> Result<()> {
 fn flush_batch(
     conn: &mut Connection,
     batch: &[Entry],
+    seen_at: i64,
+    codec: Option<&PathCodec>,
     on_batch_progress: Option<&(dyn Fn(usize) + Send)>,
 ) -> Result<usize> {
     let tx = conn.transaction().context("begin transaction")?;
     let mut stmt = tx.prepare(INSERT_PATH_SQL).context("prepare insert")?;
     for e in batch {
-        execute_insert_entry(&mut stmt, e)?;
+        execute_insert_entry(&mut stmt, e, seen_at, codec)?;
     }
     drop(stmt);
     tx.commit().context("commit transaction")?;
@@ -83,116 +300,208 @@ fn flush_batch(
     Ok(n)
 }
 
+/// Row counts from [`apply_index_diff_streaming`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteStats {
+    /// Rows written: new paths plus changed ones (an update replaces the row), and unchanged ones
+    /// given a newly tracked value (see [`StoredExtras::needs_backfill`]).
+    pub inserted: usize,
+    /// Rows deleted for paths no longer on disk (0 for `append_only` or a stopped run).
+    pub deleted: usize,
+    /// Entries received whose row was already current (`received - inserted`).
+    pub unchanged: usize,
+    /// Entries received from the pipeline.
+    pub received: usize,
+    /// Entries whose key another path of this run already had (e.g. `A.txt` and `a.txt` under
+    /// [`PathKeyPolicy::Lowercase`](crate::PathKeyPolicy::Lowercase)); each is warned about, and
+    /// only one row per key is kept.
+    pub key_collisions: usize,
+}
+
 /// Parameters for [`apply_index_diff_streaming`].
 pub struct ApplyIndexDiffStreamingParams<'a> {
     pub existing: &'a HashMap<PathBuf, StoredMeta>,
+    /// Stored creation times and symlink targets ([`super::load_index_extras`]); when set, a
+    /// changed one also counts as modified.
+    pub existing_extras: Option<&'a StoredExtras>,
     pub mtime_window_ns: i64,
+    /// Filesystem mtime resolution (see [`mtime_changed_with_granularity`]); 0 for exact.
+    pub mtime_granularity_ns: i64,
+    /// Rule for "changed" paths; [`ChangePolicy::HashOnly`] also disables stored-hash reuse.
+    pub change_policy: ChangePolicy,
     pub on_batch_progress: Option<Box<dyn Fn(usize) + Send>>,
     pub on_received_progress: Option<Box<dyn Fn(usize) + Send>>,
+    /// Called with 1 for each file at or above the hashing threshold once its hash is settled, whether
+    /// hashed or reused from the previous index. Drives the hashing bar.
+    pub on_hash_progress: Option<Box<dyn Fn(usize) + Send>>,
     pub root: Option<&'a Path>,
     pub with_hash: bool,
+    /// Hash files below the small-file threshold too ([`crate::Opts::hash_small_files`]).
+    pub hash_small_files: bool,
     /// When set, streaming checks this on each recv; if true, stops receiving, flushes batch, and returns (partial index).
     pub cancel_check: Option<Arc<AtomicBool>>,
+    /// Same as `cancel_check`, set by a [`DeadlineTimer`](crate::engine::DeadlineTimer) when the run's time budget runs out.
+    pub deadline_check: Option<Arc<AtomicBool>>,
+    /// Set by the walk when it stopped at [`crate::Opts::max_entries`]. Read once the channel is
+    /// drained: a capped run writes everything it received but, like a stopped one, deletes nothing.
+    pub walk_capped: Option<Arc<AtomicBool>>,
     /// When set, accumulate added/removed/modified for a summary after indexing (index must have existed).
     pub diff: Option<&'a mut Diff>,
     /// When set, build the current index map incrementally (path → `StoredMeta`) so caller gets it without a second `load_index`.
     pub result_map: Option<&'a mut HashMap<PathBuf, StoredMeta>>,
+    /// Codec of a compressed index (from [`super::load_path_codec`]); `None` stores plain-text paths.
+    pub path_codec: Option<&'a PathCodec>,
+    /// Keep rows of paths no longer on disk (audit trail). `diff` still lists them as removed.
+    pub append_only: bool,
+    /// Start of this run (unix seconds), written to `last_seen` of every path received (unchanged
+    /// rows only when the run was not stopped).
+    pub run_started_at: i64,
+    /// Key for keyed hashes ([`crate::Opts::hash_key`]); `None` hashes with plain blake3.
+    pub hash_key: Option<HashKey>,
+}
+
+/// Why streaming should stop early, if `cancel_check` or `deadline_check` is set.
+fn stop_requested(params: &ApplyIndexDiffStreamingParams<'_>) -> Option<&'static str> {
+    let is_set =
+        |flag: &Option<Arc<AtomicBool>>| flag.as_ref().is_some_and(|f| f.load(Ordering::Relaxed));
+    if is_set(&params.cancel_check) {
+        Some("cancelled (Ctrl+C)")
+    } else if is_set(&params.deadline_check) {
+        Some("stopped at the deadline")
+    } else {
+        None
+    }
 }
 
-/// Write entries to DB as they are received (streaming). Tracks current paths for deletes at end.
+/// Write entries to DB as they are received (streaming, in the batches the metadata workers send). Tracks current paths for deletes at end;
+/// a run stopped by `cancel_check` / `deadline_check` flushes what it received and deletes nothing,
+/// and so does an `append_only` run.
 ///
 /// # Errors
 ///
 /// Returns [`anyhow::Error`] when receiving entries or writing batches to `SQLite` fails.
 pub fn apply_index_diff_streaming(
     conn: &mut Connection,
-    entry_rx: &Receiver<Entry>,
+    entry_rx: &Receiver<EntryBatch>,
     params: &mut ApplyIndexDiffStreamingParams<'_>,
-) -> Result<usize> {
+) -> Result<WriteStats> {
     let mut current_paths = HashSet::new();
     let mut batch = Vec::with_capacity(DB_INSERT_BATCH_SIZE);
     let mut written = 0_usize;
     let mut received = 0_usize;
+    let mut key_collisions = 0_usize;
 
-    let recv_timeout = params
-        .cancel_check
-        .as_ref()
-        .map(|_| Duration::from_millis(200));
+    let recv_timeout = (params.cancel_check.is_some() || params.deadline_check.is_some())
+        .then(|| Duration::from_millis(200));
+    let mut stopped = false;
 
     loop {
-        let mut entry = match recv_timeout {
+        if let Some(reason) = stop_requested(params) {
+            log::info!("Indexing {reason}; flushing partial index...");
+            stopped = true;
+            break;
+        }
+        let entries = match recv_timeout {
             Some(ref timeout) => match entry_rx.recv_timeout(*timeout) {
-                Ok(entry) => entry,
-                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
-                    if params
-                        .cancel_check
-                        .as_ref()
-                        .is_some_and(|c| c.load(Ordering::Relaxed))
-                    {
-                        log::info!("Indexing cancelled (Ctrl+C); flushing partial index...");
-                        break;
-                    }
-                    continue;
-                }
+                Ok(entries) => entries,
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
                 Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
             },
             None => match entry_rx.recv() {
-                Ok(entry) => entry,
+                Ok(entries) => entries,
                 Err(_) => break,
             },
         };
-        received += 1;
-        if let Some(ref cb) = params.on_received_progress
-            && received.is_multiple_of(DB_INSERT_BATCH_SIZE)
-        {
-            cb(DB_INSERT_BATCH_SIZE);
-        }
-        if params.with_hash
-            && entry.size >= SMALL_FILE_THRESHOLD
-            && let Some(r) = params.root
-        {
-            let existing_meta = params.existing.get(&entry.path);
-            let reuse_hash = existing_meta.is_some_and(|(old_mtime, old_size, old_hash)| {
-                !mtime_changed(entry.mtime_ns, *old_mtime, params.mtime_window_ns)
-                    && entry.size == *old_size
-                    && old_hash.as_ref().is_some_and(|v| v.len() == 32)
-            });
-            if reuse_hash {
-                if let Some((_, _, Some(v))) = existing_meta {
-                    let mut arr = [0u8; 32];
-                    arr.copy_from_slice(v);
-                    entry.hash = Some(arr);
+        for mut entry in entries {
+            received += 1;
+            if let Some(ref cb) = params.on_received_progress
+                && received.is_multiple_of(DB_INSERT_BATCH_SIZE)
+            {
+                cb(DB_INSERT_BATCH_SIZE);
+            }
+            if params.with_hash
+                && entry.kind == FileKind::File
+                && entry.size >= hash_size_threshold(params.hash_small_files)
+                && let Some(r) = params.root
+            {
+                let existing_meta = params.existing.get(&entry.path);
+                let reuse_hash =
+                    may_reuse_hash(&params.change_policy, params.hash_small_files, entry.size)
+                        && existing_meta.is_some_and(|(old_mtime, old_size, old_hash)| {
+                            !mtime_changed_with_granularity(
+                                entry.mtime_ns,
+                                *old_mtime,
+                                params.mtime_window_ns,
+                                params.mtime_granularity_ns,
+                            ) && entry.size == *old_size
+                                && old_hash.as_ref().is_some_and(|v| v.len() == 32)
+                        });
+                if reuse_hash {
+                    if let Some((_, _, Some(v))) = existing_meta {
+                        let mut arr = [0u8; 32];
+                        arr.copy_from_slice(v);
+                        entry.hash = Some(arr);
+                    }
+                } else {
+                    let abs = r.join(entry.fs_path());
+                    if let Ok(Some(h)) = hash_file_keyed(&abs, entry.size, params.hash_key.as_ref())
+                    {
+                        entry.hash = Some(h);
+                    }
                 }
-            } else {
-                let abs = r.join(&entry.path);
-                if let Ok(Some(h)) = hash_file(&abs, entry.size) {
-                    entry.hash = Some(h);
+                if let Some(ref cb) = params.on_hash_progress {
+                    cb(1);
                 }
             }
-        }
-        current_paths.insert(entry.path.clone());
-        if let Some(ref mut map) = params.result_map {
-            let hash = entry.hash.map(|a| a.to_vec()).or_else(|| {
-                params
-                    .existing
-                    .get(&entry.path)
-                    .and_then(|(_, _, h)| h.clone())
-            });
-            map.insert(entry.path.clone(), (entry.mtime_ns, entry.size, hash));
-        }
-        if entry_needs_update(&entry, params.existing, params.mtime_window_ns) {
-            if let Some(diff) = params.diff.as_deref_mut() {
-                if params.existing.contains_key(&entry.path) {
-                    diff.modified.push(entry.path.clone());
-                } else {
-                    diff.added.push(entry.path.clone());
+            if !current_paths.insert(entry.path.clone()) {
+                key_collisions += 1;
+                log::warn!(
+                    "{} has the same index key as another path ({}); only one row is kept. Use a path key policy that keeps them apart.",
+                    entry.fs_path().display(),
+                    entry.path.display()
+                );
+            }
+            if let Some(ref mut map) = params.result_map {
+                let hash = entry.hash.map(|a| a.to_vec()).or_else(|| {
+                    params
+                        .existing
+                        .get(&entry.path)
+                        .and_then(|(_, _, h)| h.clone())
+                });
+                map.insert(entry.path.clone(), (entry.mtime_ns, entry.size, hash));
+            }
+            if entry_needs_update_with_policy(
+                &entry,
+                params.existing,
+                params.mtime_window_ns,
+                params.mtime_granularity_ns,
+                params.change_policy,
+            ) || params.existing_extras.is_some_and(|x| x.changed(&entry))
+            {
+                if let Some(diff) = params.diff.as_deref_mut() {
+                    if params.existing.contains_key(&entry.path) {
+                        diff.modified.push(entry.fs_path().to_path_buf());
+                    } else {
+                        diff.added.push(entry.fs_path().to_path_buf());
+                    }
                 }
+                batch.push(entry);
+            } else if params
+                .existing_extras
+                .is_some_and(|x| x.needs_backfill(&entry))
+            {
+                batch.push(entry);
+            }
+            if batch.len() >= DB_INSERT_BATCH_SIZE {
+                written += flush_batch(
+                    conn,
+                    &batch,
+                    params.run_started_at,
+                    params.path_codec,
+                    params.on_batch_progress.as_deref(),
+                )?;
+                batch.clear();
             }
-            batch.push(entry);
-        }
-        if batch.len() >= DB_INSERT_BATCH_SIZE {
-            written += flush_batch(conn, &batch, params.on_batch_progress.as_deref())?;
-            batch.clear();
         }
     }
 
@@ -204,21 +513,128 @@ pub fn apply_index_diff_streaming(
     }
 
     if !batch.is_empty() {
-        written += flush_batch(conn, &batch, params.on_batch_progress.as_deref())?;
+        written += flush_batch(
+            conn,
+            &batch,
+            params.run_started_at,
+            params.path_codec,
+            params.on_batch_progress.as_deref(),
+        )?;
     }
 
-    delete_removed_paths(conn, params.existing, &current_paths)?;
+    // A stopped run never saw the rest of the tree: keep those rows rather than treating them as removed.
+    stopped |= params
+        .walk_capped
+        .as_ref()
+        .is_some_and(|f| f.load(Ordering::Relaxed));
+    if !stopped {
+        touch_seen_paths(
+            conn,
+            params.existing,
+            &current_paths,
+            params.run_started_at,
+            params.path_codec,
+        )?;
+    }
+    let deleted = if !stopped && !params.append_only {
+        delete_removed_paths(conn, params.existing, &current_paths, params.path_codec)?
+    } else {
+        0
+    };
 
-    if let Some(diff) = params.diff.as_deref_mut() {
+    if !stopped && let Some(diff) = params.diff.as_deref_mut() {
         for path in params.existing.keys() {
             if !current_paths.contains(path) {
-                diff.removed.push(path.clone());
+                diff.removed.push(
+                    params
+                        .existing_extras
+                        .map_or_else(|| path.clone(), |x| x.disk_path(path)),
+                );
             }
         }
+        diff.pair_case_only_renames();
     }
 
     conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
         .context("WAL checkpoint")?;
 
-    Ok(written)
+    Ok(WriteStats {
+        inserted: written,
+        deleted,
+        unchanged: received - written,
+        received,
+        key_collisions,
+    })
+}
+
+/// Write `patch` to the index in one transaction: upsert rows are inserted or replaced (with
+/// `last_seen` set to now) and removed keys are deleted. Keys are written as given, so the patch
+/// should come from an index with the same [`crate::PathKeyPolicy`]. `received` in the returned
+/// stats is the number of upserts.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when a hash is not 64 hex digits, a path cannot be encoded, or a
+/// write fails (nothing is applied then).
+pub fn apply_patch(conn: &mut Connection, patch: &crate::Patch) -> Result<WriteStats> {
+    let codec = super::load_path_codec(conn)?;
+    let seen_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let mut deleted = 0;
+    let tx = conn.transaction().context("begin patch transaction")?;
+    {
+        let mut insert = tx.prepare(INSERT_PATH_SQL).context("prepare insert")?;
+        for e in &patch.upsert {
+            let hash = e
+                .hash
+                .as_deref()
+                .map(|h| {
+                    crate::engine::hex_decode(h)
+                        .filter(|b| b.len() == 32)
+                        .with_context(|| format!("patch entry {}: invalid hash {h:?}", e.path))
+                })
+                .transpose()?;
+            let xattr_hash = e
+                .xattr_hash
+                .as_deref()
+                .map(|h| {
+                    crate::engine::hex_decode(h)
+                        .filter(|b| b.len() == 32)
+                        .with_context(|| {
+                            format!("patch entry {}: invalid xattr hash {h:?}", e.path)
+                        })
+                })
+                .transpose()?;
+            insert
+                .execute((
+                    path_to_db_value(codec.as_ref(), Path::new(&e.path))?,
+                    e.mtime_ns,
+                    e.size as i64,
+                    hash,
+                    seen_at,
+                    e.btime_ns,
+                    e.symlink_target.as_deref(),
+                    xattr_hash,
+                    None::<&str>,
+                ))
+                .context("insert path")?;
+        }
+        let mut delete = tx
+            .prepare("DELETE FROM paths WHERE path = ?1")
+            .context("prepare delete")?;
+        for path in &patch.remove {
+            deleted += delete
+                .execute([path_to_db_value(codec.as_ref(), Path::new(path))?])
+                .context("delete path")?;
+        }
+    }
+    tx.commit().context("commit patch transaction")?;
+    Ok(WriteStats {
+        inserted: patch.upsert.len(),
+        deleted,
+        unchanged: 0,
+        received: patch.upsert.len(),
+        key_collisions: 0,
+    })
 }
@@ -0,0 +1,227 @@
+//! Index metadata (`meta` table): options the index was written with, checked against the current run.
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{Opts, PathKeyPolicy};
+
+/// Hash algorithm recorded for hashed indexes.
+pub const HASH_ALGO: &str = "blake3";
+/// `hash_algo` recorded when files are hashed with a [`HashKey`](crate::engine::HashKey).
+pub const HASH_ALGO_KEYED: &str = "blake3-keyed";
+
+/// `meta` key holding [`HashKey::check_value`](crate::engine::HashKey::check_value) of the key a
+/// keyed index was hashed under.
+pub const META_HASH_KEY_CHECK: &str = "hash_key_check";
+
+/// `meta` key holding the hex [`tree_digest`](crate::tree_digest) of the last run with `tree_digest`.
+pub const META_TREE_DIGEST: &str = "tree_digest";
+
+/// Hash algorithm a run with `opts` writes to the index.
+#[must_use]
+pub fn hash_algo_for(opts: &Opts) -> &'static str {
+    if opts.hash_key.is_some() {
+        HASH_ALGO_KEYED
+    } else {
+        HASH_ALGO
+    }
+}
+
+/// Whether the stored hashes were made under `opts.hash_key`. `None` unless both the run and the
+/// index are keyed; `Some(false)` when the recorded key check differs or is missing (an index keyed
+/// before checks were recorded cannot be trusted to match).
+#[must_use]
+pub fn hash_key_matches(stored: &IndexMeta, opts: &Opts) -> Option<bool> {
+    let key = opts.hash_key.as_ref()?;
+    if stored.hash_algo.as_deref() != Some(HASH_ALGO_KEYED) {
+        return None;
+    }
+    Some(stored.hash_key_check.as_deref() == Some(key.check_value().as_str()))
+}
+
+/// Options recorded in the `meta` table. Fields are `None` for indexes written before the table existed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexMeta {
+    pub with_hash: Option<bool>,
+    pub hash_algo: Option<String>,
+    /// Fingerprint of the hash key of a keyed index ([`META_HASH_KEY_CHECK`]).
+    pub hash_key_check: Option<String>,
+    pub mtime_window_ns: Option<i64>,
+    /// Paths stored absolute instead of relative to the root.
+    pub store_absolute: Option<bool>,
+    /// Unix seconds of the last index write.
+    pub updated_at: Option<i64>,
+    /// Path codec (e.g. [`PATH_CODEC_ZSTD`](super::PATH_CODEC_ZSTD)); `None` for plain-text paths.
+    pub path_codec: Option<String>,
+    /// Hex tree digest of the last run that computed one (see [`crate::Opts::tree_digest`]).
+    pub tree_digest: Option<String>,
+    /// Canonical form of the path keys (see [`crate::PathKeyPolicy`]); `None` for indexes written
+    /// before it was recorded.
+    pub path_key: Option<String>,
+}
+
+impl IndexMeta {
+    /// The recorded [`PathKeyPolicy`], or `None` when none (or an unknown one) was recorded.
+    #[must_use]
+    pub fn path_key_policy(&self) -> Option<PathKeyPolicy> {
+        self.path_key.as_deref().and_then(|k| k.parse().ok())
+    }
+}
+
+/// The recorded `path_key`, falling back to the `unicode_normalize` row of indexes written before
+/// Unicode normalization became a [`PathKeyPolicy`].
+fn read_path_key(conn: &Connection) -> Result<Option<String>> {
+    match read_meta(conn, "path_key")? {
+        Some(key) if key != PathKeyPolicy::AsIs.as_str() => Ok(Some(key)),
+        key => Ok(read_meta(conn, "unicode_normalize")?
+            .filter(|form| form != "none")
+            .or(key)),
+    }
+}
+
+/// The key policy lookups into this index must use ([`PathKeyPolicy::AsIs`] when none is recorded).
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails.
+pub fn stored_path_key(conn: &Connection) -> Result<PathKeyPolicy> {
+    Ok(read_path_key(conn)?
+        .and_then(|k| k.parse().ok())
+        .unwrap_or_default())
+}
+
+/// Read one value from the `meta` table.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the query fails.
+pub fn read_meta(conn: &Connection, key: &str) -> Result<Option<String>> {
+    conn.query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+        row.get(0)
+    })
+    .optional()
+    .with_context(|| format!("read meta {key}"))
+}
+
+/// Insert or replace one value in the `meta` table.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the write fails.
+pub fn write_meta(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+        [key, value],
+    )
+    .with_context(|| format!("write meta {key}"))?;
+    Ok(())
+}
+
+/// Load the recorded index options.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when reading the `meta` table fails.
+pub fn load_index_meta(conn: &Connection) -> Result<IndexMeta> {
+    Ok(IndexMeta {
+        with_hash: read_meta(conn, "with_hash")?.map(|v| v == "true"),
+        hash_algo: read_meta(conn, "hash_algo")?,
+        hash_key_check: read_meta(conn, META_HASH_KEY_CHECK)?,
+        mtime_window_ns: read_meta(conn, "mtime_window_ns")?.and_then(|v| v.parse().ok()),
+        store_absolute: read_meta(conn, "store_absolute")?.map(|v| v == "true"),
+        updated_at: read_meta(conn, "updated_at")?.and_then(|v| v.parse().ok()),
+        path_codec: read_meta(conn, "path_codec")?,
+        tree_digest: read_meta(conn, META_TREE_DIGEST)?,
+        path_key: read_path_key(conn)?,
+    })
+}
+
+/// Record the options this index was written with (called after a successful index write).
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when writing the `meta` table fails.
+pub fn store_index_meta(conn: &Connection, opts: &Opts) -> Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    write_meta(
+        conn,
+        "with_hash",
+        if opts.with_hash { "true" } else { "false" },
+    )?;
+    write_meta(conn, "hash_algo", hash_algo_for(opts))?;
+    match &opts.hash_key {
+        Some(key) => write_meta(conn, META_HASH_KEY_CHECK, &key.check_value())?,
+        None => {
+            conn.execute("DELETE FROM meta WHERE key = ?1", [META_HASH_KEY_CHECK])
+                .context("clear hash key check")?;
+        }
+    }
+    write_meta(conn, "mtime_window_ns", &opts.mtime_window_ns.to_string())?;
+    write_meta(
+        conn,
+        "store_absolute",
+        if opts.store_absolute { "true" } else { "false" },
+    )?;
+    write_meta(conn, "path_key", opts.path_key.as_str())?;
+    conn.execute("DELETE FROM meta WHERE key = 'unicode_normalize'", [])
+        .context("clear legacy unicode_normalize")?;
+    write_meta(conn, "updated_at", &now.to_string())?;
+    Ok(())
+}
+
+/// Warnings for recorded options that make a diff against this index misleading. Empty for legacy
+/// indexes (no `meta` rows). A different `mtime_window_ns` is not flagged: stored mtimes are exact,
+/// so the window only changes the tolerance of this comparison.
+#[must_use]
+pub fn index_meta_warnings(stored: &IndexMeta, opts: &Opts) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(stored_hash) = stored.with_hash
+        && stored_hash != opts.with_hash
+    {
+        let (was, now) = if stored_hash {
+            ("with", "without")
+        } else {
+            ("without", "with")
+        };
+        warnings.push(format!(
+            "Index was built {was} hashing but this run is {now} hashing; hashed files will be reported as modified. Match --check-hash to the index, or re-index to rebuild it."
+        ));
+    }
+    if let Some(stored_abs) = stored.store_absolute
+        && stored_abs != opts.store_absolute
+    {
+        let (was, now) = if stored_abs {
+            ("absolute", "relative")
+        } else {
+            ("relative", "absolute")
+        };
+        warnings.push(format!(
+            "Index stores {was} paths but this run uses {now} paths; every path will be reported as added and removed."
+        ));
+    }
+    let run_key = opts.path_key.as_str();
+    if let Some(ref key) = stored.path_key
+        && stored.path_key_policy() != Some(opts.path_key)
+    {
+        warnings.push(format!(
+            "Index path keys use policy {key}, this run uses {run_key}; affected paths will be reported as added and removed."
+        ));
+    }
+    let run_algo = hash_algo_for(opts);
+    if let Some(ref algo) = stored.hash_algo
+        && algo != run_algo
+    {
+        warnings.push(format!(
+            "Index hashes use {algo}, this run uses {run_algo}; hashed files will be reported as modified."
+        ));
+    }
+    if hash_key_matches(stored, opts) == Some(false) {
+        warnings.push(
+            "Index hashes were made under a different hash key; stored hashes are not reused."
+                .to_string(),
+        );
+    }
+    warnings
+}
@@ -2,33 +2,91 @@
 
 mod connection;
 mod indexer;
+mod meta;
+mod path_codec;
 
 pub use connection::{
-    load_index, open_db, open_db_in_memory, open_db_or_detect_encrypted, path_count_from_db,
+    apply_durability, ensure_sqlcipher, explain_db_locked, get_path_meta, index_file_digest,
+    is_db_locked, list_index, load_index, load_index_btimes, load_index_disk_paths,
+    load_index_extras, load_index_symlink_targets, load_index_xattr_hashes, open_db,
+    open_db_exclusive, open_db_in_memory, open_db_or_detect_encrypted, open_db_read_only,
+    path_count_from_db, query_stale, sqlcipher_available, warn_on_index_meta_mismatch,
 };
-pub use indexer::{ApplyIndexDiffStreamingParams, apply_index_diff_streaming, entry_needs_update};
+pub use indexer::{
+    ApplyIndexDiffStreamingParams, StoredExtras, WriteStats, apply_index_diff_streaming,
+    apply_patch, btime_changed, entry_needs_update, entry_needs_update_with_policy,
+    symlink_target_changed, xattr_changed,
+};
+pub use meta::{
+    HASH_ALGO, HASH_ALGO_KEYED, IndexMeta, META_HASH_KEY_CHECK, META_TREE_DIGEST, hash_algo_for,
+    hash_key_matches, index_meta_warnings, load_index_meta, read_meta, store_index_meta,
+    stored_path_key, write_meta,
+};
+pub use path_codec::{PATH_CODEC_ZSTD, PathCodec, compress_index_paths, load_path_codec};
 
 /// Stored row: (`mtime_ns`, size, hash).
 pub type StoredMeta = (i64, u64, Option<Vec<u8>>);
 
-/// WAL tuning pragmas (synchronous, autocheckpoint, size limit). Use after PRAGMA `journal_mode` = WAL.
-pub(crate) const WAL_PRAGMAS: &str = r"
+/// Stored creation times (`btime_ns`) by path, for rows that have one.
+pub type StoredBtimes = std::collections::HashMap<std::path::PathBuf, i64>;
+
+/// Stored link targets (`symlink_target`) by path, for rows recorded as symlinks.
+pub type StoredSymlinkTargets = std::collections::HashMap<std::path::PathBuf, std::path::PathBuf>;
+
+/// On-disk names (`disk_path`) by key, for rows whose key was rewritten from the name on disk
+/// (see [`crate::Opts::path_key`]).
+pub type StoredDiskPaths = std::collections::HashMap<std::path::PathBuf, std::path::PathBuf>;
+
+/// Stored extended-attribute hashes (`xattr_hash`) by path, for rows written with `track_xattrs`.
+pub type StoredXattrHashes = std::collections::HashMap<std::path::PathBuf, [u8; 32]>;
+
+/// WAL tuning pragmas (synchronous, autocheckpoint, size limit) for a durability level. Use after
+/// PRAGMA `journal_mode` = WAL.
+pub(crate) const fn wal_pragmas(durability: crate::DbDurability) -> &'static str {
+    match durability {
+        crate::DbDurability::Fast => {
+            r"
+        PRAGMA synchronous = OFF;
+        PRAGMA wal_autocheckpoint = 50000;
+        PRAGMA journal_size_limit = 67108864;
+        "
+        }
+        crate::DbDurability::Normal => {
+            r"
         PRAGMA synchronous = NORMAL;
         PRAGMA wal_autocheckpoint = 10000;
         PRAGMA journal_size_limit = 67108864;
-        ";
+        "
+        }
+        crate::DbDurability::Safe => {
+            r"
+        PRAGMA synchronous = FULL;
+        PRAGMA wal_autocheckpoint = 1000;
+        PRAGMA journal_size_limit = 67108864;
+        "
+        }
+    }
+}
 
-/// Insert statement for paths table.
-pub(crate) const INSERT_PATH_SQL: &str =
-    "INSERT OR REPLACE INTO paths (path, mtime_ns, size, hash) VALUES (?1, ?2, ?3, ?4)";
+/// Insert statement for paths table (`last_seen` is the run's start, unix seconds, and advances for
+/// unchanged rows on every run that is not stopped; `btime_ns` is
+/// NULL unless `track_btime`; `symlink_target` is NULL unless the path is a followed symlink;
+/// `xattr_hash` is NULL unless `track_xattrs`; `disk_path` is NULL unless the key was rewritten from
+/// the on-disk name).
+pub(crate) const INSERT_PATH_SQL: &str = "INSERT OR REPLACE INTO paths (path, mtime_ns, size, hash, last_seen, btime_ns, symlink_target, xattr_hash, disk_path) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)";
 
-/// Schema for paths and diskinfo tables.
+/// Schema for paths, diskinfo, and meta (index options as key/value) tables.
 pub(crate) const SCHEMA: &str = r"
 CREATE TABLE IF NOT EXISTS paths (
     path TEXT PRIMARY KEY,
     mtime_ns INTEGER NOT NULL,
     size INTEGER NOT NULL,
-    hash BLOB
+    hash BLOB,
+    last_seen INTEGER,
+    btime_ns INTEGER,
+    symlink_target TEXT,
+    xattr_hash BLOB,
+    disk_path TEXT
 );
 CREATE INDEX IF NOT EXISTS idx_paths_path ON paths(path);
 
@@ -36,4 +94,9 @@ CREATE TABLE IF NOT EXISTS diskinfo (
     root_path TEXT PRIMARY KEY,
     data TEXT NOT NULL
 );
+
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
 ";
@@ -0,0 +1,245 @@
+//! Optional zstd compression of the `paths.path` column (feature `compress`).
+//!
+//! A compressed index stores each path as a BLOB encoded against one raw-content dictionary made of
+//! the index's most common parent directories, so a row mostly pays for its file name. The codec and
+//! the (hex) dictionary are recorded in the `meta` table; [`load_index`](super::load_index) decodes
+//! transparently. Frames are magicless, without checksum or dictionary id: the dictionary is fixed
+//! for the life of the index.
+
+#[cfg(feature = "compress")]
+use anyhow::Context;
+use anyhow::{Result, bail};
+use rusqlite::Connection;
+use rusqlite::types::Value;
+use std::path::Path;
+
+use crate::engine::tools::path_to_db_string;
+#[cfg(feature = "compress")]
+use crate::engine::tools::{hex_decode, hex_encode};
+
+use super::read_meta;
+#[cfg(feature = "compress")]
+use super::write_meta;
+
+/// `meta` key holding the path codec name (absent for plain-text paths).
+const META_PATH_CODEC: &str = "path_codec";
+/// `meta` key holding the hex-encoded dictionary of a compressed index.
+#[cfg(feature = "compress")]
+const META_PATH_DICT: &str = "path_dict";
+/// Codec name recorded for zstd-compressed paths.
+pub const PATH_CODEC_ZSTD: &str = "zstd";
+
+/// Encoder/decoder for compressed paths. Only constructible with the `compress` feature.
+pub struct PathCodec {
+    #[cfg(feature = "compress")]
+    compressor: std::cell::RefCell<zstd::bulk::Compressor<'static>>,
+    #[cfg(feature = "compress")]
+    decompressor: std::cell::RefCell<zstd::bulk::Decompressor<'static>>,
+    #[cfg(not(feature = "compress"))]
+    never: std::convert::Infallible,
+}
+
+#[cfg(feature = "compress")]
+impl PathCodec {
+    /// Longest path accepted when decoding (bytes).
+    const MAX_PATH_BYTES: usize = 64 * 1024;
+
+    fn new(dict: &[u8]) -> Result<Self> {
+        use zstd::zstd_safe::{CParameter, DParameter, FrameFormat};
+        let mut compressor =
+            zstd::bulk::Compressor::with_dictionary(crate::utils::config::PATH_ZSTD_LEVEL, dict)
+                .context("load path dictionary")?;
+        compressor.set_parameter(CParameter::Format(FrameFormat::Magicless))?;
+        compressor.set_parameter(CParameter::ChecksumFlag(false))?;
+        compressor.set_parameter(CParameter::DictIdFlag(false))?;
+        let mut decompressor =
+            zstd::bulk::Decompressor::with_dictionary(dict).context("load path dictionary")?;
+        decompressor.set_parameter(DParameter::Format(FrameFormat::Magicless))?;
+        Ok(Self {
+            compressor: std::cell::RefCell::new(compressor),
+            decompressor: std::cell::RefCell::new(decompressor),
+        })
+    }
+
+    /// Compress one DB path string.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when zstd fails.
+    pub fn encode(&self, path: &str) -> Result<Vec<u8>> {
+        self.compressor
+            .borrow_mut()
+            .compress(path.as_bytes())
+            .context("compress path")
+    }
+
+    /// Decompress one stored path.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when the data is not a valid frame for this dictionary or not UTF-8.
+    pub fn decode(&self, data: &[u8]) -> Result<String> {
+        let raw = self
+            .decompressor
+            .borrow_mut()
+            .decompress(data, Self::MAX_PATH_BYTES)
+            .context("decompress path")?;
+        String::from_utf8(raw).context("decompressed path is not UTF-8")
+    }
+}
+
+#[cfg(not(feature = "compress"))]
+impl PathCodec {
+    /// Compress one DB path string.
+    ///
+    /// # Errors
+    ///
+    /// Never returns; a codec cannot exist without the `compress` feature.
+    pub fn encode(&self, _path: &str) -> Result<Vec<u8>> {
+        match self.never {}
+    }
+
+    /// Decompress one stored path.
+    ///
+    /// # Errors
+    ///
+    /// Never returns; a codec cannot exist without the `compress` feature.
+    pub fn decode(&self, _data: &[u8]) -> Result<String> {
+        match self.never {}
+    }
+}
+
+/// Codec recorded for this index: `None` for plain-text paths.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the index is compressed but this build lacks the `compress` feature,
+/// or the recorded codec/dictionary is invalid.
+pub fn load_path_codec(conn: &Connection) -> Result<Option<PathCodec>> {
+    let Some(codec) = read_meta(conn, META_PATH_CODEC)? else {
+        return Ok(None);
+    };
+    if codec != PATH_CODEC_ZSTD {
+        bail!("Index uses unknown path codec {codec:?}");
+    }
+    #[cfg(feature = "compress")]
+    {
+        let dict = read_meta(conn, META_PATH_DICT)?
+            .as_deref()
+            .and_then(hex_decode)
+            .context("Compressed index has no valid path dictionary")?;
+        PathCodec::new(&dict).map(Some)
+    }
+    #[cfg(not(feature = "compress"))]
+    bail!("Index paths are zstd-compressed; rebuild nefaxer with the `compress` feature to read it")
+}
+
+/// SQL value for `path` as stored in this index: BLOB when compressed, TEXT otherwise.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when compression fails.
+pub(crate) fn path_to_db_value(codec: Option<&PathCodec>, path: &Path) -> Result<Value> {
+    let s = path_to_db_string(path);
+    Ok(match codec {
+        Some(c) => Value::Blob(c.encode(&s)?),
+        None => Value::Text(s),
+    })
+}
+
+/// Inverse of [`path_to_db_value`] for a value read from the `path` column.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] for a BLOB path without a codec, a decode failure, or a non-path value.
+pub(crate) fn path_from_db_value(codec: Option<&PathCodec>, value: Value) -> Result<String> {
+    match (value, codec) {
+        (Value::Text(s), _) => Ok(s),
+        (Value::Blob(b), Some(c)) => c.decode(&b),
+        (Value::Blob(_), None) => bail!("Compressed path in an index without a path codec"),
+        (other, _) => bail!("Unexpected path value {other:?}"),
+    }
+}
+
+/// Convert a plain-text index to compressed paths: build the dictionary from its current paths,
+/// re-encode every row, record the codec in `meta`, then VACUUM and checkpoint so the file shrinks.
+/// Later runs encode new rows with the recorded dictionary. Without the `compress` feature this only
+/// logs a warning.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when reading, rewriting, or vacuuming the index fails.
+#[cfg(feature = "compress")]
+pub fn compress_index_paths(conn: &mut Connection) -> Result<()> {
+    let paths: Vec<String> = {
+        let mut stmt = conn
+            .prepare("SELECT path FROM paths")
+            .context("prepare path scan")?;
+        stmt.query_map([], |row| row.get(0))
+            .context("scan paths")?
+            .collect::<rusqlite::Result<_>>()
+            .context("read path row")?
+    };
+    let dict = build_path_dictionary(&paths);
+    let codec = PathCodec::new(&dict)?;
+    let tx = conn.transaction().context("begin compress transaction")?;
+    {
+        let mut stmt = tx
+            .prepare("UPDATE paths SET path = ?1 WHERE path = ?2")
+            .context("prepare path update")?;
+        for p in &paths {
+            stmt.execute((codec.encode(p)?, p))
+                .context("compress path row")?;
+        }
+    }
+    write_meta(&tx, META_PATH_CODEC, PATH_CODEC_ZSTD)?;
+    write_meta(&tx, META_PATH_DICT, &hex_encode(&dict))?;
+    tx.commit().context("commit compress transaction")?;
+    conn.execute_batch("VACUUM").context("vacuum index")?;
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .context("WAL checkpoint")?;
+    log::info!(
+        "Compressed {} index paths (dictionary {} bytes).",
+        paths.len(),
+        dict.len()
+    );
+    Ok(())
+}
+
+/// Convert a plain-text index to compressed paths (needs the `compress` feature).
+///
+/// # Errors
+///
+/// Never fails without the `compress` feature; logs a warning and leaves the index as is.
+#[cfg(not(feature = "compress"))]
+pub fn compress_index_paths(_conn: &mut Connection) -> Result<()> {
+    log::warn!(
+        "--compress-paths needs nefaxer built with the `compress` feature; index paths left uncompressed."
+    );
+    Ok(())
+}
+
+/// Raw-content dictionary: distinct parent directories (with trailing `/`) by how many paths share
+/// them, capped at `PATH_DICT_MAX_BYTES`. The most shared ones go last, where zstd matches are cheapest.
+#[cfg(feature = "compress")]
+fn build_path_dictionary(paths: &[String]) -> Vec<u8> {
+    use crate::utils::config::PATH_DICT_MAX_BYTES;
+    let mut counts: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+    for p in paths {
+        if let Some(i) = p.rfind('/') {
+            *counts.entry(&p[..=i]).or_default() += 1;
+        }
+    }
+    let mut dirs: Vec<(&str, usize)> = counts.into_iter().collect();
+    dirs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    let mut picked = Vec::new();
+    let mut total = 0;
+    for (dir, _) in dirs {
+        if total + dir.len() > PATH_DICT_MAX_BYTES {
+            continue;
+        }
+        total += dir.len();
+        picked.push(dir);
+    }
+    picked.iter().rev().flat_map(|d| d.bytes()).collect()
+}
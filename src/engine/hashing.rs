@@ -7,20 +7,70 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
-use crate::Entry;
 use crate::Opts;
-use crate::engine::{StoredMeta, mtime_changed};
+use crate::engine::{StoredMeta, mtime_changed_with_granularity};
 use crate::utils::config::HashingConsts;
 use crate::utils::config::SMALL_FILE_THRESHOLD;
+use crate::{Entry, FileKind};
+
+/// blake3 `derive_key` context for [`HashKey::derive`]; changing it changes every keyed hash.
+const HASH_KEY_CONTEXT: &str = "nefaxer 2026-10-16 keyed file hashes";
+/// blake3 `derive_key` context for [`HashKey::check_value`].
+const HASH_KEY_CHECK_CONTEXT: &str = "nefaxer key check";
+
+/// Key for tamper-evident file hashes (`blake3::keyed_hash`): without the secret it was derived
+/// from, nobody can compute a digest that matches the index. `Debug` does not print the key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct HashKey([u8; 32]);
+
+impl HashKey {
+    /// Derive the key from a secret (a `--hash-key-file` / `NEFAXER_HASH_KEY` value or the index
+    /// passphrase).
+    #[must_use]
+    pub fn derive(secret: &str) -> Self {
+        Self(blake3::derive_key(HASH_KEY_CONTEXT, secret.as_bytes()))
+    }
+
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Hex fingerprint recorded in the index `meta` so a run under another key is caught. It is
+    /// derived one way from the key, so it does not reveal it.
+    #[must_use]
+    pub fn check_value(&self) -> String {
+        crate::engine::hex_encode(&blake3::derive_key(HASH_KEY_CHECK_CONTEXT, &self.0))
+    }
+}
+
+impl std::fmt::Debug for HashKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HashKey(..)")
+    }
+}
 
 /// Hash a file with blake3. Uses memory-mapped I/O for files above threshold, chunked reading otherwise.
+/// Returns `Ok(None)` when `path` is not a regular file (FIFO, socket, device, directory): those are
+/// never read, since reading a FIFO or a character device can block forever.
 ///
 /// # Errors
 ///
 /// Returns [`anyhow::Error`] when opening or reading the file, or memory-mapping fails.
 pub fn hash_file(path: &Path, size: u64) -> Result<Option<[u8; 32]>> {
-    let file = File::open(path)?;
-    let mut hasher = Hasher::new();
+    hash_file_keyed(path, size, None)
+}
+
+/// Like [`hash_file`], but with `key` set the digest is `blake3::keyed_hash` under that key.
+///
+/// # Errors
+///
+/// Same as [`hash_file`].
+pub fn hash_file_keyed(path: &Path, size: u64, key: Option<&HashKey>) -> Result<Option<[u8; 32]>> {
+    let Some(file) = open_regular_file(path)? else {
+        return Ok(None);
+    };
+    let mut hasher = key.map_or_else(Hasher::new, |k| Hasher::new_keyed(k.as_bytes()));
 
     if size > HashingConsts::HASH_MMAP_THRESHOLD {
         // Memory-mapped I/O for large files (Blake3 already uses SIMD internally)
@@ -44,6 +94,140 @@ pub fn hash_file(path: &Path, size: u64) -> Result<Option<[u8; 32]>> {
     Ok(Some(*hasher.finalize().as_bytes()))
 }
 
+/// SHA-256 of a file, for checking `sha256sum` manifests (the index itself uses blake3). `Ok(None)`
+/// when `path` is not a regular file, like [`hash_file`].
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when opening or reading the file fails.
+pub fn sha256_file(path: &Path) -> Result<Option<[u8; 32]>> {
+    use sha2::Digest;
+    use std::io::Read;
+    let Some(file) = open_regular_file(path)? else {
+        return Ok(None);
+    };
+    let mut hasher = sha2::Sha256::new();
+    let mut reader = std::io::BufReader::with_capacity(HashingConsts::HASH_READ_CHUNK_SIZE, file);
+    let mut buffer = vec![0u8; HashingConsts::HASH_READ_CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(Some(hasher.finalize().into()))
+}
+
+/// Open `path` for reading only if it is a regular file. On Unix the open is non-blocking (opening a
+/// FIFO with no writer would otherwise hang) and the type is checked on the opened descriptor, so a
+/// path swapped for a FIFO after the walk is still caught.
+fn open_regular_file(path: &Path) -> Result<Option<File>> {
+    #[cfg(unix)]
+    let file = {
+        use std::os::unix::fs::OpenOptionsExt;
+        std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?
+    };
+    #[cfg(not(unix))]
+    let file = {
+        if !std::fs::metadata(path)?.is_file() {
+            return Ok(None);
+        }
+        File::open(path)?
+    };
+    Ok(file.metadata()?.is_file().then_some(file))
+}
+
+/// Blake3 over `path`'s extended attributes (each name and value, sorted by name), for
+/// [`crate::Opts::track_xattrs`]. A path with no attributes still gets a hash, so gaining the first
+/// one is a change. `Ok(None)` where the platform or filesystem has no xattrs. Links are followed,
+/// like the rest of the entry's metadata.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when listing or reading the attributes fails.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn xattr_hash(path: &Path) -> Result<Option<[u8; 32]>> {
+    let Some(list) = read_xattr_buf(|buf| rustix::fs::listxattr(path, buf))? else {
+        return Ok(None);
+    };
+    let mut names: Vec<&[u8]> = list.split(|&b| b == 0).filter(|n| !n.is_empty()).collect();
+    names.sort_unstable();
+    let mut hasher = Hasher::new();
+    for name in names {
+        let value = read_xattr_buf(|buf| rustix::fs::getxattr(path, name, buf))?;
+        // Removed between listing and reading: leave it out, as a later list would.
+        let Some(value) = value else { continue };
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name);
+        hasher.update(&(value.len() as u64).to_le_bytes());
+        hasher.update(&value);
+    }
+    Ok(Some(*hasher.finalize().as_bytes()))
+}
+
+/// No extended attributes on this platform: always `Ok(None)`.
+///
+/// # Errors
+///
+/// Never; the signature matches the Linux/macOS version.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn xattr_hash(_path: &Path) -> Result<Option<[u8; 32]>> {
+    Ok(None)
+}
+
+/// Run a size-then-fill xattr call: `call` on an empty buffer for the length, then into a buffer of
+/// that size, retrying if the data grew in between. `Ok(None)` when xattrs are unsupported
+/// (`ENOTSUP`) or the attribute is gone (`ENODATA` / `ENOATTR`).
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_xattr_buf(
+    call: impl Fn(&mut [u8]) -> rustix::io::Result<usize>,
+) -> Result<Option<Vec<u8>>> {
+    use rustix::io::Errno;
+    #[cfg(target_os = "linux")]
+    const NO_ATTR: Errno = Errno::NODATA;
+    #[cfg(target_os = "macos")]
+    const NO_ATTR: Errno = Errno::NOATTR;
+    let read = || {
+        let mut buf = vec![0u8; call(&mut [])?];
+        if !buf.is_empty() {
+            let n = call(&mut buf)?;
+            buf.truncate(n);
+        }
+        Ok(buf)
+    };
+    loop {
+        match read() {
+            Ok(buf) => return Ok(Some(buf)),
+            Err(Errno::RANGE) => {}
+            Err(e) if e == Errno::NOTSUP || e == NO_ATTR => return Ok(None),
+            Err(e) => return Err(std::io::Error::from(e).into()),
+        }
+    }
+}
+
+/// Smallest file size that gets hashed: [`SMALL_FILE_THRESHOLD`], or 0 with `hash_small_files`.
+#[must_use]
+pub fn hash_size_threshold(hash_small_files: bool) -> u64 {
+    if hash_small_files {
+        0
+    } else {
+        SMALL_FILE_THRESHOLD
+    }
+}
+
+/// Whether a stored hash may stand in for re-hashing a file of `size` whose mtime and size are
+/// unchanged: never under [`ChangePolicy::HashOnly`](crate::ChangePolicy::HashOnly), nor below
+/// [`SMALL_FILE_THRESHOLD`] with `hash_small_files`, whose point is catching small edits that keep
+/// mtime and size.
+#[must_use]
+pub fn may_reuse_hash(policy: &crate::ChangePolicy, hash_small_files: bool, size: u64) -> bool {
+    policy.reuses_hashes() && !(hash_small_files && size < SMALL_FILE_THRESHOLD)
+}
+
 /// Compare two hash options for equality
 #[must_use]
 pub fn hash_equals(hash1: &Option<[u8; 32]>, hash2: &Option<Vec<u8>>) -> bool {
@@ -54,22 +238,95 @@ pub fn hash_equals(hash1: &Option<[u8; 32]>, hash2: &Option<Vec<u8>>) -> bool {
     }
 }
 
-/// When `opts.with_hash` and size >= threshold: reuse index hash if mtime+size match, else hash file.
+/// Blake3 over `(path, mtime_ns, size, hash)` rows sorted by their DB path string, each field
+/// length- or tag-prefixed so no two distinct indexes fold to the same input.
+pub(crate) fn fold_tree_digest<'a>(
+    rows: impl Iterator<Item = (&'a Path, i64, u64, Option<&'a [u8]>)>,
+) -> [u8; 32] {
+    let mut rows: Vec<(String, i64, u64, Option<&[u8]>)> = rows
+        .map(|(path, mtime_ns, size, hash)| {
+            (crate::engine::path_to_db_string(path), mtime_ns, size, hash)
+        })
+        .collect();
+    rows.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    let mut digest = TreeDigest::new(rows.len());
+    for (path, mtime_ns, size, hash) in &rows {
+        digest.row(path, *mtime_ns, *size, *hash);
+    }
+    digest.finish()
+}
+
+/// Incremental [`fold_tree_digest`] for rows that already arrive sorted by DB path string (e.g.
+/// paged from a DB with `ORDER BY path`), so they need not be collected first.
+pub(crate) struct TreeDigest(Hasher);
+
+impl TreeDigest {
+    /// Digest of `len` rows, which must then all be passed to [`Self::row`] in order.
+    pub(crate) fn new(len: usize) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.update(&(len as u64).to_le_bytes());
+        Self(hasher)
+    }
+
+    pub(crate) fn row(&mut self, path: &str, mtime_ns: i64, size: u64, hash: Option<&[u8]>) {
+        let hasher = &mut self.0;
+        hasher.update(&(path.len() as u64).to_le_bytes());
+        hasher.update(path.as_bytes());
+        hasher.update(&mtime_ns.to_le_bytes());
+        hasher.update(&size.to_le_bytes());
+        match hash {
+            Some(h) => {
+                hasher.update(&[1]);
+                hasher.update(&(h.len() as u64).to_le_bytes());
+                hasher.update(h);
+            }
+            None => {
+                hasher.update(&[0]);
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> [u8; 32] {
+        *self.0.finalize().as_bytes()
+    }
+}
+
+/// [`tree_digest`](crate::tree_digest) of an index loaded with [`load_index`](crate::engine::load_index).
+/// Equal to the digest of the same entries as a [`Nefax`](crate::Nefax).
+#[must_use]
+pub fn index_tree_digest(index: &HashMap<PathBuf, StoredMeta>) -> [u8; 32] {
+    fold_tree_digest(
+        index
+            .iter()
+            .map(|(p, (mtime_ns, size, hash))| (p.as_path(), *mtime_ns, *size, hash.as_deref())),
+    )
+}
+
+/// When `opts.with_hash` and size >= threshold: reuse index hash if mtime+size match (unless
+/// [`may_reuse_hash`] forbids it), else hash file.
 pub fn fill_entry_hash_if_needed(
     entry: &mut Entry,
     index: &HashMap<PathBuf, StoredMeta>,
     root: &Path,
     opts: &Opts,
 ) {
-    if !opts.with_hash || entry.size < SMALL_FILE_THRESHOLD {
+    if !opts.with_hash
+        || entry.kind != FileKind::File
+        || entry.size < hash_size_threshold(opts.hash_small_files)
+    {
         return;
     }
     let existing = index.get(&entry.path);
-    let reuse = existing.is_some_and(|(old_mtime, old_size, old_hash)| {
-        !mtime_changed(entry.mtime_ns, *old_mtime, opts.mtime_window_ns)
-            && entry.size == *old_size
-            && old_hash.as_ref().is_some_and(|v| v.len() == 32)
-    });
+    let reuse = may_reuse_hash(&opts.change_policy, opts.hash_small_files, entry.size)
+        && existing.is_some_and(|(old_mtime, old_size, old_hash)| {
+            !mtime_changed_with_granularity(
+                entry.mtime_ns,
+                *old_mtime,
+                opts.mtime_window_ns,
+                opts.mtime_granularity_ns.unwrap_or(0),
+            ) && entry.size == *old_size
+                && old_hash.as_ref().is_some_and(|v| v.len() == 32)
+        });
     if reuse {
         if let Some((_, _, Some(v))) = existing {
             let mut arr = [0u8; 32];
@@ -77,8 +334,8 @@ pub fn fill_entry_hash_if_needed(
             entry.hash = Some(arr);
         }
     } else {
-        let abs = root.join(&entry.path);
-        if let Ok(Some(h)) = hash_file(&abs, entry.size) {
+        let abs = root.join(entry.fs_path());
+        if let Ok(Some(h)) = hash_file_keyed(&abs, entry.size, opts.hash_key.as_ref()) {
             entry.hash = Some(h);
         }
     }
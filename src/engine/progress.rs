@@ -21,17 +21,17 @@ pub fn refresh_bar(pb: &Arc<Mutex<Bar>>) {
 /// Configuration for creating a progress bar
 pub struct ProgressBarConfig {
     pub total: usize,
-    pub desc: &'static str,
+    pub desc: String,
     pub animation: Animation,
 }
 
 impl ProgressBarConfig {
     /// Create a new progress bar configuration
     #[must_use]
-    pub fn new(total: usize, desc: &'static str, animation: Animation) -> Self {
+    pub fn new(total: usize, desc: impl Into<String>, animation: Animation) -> Self {
         Self {
             total,
-            desc,
+            desc: desc.into(),
             animation,
         }
     }
@@ -49,16 +49,79 @@ pub fn create_progress_bar(config: ProgressBarConfig) -> Arc<Mutex<Bar>> {
 
 /// Create a counter for unknown total (shows count without percentage)
 #[must_use]
-pub fn create_counter(desc: &'static str) -> Arc<Mutex<Bar>> {
+pub fn create_counter(desc: &str) -> Arc<Mutex<Bar>> {
+    create_counter_at(desc, 0)
+}
+
+/// Create a counter at terminal line `position` (use > 0 to stack below another bar, e.g. hashing).
+#[must_use]
+pub fn create_counter_at(desc: &str, position: u16) -> Arc<Mutex<Bar>> {
     Arc::new(Mutex::new(kdam::tqdm!(
         total = 0,
         desc = desc,
         animation = Animation::Classic,
-        position = 0,
+        position = position,
+        unit = " files"
+    )))
+}
+
+/// Create the hashing bar at terminal line `position` (below the main bar), over `estimated_total`
+/// files at or above the hashing threshold, e.g. the stored hashes of the previous index. Advance it
+/// with [`hash_progress_callback`], which raises the total when more files arrive than estimated,
+/// and end it with [`finish_bar_at_count`].
+#[must_use]
+pub fn create_hashing_bar(estimated_total: usize, position: u16) -> ProgressBar {
+    Arc::new(Mutex::new(kdam::tqdm!(
+        total = estimated_total,
+        desc = "Hashing",
+        animation = Animation::Classic,
+        position = position,
         unit = " files"
     )))
 }
 
+/// Callback advancing a [`create_hashing_bar`] bar by `n` files, first raising its total to the new
+/// count when the estimate was too low.
+#[must_use]
+pub fn hash_progress_callback(bar: &Option<ProgressBar>) -> Option<Box<dyn Fn(usize) + Send>> {
+    bar.as_ref().map(|bar| {
+        let bar = Arc::clone(bar);
+        Box::new(move |n: usize| {
+            if let Ok(mut bar) = bar.try_lock() {
+                bar.total = bar.total.max(bar.counter + n);
+                let _ = bar.update(n);
+            }
+        }) as Box<dyn Fn(usize) + Send>
+    })
+}
+
+/// Set the bar's total to its count (an estimate may have been too high) and redraw it, so a
+/// finished phase shows as complete.
+pub fn finish_bar_at_count(pb: &Arc<Mutex<Bar>>) {
+    if let Ok(mut bar) = pb.lock() {
+        bar.total = bar.counter;
+        let _ = bar.refresh();
+    }
+}
+
+/// Create the main indexing bar. Local drives get a percentage bar whose total the walk sets when it
+/// finishes. Network drives get a percentage bar over `estimated_total` (e.g. the previous index's
+/// path count) when known, else a plain counter.
+#[must_use]
+pub fn create_nefaxing_bar(
+    desc: &str,
+    is_network_drive: bool,
+    estimated_total: Option<usize>,
+) -> ProgressBar {
+    match (is_network_drive, estimated_total) {
+        (false, _) => create_progress_bar(ProgressBarConfig::new(1, desc, Animation::Classic)),
+        (true, Some(total)) => {
+            create_progress_bar(ProgressBarConfig::new(total, desc, Animation::Classic))
+        }
+        (true, None) => create_counter(desc),
+    }
+}
+
 /// Update progress bar if available
 /// Uses `try_lock` to avoid blocking if mutex is contended (non-blocking)
 pub fn update_progress_bar(pb: &Arc<Mutex<Bar>>, n: usize) {
@@ -79,6 +142,29 @@ pub type ProgressSetup = (
     Option<Box<dyn Fn(usize) + Send>>,
 );
 
+/// (bar, `on_hash`) from `setup_hash_progress` for the hashing phase.
+pub type HashProgressSetup = (Option<ProgressBar>, Option<Box<dyn Fn(usize) + Send>>);
+
+/// Clear any bars from the terminal when `result` is an error, so the error is not printed after a
+/// half-drawn bar. Returns `result` unchanged.
+///
+/// # Errors
+///
+/// Returns the error in `result`.
+pub fn clear_bars_on_err<T>(
+    result: anyhow::Result<T>,
+    bars: &[&Option<ProgressBar>],
+) -> anyhow::Result<T> {
+    if result.is_err() {
+        for bar in bars.iter().copied().flatten() {
+            if let Ok(mut bar) = bar.lock() {
+                let _ = bar.clear();
+            }
+        }
+    }
+    result
+}
+
 /// Create a progress callback function that updates the progress bar.
 #[must_use]
 pub fn progress_callback(bar: &Option<ProgressBar>) -> Option<Box<dyn Fn(usize) + Send>> {
@@ -2,14 +2,16 @@
 
 use anyhow::{Context, Result};
 use log::{info, warn};
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use colored::Colorize;
 
-use crate::Diff;
-use crate::utils::config::PackagePaths;
+use crate::utils::config::{NETWORK_MTIME_GRANULARITY_NS, PackagePaths};
+use crate::{Diff, Opts, PathKeyPolicy};
 
 /// Convert absolute path to relative path from base
 #[must_use]
@@ -17,24 +19,96 @@ pub fn path_relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
     path.strip_prefix(base).ok().map(|p| p.to_path_buf())
 }
 
+/// `path` (an index key under `root`, or absolute) as seen from `base`, with `..` where `base` is
+/// not an ancestor, e.g. key `sub/a.txt` under `/data/tree` from `/data/other` is
+/// `../tree/sub/a.txt`. Both directories should be absolute; display only.
+#[must_use]
+pub fn rebase_for_display(path: &Path, root: &Path, base: &Path) -> PathBuf {
+    let abs = root.join(path);
+    let mut target = abs.components().peekable();
+    let mut from = base.components().peekable();
+    while let (Some(a), Some(b)) = (target.peek(), from.peek()) {
+        if a != b {
+            break;
+        }
+        target.next();
+        from.next();
+    }
+    let mut rebased: PathBuf = from.map(|_| std::path::Component::ParentDir).collect();
+    rebased.extend(target);
+    if rebased.as_os_str().is_empty() {
+        rebased.push(".");
+    }
+    rebased
+}
+
+/// The `(root, base)` pair [`rebase_for_display`] needs to show keys under `root` relative to
+/// `display_relative_to` ([`crate::Opts::display_relative_to`]), both made absolute since rebasing
+/// compares components. `None` when no display directory is set.
+#[must_use]
+pub fn display_rebase_dirs(
+    root: &Path,
+    display_relative_to: Option<&Path>,
+) -> Option<(PathBuf, PathBuf)> {
+    let absolute = |p: &Path| {
+        p.canonicalize()
+            .or_else(|_| std::path::absolute(p))
+            .unwrap_or_else(|_| p.to_path_buf())
+    };
+    display_relative_to.map(|base| (absolute(root), absolute(base)))
+}
+
+/// Lowercase hex of `bytes` (e.g. a blake3 hash for display or JSON).
+#[must_use]
+pub fn hex_encode(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+}
+
+/// Bytes of a hex string (either case); `None` when it has odd length or a non-hex digit.
+#[must_use]
+pub fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
 /// Normalize path for DB storage: forward slashes only. Makes DB portable across Windows/Unix.
 #[must_use]
 pub fn path_to_db_string(path: &Path) -> String {
-    path.to_string_lossy().replace('\\', "/")
+    path_to_db_key(path, PathKeyPolicy::AsIs)
+}
+
+/// [`path_to_db_string`] followed by `policy` (e.g. lowercasing): the key `path` is stored and looked
+/// up under in an index written with that policy.
+#[must_use]
+pub fn path_to_db_key(path: &Path, policy: PathKeyPolicy) -> String {
+    policy.apply(path.to_string_lossy().replace('\\', "/"))
 }
 
 /// On Windows: convert path to long-path form (`\\?\` or `\\?\UNC\`) so paths >260 chars work. On Unix: no-op.
+/// `//server/share` is treated as UNC; device paths (`\\.\`) are left alone.
 #[cfg(windows)]
 pub fn path_to_long_path(path: &Path) -> PathBuf {
     let s = path.to_string_lossy();
-    if s.starts_with(r"\\?\") {
+    if s.starts_with(r"\\?\") || s.starts_with(r"\\.\") {
         return path.to_path_buf();
     }
-    if s.starts_with(r"\\") && !s.starts_with(r"\\?\") {
-        return PathBuf::from(format!(r"\\?\UNC\{}", &s[2..]));
+    if s.starts_with(r"\\") || s.starts_with("//") {
+        return PathBuf::from(format!(r"\\?\UNC\{}", s[2..].replace('/', "\\")));
     }
     if s.len() >= 2 && s.chars().nth(1) == Some(':') {
-        return PathBuf::from(format!(r"\\?\{}", s));
+        // Verbatim paths are not normalized by Windows, so forward slashes must be converted here.
+        return PathBuf::from(format!(r"\\?\{}", s.replace('/', "\\")));
     }
     path.to_path_buf()
 }
@@ -64,7 +138,38 @@ pub fn is_os_hidden_file(path: &Path) -> bool {
     }
 }
 
+/// True if `name` starts with `.`. Compares the raw bytes, so a name that is not valid UTF-8 is
+/// still a dotfile when it starts with a dot.
+#[must_use]
+pub fn is_dot_name(name: &OsStr) -> bool {
+    name.as_encoded_bytes().first() == Some(&b'.')
+}
+
+/// True if any component of `path` below `root` is a dotfile/dot-directory (see [`is_dot_name`]).
+/// The root itself is never considered, so indexing e.g. `~/.config` still works.
+#[must_use]
+pub fn is_under_dotfile(path: &Path, root: &Path) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative.components().any(|c| is_dot_name(c.as_os_str()))
+}
+
+/// True if `path` is a `SQLite` side file of `db` (`<db>-wal`, `<db>-shm`, `<db>-journal`).
+fn is_sqlite_sidecar(path: &Path, db: &Path) -> bool {
+    let (Some(name), Some(db_name)) = (path.file_name(), db.file_name()) else {
+        return false;
+    };
+    let (name, db_name) = (name.to_string_lossy(), db_name.to_string_lossy());
+    path.parent() == db.parent()
+        && name
+            .strip_prefix(db_name.as_ref())
+            .is_some_and(|suffix| matches!(suffix, "-wal" | "-shm" | "-journal"))
+}
+
 /// Returns true if the path should be included in the walk (not excluded).
+/// When `skip_dotfiles` is true, any path with a dot-prefixed component below `root` is excluded.
+/// A pattern ending in `/` (e.g. `build/`) only matches directories: any parent component, or the
+/// path itself when it is a directory (checked with a stat only on a name match; symlinks are not
+/// directories here).
 #[must_use]
 pub fn should_include_in_walk(
     path: &Path,
@@ -72,36 +177,52 @@ pub fn should_include_in_walk(
     db_canonical: &Option<PathBuf>,
     temp_canonical: &Option<PathBuf>,
     exclude_patterns: &[String],
+    skip_dotfiles: bool,
 ) -> bool {
     if path == root {
         return false;
     }
     if let Some(db) = db_canonical
-        && path == db.as_path()
+        && (path == db.as_path() || is_sqlite_sidecar(path, db))
     {
         return false;
     }
     if let Some(temp) = temp_canonical
-        && path == temp.as_path()
+        && (path == temp.as_path() || is_sqlite_sidecar(path, temp))
     {
         return false;
     }
     if is_os_hidden_file(path) {
         return false;
     }
+    if skip_dotfiles && is_under_dotfile(path, root) {
+        return false;
+    }
     if exclude_patterns.is_empty() {
         return true;
     }
     // Gitignore-like: match pattern against each path component (segment). If any segment
     // matches, exclude this path. So "target" excludes the dir target and everything under it.
     let relative = path.strip_prefix(root).unwrap_or(path);
-    for component in relative.components() {
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
         let segment = component.as_os_str().to_str().unwrap_or("");
         if segment.is_empty() {
             continue;
         }
+        let is_last = components.peek().is_none();
         for pattern in exclude_patterns {
-            if glob_match(pattern, segment) {
+            let matched = match pattern.strip_suffix('/') {
+                // Components above the last one are directories by construction.
+                Some(dir_pattern) => {
+                    glob_match(dir_pattern, segment)
+                        && (!is_last
+                            || std::fs::symlink_metadata(path)
+                                .is_ok_and(|m| m.file_type().is_dir()))
+                }
+                None => glob_match(pattern, segment),
+            };
+            if matched {
                 return false;
             }
         }
@@ -109,6 +230,90 @@ pub fn should_include_in_walk(
     true
 }
 
+/// True if `path` (relative to the root) is inside the diff scope set by `focus` patterns: a pattern
+/// matches the whole path (`src/*`) or any one component (`*.rs`, `src`; a trailing `/` is ignored).
+/// Empty `focus` means everything is in scope.
+#[must_use]
+pub fn matches_focus(path: &Path, focus: &[String]) -> bool {
+    if focus.is_empty() {
+        return true;
+    }
+    let full = path_to_db_string(path);
+    focus.iter().any(|pattern| {
+        let pattern = pattern.trim_end_matches('/');
+        glob_match(pattern, &full)
+            || path.components().any(|c| {
+                c.as_os_str()
+                    .to_str()
+                    .is_some_and(|s| glob_match(pattern, s))
+            })
+    })
+}
+
+/// Check that every walk seed is a relative path below the root (no `..`, not absolute, not empty).
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] naming the first seed that is not.
+pub fn validate_seeds(seeds: &[PathBuf]) -> Result<()> {
+    for seed in seeds {
+        let below_root = seed
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            && seed.components().any(|c| matches!(c, Component::Normal(_)));
+        if !below_root {
+            anyhow::bail!(
+                "seed must be a relative path below the root: {}",
+                seed.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The seeds to walk: each without `.` components, sorted, with duplicates and seeds nested under
+/// another seed dropped so no subtree is walked twice.
+#[must_use]
+pub fn walk_seeds(seeds: &[PathBuf]) -> Vec<PathBuf> {
+    let mut clean: Vec<PathBuf> = seeds
+        .iter()
+        .map(|seed| {
+            seed.components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect()
+        })
+        .collect();
+    clean.sort();
+    let mut kept: Vec<PathBuf> = Vec::with_capacity(clean.len());
+    for seed in clean {
+        if !kept.iter().any(|k| seed.starts_with(k)) {
+            kept.push(seed);
+        }
+    }
+    kept
+}
+
+/// Index keys of `opts.seeds` under `root` (same path mode and key policy as walked paths), for
+/// [`under_seeds`].
+#[must_use]
+pub fn seed_keys(root: &Path, opts: &Opts) -> Vec<PathBuf> {
+    let base = if opts.store_absolute && !opts.seeds.is_empty() {
+        check_root_and_canonicalize(root).unwrap_or_else(|_| root.to_path_buf())
+    } else {
+        PathBuf::new()
+    };
+    walk_seeds(&opts.seeds)
+        .into_iter()
+        .map(|seed| PathBuf::from(path_to_db_key(&base.join(seed), opts.path_key)))
+        .collect()
+}
+
+/// True if the index key `path` is a seed or lies under one. Empty `seed_keys` means the whole root.
+#[must_use]
+pub fn under_seeds(path: &Path, seed_keys: &[PathBuf]) -> bool {
+    seed_keys.is_empty() || seed_keys.iter().any(|seed| path.starts_with(seed))
+}
+
 /// Simple glob pattern matching (supports * and ?)
 #[must_use]
 pub fn glob_match(pattern: &str, text: &str) -> bool {
@@ -159,10 +364,40 @@ pub fn glob_match(pattern: &str, text: &str) -> bool {
 /// Check if mtime has changed beyond tolerance window
 #[must_use]
 pub fn mtime_changed(new_mtime: i64, old_mtime: i64, tolerance_ns: i64) -> bool {
+    mtime_changed_with_granularity(new_mtime, old_mtime, tolerance_ns, 0)
+}
+
+/// Like [`mtime_changed`], for filesystems that store mtimes at `granularity_ns` resolution (e.g. 2 s
+/// on FAT/SMB). A difference below one granule is rounding loss from a copy and never counts, even
+/// when it straddles a rounding boundary; beyond that `tolerance_ns` applies as usual, so the
+/// effective tolerance is the larger of the two. `granularity_ns <= 0` disables rounding.
+#[must_use]
+pub fn mtime_changed_with_granularity(
+    new_mtime: i64,
+    old_mtime: i64,
+    tolerance_ns: i64,
+    granularity_ns: i64,
+) -> bool {
     let mtime_diff = (new_mtime - old_mtime).abs();
+    if mtime_diff < granularity_ns {
+        return false;
+    }
     mtime_diff > tolerance_ns
 }
 
+/// Mtime granularity for a run: the explicit setting, else [`NETWORK_MTIME_GRANULARITY_NS`] on a
+/// network drive (SMB/NFS servers often keep only whole or even seconds), else 0 (exact).
+#[must_use]
+pub fn effective_mtime_granularity_ns(
+    setting: Option<i64>,
+    drive_type: crate::disk_detect::DriveType,
+) -> i64 {
+    setting.unwrap_or(match drive_type {
+        crate::disk_detect::DriveType::Network => NETWORK_MTIME_GRANULARITY_NS,
+        _ => 0,
+    })
+}
+
 #[cfg(unix)]
 fn check_for_root(path: &Path) -> Result<(), anyhow::Error> {
     use std::os::unix::fs::MetadataExt;
@@ -229,9 +464,9 @@ pub fn canonicalize_paths(
 }
 
 macro_rules! write_diff_section {
-    ($out:expr, $paths:expr, $fmt:expr, $color_method:ident, $colorize:expr) => {
+    ($out:expr, $paths:expr, $fmt:expr, $color_method:ident, $colorize:expr, $shown:expr) => {
         for p in $paths {
-            let line = format!($fmt, p.display());
+            let line = format!($fmt, $shown(p).display());
             let _ = writeln!(
                 $out,
                 "{}",
@@ -246,51 +481,222 @@ macro_rules! write_diff_section {
 }
 
 /// Write diff path list to `out`. If `colorize` is true, prefix/lines use ANSI colors (for stdout).
-fn write_diff_paths<W: std::io::Write>(out: &mut W, diff: &Diff, colorize: bool) {
-    write_diff_section!(out, &diff.added, "+ {}", green, colorize);
-    write_diff_section!(out, &diff.removed, "- {}", red, colorize);
-    write_diff_section!(out, &diff.modified, "M {}", yellow, colorize);
+/// With `rebase` as `(root, base)`, paths are shown relative to `base` (see [`rebase_for_display`]).
+fn write_diff_paths<W: std::io::Write>(
+    out: &mut W,
+    diff: &Diff,
+    colorize: bool,
+    rebase: Option<(&Path, &Path)>,
+) {
+    let shown = |p: &Path| match rebase {
+        Some((root, base)) => rebase_for_display(p, root, base),
+        None => p.to_path_buf(),
+    };
+    write_diff_section!(out, &diff.added, "+ {}", green, colorize, shown);
+    write_diff_section!(out, &diff.removed, "- {}", red, colorize, shown);
+    write_diff_section!(out, &diff.modified, "M {}", yellow, colorize, shown);
+    write_diff_section!(out, &diff.corrupted, "! {}", magenta, colorize, shown);
+    write_diff_section!(out, &diff.touched, "T {}", blue, colorize, shown);
+    write_diff_section!(out, &diff.unreadable, "? {}", red, colorize, shown);
+    for (old, new) in &diff.case_changed {
+        let line = format!("C {} -> {}", shown(old).display(), shown(new).display());
+        let _ = writeln!(
+            out,
+            "{}",
+            if colorize {
+                format!("{}", line.cyan())
+            } else {
+                line
+            }
+        );
+    }
+}
+
+/// Write the complete diff to `path` regardless of `LIST_THRESHOLD`: one `[section]` per category
+/// (added, removed, modified, corrupted, touched, unreadable, `case_changed`), each path on its own line
+/// with the list prefix (`+`, `-`, `M`, `!`, `T`, `?`, `C`); case-only renames are written as `C <stored> -> <walked>`.
+/// With `rebase` as `(root, base)` (see [`display_rebase_dirs`]), paths are written relative to `base`.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the file cannot be created or written.
+pub fn write_diff_report(diff: &Diff, path: &Path, rebase: Option<(&Path, &Path)>) -> Result<()> {
+    use std::io::Write;
+    let shown = |p: &Path| match rebase {
+        Some((root, base)) => rebase_for_display(p, root, base),
+        None => p.to_path_buf(),
+    };
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("create diff report {}", path.display()))?;
+    let mut out = std::io::BufWriter::new(file);
+    let sections: [(&str, &str, &[PathBuf]); 6] = [
+        ("added", "+", &diff.added),
+        ("removed", "-", &diff.removed),
+        ("modified", "M", &diff.modified),
+        ("corrupted", "!", &diff.corrupted),
+        ("touched", "T", &diff.touched),
+        ("unreadable", "?", &diff.unreadable),
+    ];
+    for (name, prefix, paths) in sections {
+        writeln!(out, "[{name}]")?;
+        for p in paths {
+            writeln!(out, "{prefix} {}", shown(p).display())?;
+        }
+    }
+    writeln!(out, "[case_changed]")?;
+    for (old, new) in &diff.case_changed {
+        writeln!(
+            out,
+            "C {} -> {}",
+            shown(old).display(),
+            shown(new).display()
+        )?;
+    }
+    out.flush()
+        .with_context(|| format!("write diff report {}", path.display()))?;
+    Ok(())
+}
+
+/// Write the paths a follow-up tool should look at (added, modified, and the new spelling of
+/// case-only renames) to `out`, each followed by a NUL byte, for `xargs -0`. Paths are relative to
+/// `root`, or to `base` with `rebase` as `(root, base)` (see [`display_rebase_dirs`]), unless
+/// `absolute`. Entries that are directories under `root` are skipped unless `include_dirs`. Returns
+/// the number of paths written.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when writing to `out` fails.
+pub fn write_changed_paths_nul<W: std::io::Write>(
+    out: &mut W,
+    diff: &Diff,
+    root: &Path,
+    absolute: bool,
+    include_dirs: bool,
+    rebase: Option<(&Path, &Path)>,
+) -> Result<usize> {
+    let changed = diff
+        .added
+        .iter()
+        .chain(&diff.modified)
+        .chain(diff.case_changed.iter().map(|(_, new)| new));
+    let mut written = 0;
+    for p in changed {
+        // `join` keeps `p` as is when the index stores absolute paths.
+        let abs = root.join(p);
+        if !include_dirs && abs.is_dir() {
+            continue;
+        }
+        let shown = match rebase {
+            _ if absolute => abs,
+            Some((root, base)) => rebase_for_display(p, root, base),
+            None => p.clone(),
+        };
+        out.write_all(shown.as_os_str().as_encoded_bytes())
+            .and_then(|()| out.write_all(b"\0"))
+            .context("write changed paths")?;
+        written += 1;
+    }
+    out.flush().context("flush changed paths")?;
+    Ok(written)
+}
+
+/// The `Added: n | Removed: n | Modified: n` line of the diff summary, colored unless colors are off
+/// (see [`setup_logging`](crate::utils::setup_logging)).
+#[must_use]
+pub fn diff_counts_line(diff: &Diff) -> String {
+    format!(
+        "{} | {} | {}",
+        format!("Added: {}", diff.added.len()).green(),
+        format!("Removed: {}", diff.removed.len()).red(),
+        format!("Modified: {}", diff.modified.len()).yellow()
+    )
 }
 
 /// Print diff summary (counts: Added / Removed / Modified). When `list_paths` is true, list each path
 /// to stdout if total <= `LIST_THRESHOLD`, otherwise write to `output_dir` / `PackagePaths::results_filename()`.
 pub fn print_diff(diff: &Diff, dry_run: bool, list_paths: bool, output_dir: &Path) {
+    print_diff_with_threshold(diff, dry_run, list_paths, output_dir, None, None);
+}
+
+/// Same as [`print_diff`] with the stdout/file cutoff set by `list_threshold` (`None` =
+/// `LIST_THRESHOLD`): `Some(0)` always writes the results file, `Some(usize::MAX)` always prints.
+/// With `display_relative_to`, listed paths (keys under `output_dir`, the indexed root) are shown
+/// relative to that directory instead ([`crate::Opts::display_relative_to`]); `diff` is unchanged.
+pub fn print_diff_with_threshold(
+    diff: &Diff,
+    dry_run: bool,
+    list_paths: bool,
+    output_dir: &Path,
+    list_threshold: Option<usize>,
+    display_relative_to: Option<&Path>,
+) {
     let msg = format!(
         "Nefaxing {} results:",
         if dry_run { "dry-run" } else { "index" }
     );
     info!("{msg}");
 
-    let added_count = diff.added.len();
-    let removed_count = diff.removed.len();
-    let modified_count = diff.modified.len();
-    let total = added_count + removed_count + modified_count;
-
-    if total == 0 {
+    let touched_line = || {
+        if !diff.touched.is_empty() {
+            info!(
+                "{}",
+                format!(
+                    "Touched (mtime only, content unchanged): {}",
+                    diff.touched.len()
+                )
+                .blue()
+            );
+        }
+    };
+    if diff.is_empty() {
         info!("No changes detected.");
+        touched_line();
         return;
     }
-
-    info!(
-        "{} | {} | {}",
-        format!("Added: {added_count}").green(),
-        format!("Removed: {removed_count}").red(),
-        format!("Modified: {modified_count}").yellow()
-    );
+    let corrupted_count = diff.corrupted.len();
+    let unreadable_count = diff.unreadable.len();
+    let case_changed_count = diff.case_changed.len();
+    let total = diff.total();
+
+    info!("{}", diff_counts_line(diff));
+    if corrupted_count > 0 {
+        warn!(
+            "{}",
+            format!("Corrupted (hash mismatch, mtime/size unchanged): {corrupted_count}").magenta()
+        );
+    }
+    if unreadable_count > 0 {
+        warn!(
+            "{}",
+            format!("Unreadable (content not compared): {unreadable_count}").red()
+        );
+    }
+    if case_changed_count > 0 {
+        info!(
+            "{}",
+            format!("Case changed (renamed by letter case only): {case_changed_count}").cyan()
+        );
+    }
+    touched_line();
 
     if !list_paths {
         return;
     }
 
-    let threshold = crate::utils::config::LIST_THRESHOLD;
+    let rebase_dirs = display_rebase_dirs(output_dir, display_relative_to);
+    let rebase = rebase_dirs
+        .as_ref()
+        .map(|(root, base)| (root.as_path(), base.as_path()));
+
+    let threshold = list_threshold.unwrap_or(crate::utils::config::LIST_THRESHOLD);
     if total <= threshold {
         let mut out = std::io::stdout().lock();
-        write_diff_paths(&mut out, diff, true);
+        write_diff_paths(&mut out, diff, true, rebase);
     } else {
         let out_path = output_dir.join(PackagePaths::get().results_filename());
         match std::fs::File::create(&out_path) {
             Ok(mut f) => {
-                write_diff_paths(&mut f, diff, false);
+                write_diff_paths(&mut f, diff, false, rebase);
                 info!("Listed {} changes to {}", total, out_path.display());
             }
             Err(e) => {
@@ -300,6 +706,24 @@ pub fn print_diff(diff: &Diff, dry_run: bool, list_paths: bool, output_dir: &Pat
     }
 }
 
+/// Human-readable byte count with binary units (e.g. `4.0 KiB`, `1.5 GiB`).
+#[must_use]
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    // Display-only; u64→f64 precision loss is irrelevant at one decimal place.
+    #[allow(clippy::cast_precision_loss)]
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
 /// Create the database path from the root and `db_path` options.
 /// If `db_path` is None, use `root.join(<package index filename>)` (e.g. `.nefaxer`).
 #[must_use]
@@ -310,12 +734,25 @@ pub fn create_db_path(root: &Path, db_path: Option<&Path>) -> PathBuf {
     )
 }
 
+/// Flag shared with the process-wide Ctrl+C handler (the handler can only be installed once).
+static CANCEL_REQUESTED: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+
 /// Setup Ctrl+C handler and return a shared boolean indicating if the user has requested cancellation.
+/// Safe to call once per run: later calls reset and return the same flag instead of reinstalling.
 ///
 /// # Errors
 ///
 /// Returns [`anyhow::Error`] when the global Ctrl+C handler cannot be installed.
+///
+/// # Panics
+///
+/// Panics if the cancel flag mutex is poisoned.
 pub fn setup_ctrlc_handler() -> Result<Arc<AtomicBool>> {
+    let mut installed = CANCEL_REQUESTED.lock().unwrap();
+    if let Some(flag) = installed.as_ref() {
+        flag.store(false, Ordering::Relaxed);
+        return Ok(Arc::clone(flag));
+    }
     let cancel_requested = Arc::new(AtomicBool::new(false));
     let cancel_requested_handler = Arc::clone(&cancel_requested);
 
@@ -323,6 +760,7 @@ pub fn setup_ctrlc_handler() -> Result<Arc<AtomicBool>> {
         cancel_requested_handler.store(true, Ordering::Relaxed);
     })
     .context("set Ctrl+C handler")?;
+    *installed = Some(Arc::clone(&cancel_requested));
     Ok(cancel_requested)
 }
 
@@ -337,3 +775,53 @@ pub fn check_for_cancel(cancel_requested: &Arc<AtomicBool>) -> Result<()> {
     }
     Ok(())
 }
+
+/// Timer that sets a flag once a run has exceeded its time budget. Dropping it stops the timer thread.
+pub struct DeadlineTimer {
+    fired: Arc<AtomicBool>,
+    deadline: Duration,
+    _stop: crossbeam_channel::Sender<()>,
+}
+
+impl DeadlineTimer {
+    /// Start a thread that sets [`flag`](Self::flag) after `deadline`, unless the timer is dropped first.
+    /// A zero deadline is set already on return, so the run stops before reading anything.
+    #[must_use]
+    pub fn start(deadline: Duration) -> Self {
+        let fired = Arc::new(AtomicBool::new(deadline.is_zero()));
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+        if !deadline.is_zero() {
+            let fired_timer = Arc::clone(&fired);
+            std::thread::spawn(move || {
+                if stop_rx.recv_timeout(deadline)
+                    == Err(crossbeam_channel::RecvTimeoutError::Timeout)
+                {
+                    fired_timer.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+        Self {
+            fired,
+            deadline,
+            _stop: stop_tx,
+        }
+    }
+
+    /// Flag set when the deadline passes (for [`ApplyIndexDiffStreamingParams::deadline_check`](crate::engine::ApplyIndexDiffStreamingParams::deadline_check)).
+    #[must_use]
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.fired)
+    }
+
+    /// True once the deadline has passed.
+    #[must_use]
+    pub fn fired(&self) -> bool {
+        self.fired.load(Ordering::Relaxed)
+    }
+
+    /// The time budget this timer was started with.
+    #[must_use]
+    pub const fn deadline(&self) -> Duration {
+        self.deadline
+    }
+}
@@ -1,42 +1,42 @@
 //! Directory indexing operations
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crossbeam_channel::Receiver;
-use kdam::{Animation, Bar};
-use log::info;
+use kdam::Bar;
+use log::{debug, info, warn};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::thread;
 
 use crate::Opts;
 use crate::engine;
-use crate::engine::StoredMeta;
 use crate::engine::progress;
+use crate::engine::{StoredExtras, StoredMeta};
 use crate::pipeline::{
-    PipelineHandles, check_for_initial_error_or_skipped_paths, run_pipeline,
-    shutdown_pipeline_handles,
+    PipelineHandles, PipelineTuning, check_for_initial_error_with_skip_report, run_pipeline,
+    shutdown_pipeline_handles, walk_was_capped,
+};
+use crate::store::{IndexStore, NefaxStore, StoreBuilder};
+use crate::utils::{
+    KeySource, ThrowawayIndex, get_passphrase_for_db, prepare_index_work_path, remember_passphrase,
+    rename_temp_to_final,
 };
-use crate::utils::{get_passphrase, prepare_index_work_path, rename_temp_to_final};
 
 /// Build progress bar and callbacks for streaming index. Returns (bar, `on_batch`, `on_received`).
 /// For local drives: percentage bar + `on_batch`; `path_count_rx` is consumed in a background thread to set total.
-/// For network: counter bar + `on_received`; no total.
+/// For network: `on_received`, on a percentage bar over the stored path count when the index has
+/// one, else a counter with no total. The bar is labelled with the tuning summary.
 fn setup_progress(
     verbose: bool,
-    is_network_drive: bool,
+    tuning: &PipelineTuning,
     path_count_rx: Receiver<usize>,
 ) -> progress::ProgressSetup {
+    let is_network_drive = tuning.is_network_drive;
     let bar = verbose.then(|| {
-        let b = if is_network_drive {
-            progress::create_counter("Nefaxing")
-        } else {
-            progress::create_progress_bar(progress::ProgressBarConfig::new(
-                1,
-                "Nefaxing",
-                Animation::Classic,
-            ))
-        };
+        let desc = format!("Nefaxing ({})", tuning.summary());
+        let b = progress::create_nefaxing_bar(&desc, is_network_drive, tuning.stored_path_count);
         if is_network_drive {
             progress::refresh_bar(&b);
         }
@@ -60,6 +60,25 @@ fn setup_progress(
     (bar, on_batch, on_received)
 }
 
+/// Build the hashing bar (below the main bar) and its callback. Only when verbose and hashing.
+/// Hashing is the slow phase with `with_hash`, so it advances separately from entries written: once
+/// per file at or above the threshold, out of the files `existing` holds hashes for (raised as more
+/// arrive).
+fn setup_hash_progress(
+    verbose: bool,
+    with_hash: bool,
+    existing: &HashMap<PathBuf, StoredMeta>,
+) -> progress::HashProgressSetup {
+    let bar = (verbose && with_hash).then(|| {
+        let estimated = existing.values().filter(|(_, _, h)| h.is_some()).count();
+        let b = progress::create_hashing_bar(estimated, 1);
+        progress::refresh_bar(&b);
+        b
+    });
+    let on_hash = progress::hash_progress_callback(&bar);
+    (bar, on_hash)
+}
+
 /// Join walk and workers, then push progress bar to 100% when index was up to date (local only). Returns `path_count`.
 fn collect_pipeline_results(
     walk_handle: std::thread::JoinHandle<usize>,
@@ -91,14 +110,60 @@ fn nefax_to_stored(existing: &crate::Nefax) -> HashMap<PathBuf, StoredMeta> {
         .collect()
 }
 
+/// Prior state of a lib run: `existing` validated and converted for the diff, plus the creation
+/// times, symlink targets, and xattr hashes the run compares (see [`engine::load_index_extras`]).
+/// Rows outside [`Opts::seeds`] under `root` are left out. `None` is an empty prior index.
+fn lib_prior_state(
+    root: &Path,
+    existing: Option<&crate::Nefax>,
+    opts: &Opts,
+) -> Result<(HashMap<PathBuf, StoredMeta>, StoredExtras)> {
+    let Some(ex) = existing else {
+        return Ok((HashMap::new(), StoredExtras::default()));
+    };
+    crate::validate_nefax_with_mode(ex, opts.store_absolute)?;
+    // A `Nefax` holds keys only, so removals are reported by key.
+    let extras = StoredExtras::from_metas(ex, opts);
+    let seeds = engine::seed_keys(root, opts);
+    let mut stored = nefax_to_stored(ex);
+    stored.retain(|path, _| engine::under_seeds(path, &seeds));
+    Ok((stored, extras))
+}
+
 /// Lib path: run pipeline against in-memory conn, diff against existing (`StoredMeta` map). No DB file.
 /// Pass a no-op (e.g. `|_| {}`) when not using the callback.
 fn run_lib_pipeline_with_callback<F>(
     root: &Path,
     opts: &Opts,
     existing: &HashMap<PathBuf, StoredMeta>,
+    extras: Option<&StoredExtras>,
     on_entry: F,
-) -> Result<(crate::Nefax, crate::Diff)>
+) -> Result<(crate::Nefax, crate::Diff, crate::NefaxReport)>
+where
+    F: FnMut(&crate::Entry),
+{
+    let store = StoreBuilder::in_memory();
+    let prior = LibPrior::Map(existing, extras);
+    let (store, diff, report) = run_lib_pipeline_into_store(root, opts, prior, on_entry, store)?;
+    Ok((store.into_nefax()?, diff, report))
+}
+
+/// What a lib run diffs against.
+enum LibPrior<'a> {
+    /// A prior index held in memory, with the extras it compares.
+    Map(&'a HashMap<PathBuf, StoredMeta>, Option<&'a StoredExtras>),
+    /// A snapshot read row by row instead (see [`nefax_dir_store`]); `None` is an empty one.
+    Store(Option<&'a dyn IndexStore>),
+}
+
+/// [`run_lib_pipeline_with_callback`] collecting the snapshot into `store` (which may spill to disk).
+fn run_lib_pipeline_into_store<F>(
+    root: &Path,
+    opts: &Opts,
+    prior: LibPrior<'_>,
+    on_entry: F,
+    mut store: StoreBuilder,
+) -> Result<(NefaxStore, crate::Diff, crate::NefaxReport)>
 where
     F: FnMut(&crate::Entry),
 {
@@ -109,78 +174,384 @@ where
         worker_handles,
         first_error,
         skipped_paths,
+        tuning,
+        walk_capped,
+        too_long_paths,
         ..
     } = run_pipeline(root, opts, None, None, &conn)?;
-    let (diff, index_map) =
-        crate::check::diff_from_stream_with_callback(&entry_rx, existing, root, opts, on_entry);
+    let opts = &crate::check::with_effective_mtime_granularity(opts, &tuning);
+    let diff = match prior {
+        LibPrior::Map(existing, extras) => Ok(crate::check::diff_from_stream_into_store(
+            &entry_rx, existing, extras, root, opts, on_entry, &mut store,
+        )),
+        LibPrior::Store(existing) => crate::check::diff_from_stream_against_store(
+            &entry_rx, existing, root, opts, on_entry, &mut store,
+        ),
+    };
     shutdown_pipeline_handles(walk_handle, worker_handles)?;
-    check_for_initial_error_or_skipped_paths(opts, &first_error, &skipped_paths)?;
-    engine::print_diff(&diff, false, opts.list_paths, root);
-    Ok((index_map, diff))
+    let mut diff = diff?;
+    let capped = walk_was_capped(&walk_capped, opts.max_entries);
+    if capped {
+        // Paths past the cap were never walked, not removed.
+        diff.removed.clear();
+    }
+    let mut skipped = check_for_initial_error_with_skip_report(opts, &first_error, &skipped_paths)?;
+    skipped.too_long = std::mem::take(&mut *too_long_paths.lock().unwrap());
+    let store = store.finish()?;
+    if let LibPrior::Store(Some(existing)) = prior
+        && !capped
+    {
+        diff.removed = crate::check::removed_from_store(existing, &store, root, opts)?;
+        diff.pair_case_only_renames();
+    }
+    engine::print_diff_with_threshold(
+        &diff,
+        false,
+        opts.list_paths,
+        root,
+        opts.list_threshold,
+        opts.display_relative_to.as_deref(),
+    );
+    let tree_digest = if opts.tree_digest {
+        Some(store.tree_digest()?)
+    } else {
+        None
+    };
+    Ok((
+        store,
+        diff,
+        crate::NefaxReport {
+            tuning,
+            skipped,
+            status: if capped {
+                crate::CompletionStatus::Partial
+            } else {
+                crate::CompletionStatus::Complete
+            },
+            tree_digest,
+            write_stats: None,
+        },
+    ))
 }
 
-/// Internal: run pipeline with callback; diff against `existing` (when `None`, use empty map). Returns `(nefax, diff)`. Lib-only (streaming).
+/// Internal: run pipeline with callback; diff against `existing` (when `None`, use empty map). Returns `(nefax, diff, report)`. Lib-only (streaming).
 pub(crate) fn nefax_dir_callback<F>(
     root: &Path,
     opts: &Opts,
     existing: Option<&crate::Nefax>,
     on_entry: F,
-) -> Result<(crate::Nefax, crate::Diff)>
+) -> Result<(crate::Nefax, crate::Diff, crate::NefaxReport)>
 where
     F: FnMut(&crate::Entry),
 {
-    let existing_stored = if let Some(ex) = existing {
-        crate::validate_nefax(ex)?;
-        nefax_to_stored(ex)
-    } else {
-        let conn = engine::open_db_in_memory()?;
-        engine::load_index(&conn)?
-    };
-    run_lib_pipeline_with_callback(root, opts, &existing_stored, on_entry)
+    let (existing_stored, extras) = lib_prior_state(root, existing, opts)?;
+    run_lib_pipeline_with_callback(root, opts, &existing_stored, Some(&extras), on_entry)
 }
 
-/// Internal: full opts (CLI or lib). Non-callback path: handles both CLI (`write_to_db`) and lib (no DB). Returns `(nefax, diff)`.
+/// Lib path returning a [`NefaxStore`]: like [`nefax_dir_callback`] without a callback, but once the
+/// snapshot exceeds [`Opts::max_entries_in_memory`] it is moved to a temporary on-disk DB. `existing`
+/// (any [`IndexStore`], e.g. a previous spilled result) is never loaded whole: walked paths are
+/// looked up in it a chunk at a time, and its rows are scanned once afterwards for removals.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when `existing` cannot be read or is invalid, the spill DB cannot be
+/// created or written, or strict mode hits an access error.
+pub fn nefax_dir_store(
+    root: &Path,
+    opts: &Opts,
+    existing: Option<&dyn IndexStore>,
+) -> Result<(NefaxStore, crate::Diff, crate::NefaxReport)> {
+    let store = StoreBuilder::with_cap(opts.max_entries_in_memory);
+    run_lib_pipeline_into_store(root, opts, LibPrior::Store(existing), |_| {}, store)
+}
+
+/// One line of [`nefax_dir_jsonl`] and [`nefax_to_writer`] output.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonlEntry {
+    path: String,
+    size: u64,
+    mtime_ns: i64,
+    /// Lowercase hex blake3; `null` when not hashed (no `with_hash`, or below the size threshold).
+    hash: Option<String>,
+    /// Creation time; only present with `track_btime` where the platform reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    btime_ns: Option<i64>,
+    /// Link target; only present for symlinks when following links.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    symlink_target: Option<String>,
+    /// Lowercase hex hash of the extended attributes; only present with `track_xattrs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    xattr_hash: Option<String>,
+}
+
+/// Stream `root` as JSON lines to `out`, one `{"path","size","mtime_ns","hash"}` object per entry in
+/// the order the pipeline produces them. Nothing is collected: no map, diff, or index file (an
+/// in-memory DB is used only for tuning). Paths use the index's forward-slash form. Returns the
+/// number of lines written.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when the pipeline cannot start, writing to `out` fails, thread shutdown
+/// fails, or strict mode hits an access error.
+pub fn nefax_dir_jsonl<W: std::io::Write>(root: &Path, opts: &Opts, mut out: W) -> Result<usize> {
+    let db_path = engine::create_db_path(root, opts.db_path.as_deref());
+    let conn = engine::open_db_in_memory()?;
+    let PipelineHandles {
+        entry_rx,
+        walk_handle,
+        worker_handles,
+        first_error,
+        skipped_paths,
+        ..
+    } = run_pipeline(root, opts, Some(db_path.as_path()), None, &conn)?;
+    let no_index = HashMap::new();
+    let mut written = 0usize;
+    let mut write_result = Ok(());
+    for mut entry in entry_rx.iter().flatten() {
+        engine::fill_entry_hash_if_needed(&mut entry, &no_index, root, opts);
+        let line = JsonlEntry {
+            path: engine::path_to_db_string(&entry.path),
+            size: entry.size,
+            mtime_ns: entry.mtime_ns,
+            hash: entry.hash.map(|h| engine::hex_encode(&h)),
+            btime_ns: entry.btime_ns,
+            symlink_target: entry
+                .symlink_target
+                .as_deref()
+                .map(engine::path_to_db_string),
+            xattr_hash: entry.xattr_hash.map(|h| engine::hex_encode(&h)),
+        };
+        write_result = serde_json::to_writer(&mut out, &line)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| out.write_all(b"\n").map_err(anyhow::Error::from));
+        if write_result.is_err() {
+            // Reader went away (e.g. closed pipe): stop consuming so the pipeline winds down.
+            break;
+        }
+        written += 1;
+    }
+    drop(entry_rx);
+    shutdown_pipeline_handles(walk_handle, worker_handles)?;
+    write_result.context("write JSON lines")?;
+    out.flush().context("flush JSON lines")?;
+    check_for_initial_error_with_skip_report(opts, &first_error, &skipped_paths)?;
+    Ok(written)
+}
+
+/// Write `nefax` to `out` as JSON lines in the [`nefax_dir_jsonl`] format, sorted by path so equal
+/// snapshots produce equal bytes. `out` can be any writer, e.g. a gzip encoder for compact
+/// historical snapshots. Returns the line count.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when writing to `out` fails.
+pub fn nefax_to_writer<W: std::io::Write>(nefax: &crate::Nefax, mut out: W) -> Result<usize> {
+    let mut paths: Vec<&PathBuf> = nefax.keys().collect();
+    paths.sort();
+    for path in &paths {
+        let meta = &nefax[*path];
+        let line = JsonlEntry {
+            path: engine::path_to_db_string(path),
+            size: meta.size,
+            mtime_ns: meta.mtime_ns,
+            hash: meta.hash.map(|h| engine::hex_encode(&h)),
+            btime_ns: meta.btime_ns,
+            symlink_target: meta
+                .symlink_target
+                .as_deref()
+                .map(engine::path_to_db_string),
+            xattr_hash: meta.xattr_hash.map(|h| engine::hex_encode(&h)),
+        };
+        serde_json::to_writer(&mut out, &line).context("write snapshot")?;
+        out.write_all(b"\n").context("write snapshot")?;
+    }
+    out.flush().context("flush snapshot")?;
+    Ok(paths.len())
+}
+
+/// Read a snapshot written by [`nefax_to_writer`] (or `--jsonl` output) back into a [`Nefax`], e.g.
+/// to pass as `existing` to [`nefax_dir`](crate::nefax_dir). Blank lines are skipped.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when reading fails or a line is not a valid entry (including a `hash`
+/// that is not 64 hex digits); the error names the line.
+pub fn nefax_from_reader<R: std::io::BufRead>(reader: R) -> Result<crate::Nefax> {
+    let mut nefax = crate::Nefax::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.context("read snapshot")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JsonlEntry = serde_json::from_str(&line)
+            .with_context(|| format!("snapshot line {}: invalid entry", i + 1))?;
+        let hash = entry
+            .hash
+            .map(|h| {
+                engine::hex_decode(&h)
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .with_context(|| format!("snapshot line {}: invalid hash {h:?}", i + 1))
+            })
+            .transpose()?;
+        let xattr_hash = entry
+            .xattr_hash
+            .map(|h| {
+                engine::hex_decode(&h)
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .with_context(|| format!("snapshot line {}: invalid xattr hash {h:?}", i + 1))
+            })
+            .transpose()?;
+        nefax.insert(
+            PathBuf::from(entry.path),
+            crate::PathMeta {
+                mtime_ns: entry.mtime_ns,
+                size: entry.size,
+                hash,
+                btime_ns: entry.btime_ns,
+                symlink_target: entry.symlink_target.map(PathBuf::from),
+                xattr_hash,
+            },
+        );
+    }
+    Ok(nefax)
+}
+
+/// Save `nefax` gzip-compressed to `path` (see [`nefax_to_writer`]). Feature `gzip`.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when `path` cannot be created or written.
+#[cfg(feature = "gzip")]
+pub fn save_nefax_gz(nefax: &crate::Nefax, path: &Path) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("create snapshot {}", path.display()))?;
+    let mut gz = flate2::write::GzEncoder::new(
+        std::io::BufWriter::new(file),
+        flate2::Compression::default(),
+    );
+    nefax_to_writer(nefax, &mut gz)?;
+    gz.finish()
+        .and_then(|mut w| std::io::Write::flush(&mut w))
+        .with_context(|| format!("write snapshot {}", path.display()))?;
+    Ok(())
+}
+
+/// Load a snapshot saved by [`save_nefax_gz`]. Feature `gzip`.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when `path` cannot be opened, is not gzip, or holds an invalid snapshot.
+#[cfg(feature = "gzip")]
+pub fn load_nefax_gz(path: &Path) -> Result<crate::Nefax> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("open snapshot {}", path.display()))?;
+    nefax_from_reader(std::io::BufReader::new(flate2::read::GzDecoder::new(file)))
+        .with_context(|| format!("load snapshot {}", path.display()))
+}
+
+/// Full opts (CLI or lib). Non-callback path: handles both CLI (`write_to_db`) and lib (no DB). Returns `(nefax, diff, report)`.
+/// On the CLI path a run stopped by Ctrl+C or [`Opts::deadline`] still returns `Ok`, with
+/// [`CompletionStatus::Partial`](crate::CompletionStatus::Partial) in the report; the flushed index is partial.
 ///
 /// # Arguments
 /// * `root` - Directory to index (walk root).
 /// * `opts` - Full options (from CLI or converted from [`NefaxOpts`](crate::NefaxOpts)). When `write_to_db` is false, lib path; when true, CLI path (writes to DB).
 /// * `existing` - Used only on lib path (`write_to_db` false). When `None`, use empty prior state (in-memory DB, **no** `.nefaxer` file read; diff will be all added). When `Some`, diff against that snapshot. On CLI path this is ignored and the previous index is loaded from the `.nefaxer` file on disk if it exists.
-pub(crate) fn nefax_dir_with_opts(
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when opening or writing the index fails, `existing` is invalid, or strict
+/// mode hits an access error.
+pub fn nefax_dir_with_opts(
     root: &Path,
     opts: &Opts,
     existing: Option<&crate::Nefax>,
-) -> Result<(crate::Nefax, crate::Diff)> {
+) -> Result<(crate::Nefax, crate::Diff, crate::NefaxReport)> {
     if !opts.write_to_db {
-        let existing_stored = if let Some(ex) = existing {
-            crate::validate_nefax(ex)?;
-            nefax_to_stored(ex)
-        } else {
-            let conn = engine::open_db_in_memory()?;
-            engine::load_index(&conn)?
-        };
+        let (existing_stored, extras) = lib_prior_state(root, existing, opts)?;
         // Pass a no-op (e.g. `|_| {}`) when not using the callback.
-        return run_lib_pipeline_with_callback(root, opts, &existing_stored, |_| {});
+        return run_lib_pipeline_with_callback(root, opts, &existing_stored, Some(&extras), |_| {});
     }
 
-    // CLI path: write to DB (temp then rename).
+    // CLI path: write to DB (temp then rename), or to a throwaway copy when previewing.
     let db_path = engine::create_db_path(root, opts.db_path.as_deref());
-    let (temp_path, use_temp) = prepare_index_work_path(db_path.as_path())?;
+    // Declared before `conn` so the connection closes before the copy is removed.
+    let preview_copy = if opts.preview {
+        Some(ThrowawayIndex::create(db_path.as_path())?)
+    } else {
+        None
+    };
+    let (temp_path, use_temp) = match preview_copy.as_ref() {
+        Some(copy) => (copy.path().to_path_buf(), true),
+        None => prepare_index_work_path(db_path.as_path())?,
+    };
     let (active_path, do_rename) = if use_temp {
-        (temp_path.as_path(), true)
+        (temp_path.as_path(), preview_copy.is_none())
     } else {
         (db_path.as_path(), false)
     };
 
-    let (mut conn, _) = if opts.encrypt && !db_path.as_path().exists() {
-        let pass = get_passphrase(root, true)?;
+    let keys = KeySource {
+        store_path: Some(db_path.as_path()),
+        ..KeySource::from_opts(opts)
+    };
+    let (mut conn, passphrase) = if opts.encrypt && !db_path.as_path().exists() {
+        engine::ensure_sqlcipher()?;
+        let pass = get_passphrase_for_db(Some(db_path.as_path()), root, true, &keys)?;
         let c = engine::open_db(active_path, Some(pass.as_str()))?;
+        remember_passphrase(db_path.as_path(), &keys, &pass);
         (c, Some(pass))
     } else {
-        engine::open_db_or_detect_encrypted(active_path, root)?
+        engine::open_db_or_detect_encrypted(active_path, root, &keys)?
     };
 
-    let existing = engine::load_index(&conn)?;
+    let opts = &crate::check::with_passphrase_hash_key(opts, passphrase.as_deref())?;
+    engine::apply_durability(&conn, opts.durability)?;
+    let busy_timeout = opts
+        .busy_timeout
+        .unwrap_or(crate::utils::config::DB_BUSY_TIMEOUT);
+    conn.busy_timeout(busy_timeout)
+        .context("set busy timeout")?;
+
+    let mut existing = engine::load_index(&conn)?;
+    let seeds = engine::seed_keys(root, opts);
+    if !existing.is_empty() {
+        engine::warn_on_index_meta_mismatch(&conn, opts)?;
+        let stored_meta = engine::load_index_meta(&conn)?;
+        let stored_algo = stored_meta
+            .hash_algo
+            .as_deref()
+            .unwrap_or(engine::HASH_ALGO);
+        if stored_algo != engine::hash_algo_for(opts)
+            || engine::hash_key_matches(&stored_meta, opts) == Some(false)
+        {
+            // The run records its algorithm for the whole index, so hashed rows it does not walk
+            // would be left under the old one.
+            if existing
+                .iter()
+                .any(|(path, meta)| meta.2.is_some() && !engine::under_seeds(path, &seeds))
+            {
+                anyhow::bail!(
+                    "index {} was hashed under a different algorithm or key; re-index the whole tree (without seeds) to change it",
+                    db_path.display()
+                );
+            }
+            // Hashes under another key cannot be reused: drop them so every hashed file is rewritten.
+            for meta in existing.values_mut() {
+                meta.2 = None;
+            }
+        }
+    }
+    // Rows outside the seeds are not walked: leave them out so they are neither removed nor deleted.
+    existing.retain(|path, _| engine::under_seeds(path, &seeds));
+    let existing_extras = engine::load_index_extras(&conn, opts)?;
+    let path_codec = engine::load_path_codec(&conn)?;
     let cancel_requested = engine::setup_ctrlc_handler()?;
+    let deadline = opts.deadline.map(engine::DeadlineTimer::start);
+    let run_started_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
 
     let PipelineHandles {
         entry_rx,
@@ -190,6 +561,9 @@ pub(crate) fn nefax_dir_with_opts(
         is_network_drive,
         first_error,
         skipped_paths,
+        tuning,
+        walk_capped,
+        too_long_paths,
         ..
     } = run_pipeline(
         root,
@@ -200,43 +574,175 @@ pub(crate) fn nefax_dir_with_opts(
     )?;
 
     let (nefaxing_bar, on_batch, on_received) =
-        setup_progress(opts.verbose, is_network_drive, path_count_rx);
+        setup_progress(opts.verbose, &tuning, path_count_rx);
+    let (hashing_bar, on_hash) = setup_hash_progress(opts.verbose, opts.with_hash, &existing);
+    let bars = [&nefaxing_bar, &hashing_bar];
 
     let mut index_diff = crate::Diff::default();
     let mut stream_params = engine::ApplyIndexDiffStreamingParams {
         existing: &existing,
+        existing_extras: Some(&existing_extras),
         mtime_window_ns: opts.mtime_window_ns,
+        mtime_granularity_ns: engine::effective_mtime_granularity_ns(
+            opts.mtime_granularity_ns,
+            tuning.drive_type,
+        ),
+        change_policy: opts.change_policy,
         on_batch_progress: on_batch,
         on_received_progress: on_received,
+        on_hash_progress: on_hash,
         root: Some(root),
         with_hash: opts.with_hash,
+        hash_small_files: opts.hash_small_files,
         cancel_check: Some(Arc::clone(&cancel_requested)),
+        deadline_check: deadline.as_ref().map(engine::DeadlineTimer::flag),
+        walk_capped: Some(Arc::clone(&walk_capped)),
         diff: (!existing.is_empty()).then_some(&mut index_diff),
         result_map: None, // CLI does not need the full index; lib uses write_to_db=false and diff_from_stream.
+        path_codec: path_codec.as_ref(),
+        append_only: opts.append_only,
+        run_started_at,
+        hash_key: opts.hash_key,
     };
 
-    let written = engine::apply_index_diff_streaming(&mut conn, &entry_rx, &mut stream_params)?;
-    let _path_count = collect_pipeline_results(
-        walk_handle,
-        worker_handles,
-        is_network_drive,
-        written,
-        &nefaxing_bar,
+    // On any error below (e.g. strict mode), clear the bars before the error propagates.
+    let write_stats = progress::clear_bars_on_err(
+        engine::apply_index_diff_streaming(&mut conn, &entry_rx, &mut stream_params)
+            .map_err(|e| engine::explain_db_locked(e, active_path, busy_timeout)),
+        &bars,
     )?;
-    check_for_initial_error_or_skipped_paths(opts, &first_error, &skipped_paths)?;
+    if let Some(bar) = &hashing_bar {
+        progress::finish_bar_at_count(bar);
+    }
+    // After an early stop, closing the channel lets the workers and then the walk wind down.
+    drop(entry_rx);
+    let path_count = progress::clear_bars_on_err(
+        collect_pipeline_results(
+            walk_handle,
+            worker_handles,
+            is_network_drive,
+            write_stats.inserted,
+            &nefaxing_bar,
+        ),
+        &bars,
+    )?;
+    let mut skipped = progress::clear_bars_on_err(
+        check_for_initial_error_with_skip_report(opts, &first_error, &skipped_paths),
+        &bars,
+    )?;
+    skipped.too_long = std::mem::take(&mut *too_long_paths.lock().unwrap());
+    // Only a run that wrote the whole tree speaks for the index: a cancelled or timed-out one keeps
+    // the options (and `updated_at`) of the last run that did.
+    let stopped_early = cancel_requested.load(Ordering::Relaxed)
+        || deadline.as_ref().is_some_and(engine::DeadlineTimer::fired);
+    if !stopped_early {
+        record_index_meta(&mut conn, opts)
+            .map_err(|e| engine::explain_db_locked(e, active_path, busy_timeout))?;
+    }
+    let tree_digest = if opts.tree_digest {
+        Some(record_tree_digest(&conn)?)
+    } else {
+        None
+    };
+    if opts.compress_paths && path_codec.is_none() {
+        // Rewrites the table after streaming, so the dictionary is built from every path.
+        engine::compress_index_paths(&mut conn)?;
+    }
 
     if do_rename {
         rename_temp_to_final(&temp_path, db_path.as_path())?;
     }
 
-    engine::check_for_cancel(&cancel_requested)?;
+    let status = if cancel_requested.load(Ordering::Relaxed) {
+        warn!("Nefaxing cancelled by user; partial index was flushed");
+        crate::CompletionStatus::Partial
+    } else if let Some(timer) = deadline.as_ref().filter(|t| t.fired()) {
+        warn!(
+            "Nefaxing stopped at the {}s deadline; partial index was flushed",
+            timer.deadline().as_secs_f64()
+        );
+        crate::CompletionStatus::Partial
+    } else if walk_was_capped(&walk_capped, opts.max_entries) {
+        crate::CompletionStatus::Partial
+    } else {
+        crate::CompletionStatus::Complete
+    };
 
+    info!("Nefaxed with {}", tuning.summary());
+    debug!(
+        "Index write: {} inserted, {} deleted, {} unchanged of {} received",
+        write_stats.inserted, write_stats.deleted, write_stats.unchanged, write_stats.received
+    );
+    if opts.preview {
+        info!(
+            "Preview: the index write would write {} row(s) and delete {}; {} was not changed.",
+            write_stats.inserted,
+            write_stats.deleted,
+            db_path.display()
+        );
+    }
     if existing.is_empty() {
-        info!("New nefaxer index created.");
+        if !opts.preview {
+            info!("{}", new_index_message(path_count));
+        }
     } else {
-        engine::print_diff(&index_diff, false, opts.list_paths, root);
+        engine::print_diff_with_threshold(
+            &index_diff,
+            false,
+            opts.list_paths,
+            root,
+            opts.list_threshold,
+            opts.display_relative_to.as_deref(),
+        );
     }
 
     // CLI does not need the full index as return value; diff was built during streaming.
-    Ok((std::collections::HashMap::new(), index_diff))
+    Ok((
+        std::collections::HashMap::new(),
+        index_diff,
+        crate::NefaxReport {
+            tuning,
+            skipped,
+            status,
+            tree_digest,
+            write_stats: Some(write_stats),
+        },
+    ))
+}
+
+/// Message logged when a run creates the index: says so plainly when the walk found nothing, so an
+/// empty directory is not mistaken for a successful first index of real content.
+fn new_index_message(path_count: usize) -> &'static str {
+    if path_count == 0 {
+        "Directory is empty; empty index created."
+    } else {
+        "New nefaxer index created."
+    }
+}
+
+/// Store the run's options in `meta` in one transaction once its rows are written, and checkpoint so
+/// they are in the main file before a temp rename.
+fn record_index_meta(conn: &mut rusqlite::Connection, opts: &Opts) -> Result<()> {
+    let tx = conn.transaction().context("begin meta transaction")?;
+    engine::store_index_meta(&tx, opts)?;
+    tx.commit().context("commit meta transaction")?;
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .context("WAL checkpoint")?;
+    Ok(())
+}
+
+/// Compute the tree digest of the index just written, log it (noting when it matches the previous
+/// run's), and store it in `meta`. Checkpoints so the row is in the main file before a temp rename.
+fn record_tree_digest(conn: &rusqlite::Connection) -> Result<[u8; 32]> {
+    let digest = engine::index_tree_digest(&engine::load_index(conn)?);
+    let hex = engine::hex_encode(&digest);
+    if engine::read_meta(conn, engine::META_TREE_DIGEST)?.as_deref() == Some(hex.as_str()) {
+        info!("Tree digest {hex} (unchanged since the last run)");
+    } else {
+        info!("Tree digest {hex}");
+    }
+    engine::write_meta(conn, engine::META_TREE_DIGEST, &hex)?;
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .context("WAL checkpoint")?;
+    Ok(digest)
 }
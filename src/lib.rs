@@ -5,14 +5,17 @@ pub mod disk_detect;
 pub mod engine;
 pub mod index;
 pub mod pipeline;
+pub mod stats;
+pub mod store;
 pub mod types;
 pub mod utils;
 
 /// Re-export types for API
+pub use store::{IndexStore, NefaxStore};
 pub use types::*;
 
 use log::debug;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Result alias used by public nefaxer API
 pub use anyhow::Error;
@@ -35,10 +38,32 @@ pub fn nefax_dir<F>(
     existing: Option<&Nefax>,
     on_entry: Option<F>,
 ) -> Result<(Nefax, Diff)>
+where
+    F: FnMut(&Entry),
+{
+    let (nefax, diff, _) = nefax_dir_with_report(root, opts, existing, on_entry)?;
+    Ok((nefax, diff))
+}
+
+/// Same as [`nefax_dir`] but also returns a [`NefaxReport`] describing the run (e.g. the effective
+/// [`PipelineTuning`](crate::pipeline::PipelineTuning) that is otherwise only logged at debug level).
+///
+/// # Errors
+///
+/// Same as [`nefax_dir`].
+pub fn nefax_dir_with_report<F>(
+    root: &Path,
+    opts: &NefaxOpts,
+    existing: Option<&Nefax>,
+    on_entry: Option<F>,
+) -> Result<(Nefax, Diff, NefaxReport)>
 where
     F: FnMut(&Entry),
 {
     let opts = Opts::from(opts);
+    for warning in opts.consistency_warnings() {
+        log::warn!("{warning}");
+    }
     let config_str = format!(
         "{} CONFIG:{:#?}",
         env!("CARGO_PKG_NAME").to_string().to_uppercase(),
@@ -52,6 +77,102 @@ where
     }
 }
 
+/// Like [`nefax_dir`] (no callback), but the snapshot comes back as a [`NefaxStore`]: a map while
+/// it holds at most [`NefaxOpts::max_entries_in_memory`] entries, otherwise a temporary on-disk DB,
+/// so very large trees do not have to fit in memory. Read it through [`IndexStore`]; the diff is the
+/// same either way. `existing` can be a [`Nefax`] or a previous store.
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when `existing` is invalid, the spill DB cannot be written, or indexing
+/// / diffing fails.
+pub fn nefax_dir_store(
+    root: &Path,
+    opts: &NefaxOpts,
+    existing: Option<&dyn IndexStore>,
+) -> Result<(NefaxStore, Diff, NefaxReport)> {
+    let opts = Opts::from(opts);
+    for warning in opts.consistency_warnings() {
+        log::warn!("{warning}");
+    }
+    index::nefax_dir_store(root, &opts, existing)
+}
+
+/// Stream `root` to `out` as JSON lines (`{"path","size","mtime_ns","hash"}`), one per entry as the
+/// pipeline produces it, without building a [`Nefax`] or touching an index. Returns the line count.
+/// See [`index::nefax_dir_jsonl`].
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when walking fails (or strict mode hits an access error) or `out` cannot be written.
+pub fn nefax_dir_jsonl<W: std::io::Write>(root: &Path, opts: &NefaxOpts, out: W) -> Result<usize> {
+    index::nefax_dir_jsonl(root, &Opts::from(opts), out)
+}
+
+#[cfg(feature = "gzip")]
+pub use index::{load_nefax_gz, save_nefax_gz};
+pub use index::{nefax_from_reader, nefax_to_writer};
+
+/// Iterate `root`'s entries lazily as the walk + metadata pipeline produces them: no hashing, no
+/// DB, no [`Nefax`] map. The streaming analog of [`pipeline::collect_entries`]; entries arrive in
+/// no particular order. A strict-mode access error is yielded as the final `Err` item. Dropping the
+/// iterator early stops the pipeline and joins its threads.
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when the pipeline cannot start (e.g. `root` is not a readable directory).
+pub fn walk_entries(
+    root: &Path,
+    opts: &NefaxOpts,
+) -> Result<impl Iterator<Item = Result<Entry>> + use<>> {
+    let conn = engine::open_db_in_memory()?;
+    pipeline::iter_entries(root, &Opts::from(opts), None, None, &conn)
+}
+
+/// Diff two live directory trees without creating any index file (e.g. verify a backup against its source).
+/// `a` is treated as the original, `b` as the copy: paths only in `b` are added, only in `a` removed.
+///
+/// With `opts.with_hash` this is a content check (see [`check::compare_dirs`]).
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when walking either tree fails (or strict mode hits an access error).
+pub fn compare_dirs(a: &Path, b: &Path, opts: &NefaxOpts) -> Result<Diff> {
+    let opts = Opts::from(opts);
+    for warning in opts.consistency_warnings() {
+        log::warn!("{warning}");
+    }
+    check::compare_dirs(a, b, &opts)
+}
+
+/// Paths in the index that no longer exist under `root` (e.g. to clean up derived files such as
+/// thumbnails), without hashing or classifying anything else. `db_path` defaults to `root`'s index.
+/// See [`check::find_removed`].
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when the index is missing or unreadable, or walking fails (or strict
+/// mode hits an access error).
+pub fn find_removed(root: &Path, db_path: Option<&Path>, opts: &NefaxOpts) -> Result<Vec<PathBuf>> {
+    check::find_removed(root, db_path, &Opts::from(opts))
+}
+
+/// Verify `root` against a `sha256sum`/`b3sum` style checksum manifest (`<hex>  <path>` lines):
+/// listed files with a different hash are modified, missing ones removed, unlisted files added.
+/// See [`check::verify_manifest`].
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when the manifest is unreadable or malformed, or walking fails.
+pub fn verify_manifest(
+    root: &Path,
+    manifest_path: &Path,
+    format: ManifestFormat,
+    opts: &NefaxOpts,
+) -> Result<Diff> {
+    check::verify_manifest(root, manifest_path, format, &Opts::from(opts))
+}
+
 /// Returns `(num_threads, drive_type, use_parallel_walk)` for use in [`NefaxOpts`] when you have no DB.
 ///
 /// Calls [`determine_threads_for_drive`](determine_threads_for_drive) with `conn: None` (network probe runs but is not cached).
@@ -67,5 +188,5 @@ pub fn tuning_for_path(
     available_threads: Option<usize>,
 ) -> (usize, disk_detect::DriveType, bool) {
     let avail = available_threads.unwrap_or_else(rayon::current_num_threads);
-    disk_detect::determine_threads_for_drive(path, None, avail, None)
+    disk_detect::determine_threads_for_drive(path, None, avail, None, None, false)
 }
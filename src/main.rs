@@ -2,6 +2,7 @@
 
 use anyhow::Result;
 use clap::Parser;
+use nefaxer::check::ChangesDetected;
 use nefaxer::engine::arg_parser::Cli;
 use nefaxer::engine::handle_run;
 use std::time::Instant;
@@ -9,7 +10,14 @@ use std::time::Instant;
 fn main() -> Result<()> {
     let start_time = Instant::now();
     let cli = Cli::parse();
-    handle_run(&cli)?;
+    if let Err(e) = handle_run(&cli) {
+        // --fail-on-change: drift gets its own status so CI can tell it from a failed run.
+        if let Some(changes) = e.downcast_ref::<ChangesDetected>() {
+            log::error!("{changes}");
+            std::process::exit(ChangesDetected::EXIT_CODE);
+        }
+        return Err(e);
+    }
     log::debug!("Total time: {:?}", start_time.elapsed());
     Ok(())
 }
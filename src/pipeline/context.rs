@@ -3,11 +3,14 @@
 use crate::Entry;
 use crossbeam_channel::{Receiver, Sender, bounded};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::Duration;
 
-use crate::Opts;
-use crate::utils::config::PackagePaths;
+use crate::disk_detect::DriveType;
+use crate::utils::config::{ENTRY_SEND_BATCH_SIZE, PackagePaths, VCS_EXCLUDE_PATTERNS};
+use crate::{Opts, SkippedPath};
 
 /// Tuning derived from drive type and FD limit: worker count, walk mode, channel cap.
 /// Channel cap is drive-type default on first run; finetuned from stored path count in diskinfo on subsequent runs.
@@ -15,50 +18,130 @@ use crate::utils::config::PackagePaths;
 pub struct PipelineTuning {
     pub num_threads: usize,
     pub parallel_walk: bool,
+    pub drive_type: DriveType,
     pub is_network_drive: bool,
     /// Capacity for path and entry channels (drive-type default or from diskinfo path count).
     pub channel_cap: usize,
+    /// Path count of the existing index, when there is one. Estimates the total for the progress
+    /// bar before the walk has finished counting (network drives never get the walk's count).
+    pub stored_path_count: Option<usize>,
+}
+
+impl PipelineTuning {
+    /// Short description of the worker count and drive type, e.g. `8 threads, SSD`. Shown in the
+    /// verbose progress bar and the end-of-run summary.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let noun = if self.num_threads == 1 {
+            "thread"
+        } else {
+            "threads"
+        };
+        format!("{} {noun}, {}", self.num_threads, self.drive_type)
+    }
 }
 
 /// Shared context for the walk + metadata pipeline. Built in `run_pipeline` and passed
 /// into the walk thread so the common walk loop has root, exclude, strict, and error/skip state.
 pub struct PipelineContext {
     pub root: PathBuf,
+    /// Absolute start points of the walk ([`Opts::seeds`], deduplicated by [`crate::engine::walk_seeds`],
+    /// joined to the root); empty walks `root`.
+    pub seeds: Vec<PathBuf>,
     pub db_canonical: Option<PathBuf>,
     pub temp_canonical: Option<PathBuf>,
     pub exclude: Vec<String>,
     pub strict: bool,
     pub follow_links: bool,
+    /// When set (with `follow_links`), only symlinks at most this deep below the root are descended.
+    pub follow_depth: Option<usize>,
+    /// Skip (and prune) dotfiles and dot-directories below the root.
+    pub skip_dotfiles: bool,
+    /// Skip the contents of directories below the root that hold their own index.
+    pub prune_nested: bool,
     pub first_error: Arc<Mutex<Option<String>>>,
-    pub skipped_paths: Arc<Mutex<Vec<(PathBuf, String)>>>,
+    pub skipped_paths: Arc<Mutex<Vec<SkippedPath>>>,
+    /// Directories below the root found holding another index file (filled by the walk).
+    pub nested_indexes: Arc<Mutex<Vec<PathBuf>>>,
+    /// Stop the walk after this many paths ([`Opts::max_entries`]).
+    pub max_entries: Option<usize>,
+    /// Set by the walk when it stopped at `max_entries` with paths left.
+    pub walk_capped: Arc<AtomicBool>,
+    /// Skip paths longer than this many bytes relative to the root ([`Opts::max_path_len`]).
+    pub max_path_len: Option<usize>,
+    /// Paths skipped for exceeding `max_path_len` (filled by the walk, sorted when it finishes).
+    pub too_long_paths: Arc<Mutex<Vec<PathBuf>>>,
 }
 
+/// Error/skip state shared with metadata workers (clones of the pipeline's Arcs).
+/// A path that vanished between walk and stat (`NotFound`) is a benign race on a live tree: it is only
+/// counted in `vanished`. Any other metadata error is recorded like a walk error.
+#[derive(Clone)]
+pub struct WorkerErrorState {
+    pub strict: bool,
+    pub first_error: Arc<Mutex<Option<String>>>,
+    pub skipped_paths: Arc<Mutex<Vec<SkippedPath>>>,
+    pub vanished: Arc<AtomicUsize>,
+}
+
+/// Entries sent together by one metadata worker (at most [`ENTRY_SEND_BATCH_SIZE`], never empty).
+pub type EntryBatch = Vec<Entry>;
+
 /// Result of [`collect_entries`]: (entries, `path_count`).
 pub type CollectEntriesResult = (Vec<Entry>, usize);
 
-/// Handles returned by [`run_pipeline`] for streaming: receive entries and join when done.
+/// Result of [`collect_entries_with_stats`](super::collect_entries_with_stats): entries plus totals
+/// and timings for tuning. Walk and metadata overlap, so both durations run from pipeline start.
+#[derive(Debug, Default)]
+pub struct CollectStats {
+    pub entries: Vec<Entry>,
+    /// Paths the walk sent to the metadata workers.
+    pub path_count: usize,
+    /// Sum of `size` over file entries (hard links counted once per path).
+    pub total_bytes: u64,
+    /// Until the walk finished.
+    pub walk_elapsed: Duration,
+    /// Until the last entry was received (metadata workers done).
+    pub metadata_elapsed: Duration,
+    /// Directories below the root holding their own index (pruned with `prune_nested`).
+    pub nested_indexes: Vec<PathBuf>,
+}
+
+/// Handles returned by [`run_pipeline`] for streaming: receive entry batches and join when done.
 /// `path_count_rx`: receives the walk's path count when the walk finishes (use to set progress bar total).
 /// `is_network_drive`: true when indexing a network path (use counter-style progress, no total).
 pub struct PipelineHandles {
-    pub entry_rx: Receiver<Entry>,
+    pub entry_rx: Receiver<EntryBatch>,
     pub path_count_rx: Receiver<usize>,
     pub walk_handle: JoinHandle<usize>,
     pub worker_handles: Vec<JoinHandle<()>>,
     pub is_network_drive: bool,
     pub first_error: Arc<Mutex<Option<String>>>,
-    pub skipped_paths: Arc<Mutex<Vec<(PathBuf, String)>>>,
+    pub skipped_paths: Arc<Mutex<Vec<SkippedPath>>>,
+    /// Directories below the root holding their own index; complete once the walk has finished.
+    pub nested_indexes: Arc<Mutex<Vec<PathBuf>>>,
+    /// Paths that disappeared between walk and metadata read (not counted as skipped).
+    pub vanished: Arc<AtomicUsize>,
+    /// Tuning the pipeline was started with (threads, walk mode, drive type, channel cap).
+    pub tuning: PipelineTuning,
+    /// Set when the walk stopped at [`Opts::max_entries`]; final once the entry channel is closed.
+    pub walk_capped: Arc<AtomicBool>,
+    /// Paths skipped for exceeding [`Opts::max_path_len`]; complete once the walk has finished.
+    pub too_long_paths: Arc<Mutex<Vec<PathBuf>>>,
 }
 
 /// Channels and shared state for the pipeline. Walk thread gets `path_tx`, `path_count_tx`, ctx; workers get `path_rx`, `entry_tx`.
 pub struct PipelineChannels {
     pub path_tx: Sender<PathBuf>,
     pub path_rx: Receiver<PathBuf>,
-    pub entry_tx: Sender<Entry>,
-    pub entry_rx: Receiver<Entry>,
+    pub entry_tx: Sender<EntryBatch>,
+    pub entry_rx: Receiver<EntryBatch>,
     pub path_count_tx: Sender<usize>,
     pub path_count_rx: Receiver<usize>,
     pub first_error: Arc<Mutex<Option<String>>>,
-    pub skipped_paths: Arc<Mutex<Vec<(PathBuf, String)>>>,
+    pub skipped_paths: Arc<Mutex<Vec<SkippedPath>>>,
+    pub nested_indexes: Arc<Mutex<Vec<PathBuf>>>,
+    pub worker_errors: WorkerErrorState,
     pub ctx: PipelineContext,
 }
 
@@ -71,23 +154,54 @@ pub fn create_pipeline_channels(
     channel_cap: usize,
 ) -> PipelineChannels {
     let (path_tx, path_rx) = bounded::<PathBuf>(channel_cap);
-    let (entry_tx, entry_rx) = bounded::<Entry>(channel_cap);
+    // `channel_cap` counts entries; the entry channel carries batches of them.
+    let (entry_tx, entry_rx) = bounded::<EntryBatch>(channel_cap.div_ceil(ENTRY_SEND_BATCH_SIZE));
     let (path_count_tx, path_count_rx) = bounded::<usize>(1);
     let first_error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
-    let skipped_paths: Arc<Mutex<Vec<(PathBuf, String)>>> = Arc::new(Mutex::new(Vec::new()));
+    let skipped_paths: Arc<Mutex<Vec<SkippedPath>>> = Arc::new(Mutex::new(Vec::new()));
+    let nested_indexes: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(Vec::new()));
 
-    let mut exclude = PackagePaths::get().default_exclude_patterns();
+    // With `index_self` and no index inside the tree, files named like our artifacts are user data.
+    // The active temp file is still excluded below via `temp_canonical`.
+    let db_external = db_canonical.as_ref().is_none_or(|db| !db.starts_with(root));
+    let mut exclude = if opts.index_self && db_external {
+        Vec::new()
+    } else {
+        PackagePaths::get().default_exclude_patterns()
+    };
     exclude.extend(opts.exclude.iter().cloned());
+    if opts.exclude_vcs {
+        exclude.extend(VCS_EXCLUDE_PATTERNS.iter().map(|p| (*p).to_string()));
+    }
+
+    let worker_errors = WorkerErrorState {
+        strict: opts.strict,
+        first_error: Arc::clone(&first_error),
+        skipped_paths: Arc::clone(&skipped_paths),
+        vanished: Arc::new(AtomicUsize::new(0)),
+    };
 
     let ctx = PipelineContext {
         root: root.to_path_buf(),
+        seeds: crate::engine::walk_seeds(&opts.seeds)
+            .iter()
+            .map(|s| root.join(s))
+            .collect(),
         db_canonical: db_canonical.clone(),
         temp_canonical: temp_canonical.clone(),
         exclude,
         strict: opts.strict,
-        follow_links: opts.follow_links,
+        follow_links: opts.follow_links || opts.follow_depth.is_some(),
+        follow_depth: opts.follow_depth,
+        skip_dotfiles: opts.skip_dotfiles,
+        prune_nested: opts.prune_nested,
         first_error: Arc::clone(&first_error),
         skipped_paths: Arc::clone(&skipped_paths),
+        nested_indexes: Arc::clone(&nested_indexes),
+        max_entries: opts.max_entries,
+        walk_capped: Arc::new(AtomicBool::new(false)),
+        max_path_len: opts.max_path_len,
+        too_long_paths: Arc::new(Mutex::new(Vec::new())),
     };
 
     PipelineChannels {
@@ -99,6 +213,8 @@ pub fn create_pipeline_channels(
         path_count_rx,
         first_error,
         skipped_paths,
+        nested_indexes,
+        worker_errors,
         ctx,
     }
 }
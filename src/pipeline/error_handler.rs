@@ -1,13 +1,11 @@
 use anyhow::Result;
 use log::warn;
-use std::collections::HashMap;
-use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-use crate::Opts;
+use crate::{Opts, SkipReport, SkippedPath};
 
 /// Check pipeline result: if strict and a first error was recorded, return it; otherwise log skipped paths.
-/// Call after joining walk and workers. Counts by error type and prints one warn with breakdown.
+/// Call after joining walk and workers. Counts by [`crate::SkipReason`] and prints one warn with breakdown.
 ///
 /// # Errors
 ///
@@ -19,28 +17,45 @@ use crate::Opts;
 pub fn check_for_initial_error_or_skipped_paths(
     opts: &Opts,
     first_error: &Arc<Mutex<Option<String>>>,
-    skipped_paths: &Arc<Mutex<Vec<(PathBuf, String)>>>,
+    skipped_paths: &Arc<Mutex<Vec<SkippedPath>>>,
 ) -> Result<()> {
+    check_for_initial_error_with_skip_report(opts, first_error, skipped_paths).map(|_| ())
+}
+
+/// Same as [`check_for_initial_error_or_skipped_paths`] but also returns the skipped paths grouped by
+/// reason (empty in strict mode).
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when `opts.strict` is true and a walk/metadata error was recorded.
+///
+/// # Panics
+///
+/// Panics if `first_error` or `skipped_paths` mutexes are poisoned.
+pub fn check_for_initial_error_with_skip_report(
+    opts: &Opts,
+    first_error: &Arc<Mutex<Option<String>>>,
+    skipped_paths: &Arc<Mutex<Vec<SkippedPath>>>,
+) -> Result<SkipReport> {
     if opts.strict
         && let Some(msg) = first_error.lock().unwrap().take()
     {
         return Err(anyhow::anyhow!("{msg}"));
     }
-    let skipped = skipped_paths.lock().unwrap();
-    let total = skipped.len();
-    if total > 0 && !opts.strict {
-        let mut by_msg: HashMap<&str, usize> = HashMap::new();
-        for (_, msg) in skipped.iter() {
-            *by_msg.entry(msg.as_str()).or_insert(0) += 1;
-        }
+    if opts.strict {
+        return Ok(SkipReport::default());
+    }
+    let report = SkipReport::from_skipped(&skipped_paths.lock().unwrap());
+    let total = report.total;
+    if total > 0 {
         warn!("Skipped/issue breakdown:");
         warn!("  - Total: {total}");
-        for (msg, count) in by_msg {
+        for (reason, count) in &report.by_reason {
             // Log-only percentage; usize→f64 is acceptable for display.
             #[allow(clippy::cast_precision_loss)]
-            let pct = (count as f64 / total as f64) * 100.0;
-            warn!("  - {}: {} paths ({pct:.2}%)", msg, count);
+            let pct = (*count as f64 / total as f64) * 100.0;
+            warn!("  - {}: {} paths ({pct:.2}%)", reason.as_str(), count);
         }
     }
-    Ok(())
+    Ok(report)
 }
@@ -1,63 +1,236 @@
-use crate::Entry;
-use anyhow::Result;
-use crossbeam_channel::{Receiver, Sender};
+use crate::{Entry, FileKind, Opts, PathKeyPolicy, SkipReason};
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use log::{debug, warn};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
 use std::thread::{self, JoinHandle};
 
-use crate::engine::hashing::hash_file;
-use crate::engine::tools::{path_relative_to, path_to_db_string};
-use crate::utils::config::SMALL_FILE_THRESHOLD;
+use crate::engine::hashing::xattr_hash;
+use crate::engine::tools::{path_relative_to, path_to_db_key};
 
-/// Single metadata worker: read paths from `path_rx`, turn into entries, send on `entry_tx`.
-/// Hashing is done in the streaming receiver when `with_hash` is set, not here.
-fn metadata_worker_loop(path_rx: &Receiver<PathBuf>, entry_tx: Sender<Entry>, root: &Path) {
-    while let Ok(abs_path) = path_rx.recv() {
-        if let Ok(entry) = path_to_entry(&abs_path, root, false) {
-            let _ = entry_tx.send(entry);
+use super::context::{EntryBatch, WorkerErrorState};
+
+/// How metadata workers shape each [`Entry`]: the path form and which optional fields to read.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EntryOptions {
+    /// Key entries by absolute path instead of relative to the root.
+    pub store_absolute: bool,
+    /// Canonical form of the key (see [`PathKeyPolicy`]).
+    pub path_key: PathKeyPolicy,
+    /// Read the creation time into [`Entry::btime_ns`] (where the platform reports one).
+    pub track_btime: bool,
+    /// Record the link target of symlinks in [`Entry::symlink_target`] (set when following links).
+    pub record_symlinks: bool,
+    /// Read the extended-attribute hash into [`Entry::xattr_hash`] (Linux/macOS).
+    pub track_xattrs: bool,
+}
+
+impl EntryOptions {
+    #[must_use]
+    pub fn from_opts(opts: &Opts) -> Self {
+        Self {
+            store_absolute: opts.store_absolute,
+            path_key: opts.path_key,
+            track_btime: opts.track_btime,
+            record_symlinks: opts.follow_links || opts.follow_depth.is_some(),
+            track_xattrs: opts.track_xattrs,
         }
     }
-    drop(entry_tx);
+}
+
+/// Record a metadata error for `abs_path`: `NotFound` (deleted after the walk saw it) is only counted
+/// as vanished; anything else goes to `first_error` in strict mode, else to `skipped_paths` (like the walk).
+/// The recorded message names the read that failed and its cause, e.g. `read link target: ...`.
+///
+/// # Panics
+///
+/// Panics if `errors.first_error` or `errors.skipped_paths` mutexes are poisoned.
+fn record_metadata_error(abs_path: &Path, err: &anyhow::Error, errors: &WorkerErrorState) {
+    let reason = SkipReason::from_error(err);
+    if reason == SkipReason::NotFound {
+        debug!("Path vanished before metadata read: {}", abs_path.display());
+        errors.vanished.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    let msg = format!("{err:#}");
+    if errors.strict {
+        let _ = errors
+            .first_error
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| format!("{}: {msg}", abs_path.display()));
+        return;
+    }
+    errors
+        .skipped_paths
+        .lock()
+        .unwrap()
+        .push((abs_path.to_path_buf(), reason, msg));
+}
+
+/// Send `batch` (if non-empty) and start a new one. False when the receiver is gone.
+fn send_batch(entry_tx: &Sender<EntryBatch>, batch: &mut EntryBatch, batch_size: usize) -> bool {
+    if batch.is_empty() {
+        return true;
+    }
+    let full = std::mem::replace(batch, Vec::with_capacity(batch_size));
+    entry_tx.send(full).is_ok()
+}
+
+/// Single metadata worker: read paths from `path_rx`, turn into entries, send them on `entry_tx`
+/// in batches of up to `batch_size`. A partial batch goes out before waiting on an empty `path_rx`,
+/// so a slow walk does not hold entries back. Hashing is done in the streaming receiver when
+/// `with_hash` is set, not here.
+fn metadata_worker_loop(
+    path_rx: &Receiver<PathBuf>,
+    entry_tx: Sender<EntryBatch>,
+    root: &Path,
+    shape: EntryOptions,
+    batch_size: usize,
+    errors: &WorkerErrorState,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    loop {
+        let abs_path = match path_rx.try_recv() {
+            Ok(path) => path,
+            Err(TryRecvError::Empty) => {
+                // Receiver gone (e.g. indexing stopped early): stop so the walk sees a closed channel too.
+                if !send_batch(&entry_tx, &mut batch, batch_size) {
+                    return;
+                }
+                match path_rx.recv() {
+                    Ok(path) => path,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        match path_to_entry(&abs_path, root, shape) {
+            Ok(entry) => {
+                batch.push(entry);
+                if batch.len() >= batch_size && !send_batch(&entry_tx, &mut batch, batch_size) {
+                    return;
+                }
+            }
+            Err(e) => record_metadata_error(&abs_path, &e, errors),
+        }
+    }
+    send_batch(&entry_tx, &mut batch, batch_size);
 }
 
 /// Spawn metadata workers: read paths from `path_rx`, turn into entries, send on `entry_tx`. Caller must drop its sender after this so workers exit.
+/// Entries are shaped by `shape` (see [`EntryOptions`]) and sent in batches of up to `batch_size`
+/// ([`ENTRY_SEND_BATCH_SIZE`](crate::utils::config::ENTRY_SEND_BATCH_SIZE) in the pipeline; 1 sends
+/// each entry on its own).
 #[must_use]
 pub fn spawn_metadata_workers(
     path_rx: &Receiver<PathBuf>,
-    entry_tx: &Sender<Entry>,
+    entry_tx: &Sender<EntryBatch>,
     root: &Path,
+    shape: EntryOptions,
+    batch_size: usize,
     num_threads: usize,
+    errors: &WorkerErrorState,
 ) -> Vec<JoinHandle<()>> {
     let root = root.to_path_buf();
+    let batch_size = batch_size.max(1);
     (0..num_threads)
         .map(|_| {
             let path_rx = path_rx.clone();
             let entry_tx = entry_tx.clone();
             let root = root.clone();
-            thread::spawn(move || metadata_worker_loop(&path_rx, entry_tx, root.as_path()))
+            let errors = errors.clone();
+            thread::spawn(move || {
+                metadata_worker_loop(
+                    &path_rx,
+                    entry_tx,
+                    root.as_path(),
+                    shape,
+                    batch_size,
+                    &errors,
+                );
+            })
         })
         .collect()
 }
 
-/// Process a single path into an Entry (metadata + optional hash).
-fn path_to_entry(abs_path: &Path, root: &Path, with_hash: bool) -> Result<Entry> {
-    let meta = std::fs::metadata(abs_path)?;
+/// Process a single path into an Entry (metadata only). Hashes are filled in later by the indexer or
+/// the check, which can reuse stored ones.
+fn path_to_entry(abs_path: &Path, root: &Path, shape: EntryOptions) -> Result<Entry> {
+    let meta = std::fs::metadata(abs_path).context("read metadata")?;
     let mtime_ns = meta
         .modified()
         .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as i64)
         .unwrap_or(0);
-    let size = meta.len();
-    let is_file = meta.is_file();
-    let rel = path_relative_to(abs_path, root).unwrap_or_else(|| abs_path.to_path_buf());
-    let path = PathBuf::from(path_to_db_string(&rel));
-    let hash = if with_hash && is_file && size >= SMALL_FILE_THRESHOLD {
-        hash_file(abs_path, size)?
+    // `created()` errors where the platform or filesystem has no birth time.
+    let btime_ns = shape
+        .track_btime
+        .then(|| meta.created().ok())
+        .flatten()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as i64);
+    // `metadata` follows links, so only `symlink_metadata` tells a link from its target.
+    let symlink_target = if shape.record_symlinks
+        && std::fs::symlink_metadata(abs_path)
+            .context("read link metadata")?
+            .file_type()
+            .is_symlink()
+    {
+        Some(std::fs::read_link(abs_path).context("read link target")?)
+    } else {
+        None
+    };
+    // An unreadable attribute set leaves the hash unknown rather than dropping the entry (and its row).
+    let xattr_hash = if shape.track_xattrs {
+        xattr_hash(abs_path).unwrap_or_else(|e| {
+            warn!(
+                "Cannot read extended attributes of {}: {e:#}",
+                abs_path.display()
+            );
+            None
+        })
     } else {
         None
     };
+    let size = meta.len();
+    // FIFOs, sockets and devices are `Other`: recorded, but never opened for hashing.
+    let file_type = meta.file_type();
+    let is_file = file_type.is_file();
+    let kind = if is_file {
+        FileKind::File
+    } else if file_type.is_dir() {
+        FileKind::Dir
+    } else {
+        FileKind::Other
+    };
+    #[cfg(unix)]
+    let hardlink_id = {
+        use std::os::unix::fs::MetadataExt;
+        (is_file && meta.nlink() > 1).then(|| (meta.dev(), meta.ino()))
+    };
+    #[cfg(not(unix))]
+    let hardlink_id = None;
+    let rel = if shape.store_absolute {
+        abs_path.to_path_buf()
+    } else {
+        path_relative_to(abs_path, root).unwrap_or_else(|| abs_path.to_path_buf())
+    };
+    // Only the key is rewritten; the walked name is kept to open and report the file.
+    let plain = path_to_db_key(&rel, PathKeyPolicy::AsIs);
+    let key = path_to_db_key(&rel, shape.path_key);
+    let disk_path = (key != plain).then_some(rel);
+    let path = PathBuf::from(key);
     Ok(Entry {
         path,
+        disk_path,
         mtime_ns,
         size,
-        hash,
+        hash: None,
+        kind,
+        hardlink_id,
+        btime_ns,
+        symlink_target,
+        xattr_hash,
     })
 }
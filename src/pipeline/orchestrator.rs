@@ -2,16 +2,18 @@ use anyhow::Result;
 use log::debug;
 use rusqlite::Connection;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::Opts;
 use crate::disk_detect::{channel_cap_for_drive, determine_threads_for_drive};
 use crate::engine::parallel::parallel_walk_handler;
 use crate::engine::{path_count_from_db, tools::canonicalize_paths};
 use crate::pipeline;
-use crate::utils::config::{StreamingChannelCap, WorkerThreadLimits};
+use crate::utils::config::{ENTRY_SEND_BATCH_SIZE, StreamingChannelCap, WorkerThreadLimits};
+use crate::{FileKind, Opts, SkippedPath};
 
-/// Start the walk + metadata pipeline. Returns receiver and handles; caller receives from
-/// `entry_rx` and must join `walk_handle` and `worker_handles` when done.
+/// Start the walk + metadata pipeline. Returns receiver and handles; caller receives entry batches
+/// from `entry_rx` and must join `walk_handle` and `worker_handles` when done.
 ///
 /// # Errors
 ///
@@ -23,6 +25,7 @@ pub fn run_pipeline(
     temp_path: Option<&Path>,
     conn: &Connection,
 ) -> Result<pipeline::PipelineHandles> {
+    crate::engine::validate_seeds(&opts.seeds)?;
     let (root, db_canonical, temp_canonical, tuning) =
         setup_pipeline_root_and_tuning(root, opts, db_path, temp_path, conn)?;
 
@@ -34,6 +37,8 @@ pub fn run_pipeline(
         tuning.channel_cap,
     );
 
+    let walk_capped = Arc::clone(&channels.ctx.walk_capped);
+    let too_long_paths = Arc::clone(&channels.ctx.too_long_paths);
     let walk_handle = pipeline::spawn_walk_thread(
         channels.path_tx,
         channels.path_count_tx,
@@ -45,7 +50,10 @@ pub fn run_pipeline(
         &channels.path_rx,
         &channels.entry_tx,
         &root,
+        pipeline::EntryOptions::from_opts(opts),
+        ENTRY_SEND_BATCH_SIZE,
         tuning.num_threads,
+        &channels.worker_errors,
     );
 
     // Dropping the last sender closes the channel so workers exit.
@@ -59,6 +67,11 @@ pub fn run_pipeline(
         is_network_drive: tuning.is_network_drive,
         first_error: channels.first_error,
         skipped_paths: channels.skipped_paths,
+        nested_indexes: channels.nested_indexes,
+        vanished: channels.worker_errors.vanished,
+        tuning,
+        walk_capped,
+        too_long_paths,
     })
 }
 
@@ -105,6 +118,8 @@ pub fn setup_pipeline_root_and_tuning(
         Some(conn),
         WorkerThreadLimits::current().all_threads,
         opts.num_threads,
+        opts.fds_per_worker,
+        opts.force_parallel_walk,
     );
 
     // Channel cap: if .nefaxer exists, get path count from DB (fast COUNT(*)); else drive-type default.
@@ -117,7 +132,7 @@ pub fn setup_pipeline_root_and_tuning(
         "Streaming cap set to {} ({})",
         channel_cap,
         stored_count.map_or_else(
-            || format!("drive default {drive_type:?}"),
+            || format!("drive default {drive_type}"),
             |n| format!("{n} paths from index")
         )
     );
@@ -126,8 +141,10 @@ pub fn setup_pipeline_root_and_tuning(
     let tuning = pipeline::PipelineTuning {
         num_threads,
         parallel_walk,
+        drive_type,
         is_network_drive: drive_type.is_network(),
         channel_cap,
+        stored_path_count: stored_count,
     };
     Ok((root, db_canonical, temp_canonical, tuning))
 }
@@ -147,20 +164,142 @@ pub fn collect_entries(
     temp_path: Option<&Path>,
     conn: &Connection,
 ) -> Result<pipeline::CollectEntriesResult> {
+    collect_entries_with_stats(root, opts, db_path, temp_path, conn)
+        .map(|stats| (stats.entries, stats.path_count))
+}
+
+/// Streaming analog of [`collect_entries`]: start the pipeline and return an iterator over entries
+/// as workers produce them (no `Vec`, no hashing). See [`EntryIter`].
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] when [`run_pipeline`] fails.
+pub fn iter_entries(
+    root: &Path,
+    opts: &Opts,
+    db_path: Option<&Path>,
+    temp_path: Option<&Path>,
+    conn: &Connection,
+) -> Result<EntryIter> {
+    let handles = run_pipeline(root, opts, db_path, temp_path, conn)?;
+    Ok(EntryIter {
+        entry_rx: Some(handles.entry_rx),
+        pending: Vec::new().into_iter(),
+        walk_handle: Some(handles.walk_handle),
+        worker_handles: handles.worker_handles,
+        first_error: handles.first_error,
+        skipped_paths: handles.skipped_paths,
+        opts: opts.clone(),
+    })
+}
+
+/// Entries of a running pipeline, yielded as they arrive (order is not stable). When the stream
+/// ends, threads are joined and a strict-mode error (or walk panic) is yielded as the last item.
+/// Dropping the iterator early closes the channel, which stops the walk and workers, and joins them.
+pub struct EntryIter {
+    entry_rx: Option<crossbeam_channel::Receiver<pipeline::EntryBatch>>,
+    /// Rest of the last batch received.
+    pending: std::vec::IntoIter<crate::Entry>,
+    walk_handle: Option<std::thread::JoinHandle<usize>>,
+    worker_handles: Vec<std::thread::JoinHandle<()>>,
+    first_error: std::sync::Arc<std::sync::Mutex<Option<String>>>,
+    skipped_paths: std::sync::Arc<std::sync::Mutex<Vec<SkippedPath>>>,
+    opts: Opts,
+}
+
+impl EntryIter {
+    /// Close the stream and join the threads; `None` once already finished.
+    fn finish(&mut self) -> Option<Result<()>> {
+        self.entry_rx.take();
+        let walk_handle = self.walk_handle.take()?;
+        let worker_handles = std::mem::take(&mut self.worker_handles);
+        Some(shutdown_pipeline_handles(walk_handle, worker_handles))
+    }
+}
+
+impl Iterator for EntryIter {
+    type Item = Result<crate::Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.pending.next() {
+                return Some(Ok(entry));
+            }
+            match self.entry_rx.as_ref().and_then(|rx| rx.recv().ok()) {
+                Some(batch) => self.pending = batch.into_iter(),
+                None => break,
+            }
+        }
+        let joined = self.finish()?.and_then(|()| {
+            pipeline::check_for_initial_error_or_skipped_paths(
+                &self.opts,
+                &self.first_error,
+                &self.skipped_paths,
+            )
+        });
+        joined.err().map(Err)
+    }
+}
+
+impl Drop for EntryIter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Same as [`collect_entries`] but also returns file byte totals and walk / metadata timings.
+///
+/// # Errors
+///
+/// Same as [`collect_entries`].
+pub fn collect_entries_with_stats(
+    root: &Path,
+    opts: &Opts,
+    db_path: Option<&Path>,
+    temp_path: Option<&Path>,
+    conn: &Connection,
+) -> Result<pipeline::CollectStats> {
+    let started = Instant::now();
     let pipeline::PipelineHandles {
         entry_rx,
-        path_count_rx: _path_count_rx,
+        path_count_rx,
         walk_handle,
         worker_handles,
         is_network_drive: _,
         first_error,
         skipped_paths,
+        nested_indexes,
+        vanished: _,
+        tuning: _,
+        walk_capped: _,
+        too_long_paths: _,
     } = run_pipeline(root, opts, db_path, temp_path, conn)?;
 
     let mut entries = Vec::new();
-    while let Ok(entry) = entry_rx.recv() {
-        entries.push(entry);
+    let mut total_bytes = 0_u64;
+    let mut walk_elapsed = None;
+    // The walk sends its count once when done; after that, stop selecting on it.
+    let mut count_rx = path_count_rx;
+    loop {
+        crossbeam_channel::select! {
+            recv(entry_rx) -> batch => match batch {
+                Ok(batch) => {
+                    total_bytes += batch
+                        .iter()
+                        .filter(|e| e.kind == FileKind::File)
+                        .map(|e| e.size)
+                        .sum::<u64>();
+                    entries.extend(batch);
+                }
+                Err(_) => break,
+            },
+            recv(count_rx) -> _ => {
+                walk_elapsed = Some(started.elapsed());
+                count_rx = crossbeam_channel::never();
+            }
+        }
     }
+    let metadata_elapsed = started.elapsed();
     debug!(
         "main: channel closed, total {} entries (metadata phase done)",
         entries.len()
@@ -175,5 +314,13 @@ pub fn collect_entries(
 
     pipeline::check_for_initial_error_or_skipped_paths(opts, &first_error, &skipped_paths)?;
 
-    Ok((entries, path_count))
+    Ok(pipeline::CollectStats {
+        entries,
+        path_count,
+        total_bytes,
+        // Workers only finish after the walk, so a missed count message means it ended just now.
+        walk_elapsed: walk_elapsed.unwrap_or(metadata_elapsed),
+        metadata_elapsed,
+        nested_indexes: std::mem::take(&mut *nested_indexes.lock().unwrap()),
+    })
 }
@@ -1,17 +1,37 @@
 //! Common walk loop: consumes an iterator of Ok(path) / Err and sends to `path_tx`, handles strict/skipped.
 
 use crossbeam_channel::Sender;
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 
-use crate::engine::tools::should_include_in_walk;
+use crate::SkipReason;
+use crate::engine::tools::{is_dot_name, should_include_in_walk};
+use crate::utils::config::PackagePaths;
 
 use super::context::PipelineContext;
 
 /// One result from a directory walk: either a path to consider or an error with optional path.
 pub enum WalkOutcome {
     Ok(PathBuf),
-    Err { msg: String, path: Option<PathBuf> },
+    Err {
+        msg: String,
+        reason: SkipReason,
+        path: Option<PathBuf>,
+    },
+}
+
+/// Reason for a walk error: a directory cycle, else the kind of the underlying I/O error.
+fn walk_skip_reason(io_error: Option<&std::io::Error>, is_loop: bool) -> SkipReason {
+    if is_loop {
+        return SkipReason::Io("filesystem loop".to_string());
+    }
+    io_error.map_or_else(
+        || SkipReason::from_io_kind(std::io::ErrorKind::Other),
+        |e| SkipReason::from_io_kind(e.kind()),
+    )
 }
 
 /// Convert a jwalk result into [`WalkOutcome`].
@@ -20,6 +40,7 @@ pub fn to_outcome_jwalk(r: Result<jwalk::DirEntry<((), ())>, jwalk::Error>) -> W
         Ok(entry) => WalkOutcome::Ok(entry.path().to_path_buf()),
         Err(err) => WalkOutcome::Err {
             msg: format!("{err}"),
+            reason: walk_skip_reason(err.io_error(), err.loop_ancestor().is_some()),
             path: err.path().map(PathBuf::from),
         },
     }
@@ -31,34 +52,123 @@ pub fn to_outcome_walkdir(r: Result<walkdir::DirEntry, walkdir::Error>) -> WalkO
         Ok(entry) => WalkOutcome::Ok(entry.into_path()),
         Err(err) => WalkOutcome::Err {
             msg: format!("{err}"),
+            reason: walk_skip_reason(err.io_error(), err.loop_ancestor().is_some()),
             path: err.path().map(PathBuf::from),
         },
     }
 }
 
-fn jwalk_iter(ctx: &PipelineContext) -> Box<dyn Iterator<Item = WalkOutcome>> {
+/// Spots directories below the root that hold another index file, so nested trees can be reported
+/// (or pruned) without an extra pass. The root's own index and the active DB are not nested indexes.
+#[derive(Clone)]
+struct NestedIndexScan {
+    root: PathBuf,
+    db_canonical: Option<PathBuf>,
+    found: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl NestedIndexScan {
+    fn new(ctx: &PipelineContext) -> Self {
+        Self {
+            root: ctx.root.clone(),
+            db_canonical: ctx.db_canonical.clone(),
+            found: Arc::clone(&ctx.nested_indexes),
+        }
+    }
+
+    /// Record `dir` when its entry `name` is an index file of another tree. Returns true if recorded.
+    fn record(&self, dir: &Path, name: &OsStr) -> bool {
+        if dir == self.root || name != PackagePaths::get().output_filename() {
+            return false;
+        }
+        let index = dir.join(name);
+        if self.db_canonical.as_deref() == Some(index.as_path()) {
+            return false;
+        }
+        self.found.lock().unwrap().push(dir.to_path_buf());
+        true
+    }
+}
+
+fn jwalk_iter(ctx: &PipelineContext, start: &Path) -> Box<dyn Iterator<Item = WalkOutcome>> {
     use jwalk::Parallelism;
     use std::time::Duration;
-    Box::new(
-        jwalk::WalkDir::new(&ctx.root)
-            .skip_hidden(false) // include dotfiles (e.g. .git); filtering is done in should_include_in_walk
-            .follow_links(ctx.follow_links)
-            .parallelism(Parallelism::RayonDefaultPool {
-                busy_timeout: Duration::from_secs(60),
-            })
-            .into_iter()
-            .map(to_outcome_jwalk),
-    )
+    let skip_dotfiles = ctx.skip_dotfiles;
+    let follow_depth = ctx.follow_depth;
+    let prune_nested = ctx.prune_nested;
+    let nested = NestedIndexScan::new(ctx);
+    let walk = jwalk::WalkDir::new(start)
+        // Dotfiles are dropped below instead, after looking for a nested index among them.
+        .skip_hidden(false)
+        .follow_links(ctx.follow_links)
+        .parallelism(Parallelism::RayonDefaultPool {
+            busy_timeout: Duration::from_secs(60),
+        })
+        .process_read_dir(move |_, dir, _, children| {
+            let holds_index = children
+                .iter()
+                .flatten()
+                .any(|child| nested.record(dir, &child.file_name));
+            if holds_index && prune_nested {
+                children.clear();
+                return;
+            }
+            if skip_dotfiles {
+                // Removed children are neither yielded nor descended.
+                children
+                    .retain(|child| child.as_ref().map_or(true, |c| !is_dot_name(&c.file_name)));
+            }
+            if let Some(max_depth) = follow_depth {
+                // Links deeper than follow_depth are still yielded, just not read.
+                for child in children.iter_mut().flatten() {
+                    if child.path_is_symlink() && child.depth > max_depth {
+                        child.read_children_path = None;
+                    }
+                }
+            }
+        });
+    Box::new(walk.into_iter().map(to_outcome_jwalk))
 }
 
-fn walkdir_iter(ctx: &PipelineContext) -> Box<dyn Iterator<Item = WalkOutcome>> {
+fn walkdir_iter(ctx: &PipelineContext, start: &Path) -> Box<dyn Iterator<Item = WalkOutcome>> {
     use walkdir::WalkDir;
-    Box::new(
-        WalkDir::new(&ctx.root)
-            .follow_links(ctx.follow_links)
-            .into_iter()
-            .map(to_outcome_walkdir),
-    )
+    let skip_dotfiles = ctx.skip_dotfiles;
+    let follow_depth = ctx.follow_depth;
+    let prune_nested = ctx.prune_nested;
+    let nested = NestedIndexScan::new(ctx);
+    let scan = nested.clone();
+    let mut it = WalkDir::new(start)
+        .follow_links(ctx.follow_links)
+        .into_iter()
+        // Prune dot-directories so their subtrees are never enumerated (the start is depth 0).
+        .filter_entry(move |e| {
+            if !prune_nested && let Some(dir) = e.path().parent() {
+                scan.record(dir, e.file_name());
+            }
+            !skip_dotfiles || e.depth() == 0 || !is_dot_name(e.file_name())
+        });
+    let index_name = PackagePaths::get().output_filename();
+    Box::new(std::iter::from_fn(move || {
+        let next = it.next()?;
+        // Entries come depth-first, so a nested index can only be pruned by probing each directory.
+        if prune_nested
+            && let Ok(e) = &next
+            && e.file_type().is_dir()
+            && e.path().join(index_name).is_file()
+            && nested.record(e.path(), OsStr::new(index_name))
+        {
+            it.skip_current_dir();
+        }
+        // Yield a symlinked dir deeper than follow_depth but do not descend into it.
+        if let (Some(max_depth), Ok(e)) = (follow_depth, &next)
+            && e.path_is_symlink()
+            && e.file_type().is_dir()
+            && e.depth() > max_depth
+        {
+            it.skip_current_dir();
+        }
+        Some(to_outcome_walkdir(next))
+    }))
 }
 #[must_use]
 pub fn spawn_walk_thread(
@@ -68,11 +178,19 @@ pub fn spawn_walk_thread(
     parallel_walk: bool,
 ) -> JoinHandle<usize> {
     thread::spawn(move || {
-        let iter: Box<dyn Iterator<Item = WalkOutcome>> = if parallel_walk {
-            jwalk_iter(&ctx)
+        let starts = if ctx.seeds.is_empty() {
+            vec![ctx.root.clone()]
         } else {
-            walkdir_iter(&ctx)
+            ctx.seeds.clone()
         };
+        // One seed after another; each walk starts only when the previous one is drained.
+        let iter = starts.into_iter().flat_map(|start| {
+            if parallel_walk {
+                jwalk_iter(&ctx, &start)
+            } else {
+                walkdir_iter(&ctx, &start)
+            }
+        });
         run_walk_loop(path_tx, &path_count_tx, &ctx, iter, !parallel_walk)
     })
 }
@@ -80,6 +198,8 @@ pub fn spawn_walk_thread(
 /// Run the common walk loop: consume `iter` of [`WalkOutcome`], filter with `should_include_in_walk`,
 /// send included paths to `path_tx`, handle errors (strict → set `first_error` and break; else log and push to `skipped_paths`).
 /// Sends total count on `path_count_tx` and drops `path_tx` when done. Returns the count of paths sent.
+/// With `ctx.max_entries`, stops (and sets `ctx.walk_capped`) at the first included path past the cap.
+/// With `ctx.max_path_len`, over-long paths are left out and recorded in `ctx.too_long_paths`.
 /// When `track_last_path` is true (walkdir/serial), we record the last path seen and use it when an error has no path.
 /// When false (jwalk/parallel), we don't track—avoids cloning on every Ok and "last path" would be nondeterministic anyway.
 ///
@@ -110,14 +230,26 @@ where
                     &ctx.db_canonical,
                     &ctx.temp_canonical,
                     &ctx.exclude,
+                    ctx.skip_dotfiles,
                 ) {
+                    if ctx
+                        .max_path_len
+                        .is_some_and(|max| relative_path_len(&path, &ctx.root) > max)
+                    {
+                        ctx.too_long_paths.lock().unwrap().push(path);
+                        continue;
+                    }
+                    if ctx.max_entries.is_some_and(|max| count >= max) {
+                        ctx.walk_capped.store(true, Ordering::Relaxed);
+                        break;
+                    }
                     if path_tx.send(path).is_err() {
                         break;
                     }
                     count += 1;
                 }
             }
-            WalkOutcome::Err { msg, path } => {
+            WalkOutcome::Err { msg, reason, path } => {
                 if ctx.strict {
                     let _ = ctx.first_error.lock().unwrap().get_or_insert_with(|| msg);
                     break;
@@ -131,11 +263,75 @@ where
                             .map_or_else(|| "<none>".to_string(), |p| p.display().to_string())
                     ))
                 });
-                ctx.skipped_paths.lock().unwrap().push((to_push, msg));
+                ctx.skipped_paths
+                    .lock()
+                    .unwrap()
+                    .push((to_push, reason, msg));
             }
         }
     }
+    warn_nested_indexes(ctx);
+    warn_too_long_paths(ctx);
     let _ = path_count_tx.send(count);
     drop(path_tx);
     count
 }
+
+/// True when the walk stopped at `max_entries` (see [`PipelineContext::walk_capped`]); logs a warning
+/// so the partial result is not mistaken for the whole tree. Read after the entry channel is drained.
+pub fn walk_was_capped(walk_capped: &AtomicBool, max_entries: Option<usize>) -> bool {
+    let capped = walk_capped.load(Ordering::Relaxed);
+    if capped && let Some(max) = max_entries {
+        log::warn!(
+            "Walk stopped after {max} paths (max_entries); the result covers part of the tree"
+        );
+    }
+    capped
+}
+
+/// Length in bytes of `path` relative to `root` (the index key, before any path-key policy).
+fn relative_path_len(path: &Path, root: &Path) -> usize {
+    path.strip_prefix(root).unwrap_or(path).as_os_str().len()
+}
+
+/// Sort the paths skipped for `max_path_len` and log how many there were.
+fn warn_too_long_paths(ctx: &PipelineContext) {
+    let mut too_long = ctx.too_long_paths.lock().unwrap();
+    if too_long.is_empty() {
+        return;
+    }
+    too_long.sort();
+    if let Some(max) = ctx.max_path_len {
+        log::warn!(
+            "Skipped {} path(s) longer than {max} bytes (max_path_len)",
+            too_long.len()
+        );
+    }
+    for path in too_long.iter() {
+        log::debug!("  too long: {}", path.display());
+    }
+}
+
+/// Log the directories found holding their own index (sorted, once each).
+fn warn_nested_indexes(ctx: &PipelineContext) {
+    let mut nested = ctx.nested_indexes.lock().unwrap();
+    if nested.is_empty() {
+        return;
+    }
+    nested.sort();
+    nested.dedup();
+    let list = nested
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if ctx.prune_nested {
+        log::warn!("Skipped {} nested index tree(s): {list}", nested.len());
+    } else {
+        log::warn!(
+            "Found {} nested index tree(s) under {}; they are indexed twice (use --prune-nested to skip them): {list}",
+            nested.len(),
+            ctx.root.display()
+        );
+    }
+}
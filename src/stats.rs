@@ -0,0 +1,168 @@
+//! Walk-only tree profiling: counts, total bytes, and file-size histogram without touching an index.
+
+use anyhow::Result;
+use log::info;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::engine;
+use crate::pipeline::{
+    PipelineHandles, check_for_initial_error_or_skipped_paths, run_pipeline,
+    shutdown_pipeline_handles,
+};
+use crate::utils::config::{
+    NO_EXTENSION, SMALL_FILE_THRESHOLD, STATS_SIZE_BUCKETS, STATS_TOP_EXTENSIONS,
+};
+use crate::{Entry, FileKind, Opts, SizeBucket, TreeProfile};
+
+/// Profile `root` with the same walk + metadata pipeline as indexing, aggregating as entries stream in.
+/// No index is read or written (an in-memory DB is used only for tuning); the index file itself is not counted.
+///
+/// # Errors
+///
+/// Returns [`crate::Error`] when the pipeline cannot start, thread shutdown fails, or strict mode
+/// hits a walk/metadata error.
+pub fn profile_dir(root: &Path, opts: &Opts) -> Result<TreeProfile> {
+    let db_path = engine::create_db_path(root, opts.db_path.as_deref());
+    let conn = engine::open_db_in_memory()?;
+
+    let PipelineHandles {
+        entry_rx,
+        walk_handle,
+        worker_handles,
+        first_error,
+        skipped_paths,
+        ..
+    } = run_pipeline(root, opts, Some(db_path.as_path()), None, &conn)?;
+
+    let mut profile = empty_profile();
+    let mut seen_links = HashSet::new();
+    for entry in entry_rx.iter().flatten() {
+        add_entry_to_profile(&mut profile, &entry, &mut seen_links);
+    }
+
+    shutdown_pipeline_handles(walk_handle, worker_handles)?;
+    check_for_initial_error_or_skipped_paths(opts, &first_error, &skipped_paths)?;
+    Ok(profile)
+}
+
+/// Profile with one zeroed bucket per [`STATS_SIZE_BUCKETS`] bound plus the unbounded tail.
+fn empty_profile() -> TreeProfile {
+    let mut size_histogram: Vec<SizeBucket> = STATS_SIZE_BUCKETS
+        .iter()
+        .map(|&below| SizeBucket {
+            below: Some(below),
+            ..Default::default()
+        })
+        .collect();
+    size_histogram.push(SizeBucket::default());
+    TreeProfile {
+        size_histogram,
+        ..Default::default()
+    }
+}
+
+/// Add one entry to the profile. `seen_links` holds `(dev, ino)` of hard-linked files already counted,
+/// so further links to the same inode add to `files`/`hardlinks` but not to bytes or the histogram.
+fn add_entry_to_profile(
+    profile: &mut TreeProfile,
+    entry: &Entry,
+    seen_links: &mut HashSet<(u64, u64)>,
+) {
+    match entry.kind {
+        FileKind::Dir => profile.dirs += 1,
+        FileKind::Other => profile.other += 1,
+        FileKind::File => {
+            profile.files += 1;
+            if let Some(id) = entry.hardlink_id
+                && !seen_links.insert(id)
+            {
+                profile.hardlinks += 1;
+                return;
+            }
+            profile.total_bytes += entry.size;
+            if entry.size >= SMALL_FILE_THRESHOLD {
+                profile.hash_candidates += 1;
+            } else {
+                profile.below_hash_threshold += 1;
+            }
+            if let Some(bucket) = profile
+                .size_histogram
+                .iter_mut()
+                .find(|b| b.below.is_none_or(|below| entry.size < below))
+            {
+                bucket.count += 1;
+                bucket.bytes += entry.size;
+            }
+            let ext = entry.path.extension().map_or_else(
+                || NO_EXTENSION.to_string(),
+                |e| e.to_string_lossy().to_lowercase(),
+            );
+            let stat = profile.by_extension.entry(ext).or_default();
+            stat.count += 1;
+            stat.bytes += entry.size;
+        }
+    }
+}
+
+/// Log the profile summary and histogram (CLI `--stats`).
+pub fn print_profile(profile: &TreeProfile) {
+    info!("Nefaxing stats results:");
+    info!(
+        "Files: {} | Dirs: {} | Other: {}",
+        profile.files, profile.dirs, profile.other
+    );
+    info!("Total size: {}", engine::format_bytes(profile.total_bytes));
+    if profile.hardlinks > 0 {
+        info!(
+            "Extra hard links (size counted once): {}",
+            profile.hardlinks
+        );
+    }
+    info!(
+        "Would hash (>= {}): {} | Below threshold: {}",
+        engine::format_bytes(SMALL_FILE_THRESHOLD),
+        profile.hash_candidates,
+        profile.below_hash_threshold
+    );
+    info!("File sizes:");
+    let mut lower = 0_u64;
+    for bucket in &profile.size_histogram {
+        let label = match bucket.below {
+            Some(below) => format!(
+                "{} - {}",
+                engine::format_bytes(lower),
+                engine::format_bytes(below)
+            ),
+            None => format!(">= {}", engine::format_bytes(lower)),
+        };
+        info!(
+            "  - {label}: {} files ({})",
+            bucket.count,
+            engine::format_bytes(bucket.bytes)
+        );
+        lower = bucket.below.unwrap_or(lower);
+    }
+    if profile.by_extension.is_empty() {
+        return;
+    }
+    let mut by_size: Vec<_> = profile.by_extension.iter().collect();
+    by_size.sort_unstable_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(b.0)));
+    info!(
+        "By extension (top {} of {} by size):",
+        by_size.len().min(STATS_TOP_EXTENSIONS),
+        by_size.len()
+    );
+    for (ext, stat) in by_size.into_iter().take(STATS_TOP_EXTENSIONS) {
+        let share = if profile.total_bytes == 0 {
+            0.0
+        } else {
+            stat.bytes as f64 * 100.0 / profile.total_bytes as f64
+        };
+        info!(
+            "  - {ext}: {} files ({}, {share:.1}%)",
+            stat.count,
+            engine::format_bytes(stat.bytes)
+        );
+    }
+}
@@ -0,0 +1,384 @@
+//! Index snapshots that may outgrow memory: the [`IndexStore`] trait over a [`Nefax`] map or a
+//! temporary on-disk `SQLite` table, and the builder that spills from one to the other.
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::engine::hashing::TreeDigest;
+use crate::engine::{self, StoredMeta};
+use crate::utils::config::DB_INSERT_BATCH_SIZE;
+use crate::{DbDurability, Nefax, PathMeta};
+
+/// Rows fetched per query while iterating a [`SpilledNefax`].
+const SPILL_PAGE_SIZE: usize = 1000;
+
+/// Read access to an index snapshot (path → [`PathMeta`]), whether it lives in memory or on disk.
+pub trait IndexStore {
+    /// Metadata stored for `path`, or `None` when the path is not in the snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when a disk-backed store cannot be queried.
+    fn get(&self, path: &Path) -> Result<Option<PathMeta>>;
+
+    /// Number of paths in the snapshot.
+    fn len(&self) -> usize;
+
+    /// True when the snapshot holds no paths.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every `(path, meta)` in the snapshot. Order is unspecified for a map; a disk-backed store
+    /// yields paths sorted and reads them in pages, so iterating does not load the whole snapshot.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(PathBuf, PathMeta)>> + '_>;
+}
+
+impl IndexStore for Nefax {
+    fn get(&self, path: &Path) -> Result<Option<PathMeta>> {
+        Ok(Self::get(self, path).cloned())
+    }
+
+    fn len(&self) -> usize {
+        Self::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(PathBuf, PathMeta)>> + '_> {
+        Box::new(Self::iter(self).map(|(p, m)| Ok((p.clone(), m.clone()))))
+    }
+}
+
+/// The spill DB file. Created with [`tempfile`] (new file, owner-only permissions) so nothing planted
+/// in the shared temp dir is opened and other users cannot read the spilled paths; removed with its
+/// WAL/SHM files when dropped.
+struct SpillFile(tempfile::TempPath);
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        for suffix in ["-wal", "-shm"] {
+            let mut name = self.0.as_os_str().to_owned();
+            name.push(suffix);
+            let _ = std::fs::remove_file(name);
+        }
+    }
+}
+
+/// [`PathMeta`] from the `mtime_ns, size, hash, btime_ns, symlink_target, xattr_hash` columns starting at index
+/// `first`.
+fn meta_from_row(row: &rusqlite::Row<'_>, first: usize) -> rusqlite::Result<PathMeta> {
+    let size: i64 = row.get(first + 1)?;
+    let hash: Option<Vec<u8>> = row.get(first + 2)?;
+    let stored: StoredMeta = (row.get(first)?, size.max(0) as u64, hash);
+    Ok(PathMeta {
+        btime_ns: row.get(first + 3)?,
+        symlink_target: row.get::<_, Option<String>>(first + 4)?.map(PathBuf::from),
+        xattr_hash: row
+            .get::<_, Option<Vec<u8>>>(first + 5)?
+            .and_then(|h| h.try_into().ok()),
+        ..PathMeta::from(&stored)
+    })
+}
+
+/// Index snapshot backed by a temporary `SQLite` file in [`std::env::temp_dir`], deleted on drop.
+/// Produced by [`nefax_dir_store`](crate::nefax_dir_store) when the tree has more entries than
+/// [`NefaxOpts::max_entries_in_memory`](crate::NefaxOpts::max_entries_in_memory).
+pub struct SpilledNefax {
+    // Declared before `file` so the connection closes before the file is removed.
+    conn: Connection,
+    file: SpillFile,
+    len: usize,
+}
+
+impl SpilledNefax {
+    fn create() -> Result<Self> {
+        let path = tempfile::Builder::new()
+            .prefix("nefaxer-spill-")
+            .suffix(".db")
+            .tempfile()
+            .context("create spill file")?
+            .into_temp_path();
+        let file = SpillFile(path);
+        let conn = engine::open_db(&file.0, None)?;
+        engine::apply_durability(&conn, DbDurability::Fast)?;
+        Ok(Self { conn, file, len: 0 })
+    }
+
+    /// Path of the temporary DB file (same schema as a `.nefaxer` index).
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.file.0
+    }
+
+    fn insert_batch(&mut self, batch: &[(PathBuf, PathMeta)]) -> Result<()> {
+        let tx = self.conn.transaction().context("begin spill transaction")?;
+        {
+            let mut stmt = tx
+                .prepare_cached("INSERT OR REPLACE INTO paths (path, mtime_ns, size, hash, btime_ns, symlink_target, xattr_hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+                .context("prepare spill insert")?;
+            for (path, meta) in batch {
+                stmt.execute(rusqlite::params![
+                    engine::path_to_db_string(path),
+                    meta.mtime_ns,
+                    meta.size as i64,
+                    meta.hash.as_ref().map(<[u8; 32]>::as_slice),
+                    meta.btime_ns,
+                    meta.symlink_target
+                        .as_deref()
+                        .map(engine::path_to_db_string),
+                    meta.xattr_hash.as_ref().map(<[u8; 32]>::as_slice),
+                ])
+                .context("insert spilled entry")?;
+            }
+        }
+        tx.commit().context("commit spill transaction")
+    }
+
+    /// Up to [`SPILL_PAGE_SIZE`] rows sorted by path, starting after `after`.
+    fn page(&self, after: Option<&str>) -> Result<Vec<(String, PathMeta)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT path, mtime_ns, size, hash, btime_ns, symlink_target, xattr_hash FROM paths WHERE ?1 IS NULL OR path > ?1 ORDER BY path LIMIT ?2",
+            )
+            .context("prepare spill page query")?;
+        let rows = stmt
+            .query_map(rusqlite::params![after, SPILL_PAGE_SIZE as i64], |row| {
+                Ok((row.get(0)?, meta_from_row(row, 1)?))
+            })
+            .context("query spill page")?;
+        rows.collect::<rusqlite::Result<_>>()
+            .context("read spill page")
+    }
+}
+
+impl IndexStore for SpilledNefax {
+    fn get(&self, path: &Path) -> Result<Option<PathMeta>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT mtime_ns, size, hash, btime_ns, symlink_target, xattr_hash FROM paths WHERE path = ?1",
+            )
+            .context("prepare spill lookup")?;
+        stmt.query_row([engine::path_to_db_string(path)], |row| {
+            meta_from_row(row, 0)
+        })
+        .optional()
+        .context("look up spilled entry")
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(PathBuf, PathMeta)>> + '_> {
+        Box::new(SpillIter {
+            store: self,
+            buf: VecDeque::new(),
+            last: None,
+            done: false,
+        })
+    }
+}
+
+/// Pages through a [`SpilledNefax`] by path (keyset pagination), holding one page at a time.
+struct SpillIter<'a> {
+    store: &'a SpilledNefax,
+    buf: VecDeque<(String, PathMeta)>,
+    last: Option<String>,
+    done: bool,
+}
+
+impl Iterator for SpillIter<'_> {
+    type Item = Result<(PathBuf, PathMeta)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() && !self.done {
+            match self.store.page(self.last.as_deref()) {
+                Ok(page) => {
+                    self.done = page.len() < SPILL_PAGE_SIZE;
+                    self.buf.extend(page);
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let (path, meta) = self.buf.pop_front()?;
+        let item = (PathBuf::from(&path), meta);
+        self.last = Some(path);
+        Some(Ok(item))
+    }
+}
+
+/// Snapshot returned by [`nefax_dir_store`](crate::nefax_dir_store): an in-memory [`Nefax`] when
+/// the tree fit under the cap, otherwise a [`SpilledNefax`].
+pub enum NefaxStore {
+    /// Held in memory (at most the cap's worth of entries, or no cap).
+    Memory(Nefax),
+    /// Moved to a temporary on-disk DB.
+    Spilled(SpilledNefax),
+}
+
+impl NefaxStore {
+    /// True when the snapshot was moved to a temporary on-disk DB.
+    #[must_use]
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, Self::Spilled(_))
+    }
+
+    /// Collect the snapshot into a [`Nefax`] map (loads a spilled snapshot fully into memory).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when reading a spilled snapshot fails.
+    pub fn into_nefax(self) -> Result<Nefax> {
+        match self {
+            Self::Memory(map) => Ok(map),
+            Self::Spilled(spilled) => spilled.iter().collect(),
+        }
+    }
+
+    /// Blake3 digest of the snapshot; see [`crate::tree_digest`]. A spilled snapshot is read in
+    /// pages, already in digest order, rather than loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when reading a spilled snapshot fails.
+    pub fn tree_digest(&self) -> Result<[u8; 32]> {
+        match self {
+            Self::Memory(map) => Ok(crate::tree_digest(map)),
+            Self::Spilled(spilled) => {
+                let mut digest = TreeDigest::new(spilled.len());
+                for row in spilled.iter() {
+                    let (path, meta) = row?;
+                    digest.row(
+                        &engine::path_to_db_string(&path),
+                        meta.mtime_ns,
+                        meta.size,
+                        meta.hash.as_ref().map(<[u8; 32]>::as_slice),
+                    );
+                }
+                Ok(digest.finish())
+            }
+        }
+    }
+}
+
+impl IndexStore for NefaxStore {
+    fn get(&self, path: &Path) -> Result<Option<PathMeta>> {
+        match self {
+            Self::Memory(map) => IndexStore::get(map, path),
+            Self::Spilled(spilled) => spilled.get(path),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Memory(map) => map.len(),
+            Self::Spilled(spilled) => spilled.len(),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(PathBuf, PathMeta)>> + '_> {
+        match self {
+            Self::Memory(map) => IndexStore::iter(map),
+            Self::Spilled(spilled) => spilled.iter(),
+        }
+    }
+}
+
+/// Collects the current snapshot during a diff: a map until it holds more than `cap` entries, then
+/// a [`SpilledNefax`] fed in batches. A spill error is kept and returned by [`Self::finish`], since
+/// the diff loop itself cannot fail.
+pub(crate) struct StoreBuilder {
+    cap: Option<usize>,
+    map: Nefax,
+    spilled: Option<SpilledNefax>,
+    pending: Vec<(PathBuf, PathMeta)>,
+    error: Option<anyhow::Error>,
+}
+
+impl StoreBuilder {
+    /// Builder that never spills.
+    pub(crate) fn in_memory() -> Self {
+        Self::with_cap(None)
+    }
+
+    pub(crate) fn with_cap(cap: Option<usize>) -> Self {
+        Self {
+            cap,
+            map: Nefax::new(),
+            spilled: None,
+            pending: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, path: PathBuf, meta: PathMeta) {
+        if self.error.is_some() {
+            return;
+        }
+        if self.spilled.is_none() {
+            self.map.insert(path, meta);
+            if self.cap.is_some_and(|cap| self.map.len() > cap) {
+                self.spill();
+            }
+            return;
+        }
+        self.pending.push((path, meta));
+        if self.pending.len() >= DB_INSERT_BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    /// Move the map into a new spill DB.
+    fn spill(&mut self) {
+        log::debug!(
+            "Index exceeded {} entries in memory; spilling to a temporary DB",
+            self.cap.unwrap_or_default()
+        );
+        self.pending.extend(std::mem::take(&mut self.map));
+        match SpilledNefax::create() {
+            Ok(spilled) => self.spilled = Some(spilled),
+            Err(e) => self.error = Some(e),
+        }
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        if let Some(spilled) = self.spilled.as_mut()
+            && !self.pending.is_empty()
+        {
+            for batch in self.pending.chunks(DB_INSERT_BATCH_SIZE) {
+                if let Err(e) = spilled.insert_batch(batch) {
+                    self.error = Some(e);
+                    break;
+                }
+            }
+            self.pending.clear();
+        }
+    }
+
+    /// The map, for callers built with [`Self::in_memory`] (which cannot spill).
+    pub(crate) fn into_map(self) -> Nefax {
+        self.map
+    }
+
+    pub(crate) fn finish(mut self) -> Result<NefaxStore> {
+        self.flush();
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        match self.spilled {
+            Some(mut spilled) => {
+                spilled.len =
+                    engine::path_count_from_db(&spilled.conn).context("count spilled entries")?;
+                Ok(NefaxStore::Spilled(spilled))
+            }
+            None => Ok(NefaxStore::Memory(self.map)),
+        }
+    }
+}
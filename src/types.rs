@@ -1,17 +1,59 @@
 //! Public and internal types for the nefaxer API and pipeline.
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 
-/// Metadata for a single path (file or dir). Dirs have size 0 and no hash.
-#[derive(Clone, Debug)]
+/// Kind of filesystem object an [`Entry`] was read from (after following symlinks when enabled).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FileKind {
+    #[default]
+    File,
+    Dir,
+    /// Anything else (FIFO, socket, device, ...).
+    Other,
+}
+
+/// Metadata for a single path (file or dir). Dirs have no hash.
+#[derive(Clone, Debug, Default)]
 pub struct Entry {
+    /// Index key: the path as walked, rewritten by the run's `path_key`.
     pub path: PathBuf,
+    /// The path as found on disk when `path` was rewritten from it; `None` when they are the same.
+    /// Files are opened and reported through [`Self::fs_path`].
+    pub disk_path: Option<PathBuf>,
     pub mtime_ns: i64,
     pub size: u64,
     pub hash: Option<[u8; 32]>,
+    /// Not stored in the index; set by the walk so consumers can tell files from dirs.
+    pub kind: FileKind,
+    /// `(device, inode)` when the file has more than one hard link (Unix only; not stored in the index).
+    /// Lets size aggregation count shared data once.
+    pub hardlink_id: Option<(u64, u64)>,
+    /// Creation time in nanoseconds since epoch, with `track_btime` on a platform/filesystem that
+    /// reports it; otherwise `None`.
+    pub btime_ns: Option<i64>,
+    /// Target of the symlink this path is, as read from the link, when following links; `None` for
+    /// paths that are not symlinks or when links are not followed.
+    pub symlink_target: Option<PathBuf>,
+    /// Hash of the extended attributes with `track_xattrs` on Linux/macOS (see
+    /// [`xattr_hash`](crate::engine::xattr_hash)); otherwise `None`.
+    pub xattr_hash: Option<[u8; 32]>,
+}
+
+impl Entry {
+    /// The path to open this entry under (relative to the root unless stored absolute).
+    #[must_use]
+    pub fn fs_path(&self) -> &Path {
+        self.disk_path.as_deref().unwrap_or(&self.path)
+    }
+
+    /// [`Self::fs_path`] by value, for diff lists.
+    #[must_use]
+    pub fn into_fs_path(self) -> PathBuf {
+        self.disk_path.unwrap_or(self.path)
+    }
 }
 
 /// Metadata for one path in the index (same shape as a row in the `.nefaxer` DB).
@@ -25,14 +67,748 @@ pub struct PathMeta {
     pub size: u64,
     /// Blake3 hash (32 bytes), or `None` if not computed.
     pub hash: Option<[u8; 32]>,
+    /// Creation time in nanoseconds since epoch (`track_btime`), or `None` when not tracked or not
+    /// reported by the platform. Not part of [`Self::effectively_equal`].
+    pub btime_ns: Option<i64>,
+    /// Link target when the path is a symlink and links were followed (the other fields describe
+    /// what it points to). Not part of [`Self::effectively_equal`].
+    pub symlink_target: Option<PathBuf>,
+    /// Hash of the extended attributes (`track_xattrs`), or `None` when not tracked or not
+    /// supported. Not part of [`Self::effectively_equal`].
+    pub xattr_hash: Option<[u8; 32]>,
+}
+
+impl PathMeta {
+    /// True when `other` describes the same content: equal size and hash, and mtimes within
+    /// `window_ns` (see [`mtime_changed`](crate::engine::mtime_changed)).
+    #[must_use]
+    pub fn effectively_equal(&self, other: &PathMeta, window_ns: i64) -> bool {
+        self.effectively_equal_with_granularity(other, window_ns, 0)
+    }
+
+    /// Like [`Self::effectively_equal`], with mtimes compared at `granularity_ns` resolution
+    /// (see [`mtime_changed_with_granularity`](crate::engine::mtime_changed_with_granularity)).
+    #[must_use]
+    pub fn effectively_equal_with_granularity(
+        &self,
+        other: &PathMeta,
+        window_ns: i64,
+        granularity_ns: i64,
+    ) -> bool {
+        self.size == other.size
+            && self.hash == other.hash
+            && !crate::engine::mtime_changed_with_granularity(
+                self.mtime_ns,
+                other.mtime_ns,
+                window_ns,
+                granularity_ns,
+            )
+    }
+}
+
+impl From<&Entry> for PathMeta {
+    fn from(entry: &Entry) -> Self {
+        Self {
+            mtime_ns: entry.mtime_ns,
+            size: entry.size,
+            hash: entry.hash,
+            btime_ns: entry.btime_ns,
+            symlink_target: entry.symlink_target.clone(),
+            xattr_hash: entry.xattr_hash,
+        }
+    }
+}
+
+impl From<&crate::engine::StoredMeta> for PathMeta {
+    /// A stored hash that is not 32 bytes is treated as absent.
+    fn from((mtime_ns, size, hash): &crate::engine::StoredMeta) -> Self {
+        Self {
+            mtime_ns: *mtime_ns,
+            size: *size,
+            hash: hash.as_deref().and_then(|h| h.try_into().ok()),
+            btime_ns: None,
+            symlink_target: None,
+            xattr_hash: None,
+        }
+    }
+}
+
+/// Rule deciding whether a path present in both the index and the walk has changed.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ChangePolicy {
+    /// Mtime (outside `mtime_window_ns`), size, or hash differ.
+    #[default]
+    MtimeSize,
+    /// Only a size change counts; mtime and hash are ignored.
+    SizeOnly,
+    /// Only a content change counts: hashes are always recomputed (never reused from the index) and
+    /// mtimes are ignored. Implies `with_hash`. Entries without a hash on either side (dirs, files
+    /// below the hashing threshold) fall back to [`Self::MtimeSize`].
+    HashOnly,
+    /// Caller rule: `(current entry, stored (mtime_ns, size, hash), mtime_window_ns)`, true = changed.
+    /// Mtime granularity is not applied; the rule sees raw mtimes.
+    Custom(fn(&Entry, &crate::engine::StoredMeta, i64) -> bool),
+}
+
+impl ChangePolicy {
+    /// True when `entry` differs from its `stored` index row under this policy. Mtimes are compared
+    /// with [`mtime_changed_with_granularity`](crate::engine::mtime_changed_with_granularity).
+    #[must_use]
+    pub fn is_changed(
+        &self,
+        entry: &Entry,
+        stored: &crate::engine::StoredMeta,
+        mtime_window_ns: i64,
+        mtime_granularity_ns: i64,
+    ) -> bool {
+        let (_, old_size, old_hash) = stored;
+        match self {
+            Self::MtimeSize => !PathMeta::from(entry).effectively_equal_with_granularity(
+                &PathMeta::from(stored),
+                mtime_window_ns,
+                mtime_granularity_ns,
+            ),
+            Self::SizeOnly => entry.size != *old_size,
+            Self::HashOnly if entry.hash.is_none() && old_hash.is_none() => {
+                Self::MtimeSize.is_changed(entry, stored, mtime_window_ns, mtime_granularity_ns)
+            }
+            Self::HashOnly => !crate::engine::hash_equals(&entry.hash, old_hash),
+            Self::Custom(changed) => changed(entry, stored, mtime_window_ns),
+        }
+    }
+
+    /// Whether a stored hash may be reused when mtime and size are unchanged (false for [`Self::HashOnly`]).
+    #[must_use]
+    pub fn reuses_hashes(&self) -> bool {
+        !matches!(self, Self::HashOnly)
+    }
+
+    /// Name accepted by `--change-policy` and `.nefaxer.toml`; `None` for `Custom` (lib-only).
+    #[must_use]
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::MtimeSize => Some("mtime-size"),
+            Self::SizeOnly => Some("size-only"),
+            Self::HashOnly => Some("hash-only"),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+impl std::str::FromStr for ChangePolicy {
+    type Err = String;
+
+    /// Parse `mtime-size`, `size-only`, or `hash-only` (`Custom` is lib-only).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mtime-size" => Ok(Self::MtimeSize),
+            "size-only" => Ok(Self::SizeOnly),
+            "hash-only" => Ok(Self::HashOnly),
+            other => Err(format!(
+                "unknown change policy {other:?} (expected mtime-size, size-only, or hash-only)"
+            )),
+        }
+    }
+}
+
+/// Format of the CLI's log lines (see [`setup_logging`](crate::utils::setup_logging)).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `[nefaxer] msg` with ANSI colors, for terminals.
+    #[default]
+    Pretty,
+    /// One `{"level","target","msg","ts"}` JSON object per line, no ANSI; `ts` is Unix milliseconds.
+    /// For log aggregation.
+    Json,
+}
+
+impl LogFormat {
+    /// Name accepted by `--log-format` and `.nefaxer.toml`.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pretty => "pretty",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    /// Parse `pretty` or `json`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            other => Err(format!(
+                "unknown log format {other:?} (expected pretty or json)"
+            )),
+        }
+    }
+}
+
+/// Line format of `--ls` (see [`list_index`](crate::engine::list_index)).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListFormat {
+    /// One path per line.
+    #[default]
+    Plain,
+    /// `path<TAB>size<TAB>mtime_ns` per line.
+    Tsv,
+}
+
+impl std::str::FromStr for ListFormat {
+    type Err = String;
+
+    /// Parse `plain` or `tsv`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Self::Plain),
+            "tsv" => Ok(Self::Tsv),
+            other => Err(format!(
+                "unknown list format {other:?} (expected plain or tsv)"
+            )),
+        }
+    }
+}
+
+/// How hard the index DB works to survive a crash or power loss, traded against write speed. Selects
+/// the `synchronous` level and WAL auto-checkpoint interval of the connection that writes the index.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DbDurability {
+    /// `synchronous = OFF`, rare checkpoints: fastest; a power loss (not a crash of nefaxer) can
+    /// corrupt the index. For battery-backed or throwaway storage.
+    Fast,
+    /// `synchronous = NORMAL`: the last commits may be lost on power loss, but the index stays
+    /// consistent.
+    #[default]
+    Normal,
+    /// `synchronous = FULL`, frequent checkpoints: every commit is durable; slowest. For unreliable
+    /// storage.
+    Safe,
+}
+
+impl DbDurability {
+    /// Name accepted by `--durability` and `.nefaxer.toml`.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Fast => "fast",
+            Self::Normal => "normal",
+            Self::Safe => "safe",
+        }
+    }
+}
+
+impl std::str::FromStr for DbDurability {
+    type Err = String;
+
+    /// Parse `fast`, `normal`, or `safe`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(Self::Fast),
+            "normal" => Ok(Self::Normal),
+            "safe" => Ok(Self::Safe),
+            other => Err(format!(
+                "unknown durability {other:?} (expected fast, normal, or safe)"
+            )),
+        }
+    }
+}
+
+/// How an entry path is canonicalized into its index key (see `path_key` on [`Opts`]). Applied after
+/// separators are normalized to `/`, on write and on every lookup, so both sides of a comparison use
+/// the same form. Only the key is rewritten: entries keep their on-disk name in
+/// [`Entry::disk_path`], which is what is opened, hashed, and reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathKeyPolicy {
+    /// Keep the path as walked.
+    #[default]
+    AsIs,
+    /// Lowercase the whole path, so `Readme.md` and `README.md` share a key. Meant for
+    /// case-insensitive filesystems; on a case-sensitive one, names differing only by case collide
+    /// (an index run warns and keeps one row).
+    Lowercase,
+    /// Unicode NFC (composed, `é` as one code point): what Linux and Windows tools usually write.
+    Nfc,
+    /// Unicode NFD (decomposed, `e` + combining accent): how HFS+ stores names on macOS.
+    Nfd,
+}
+
+impl PathKeyPolicy {
+    /// Name recorded in the index `meta` table and accepted by `--path-key`.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::AsIs => "as-is",
+            Self::Lowercase => "lowercase",
+            Self::Nfc => "nfc",
+            Self::Nfd => "nfd",
+        }
+    }
+
+    /// `s` under this policy.
+    #[must_use]
+    pub fn apply(self, s: String) -> String {
+        use unicode_normalization::UnicodeNormalization;
+        match self {
+            Self::AsIs => s,
+            Self::Lowercase => s.to_lowercase(),
+            Self::Nfc => s.nfc().collect(),
+            Self::Nfd => s.nfd().collect(),
+        }
+    }
+}
+
+impl std::str::FromStr for PathKeyPolicy {
+    type Err = String;
+
+    /// Parse `as-is`, `lowercase`, `nfc`, or `nfd` (`unicode-nfc` is read as `nfc`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "as-is" => Ok(Self::AsIs),
+            "lowercase" => Ok(Self::Lowercase),
+            "nfc" | "unicode-nfc" => Ok(Self::Nfc),
+            "nfd" => Ok(Self::Nfd),
+            other => Err(format!(
+                "unknown path key policy {other:?} (expected as-is, lowercase, nfc, or nfd)"
+            )),
+        }
+    }
+}
+
+/// Checksum algorithm of a `<hex>  <path>` manifest read by
+/// [`verify_manifest`](crate::check::verify_manifest): `sha256sum` output (`SHA256SUMS`) or `b3sum`
+/// output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ManifestFormat {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl std::str::FromStr for ManifestFormat {
+    type Err = String;
+
+    /// Parse `sha256` or `blake3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            other => Err(format!(
+                "unknown manifest format {other:?} (expected sha256 or blake3)"
+            )),
+        }
+    }
 }
 
 /// Result of comparing a directory to an existing index.
+///
+/// Paths are the names on disk (what scripts and `--print0` act on), which differ from the index
+/// keys only under a rewriting `path_key`.
 #[derive(Default)]
 pub struct Diff {
     pub added: Vec<PathBuf>,
     pub removed: Vec<PathBuf>,
     pub modified: Vec<PathBuf>,
+    /// Files whose content no longer matches the stored hash although mtime and size are unchanged
+    /// (bit rot / silent corruption). Only filled by check with `verify_sample`.
+    pub corrupted: Vec<PathBuf>,
+    /// Files renamed only by letter case, as `(stored, walked)` (e.g. `Readme.md` → `README.md`).
+    /// Only filled on case-insensitive platforms; see [`Diff::pair_case_only_renames`].
+    pub case_changed: Vec<(PathBuf, PathBuf)>,
+    /// Files whose hash and size match the index and only mtime moved (e.g. `touch`). Only filled by
+    /// check with `with_hash` and `report_touched`; not counted as changes by [`Diff::total`].
+    pub touched: Vec<PathBuf>,
+    /// Files that could not be read to compare their content, so are neither shown equal nor
+    /// modified. Only filled by [`verify_manifest`](crate::check::verify_manifest).
+    pub unreadable: Vec<PathBuf>,
+}
+
+impl Diff {
+    /// Number of reported paths: added + removed + modified + corrupted + case-changed + unreadable.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.added.len()
+            + self.removed.len()
+            + self.modified.len()
+            + self.corrupted.len()
+            + self.case_changed.len()
+            + self.unreadable.len()
+    }
+
+    /// True when no path was added, removed, modified, renamed by case, found corrupted, or unreadable.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+
+    /// Move each added path whose spelling differs from a removed path only by letter case into
+    /// `case_changed`. The index keeps paths case-sensitively, so such a rename otherwise shows up as
+    /// an add plus a remove. No-op unless [`CASE_INSENSITIVE_PATHS`](crate::utils::config::CASE_INSENSITIVE_PATHS):
+    /// on a case-sensitive filesystem both spellings are distinct files.
+    pub fn pair_case_only_renames(&mut self) {
+        if !crate::utils::config::CASE_INSENSITIVE_PATHS
+            || self.added.is_empty()
+            || self.removed.is_empty()
+        {
+            return;
+        }
+        let fold = |p: &Path| p.to_string_lossy().to_lowercase();
+        let mut removed_by_fold: HashMap<String, PathBuf> = HashMap::new();
+        let mut removed = Vec::new();
+        for p in self.removed.drain(..) {
+            match removed_by_fold.entry(fold(&p)) {
+                std::collections::hash_map::Entry::Vacant(v) => {
+                    v.insert(p);
+                }
+                std::collections::hash_map::Entry::Occupied(_) => removed.push(p),
+            }
+        }
+        let mut added = Vec::new();
+        for p in std::mem::take(&mut self.added) {
+            match removed_by_fold.remove(&fold(&p)) {
+                Some(old) => self.case_changed.push((old, p)),
+                None => added.push(p),
+            }
+        }
+        removed.extend(removed_by_fold.into_values());
+        self.added = added;
+        self.removed = removed;
+    }
+
+    /// POSIX `sh` script that applies this diff to a copy of the tree (run from the copy's root):
+    /// removed paths are deleted (directories with `rmdir`, deepest first, so each is empty by then),
+    /// case-only renames are moved, and added / modified / corrupted / unreadable paths are only logged (their
+    /// content has to come from the original). Each path is single-quoted, so spaces, quotes, and `$`
+    /// are taken literally. Names that are not UTF-8 or hold control characters are written byte for
+    /// byte as `$'…'` with `\xHH` escapes; a script with any starts with `#!/usr/bin/env bash`,
+    /// since not every `sh` reads that form.
+    #[must_use]
+    pub fn to_shell_script(&self, style: ScriptStyle) -> String {
+        use std::fmt::Write;
+        let (rm, mv, prefix) = match style {
+            ScriptStyle::Interactive => ("rm -i --", "mv -i --", ""),
+            ScriptStyle::Force => ("rm -f --", "mv -f --", ""),
+            ScriptStyle::LogOnly => ("rm --", "mv --", "# "),
+        };
+        let mut removed: Vec<&PathBuf> = self.removed.iter().collect();
+        removed.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+        let needs_bash = self
+            .removed
+            .iter()
+            .chain(self.case_changed.iter().flat_map(|(old, new)| [old, new]))
+            .chain(&self.added)
+            .chain(&self.modified)
+            .chain(&self.corrupted)
+            .chain(&self.unreadable)
+            .any(|p| shell_quote(p).starts_with('$'));
+        let shell = if needs_bash {
+            "/usr/bin/env bash"
+        } else {
+            "/bin/sh"
+        };
+        let mut s = format!("#!{shell}\n# Generated by nefaxer. Review before running.\n");
+        let _ = writeln!(s, "\n# Removed ({})", removed.len());
+        if !removed.is_empty() {
+            // Whether a path is a directory is only known on the copy.
+            let _ = writeln!(
+                s,
+                r#"{prefix}remove() {{ if [ -d "$1" ] && [ ! -L "$1" ]; then rmdir -- "$1"; else {rm} "$1"; fi; }}"#
+            );
+        }
+        for p in removed {
+            let _ = writeln!(s, "{prefix}remove {}", shell_quote(p));
+        }
+        let _ = writeln!(s, "\n# Case changed ({})", self.case_changed.len());
+        for (old, new) in &self.case_changed {
+            let _ = writeln!(s, "{prefix}{mv} {} {}", shell_quote(old), shell_quote(new));
+        }
+        let logged = [
+            ("Added", "added", &self.added),
+            ("Modified", "modified", &self.modified),
+            ("Corrupted", "corrupted", &self.corrupted),
+            ("Unreadable", "unreadable", &self.unreadable),
+        ];
+        for (heading, label, paths) in logged {
+            let _ = writeln!(s, "\n# {heading} ({})", paths.len());
+            for p in paths {
+                let _ = writeln!(s, "printf '{label}: %s\\n' {}", shell_quote(p));
+            }
+        }
+        s
+    }
+}
+
+/// How [`Diff::to_shell_script`] writes the destructive actions (removals and case-only renames).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScriptStyle {
+    /// `rm -i` / `mv -i`: the shell asks before each action.
+    #[default]
+    Interactive,
+    /// `rm -f` / `mv -f`: no prompts.
+    Force,
+    /// Removals and renames are only listed as comments; the script just logs changes.
+    LogOnly,
+}
+
+/// Single-quote `path` for `sh`: `'` becomes `'\''`; everything else is literal inside quotes. A
+/// name that is not UTF-8 or holds a control character is written as `$'…'` instead, with every
+/// byte outside printable ASCII as `\xHH`.
+fn shell_quote(path: &Path) -> String {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    match std::str::from_utf8(bytes) {
+        Ok(s) if !s.chars().any(char::is_control) => format!("'{}'", s.replace('\'', r"'\''")),
+        _ => {
+            let mut quoted = String::from("$'");
+            for &b in bytes {
+                match b {
+                    b'\'' | b'\\' => {
+                        quoted.push('\\');
+                        quoted.push(char::from(b));
+                    }
+                    0x20..=0x7e => quoted.push(char::from(b)),
+                    _ => quoted.push_str(&format!("\\x{b:02x}")),
+                }
+            }
+            quoted.push('\'');
+            quoted
+        }
+    }
+}
+
+/// One row to write in a [`Patch`]: an index key (forward slashes) with its new metadata.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PatchEntry {
+    pub path: String,
+    pub size: u64,
+    pub mtime_ns: i64,
+    /// Lowercase hex blake3, or `None` when not hashed.
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub btime_ns: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink_target: Option<String>,
+    /// Lowercase hex of the extended-attribute hash (`track_xattrs`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xattr_hash: Option<String>,
+}
+
+/// Index changes between two runs, to bring another copy of the index up to date without walking
+/// its tree (see [`diff_to_patch`] and [`apply_patch`](crate::engine::apply_patch)). Serializes
+/// with serde, e.g. to JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Patch {
+    /// Rows to insert or replace (added, modified, and touched paths, and new spellings of case-only renames), sorted by path.
+    pub upsert: Vec<PatchEntry>,
+    /// Index keys to delete (removed paths and old spellings of case-only renames), sorted.
+    pub remove: Vec<String>,
+}
+
+impl Patch {
+    /// True when the patch changes nothing.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.upsert.is_empty() && self.remove.is_empty()
+    }
+}
+
+/// Build a [`Patch`] from `diff` and the index it produced (`new_index`, e.g. the [`Nefax`]
+/// returned with the diff by [`nefax_dir`](crate::nefax_dir)). `path_key` is the policy the run
+/// used: the diff holds on-disk names, which it maps to the index keys `new_index` and the patch
+/// use. Changed paths missing from `new_index` are left out; corrupted paths keep their stored row
+/// and are not included.
+#[must_use]
+pub fn diff_to_patch(diff: &Diff, new_index: &Nefax, path_key: PathKeyPolicy) -> Patch {
+    let key = |p: &Path| crate::engine::path_to_db_key(p, path_key);
+    let changed = diff
+        .added
+        .iter()
+        .chain(&diff.modified)
+        .chain(&diff.touched)
+        .chain(diff.case_changed.iter().map(|(_, new)| new));
+    let mut upsert: Vec<PatchEntry> = changed
+        .filter_map(|p| {
+            let path = key(p);
+            let meta = new_index.get(Path::new(&path))?;
+            Some(PatchEntry {
+                path,
+                size: meta.size,
+                mtime_ns: meta.mtime_ns,
+                hash: meta.hash.map(|h| crate::engine::hex_encode(&h)),
+                btime_ns: meta.btime_ns,
+                symlink_target: meta
+                    .symlink_target
+                    .as_deref()
+                    .map(crate::engine::path_to_db_string),
+                xattr_hash: meta.xattr_hash.map(|h| crate::engine::hex_encode(&h)),
+            })
+        })
+        .collect();
+    upsert.sort_by(|a, b| a.path.cmp(&b.path));
+    upsert.dedup_by(|a, b| a.path == b.path);
+    let mut remove: Vec<String> = diff
+        .removed
+        .iter()
+        .chain(diff.case_changed.iter().map(|(old, _)| old))
+        .map(|p| key(p))
+        .collect();
+    remove.sort();
+    remove.dedup();
+    Patch { upsert, remove }
+}
+
+/// Count and total bytes of files in one size bucket of [`TreeProfile::size_histogram`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeBucket {
+    /// Exclusive upper bound in bytes; `None` for the last (unbounded) bucket.
+    pub below: Option<u64>,
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Files and bytes of one extension in [`TreeProfile::by_extension`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExtensionStat {
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Walk-only profile of a tree (see [`profile_dir`](crate::stats::profile_dir)): counts, bytes, size
+/// histogram, and a breakdown by extension.
+#[derive(Clone, Debug, Default)]
+pub struct TreeProfile {
+    pub files: usize,
+    pub dirs: usize,
+    /// Non-regular, non-directory entries (FIFO, socket, device, ...).
+    pub other: usize,
+    /// Sum of file sizes (directories not included). Hard-linked data is counted once.
+    pub total_bytes: u64,
+    /// Files that are additional hard links to data already counted (not added to `total_bytes` or
+    /// the histogram). Reflinks/clones are separate inodes and are not detected.
+    pub hardlinks: usize,
+    /// Files at or above the hashing threshold (hashed when `with_hash`).
+    pub hash_candidates: usize,
+    /// Files below the hashing threshold (compared by mtime/size only).
+    pub below_hash_threshold: usize,
+    pub size_histogram: Vec<SizeBucket>,
+    /// Lowercased extension (without the dot) → files and bytes; files without one are under
+    /// [`NO_EXTENSION`](crate::utils::config::NO_EXTENSION). Extra hard links are not counted.
+    pub by_extension: HashMap<String, ExtensionStat>,
+}
+
+/// Run details returned by [`nefax_dir_with_report`](crate::nefax_dir_with_report) alongside `(nefax, diff)`.
+#[derive(Clone, Debug)]
+pub struct NefaxReport {
+    /// Effective tuning: thread count, walk mode, detected drive type, channel cap.
+    pub tuning: crate::pipeline::PipelineTuning,
+    /// Paths the walk or metadata workers could not read (empty in strict mode, which fails instead).
+    pub skipped: SkipReport,
+    /// Whether the run covered the whole tree or stopped early (Ctrl+C, [`Opts::deadline`], or
+    /// [`Opts::max_entries`]).
+    pub status: CompletionStatus,
+    /// [`tree_digest`] of the resulting index when `tree_digest` was requested; compare it with an
+    /// earlier run's to tell whether anything changed.
+    pub tree_digest: Option<[u8; 32]>,
+    /// Rows the index write inserted, deleted, and left unchanged; `None` when no index was written.
+    pub write_stats: Option<crate::engine::WriteStats>,
+}
+
+/// Whether an indexing run finished. A [`Partial`](Self::Partial) index holds what was received before
+/// the stop; paths not reached keep their previous rows and nothing was removed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompletionStatus {
+    /// The whole tree was walked and the index reflects it.
+    #[default]
+    Complete,
+    /// Stopped early (Ctrl+C, deadline, or `max_entries`); the index may be incomplete.
+    Partial,
+}
+
+/// Why a path was skipped, set where the error happened (walk or metadata worker). Reports group by
+/// this rather than by message text, which differs per path; the full message is kept alongside.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    /// The directory or file could not be read for lack of permission.
+    PermissionDenied,
+    /// The path disappeared while it was being read.
+    NotFound,
+    /// The read timed out (e.g. an unresponsive network mount).
+    Timeout,
+    /// Left out by an exclude rule. The walk drops excluded paths silently, so this only shows up in
+    /// reports built by callers.
+    Excluded,
+    /// Any other I/O failure, named by its [`std::io::ErrorKind`] (e.g. `not a directory`).
+    Io(String),
+}
+
+impl SkipReason {
+    /// Reason for an I/O error of `kind`.
+    #[must_use]
+    pub fn from_io_kind(kind: std::io::ErrorKind) -> Self {
+        match kind {
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            std::io::ErrorKind::NotFound => Self::NotFound,
+            std::io::ErrorKind::TimedOut => Self::Timeout,
+            other => Self::Io(other.to_string()),
+        }
+    }
+
+    /// Reason for `err`, from the first [`std::io::Error`] in its chain (`other error` when there is none).
+    #[must_use]
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        let kind = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<std::io::Error>())
+            .map_or(std::io::ErrorKind::Other, std::io::Error::kind);
+        Self::from_io_kind(kind)
+    }
+
+    /// Short label used in the skip breakdown.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::PermissionDenied => "permission denied",
+            Self::NotFound => "not found",
+            Self::Timeout => "timed out",
+            Self::Excluded => "excluded",
+            Self::Io(kind) => kind,
+        }
+    }
+}
+
+/// A skipped path: where, why, and the full error message for display.
+pub type SkippedPath = (PathBuf, SkipReason, String);
+
+/// Paths skipped during a run, grouped by [`SkipReason`] (what the CLI logs as the skip breakdown).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkipReport {
+    /// Number of skipped paths.
+    pub total: usize,
+    /// Reason → number of paths skipped with it.
+    pub by_reason: HashMap<SkipReason, usize>,
+    /// Every skipped path with its reason and error message, in the order they were recorded.
+    pub paths: Vec<SkippedPath>,
+    /// Paths left out for exceeding [`Opts::max_path_len`], sorted. Not counted in `total`.
+    pub too_long: Vec<PathBuf>,
+}
+
+impl SkipReport {
+    /// Group skipped paths by reason.
+    #[must_use]
+    pub fn from_skipped(skipped: &[SkippedPath]) -> Self {
+        let mut by_reason: HashMap<SkipReason, usize> = HashMap::new();
+        for (_, reason, _) in skipped {
+            *by_reason.entry(reason.clone()).or_insert(0) += 1;
+        }
+        Self {
+            total: skipped.len(),
+            by_reason,
+            paths: skipped.to_vec(),
+            too_long: Vec::new(),
+        }
+    }
 }
 
 /// Map of path → metadata for the indexed tree. Same shape as the `.nefaxer` DB.
@@ -48,6 +824,22 @@ const MTIME_NS_MAX: i64 = 4_611_686_018_427_387_903; // ~year 2242 in ns since e
 /// Max file size (1 exabyte). Rejects overflow/corruption sentinels.
 const SIZE_MAX: u64 = 1_000_000_000_000_000_000;
 
+/// Digest of a whole snapshot: blake3 over every `(path, mtime_ns, size, hash)` in path order. Two
+/// snapshots with the same digest have identical entries, so comparing the digests of two runs
+/// answers "did anything change" without a diff. Hashes are part of the input: compare digests of
+/// runs made with the same `with_hash` (and hash key).
+#[must_use]
+pub fn tree_digest(nefax: &Nefax) -> [u8; 32] {
+    crate::engine::fold_tree_digest(nefax.iter().map(|(p, m)| {
+        (
+            p.as_path(),
+            m.mtime_ns,
+            m.size,
+            m.hash.as_ref().map(<[u8; 32]>::as_slice),
+        )
+    }))
+}
+
 /// Validates that a [`Nefax`] map is suitable for use as `existing` in [`nefax_dir`](crate::nefax_dir).
 /// Single pass: paths must be relative and non-empty; [`PathMeta`] fields must be in plausible ranges (rejects corrupted data).
 ///
@@ -55,33 +847,49 @@ const SIZE_MAX: u64 = 1_000_000_000_000_000_000;
 ///
 /// Returns [`anyhow::Error`] when a path is absolute or empty, or `mtime_ns` / `size` are out of range.
 pub fn validate_nefax(nefax: &Nefax) -> Result<()> {
+    validate_nefax_with_mode(nefax, false)
+}
+
+/// Like [`validate_nefax`], but absolute paths are accepted when `store_absolute` (index built with
+/// [`NefaxOpts::store_absolute`]).
+///
+/// # Errors
+///
+/// Same as [`validate_nefax`], minus the absolute-path check when `store_absolute`.
+pub fn validate_nefax_with_mode(nefax: &Nefax, store_absolute: bool) -> Result<()> {
     for (path, meta) in nefax {
-        if path.as_path().is_absolute() {
-            anyhow::bail!(
-                "existing index contains absolute path (must be relative to indexed root): {}",
-                path.display()
-            );
-        }
-        if path.as_os_str().is_empty() {
-            anyhow::bail!("existing index contains empty path");
-        }
-        if meta.mtime_ns < MTIME_NS_MIN || meta.mtime_ns > MTIME_NS_MAX {
-            anyhow::bail!(
-                "existing index invalid mtime_ns for path {}: {} (expected {}..={})",
-                path.display(),
-                meta.mtime_ns,
-                MTIME_NS_MIN,
-                MTIME_NS_MAX
-            );
-        }
-        if meta.size > SIZE_MAX {
-            anyhow::bail!(
-                "existing index invalid size for path {}: {} (max {})",
-                path.display(),
-                meta.size,
-                SIZE_MAX
-            );
-        }
+        validate_path_meta(path, meta, store_absolute)?;
+    }
+    Ok(())
+}
+
+/// One row of [`validate_nefax_with_mode`], for snapshots checked as they are read.
+pub(crate) fn validate_path_meta(path: &Path, meta: &PathMeta, store_absolute: bool) -> Result<()> {
+    if !store_absolute && path.is_absolute() {
+        anyhow::bail!(
+            "existing index contains absolute path (must be relative to indexed root): {}",
+            path.display()
+        );
+    }
+    if path.as_os_str().is_empty() {
+        anyhow::bail!("existing index contains empty path");
+    }
+    if meta.mtime_ns < MTIME_NS_MIN || meta.mtime_ns > MTIME_NS_MAX {
+        anyhow::bail!(
+            "existing index invalid mtime_ns for path {}: {} (expected {}..={})",
+            path.display(),
+            meta.mtime_ns,
+            MTIME_NS_MIN,
+            MTIME_NS_MAX
+        );
+    }
+    if meta.size > SIZE_MAX {
+        anyhow::bail!(
+            "existing index invalid size for path {}: {} (max {})",
+            path.display(),
+            meta.size,
+            SIZE_MAX
+        );
     }
     Ok(())
 }
@@ -89,24 +897,117 @@ pub fn validate_nefax(nefax: &Nefax) -> Result<()> {
 /// Lib-only options for [`nefax_dir`](crate::nefax_dir). Only the fields that apply when using the crate (no DB).
 #[derive(Clone, Debug, Default)]
 pub struct NefaxOpts {
-    /// Override worker thread count. When None, derived from drive type and FD limit.
+    /// Override worker thread count (1 or more; still capped by the FD limit). When None or
+    /// `Some(0)`, derived from drive type and FD limit.
     pub num_threads: Option<usize>,
     /// When set together with [`Self::num_threads`] and [`Self::use_parallel_walk`], skip disk detection and use these values (e.g. from [`tuning_for_path`](crate::tuning_for_path) or [`determine_threads_for_drive`](crate::disk_detect::determine_threads_for_drive) with `conn: None`).
     pub drive_type: Option<crate::disk_detect::DriveType>,
     /// Use parallel walk (jwalk). When set with `num_threads` and `drive_type`, skip disk detection.
     pub use_parallel_walk: Option<bool>,
+    /// Estimated file descriptors per worker, used to cap threads by the FD limit. When None, `FDS_PER_WORKER` (10).
+    pub fds_per_worker: Option<usize>,
+    /// Walk in parallel (jwalk) even on an HDD, where detection otherwise picks the serial walk unless
+    /// the root is wide and shallow (see [`hdd_parallel_walk`](crate::disk_detect::hdd_parallel_walk)).
+    pub force_parallel_walk: bool,
     /// Compute blake3 hash for files (slower but accurate change detection).
     pub with_hash: bool,
+    /// With `with_hash`, also hash files below the 4 KB small-file threshold (normally compared by
+    /// mtime/size only), so an in-place edit of a small file that keeps its size and mtime is caught:
+    /// their stored hashes are never reused. Turning it on for an index built without it reports small
+    /// files as modified once, since they gain a hash.
+    pub hash_small_files: bool,
     /// Follow symbolic links.
     pub follow_links: bool,
+    /// Follow only symlinks at most this many levels below the root (1 = entries directly under it);
+    /// deeper links are indexed but not descended. Implies `follow_links`.
+    pub follow_depth: Option<usize>,
+    /// Skip all dotfiles and dot-directories (e.g. `.git/`); dot-directories are pruned, not descended.
+    pub skip_dotfiles: bool,
+    /// Index in-tree files named like nefaxer artifacts (probe dir, results file) instead of skipping them.
+    /// Only applies when there is no index DB inside the tree.
+    pub index_self: bool,
+    /// Do not descend into directories below the root that hold their own index (`.nefaxer`); the
+    /// directory itself is still indexed. Without it, nested indexes are only reported with a warning.
+    pub prune_nested: bool,
+    /// Key entries by absolute path instead of relative to the root. `existing` must use the same mode.
+    pub store_absolute: bool,
+    /// Canonical form of the path keys (lowercased, or NFC/NFD so the same name spelled on Linux
+    /// and macOS gets one key). `existing` must have been built with the same policy.
+    pub path_key: PathKeyPolicy,
     /// Exclude patterns (glob syntax, e.g. `node_modules`, `*.log`).
     pub exclude: Vec<String>,
+    /// Also exclude common VCS and build directories (`.git/`, `node_modules/`, `target/`, ...; see
+    /// [`VCS_EXCLUDE_PATTERNS`](crate::utils::config::VCS_EXCLUDE_PATTERNS)).
+    pub exclude_vcs: bool,
+    /// Walk only these subtrees of the root (relative paths, e.g. `photos/2024`); empty walks the
+    /// whole root. Keys stay relative to the root, and only rows under a seed can be reported
+    /// removed, so `existing` may also hold paths from other subtrees. `follow_depth` counts from
+    /// the seed. A seed repeated or nested under another seed is walked only once.
+    pub seeds: Vec<PathBuf>,
+    /// Record each entry's creation time in [`PathMeta::btime_ns`] where the platform reports one
+    /// (macOS, Windows, Linux with statx on most filesystems); `None` elsewhere.
+    pub track_btime: bool,
+    /// A creation time that differs from `existing`'s counts as modified (a file replaced by a
+    /// copy with the same mtime and size). Implies `track_btime`; paths without a stored btime are
+    /// compared as usual.
+    pub btime_is_change: bool,
+    /// Record a hash of each entry's extended attributes (tags, quarantine flags, ...) in
+    /// [`PathMeta::xattr_hash`] and count a change to them as modified, content unchanged or not.
+    /// Linux and macOS only; a no-op elsewhere. Paths without a stored hash are compared as usual
+    /// (and get one on the next index run), as are paths whose attributes cannot be read (warned).
+    pub track_xattrs: bool,
     /// Mtime tolerance window in nanoseconds.
     pub mtime_window_ns: i64,
+    /// Mtime resolution of the filesystem in nanoseconds (e.g. 2 s for FAT/SMB copies): differences
+    /// below it never count as changes. When None, 2 s on network drives, else exact.
+    pub mtime_granularity_ns: Option<i64>,
+    /// Rule for "changed" (default mtime/size/hash). [`ChangePolicy::HashOnly`] turns on `with_hash`.
+    pub change_policy: ChangePolicy,
     /// Strict mode: fail on first permission/access error instead of skipping.
     pub strict: bool,
     /// Paranoid mode: re-hash when hash matches but mtime/size differ.
     pub paranoid: bool,
+    /// With `with_hash`: put paths whose hash matches but mtime differs in [`Diff::touched`]
+    /// instead of [`Diff::modified`].
+    pub report_touched: bool,
+    /// Hash files with `blake3::keyed_hash` under this key (tamper-evident digests); implies
+    /// `with_hash`. Snapshots passed as `existing` must have been hashed with the same key.
+    pub hash_key: Option<crate::engine::HashKey>,
+    /// Compute [`tree_digest`] of the result into [`NefaxReport::tree_digest`].
+    pub tree_digest: bool,
+    /// With [`nefax_dir_store`](crate::nefax_dir_store): once the snapshot holds more than this many
+    /// entries, move it to a temporary on-disk DB ([`SpilledNefax`](crate::store::SpilledNefax))
+    /// instead of growing the map. `None` keeps everything in memory. `existing` is still loaded
+    /// into memory for the diff.
+    pub max_entries_in_memory: Option<usize>,
+    /// Stop the walk after this many paths (a quick probe of a huge tree). The result is
+    /// [`CompletionStatus::Partial`] and paths not reached are not reported as removed.
+    pub max_entries: Option<usize>,
+    /// Skip paths whose root-relative path is longer than this many bytes (e.g. for downstream
+    /// systems with a path limit). They are listed in [`SkipReport::too_long`].
+    pub max_path_len: Option<usize>,
+}
+
+impl NefaxOpts {
+    /// Run drive detection for `path` once and fill [`Self::num_threads`], [`Self::drive_type`], and
+    /// [`Self::use_parallel_walk`], so later [`nefax_dir`](crate::nefax_dir) calls with these opts skip detection.
+    ///
+    /// Same as [`tuning_for_path`](crate::tuning_for_path) (`conn: None`, network probe not cached). An already-set
+    /// `num_threads` is kept as the thread override (still capped by the FD limit).
+    pub fn auto_tune(&mut self, path: &Path) {
+        let (num_threads, drive_type, use_parallel_walk) =
+            crate::disk_detect::determine_threads_for_drive(
+                path,
+                None,
+                rayon::current_num_threads(),
+                self.num_threads,
+                self.fds_per_worker,
+                self.force_parallel_walk,
+            );
+        self.num_threads = Some(num_threads);
+        self.drive_type = Some(drive_type);
+        self.use_parallel_walk = Some(use_parallel_walk);
+    }
 }
 
 impl From<&NefaxOpts> for Opts {
@@ -116,16 +1017,60 @@ impl From<&NefaxOpts> for Opts {
             num_threads: o.num_threads,
             drive_type: o.drive_type,
             use_parallel_walk: o.use_parallel_walk,
-            with_hash: o.with_hash,
+            fds_per_worker: o.fds_per_worker,
+            force_parallel_walk: o.force_parallel_walk,
+            with_hash: o.with_hash
+                || o.hash_key.is_some()
+                || matches!(o.change_policy, ChangePolicy::HashOnly),
+            hash_small_files: o.hash_small_files,
             follow_links: o.follow_links,
+            follow_depth: o.follow_depth,
+            skip_dotfiles: o.skip_dotfiles,
+            index_self: o.index_self,
+            prune_nested: o.prune_nested,
+            store_absolute: o.store_absolute,
+            path_key: o.path_key,
             exclude: o.exclude.clone(),
+            exclude_vcs: o.exclude_vcs,
+            seeds: o.seeds.clone(),
+            track_btime: o.track_btime || o.btime_is_change,
+            btime_is_change: o.btime_is_change,
+            track_xattrs: o.track_xattrs,
             verbose: false,
+            quiet: false,
+            log_format: LogFormat::default(),
             mtime_window_ns: o.mtime_window_ns,
+            mtime_granularity_ns: o.mtime_granularity_ns,
+            change_policy: o.change_policy,
             strict: o.strict,
             paranoid: o.paranoid,
+            report_touched: o.report_touched,
+            hash_key: o.hash_key,
+            keyed_hash: false,
             encrypt: false,
+            key_file: None,
+            keyring: false,
+            compress_paths: false,
+            append_only: false,
+            durability: DbDurability::default(),
+            tree_digest: o.tree_digest,
+            max_entries_in_memory: o.max_entries_in_memory,
+            max_entries: o.max_entries,
+            max_path_len: o.max_path_len,
+            deadline: None,
+            busy_timeout: None,
             list_paths: false,
+            list_threshold: None,
             write_to_db: false,
+            preview: false,
+            index_from: None,
+            verify_sample: None,
+            focus: Vec::new(),
+            since_last_index: false,
+            fail_on_change: false,
+            prune_empty_dirs: false,
+            output: None,
+            display_relative_to: None,
         }
     }
 }
@@ -135,30 +1080,181 @@ impl From<&NefaxOpts> for Opts {
 pub struct Opts {
     /// Index database path. When None, uses `root.join(<package index filename>)` (e.g. `.nefaxer`).
     pub db_path: Option<PathBuf>,
-    /// Override worker thread count. When None, derived from drive type and FD limit.
+    /// Override worker thread count (1 or more; still capped by the FD limit). When None or
+    /// `Some(0)`, derived from drive type and FD limit.
     pub num_threads: Option<usize>,
     /// When set with `num_threads` and `use_parallel_walk`, skip disk detection (e.g. lib caller passed result of [`tuning_for_path`](crate::tuning_for_path) or [`determine_threads_for_drive`](crate::disk_detect::determine_threads_for_drive)).
     pub drive_type: Option<crate::disk_detect::DriveType>,
     /// Use parallel walk (jwalk). When set with `num_threads` and `drive_type`, skip disk detection.
     pub use_parallel_walk: Option<bool>,
+    /// Estimated file descriptors per worker, used to cap threads by the FD limit. When None, `FDS_PER_WORKER` (10).
+    pub fds_per_worker: Option<usize>,
+    /// Walk in parallel (jwalk) even on an HDD, where detection otherwise picks the serial walk unless
+    /// the root is wide and shallow (see [`hdd_parallel_walk`](crate::disk_detect::hdd_parallel_walk)).
+    pub force_parallel_walk: bool,
     /// Compute blake3 hash for files (slower but accurate change detection).
     pub with_hash: bool,
+    /// With `with_hash`, also hash files below the 4 KB small-file threshold (normally compared by
+    /// mtime/size only), so an in-place edit of a small file that keeps its size and mtime is caught:
+    /// their stored hashes are never reused. Turning it on for an index built without it reports small
+    /// files as modified once, since they gain a hash.
+    pub hash_small_files: bool,
     /// Follow symbolic links.
     pub follow_links: bool,
+    /// Follow only symlinks at most this many levels below the root (1 = entries directly under it);
+    /// deeper links are indexed but not descended. Implies `follow_links`.
+    pub follow_depth: Option<usize>,
+    /// Skip all dotfiles and dot-directories (e.g. `.git/`); dot-directories are pruned, not descended.
+    pub skip_dotfiles: bool,
+    /// Index in-tree files named like nefaxer artifacts (probe dir, results file) when the index DB
+    /// lives outside the tree. The index and its temp file are always excluded.
+    pub index_self: bool,
+    /// Skip the contents of directories below the root that hold their own index (`.nefaxer`).
+    pub prune_nested: bool,
+    /// Store absolute paths instead of paths relative to the root (recorded in the index `meta` table;
+    /// check follows the recorded mode).
+    pub store_absolute: bool,
+    /// Canonical form of the index keys (see [`PathKeyPolicy`]), e.g. NFC so an index built on
+    /// Linux matches the same names read on macOS. Recorded in the index `meta` table; a run with a
+    /// different policy is warned about. Files are still opened by their on-disk names.
+    pub path_key: PathKeyPolicy,
     /// Exclude patterns (glob syntax).
     pub exclude: Vec<String>,
+    /// Append [`VCS_EXCLUDE_PATTERNS`](crate::utils::config::VCS_EXCLUDE_PATTERNS) to `exclude`.
+    pub exclude_vcs: bool,
+    /// Walk only these subtrees, relative to the root (see [`NefaxOpts::seeds`]). Rows outside them
+    /// are left alone: not reported removed, not deleted.
+    pub seeds: Vec<PathBuf>,
+    /// Read creation times into the index's nullable `btime_ns` column. Unchanged rows are not
+    /// rewritten, so existing rows gain a btime only when they next change.
+    pub track_btime: bool,
+    /// Count a changed creation time as a modification (needs `track_btime`; see
+    /// [`NefaxOpts::btime_is_change`]).
+    pub btime_is_change: bool,
+    /// Read extended attributes into the index's nullable `xattr_hash` column and count a change as a
+    /// modification (see [`NefaxOpts::track_xattrs`]).
+    pub track_xattrs: bool,
     /// Show progress bar (verbose mode).
     pub verbose: bool,
+    /// CLI: log warnings and errors only (no info summary). Overrides `verbose`.
+    pub quiet: bool,
+    /// CLI: log line format (colored text or JSON lines).
+    pub log_format: LogFormat,
     /// Mtime tolerance window in nanoseconds.
     pub mtime_window_ns: i64,
+    /// Mtime resolution of the filesystem in nanoseconds; differences below it are rounding loss,
+    /// not changes (the effective tolerance is the larger of this and `mtime_window_ns`). When None,
+    /// [`effective_mtime_granularity_ns`](crate::engine::effective_mtime_granularity_ns) picks 2 s on
+    /// network drives and exact elsewhere; `Some(0)` forces exact.
+    pub mtime_granularity_ns: Option<i64>,
+    /// Rule for "changed" (default mtime/size/hash). [`ChangePolicy::HashOnly`] needs `with_hash`;
+    /// the CLI and the [`NefaxOpts`] conversion turn it on.
+    pub change_policy: ChangePolicy,
     /// Strict mode: fail on first permission/access error instead of skipping.
     pub strict: bool,
     /// Paranoid mode (check): re-hash when hash matches but mtime/size differ.
     pub paranoid: bool,
+    /// Check with `with_hash`: report content-identical files whose mtime moved in
+    /// [`Diff::touched`] rather than [`Diff::modified`]. Index runs still rewrite their rows.
+    pub report_touched: bool,
+    /// Hash files with `blake3::keyed_hash` under this key, so digests in the index cannot be
+    /// recomputed by someone who only has the content. The index records `hash_algo = blake3-keyed`;
+    /// hashes made under another key (or none) read as modified.
+    pub hash_key: Option<crate::engine::HashKey>,
+    /// Index/check: derive [`Self::hash_key`] from the `SQLCipher` passphrase (needs an encrypted
+    /// index). Ignored when `hash_key` is already set.
+    pub keyed_hash: bool,
     /// Encrypt the index database with `SQLCipher`.
     pub encrypt: bool,
+    /// Read the `SQLCipher` passphrase from this file (trimmed) before env / .env / prompt.
+    pub key_file: Option<PathBuf>,
+    /// Fetch the passphrase from the OS keyring before prompting, and save it there once it opens the
+    /// index (needs the `keyring` feature).
+    pub keyring: bool,
+    /// Index only: store paths zstd-compressed (needs the `compress` feature). Converts a plain-text
+    /// index after this run's write; a compressed index stays compressed.
+    pub compress_paths: bool,
+    /// Index only: never delete rows, so the index keeps every path ever seen. Paths gone from disk
+    /// are still reported as removed (and keep being reported by check) but their rows stay.
+    pub append_only: bool,
+    /// Index only: crash safety vs. write speed of the index DB (see [`DbDurability`]).
+    pub durability: DbDurability,
+    /// Compute the [`tree_digest`] of the resulting index: returned in [`NefaxReport::tree_digest`]
+    /// and, when writing the DB, stored in `meta` as `tree_digest` (hex).
+    pub tree_digest: bool,
+    /// Lib only: entries the returned snapshot may hold in memory before it spills to a temporary
+    /// DB (see [`NefaxOpts::max_entries_in_memory`]).
+    pub max_entries_in_memory: Option<usize>,
+    /// Stop the walk once this many paths were sent to the workers. What was collected is still
+    /// written or diffed, but the run is [`CompletionStatus::Partial`]: paths not reached keep their
+    /// rows and are not reported as removed. Unlike a deadline, the CLI exits successfully.
+    pub max_entries: Option<usize>,
+    /// Skip (and report) paths whose root-relative path is longer than this many bytes. They are
+    /// kept apart from unreadable paths: see [`SkipReport::too_long`].
+    pub max_path_len: Option<usize>,
+    /// Index only: time budget for the run. When it passes, streaming stops and flushes what was
+    /// received, so the index is **partial** (unvisited paths keep their previous rows, nothing is
+    /// marked removed) and the run returns an error.
+    pub deadline: Option<std::time::Duration>,
+    /// Index only: how long a write waits for another process holding the index lock before failing.
+    /// `None` uses `DB_BUSY_TIMEOUT` (5 s), which also applies while opening.
+    pub busy_timeout: Option<std::time::Duration>,
     /// List each changed path (added/removed/modified). If total > `LIST_THRESHOLD`, write to nefaxer.results instead of stdout.
     pub list_paths: bool,
+    /// Overrides `LIST_THRESHOLD` for `list_paths`: `Some(0)` always writes nefaxer.results,
+    /// `Some(usize::MAX)` always prints to stdout.
+    pub list_threshold: Option<usize>,
     /// When true, write index to DB (CLI). When false, run pipeline and return diff only (lib).
     pub write_to_db: bool,
+    /// With `write_to_db`: run the real index write (hashing included) against a throwaway copy of
+    /// the index and report what it would change; the index on disk is left untouched.
+    pub preview: bool,
+    /// Check only: load the prior index from this DB instead of `db_path` (e.g. the original tree's
+    /// `.nefaxer` when verifying a copy). The walked root's own index file is still excluded.
+    pub index_from: Option<PathBuf>,
+    /// Check only: fraction (0.0–1.0) of unchanged hashed files to re-hash against the stored hash.
+    /// Mismatches are reported in [`Diff::corrupted`]. Needs an index built with hashing.
+    pub verify_sample: Option<f64>,
+    /// Check only: restrict the diff (added, modified, and removed) to paths matching these globs
+    /// (see [`matches_focus`](crate::engine::matches_focus)). Unlike `exclude`, the walk is unchanged.
+    pub focus: Vec<String>,
+    /// Check only: report a walked path as added/modified only when its mtime is newer than the
+    /// index's `updated_at` (the start of the last index run), without comparing it to the stored
+    /// row. Removed paths are still found by presence, since a deleted file leaves no timestamp.
+    /// Falls back to a full diff (with a warning) for indexes without `updated_at`.
+    pub since_last_index: bool,
+    /// Check only: return [`ChangesDetected`](crate::check::ChangesDetected) after printing the diff
+    /// when anything changed, so CI can gate on "no drift".
+    pub fail_on_change: bool,
+    /// Check only: after the diff, list directories left empty because everything they held was
+    /// removed (see [`newly_empty_dirs`](crate::check::newly_empty_dirs)), as candidates to prune.
+    pub prune_empty_dirs: bool,
+    /// Check only: write the full diff to this file (see [`write_diff_report`](crate::engine::write_diff_report))
+    /// instead of listing to stdout / the results file; the summary is still logged.
+    pub output: Option<PathBuf>,
+    /// Show listed diff paths relative to this directory (e.g. the current one) instead of the
+    /// indexed root, in the terminal list, the `output` report, and the CLI's `--print0`. Display only: the index and the returned [`Diff`] keep root-relative keys.
+    pub display_relative_to: Option<PathBuf>,
+}
+
+impl Opts {
+    /// Option combinations that do nothing, as messages to log. `paranoid` without `with_hash` has
+    /// no hashes to re-check, so it is ignored and the run compares mtime/size only.
+    #[must_use]
+    pub fn consistency_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.paranoid && !self.with_hash {
+            warnings.push(
+                "paranoid mode has no effect without hashing (--check-hash); comparing mtime/size only"
+                    .to_string(),
+            );
+        }
+        if self.report_touched && !self.with_hash {
+            warnings.push(
+                "touched reporting has no effect without hashing (--check-hash); mtime-only changes count as modified"
+                    .to_string(),
+            );
+        }
+        warnings
+    }
 }
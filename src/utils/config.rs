@@ -60,6 +60,24 @@ impl PackagePaths {
     }
 }
 
+/// VCS metadata and build/cache directories excluded by `exclude_vcs` (`--exclude-vcs`). Trailing
+/// `/` so only directories match (a file named `target` is still indexed).
+pub const VCS_EXCLUDE_PATTERNS: &[&str] = &[
+    ".git/",
+    ".hg/",
+    ".svn/",
+    ".bzr/",
+    ".jj/",
+    "node_modules/",
+    "target/",
+    ".venv/",
+    "__pycache__/",
+    ".tox/",
+    ".mypy_cache/",
+    ".pytest_cache/",
+    ".gradle/",
+];
+
 // ---- Worker threads ----
 
 /// Thread limits for drive-type-based tuning.
@@ -135,16 +153,62 @@ impl HashingConsts {
 
 // ---- Indexing ----
 
+/// Mtime granularity assumed on network drives when none is configured (SMB/FAT keep 1–2 s). 2 s.
+pub const NETWORK_MTIME_GRANULARITY_NS: i64 = 2_000_000_000;
+
 /// Files smaller than this are not hashed; mtime/size only (bytes).
 pub const SMALL_FILE_THRESHOLD: u64 = 4 * 1024; // 4 KB
 
+/// Upper bounds (exclusive, bytes) of the file-size histogram buckets in `--stats`; a final bucket holds the rest.
+pub const STATS_SIZE_BUCKETS: &[u64] = &[
+    1024,
+    SMALL_FILE_THRESHOLD,
+    64 * 1024,
+    1024 * 1024,
+    16 * 1024 * 1024,
+    256 * 1024 * 1024,
+    4 * 1024 * 1024 * 1024,
+];
+
+/// Extensions listed (largest total size first) in the `--stats` breakdown by extension.
+pub const STATS_TOP_EXTENSIONS: usize = 10;
+
+/// Key for files without an extension in [`TreeProfile::by_extension`](crate::TreeProfile::by_extension).
+pub const NO_EXTENSION: &str = "<none>";
+
+/// Whether paths that differ only in letter case name the same file on this platform's usual
+/// filesystems (APFS/HFS+, NTFS). Enables [`Diff::pair_case_only_renames`](crate::Diff::pair_case_only_renames).
+pub const CASE_INSENSITIVE_PATHS: bool = cfg!(any(target_os = "macos", windows));
+
 // ---- Database ----
 
 /// Batch size for DB insert/update chunks (balance transaction size vs round-trips).
 pub const DB_INSERT_BATCH_SIZE: usize = 1000;
 
+/// How long a connection waits for another process's lock on the index before failing with "database
+/// is locked" (`SQLite` `busy_timeout`). Override per run with `Opts::busy_timeout`.
+pub const DB_BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Paths per `DELETE ... WHERE path IN (...)` statement (well under `SQLite`'s bound-parameter limit).
+pub const DB_DELETE_BATCH_SIZE: usize = 500;
+
+/// Upper bound on the path dictionary built for a compressed index (bytes of common parent dirs). 64 KB.
+pub const PATH_DICT_MAX_BYTES: usize = 64 * 1024;
+
+/// zstd level for compressed paths (paths are tiny; the dictionary does most of the work).
+pub const PATH_ZSTD_LEVEL: i32 = 9;
+
 // ---- Streaming channel cap ----
 
+/// Entries a metadata worker collects before sending them as one batch (fewer channel operations
+/// per path). A partial batch is sent whenever the worker would otherwise wait for the walk.
+pub const ENTRY_SEND_BATCH_SIZE: usize = 64;
+
+/// Top-level entries at which an HDD root counts as wide and shallow (one big directory of files)
+/// and is walked in parallel: its metadata reads stay within one directory, so they do not thrash
+/// the head the way a deep tree does.
+pub const HDD_PARALLEL_WALK_FANOUT: usize = 5_000;
+
 /// Channel cap (path + entry) tuned by drive type; after first run, finetuned from stored path count in diskinfo.
 pub struct StreamingChannelCap;
 
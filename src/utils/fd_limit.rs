@@ -29,22 +29,41 @@ pub fn max_open_fds() -> Option<u64> {
     None
 }
 
-/// Suggested max parallelism (thread count) so we stay under ~80% of the FD limit.
-/// Returns `None` if no limit is available (use caller's default).
+/// Worker cap for an FD `limit` when each worker is estimated to use `fds_per_worker` descriptors.
+/// Uses ~80% of the limit; always at least 1. A zero budget is treated as [`FDS_PER_WORKER`].
 #[must_use]
-pub fn max_workers_by_fd_limit() -> Option<usize> {
-    let limit = max_open_fds()?;
+pub fn workers_for_fd_limit(limit: u64, fds_per_worker: usize) -> usize {
+    let per_worker = if fds_per_worker == 0 {
+        FDS_PER_WORKER
+    } else {
+        fds_per_worker
+    };
     // 80% of limit, integer math (avoids u64→f64 precision loss; matches prior float intent).
     let usable = (limit.saturating_mul(80) / 100) as usize;
-    if usable < FDS_PER_WORKER {
-        return Some(1);
+    if usable < per_worker {
+        return 1;
     }
-    Some(usable / FDS_PER_WORKER)
+    usable / per_worker
+}
+
+/// Suggested max parallelism (thread count) so we stay under ~80% of the FD limit.
+/// `fds_per_worker` overrides [`FDS_PER_WORKER`] (raise for deep trees, lower for shallow ones).
+/// Returns `None` if no limit is available (use caller's default).
+#[must_use]
+pub fn max_workers_by_fd_limit(fds_per_worker: Option<usize>) -> Option<usize> {
+    let limit = max_open_fds()?;
+    Some(workers_for_fd_limit(
+        limit,
+        fds_per_worker.unwrap_or(FDS_PER_WORKER),
+    ))
 }
 
 #[must_use]
-pub fn determine_threads_given_fd_limit(num_threads: usize) -> usize {
-    match max_workers_by_fd_limit() {
+pub fn determine_threads_given_fd_limit(
+    num_threads: usize,
+    fds_per_worker: Option<usize>,
+) -> usize {
+    match max_workers_by_fd_limit(fds_per_worker) {
         Some(fd_cap) if fd_cap < num_threads => {
             debug!("Capping threads {num_threads} -> {fd_cap} (FD limit ~80%)");
             fd_cap
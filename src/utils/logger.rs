@@ -1,12 +1,20 @@
 use colored::Colorize;
 use env_logger::Builder;
-use log::Level;
+use log::{Level, Record};
 use std::io::Write;
 
-pub fn setup_logging(verbose: bool) {
+use crate::LogFormat;
+
+/// Install the CLI logger: debug when `verbose`, warnings/errors only when `quiet`, info otherwise.
+/// Lines are rendered by [`format_log_record`] in `format`; colors follow [`set_colors_for_format`].
+pub fn setup_logging(verbose: bool, quiet: bool, format: LogFormat) {
     use log::LevelFilter;
 
-    let level = if verbose {
+    set_colors_for_format(format);
+
+    let level = if quiet {
+        log::LevelFilter::Warn
+    } else if verbose {
         log::LevelFilter::Debug
     } else {
         log::LevelFilter::Info
@@ -15,21 +23,53 @@ pub fn setup_logging(verbose: bool) {
     Builder::from_default_env()
         .filter_level(LevelFilter::Warn) // Default: only warnings from dependencies
         .filter_module(env!("CARGO_PKG_NAME"), level) // Our crate: use requested level
-        .format(|buf, record| {
-            let name = env!("CARGO_PKG_NAME");
-            let line = match record.level() {
-                Level::Error | Level::Warn => {
-                    let level_str = match record.level() {
-                        Level::Warn => "WARN".yellow(),
-                        Level::Error => "ERROR".red(),
-                        _ => unreachable!(),
-                    };
-                    let path = record.target().to_string().white();
-                    format!("[{} {} {}] {}", name.cyan(), level_str, path, record.args())
-                }
-                _ => format!("[{}] {}", name.cyan(), record.args()),
+        .format(move |buf, record| writeln!(buf, "{}", format_log_record(record, format)))
+        .try_init()
+        .ok(); // Already initialized (e.g. several runs in one process): keep the first logger.
+}
+
+/// Turn colors off process-wide for `Json`, since messages are colored before they reach the
+/// logger; `Pretty` leaves them as they are.
+pub fn set_colors_for_format(format: LogFormat) {
+    if format == LogFormat::Json {
+        colored::control::set_override(false);
+    }
+}
+
+/// One log line (without the newline). `Pretty`: `[nefaxer] msg`, with level and target for
+/// warnings and errors, colored. `Json`: `{"level","target","msg","ts"}` with `ts` in Unix
+/// milliseconds and no ANSI codes.
+#[must_use]
+pub fn format_log_record(record: &Record, format: LogFormat) -> String {
+    match format {
+        LogFormat::Pretty => pretty_line(record),
+        LogFormat::Json => {
+            let ts = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis());
+            serde_json::json!({
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "msg": record.args().to_string(),
+                "ts": ts,
+            })
+            .to_string()
+        }
+    }
+}
+
+fn pretty_line(record: &Record) -> String {
+    let name = env!("CARGO_PKG_NAME");
+    match record.level() {
+        Level::Error | Level::Warn => {
+            let level_str = match record.level() {
+                Level::Warn => "WARN".yellow(),
+                Level::Error => "ERROR".red(),
+                _ => unreachable!(),
             };
-            writeln!(buf, "{line}")
-        })
-        .init();
+            let path = record.target().to_string().white();
+            format!("[{} {} {}] {}", name.cyan(), level_str, path, record.args())
+        }
+        _ => format!("[{}] {}", name.cyan(), record.args()),
+    }
 }
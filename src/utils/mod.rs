@@ -6,7 +6,8 @@ pub mod passphrase;
 pub mod tempfiles;
 
 pub use config::*;
-pub use fd_limit::{FDS_PER_WORKER, max_open_fds, max_workers_by_fd_limit};
-pub use logger::setup_logging;
+pub use fd_limit::{FDS_PER_WORKER, max_open_fds, max_workers_by_fd_limit, workers_for_fd_limit};
+pub use logger::{format_log_record, set_colors_for_format, setup_logging};
+pub use nefaxer_toml::{Config, Settings};
 pub use passphrase::*;
 pub use tempfiles::*;
@@ -1,37 +1,213 @@
-//! Load `.nefaxer.toml` from a directory (CLI only). Lib does not use this; the consuming program injects config via `NefaxOpts`.
+//! `.nefaxer.toml`: the `[settings]` table the CLI loads from DIR before applying flags. [`Config`]
+//! is public so tools can also build, persist, and load nefaxer configs programmatically.
 
-use serde::Deserialize;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 use crate::Opts;
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct NefaxerToml {
+/// Contents of a `.nefaxer.toml`. Serializes to the same TOML the CLI reads; unset fields are
+/// omitted and leave the corresponding [`Opts`] field alone.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
     #[serde(default)]
-    settings: IndexSection,
+    pub settings: Settings,
+    /// Named `[profiles.<name>]` tables, applied over `settings` with `--profile <name>`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Settings>,
 }
 
-#[derive(Debug, Default, Deserialize)]
-struct IndexSection {
-    db_path: Option<String>,
-    hash: Option<bool>,
-    follow_links: Option<bool>,
-    exclude: Option<Vec<String>>,
-    list: Option<bool>,
-    verbose: Option<bool>,
-    mtime_window: Option<i64>,
-    strict: Option<bool>,
-    paranoid: Option<bool>,
-    encrypt: Option<bool>,
+/// The `[settings]` table. Durations are whole seconds; enum options use their CLI names
+/// (e.g. `change_policy = "size-only"`).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settings {
+    pub db_path: Option<String>,
+    pub hash: Option<bool>,
+    pub hash_small_files: Option<bool>,
+    pub follow_links: Option<bool>,
+    pub follow_depth: Option<usize>,
+    pub skip_dotfiles: Option<bool>,
+    pub index_self: Option<bool>,
+    pub prune_nested: Option<bool>,
+    pub store_absolute: Option<bool>,
+    pub track_btime: Option<bool>,
+    pub btime_is_change: Option<bool>,
+    pub track_xattrs: Option<bool>,
+    #[serde(alias = "unicode_normalize")]
+    pub path_key: Option<String>,
+    pub fds_per_worker: Option<usize>,
+    pub force_parallel_walk: Option<bool>,
+    pub seeds: Option<Vec<PathBuf>>,
+    pub exclude: Option<Vec<String>>,
+    pub exclude_vcs: Option<bool>,
+    pub list: Option<bool>,
+    pub list_threshold: Option<usize>,
+    pub verbose: Option<bool>,
+    pub quiet: Option<bool>,
+    pub log_format: Option<String>,
+    pub mtime_window: Option<i64>,
+    pub mtime_granularity: Option<i64>,
+    pub change_policy: Option<String>,
+    pub strict: Option<bool>,
+    pub paranoid: Option<bool>,
+    pub touched: Option<bool>,
+    pub encrypt: Option<bool>,
+    pub keyring: Option<bool>,
+    pub keyed_hash: Option<bool>,
+    pub compress_paths: Option<bool>,
+    pub append_only: Option<bool>,
+    pub tree_digest: Option<bool>,
+    pub durability: Option<String>,
+    pub deadline: Option<u64>,
+    pub max_entries: Option<usize>,
+    pub max_path_len: Option<usize>,
+    pub busy_timeout: Option<u64>,
 }
 
-/// Load `.nefaxer.toml` from `dir` if present. Returns None if file missing or unreadable. CLI only.
-pub(crate) fn load_nefaxer_toml(dir: &Path) -> Option<NefaxerToml> {
+impl Config {
+    /// Parse TOML in the `.nefaxer.toml` format.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when `s` is not valid TOML or a field has the wrong type.
+    pub fn from_toml_str(s: &str) -> Result<Self> {
+        toml::from_str(s).context("parse nefaxer config")
+    }
+
+    /// Serialize to TOML in the `.nefaxer.toml` format.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when serialization fails.
+    pub fn to_toml_string(&self) -> Result<String> {
+        toml::to_string(self).context("serialize nefaxer config")
+    }
+
+    /// Every file-configurable field of `opts`. Mtime windows and durations are truncated to whole
+    /// seconds; a [`ChangePolicy::Custom`](crate::ChangePolicy::Custom) policy is left out.
+    #[must_use]
+    pub fn from_opts(opts: &Opts) -> Self {
+        const NS: i64 = 1_000_000_000;
+        Self {
+            settings: Settings {
+                db_path: opts
+                    .db_path
+                    .as_ref()
+                    .map(|p| p.to_string_lossy().into_owned()),
+                hash: Some(opts.with_hash),
+                hash_small_files: Some(opts.hash_small_files),
+                follow_links: Some(opts.follow_links),
+                follow_depth: opts.follow_depth,
+                skip_dotfiles: Some(opts.skip_dotfiles),
+                index_self: Some(opts.index_self),
+                prune_nested: Some(opts.prune_nested),
+                store_absolute: Some(opts.store_absolute),
+                track_btime: Some(opts.track_btime),
+                btime_is_change: Some(opts.btime_is_change),
+                track_xattrs: Some(opts.track_xattrs),
+                path_key: Some(opts.path_key.as_str().to_string()),
+                fds_per_worker: opts.fds_per_worker,
+                force_parallel_walk: Some(opts.force_parallel_walk),
+                seeds: Some(opts.seeds.clone()),
+                exclude: Some(opts.exclude.clone()),
+                exclude_vcs: Some(opts.exclude_vcs),
+                list: Some(opts.list_paths),
+                list_threshold: opts.list_threshold,
+                verbose: Some(opts.verbose),
+                quiet: Some(opts.quiet),
+                log_format: Some(opts.log_format.as_str().to_string()),
+                mtime_window: Some(opts.mtime_window_ns / NS),
+                mtime_granularity: opts.mtime_granularity_ns.map(|ns| ns / NS),
+                change_policy: opts.change_policy.name().map(str::to_string),
+                strict: Some(opts.strict),
+                paranoid: Some(opts.paranoid),
+                touched: Some(opts.report_touched),
+                encrypt: Some(opts.encrypt),
+                keyring: Some(opts.keyring),
+                keyed_hash: Some(opts.keyed_hash),
+                compress_paths: Some(opts.compress_paths),
+                append_only: Some(opts.append_only),
+                tree_digest: Some(opts.tree_digest),
+                durability: Some(opts.durability.as_str().to_string()),
+                deadline: opts.deadline.map(|d| d.as_secs()),
+                max_entries: opts.max_entries,
+                max_path_len: opts.max_path_len,
+                busy_timeout: opts.busy_timeout.map(|d| d.as_secs()),
+            },
+            profiles: BTreeMap::new(),
+        }
+    }
+
+    /// [`Opts::default`] with this config applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when an enum option has an unknown name (see [`Self::apply_to`]).
+    pub fn to_opts(&self) -> Result<Opts> {
+        let mut opts = Opts::default();
+        self.apply_to(&mut opts)?;
+        Ok(opts)
+    }
+
+    /// Set the fields of `opts` that the base `[settings]` set (the CLI calls this before applying
+    /// flags).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when `change_policy`, `path_key`, `log_format`, or `durability`
+    /// is not a name the CLI accepts.
+    pub fn apply_to(&self, opts: &mut Opts) -> Result<()> {
+        apply_settings_to_opts(&self.settings, opts)
+    }
+
+    /// [`Self::apply_to`], then the fields set by `[profiles.<profile>]` on top.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] naming the defined profiles when `profile` is not one of them, or
+    /// when either table has an unknown enum name.
+    pub fn apply_profile_to(&self, profile: &str, opts: &mut Opts) -> Result<()> {
+        let Some(settings) = self.profiles.get(profile) else {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            anyhow::bail!(
+                "profile {profile:?} is not defined in .nefaxer.toml (defined: {})",
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            );
+        };
+        self.apply_to(opts)?;
+        apply_settings_to_opts(settings, opts).with_context(|| format!("profile {profile:?}"))
+    }
+}
+
+impl TryFrom<&Config> for Opts {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &Config) -> Result<Self> {
+        config.to_opts()
+    }
+}
+
+/// Load `.nefaxer.toml` from `dir` if present. Returns None if the file is missing.
+///
+/// # Errors
+///
+/// Returns [`anyhow::Error`] naming the file when it exists but cannot be read or parsed.
+pub(crate) fn load_nefaxer_toml(dir: &Path) -> Result<Option<Config>> {
     let path = dir.join(".nefaxer.toml");
-    let s = std::fs::read_to_string(&path).ok()?;
+    let s = match std::fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
     toml::from_str(&s)
-        .map_err(|e| log::warn!("{}: {}", path.display(), e))
-        .ok()
+        .map(Some)
+        .with_context(|| format!("parse {}", path.display()))
 }
 
 /// Overwrite opts field from file when present.
@@ -43,23 +219,87 @@ macro_rules! apply_file_opt {
     };
 }
 
+/// Parse an enum option's name from `.nefaxer.toml`; `field` names it in the error.
+fn parse_setting<T: std::str::FromStr<Err = String>>(field: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|e| anyhow::anyhow!(".nefaxer.toml: {field}: {e}"))
+}
+
 /// Apply file config to opts (only set fields present in the file). Call before applying CLI. `dry_run` is never in the file.
-pub(crate) fn apply_file_to_opts(file: &NefaxerToml, opts: &mut Opts) {
-    let idx = &file.settings;
+fn apply_settings_to_opts(idx: &Settings, opts: &mut Opts) -> Result<()> {
     if let Some(ref p) = idx.db_path {
         opts.db_path = Some(PathBuf::from(p));
     }
     apply_file_opt!(idx, opts, hash => with_hash);
+    apply_file_opt!(idx, opts, hash_small_files => hash_small_files);
     apply_file_opt!(idx, opts, follow_links => follow_links);
+    if idx.follow_depth.is_some() {
+        opts.follow_depth = idx.follow_depth;
+    }
+    apply_file_opt!(idx, opts, skip_dotfiles => skip_dotfiles);
+    apply_file_opt!(idx, opts, index_self => index_self);
+    apply_file_opt!(idx, opts, prune_nested => prune_nested);
+    apply_file_opt!(idx, opts, store_absolute => store_absolute);
+    apply_file_opt!(idx, opts, track_btime => track_btime);
+    apply_file_opt!(idx, opts, btime_is_change => btime_is_change);
+    opts.track_btime |= opts.btime_is_change;
+    apply_file_opt!(idx, opts, track_xattrs => track_xattrs);
+    if let Some(ref v) = idx.path_key {
+        opts.path_key = parse_setting("path_key", v)?;
+    }
+    if idx.fds_per_worker.is_some() {
+        opts.fds_per_worker = idx.fds_per_worker;
+    }
+    apply_file_opt!(idx, opts, force_parallel_walk => force_parallel_walk);
+    if let Some(ref v) = idx.seeds {
+        opts.seeds.clone_from(v);
+    }
     if let Some(ref v) = idx.exclude {
         opts.exclude.clone_from(v);
     }
+    apply_file_opt!(idx, opts, exclude_vcs => exclude_vcs);
     apply_file_opt!(idx, opts, list => list_paths);
+    if idx.list_threshold.is_some() {
+        opts.list_threshold = idx.list_threshold;
+    }
     apply_file_opt!(idx, opts, verbose => verbose);
+    apply_file_opt!(idx, opts, quiet => quiet);
+    if let Some(ref v) = idx.log_format {
+        opts.log_format = parse_setting("log_format", v)?;
+    }
     if let Some(secs) = idx.mtime_window {
         opts.mtime_window_ns = secs * 1_000_000_000;
     }
+    if let Some(secs) = idx.mtime_granularity {
+        opts.mtime_granularity_ns = Some(secs * 1_000_000_000);
+    }
+    if let Some(ref v) = idx.change_policy {
+        opts.change_policy = parse_setting("change_policy", v)?;
+    }
     apply_file_opt!(idx, opts, strict => strict);
     apply_file_opt!(idx, opts, paranoid => paranoid);
+    apply_file_opt!(idx, opts, touched => report_touched);
     apply_file_opt!(idx, opts, encrypt => encrypt);
+    apply_file_opt!(idx, opts, keyring => keyring);
+    apply_file_opt!(idx, opts, keyed_hash => keyed_hash);
+    apply_file_opt!(idx, opts, compress_paths => compress_paths);
+    apply_file_opt!(idx, opts, append_only => append_only);
+    apply_file_opt!(idx, opts, tree_digest => tree_digest);
+    if let Some(ref v) = idx.durability {
+        opts.durability = parse_setting("durability", v)?;
+    }
+    if let Some(secs) = idx.deadline {
+        opts.deadline = Some(std::time::Duration::from_secs(secs));
+    }
+    if idx.max_entries.is_some() {
+        opts.max_entries = idx.max_entries;
+    }
+    if idx.max_path_len.is_some() {
+        opts.max_path_len = idx.max_path_len;
+    }
+    if let Some(secs) = idx.busy_timeout {
+        opts.busy_timeout = Some(std::time::Duration::from_secs(secs));
+    }
+    Ok(())
 }
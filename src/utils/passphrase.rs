@@ -1,12 +1,111 @@
-//! Passphrase loading for `SQLCipher`: env var → .env in dir → secure prompt.
+//! Passphrase loading for `SQLCipher`: key file → env var → .env in dir → secret store → secure prompt.
 
 use anyhow::{Context, Result};
 use colored::Colorize;
-use log::{info, warn};
+use log::{debug, info, warn};
 use std::path::Path;
 
 const ENV_KEY: &str = "NEFAXER_DB_KEY";
 
+/// Service name passphrases are kept under in a [`SecretStore`] (the account is the index path).
+pub const KEYRING_SERVICE: &str = env!("CARGO_PKG_NAME");
+
+/// Key/value secret store for index passphrases (OS keyring with the `keyring` feature, or a test double).
+pub trait SecretStore {
+    /// Secret saved for `service`/`account`; `Ok(None)` when there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when the store is unavailable.
+    fn get(&self, service: &str, account: &str) -> Result<Option<String>>;
+
+    /// Save (or replace) the secret for `service`/`account`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when the store is unavailable or rejects the write.
+    fn set(&self, service: &str, account: &str, secret: &str) -> Result<()>;
+}
+
+/// The platform secret store: Keychain (macOS), Credential Manager (Windows), kernel keyring (Linux).
+#[cfg(feature = "keyring")]
+pub struct OsKeyring;
+
+#[cfg(feature = "keyring")]
+impl SecretStore for OsKeyring {
+    fn get(&self, service: &str, account: &str) -> Result<Option<String>> {
+        match keyring::Entry::new(service, account)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set(&self, service: &str, account: &str, secret: &str) -> Result<()> {
+        keyring::Entry::new(service, account)?.set_password(secret)?;
+        Ok(())
+    }
+}
+
+/// Passphrase sources tried before env / .env / prompt.
+#[derive(Clone, Copy, Default)]
+pub struct KeySource<'a> {
+    /// File holding the passphrase (see [`crate::Opts::key_file`]).
+    pub key_file: Option<&'a Path>,
+    /// Store consulted before prompting; a passphrase that opens the index is saved back to it.
+    pub store: Option<&'a dyn SecretStore>,
+    /// Index path the store entry is keyed by, when the opened file is a temp copy of it.
+    pub store_path: Option<&'a Path>,
+}
+
+impl<'a> KeySource<'a> {
+    /// Sources selected by `opts` (`key_file`, and the OS keyring when `keyring` is set).
+    #[must_use]
+    pub fn from_opts(opts: &'a crate::Opts) -> Self {
+        Self {
+            key_file: opts.key_file.as_deref(),
+            store: os_keyring(opts.keyring),
+            store_path: None,
+        }
+    }
+}
+
+fn os_keyring(enabled: bool) -> Option<&'static dyn SecretStore> {
+    #[cfg(feature = "keyring")]
+    {
+        enabled.then_some(&OsKeyring as &dyn SecretStore)
+    }
+    #[cfg(not(feature = "keyring"))]
+    {
+        if enabled {
+            warn!("--keyring needs nefaxer built with the `keyring` feature; ignoring");
+        }
+        None
+    }
+}
+
+/// Secret-store account for the index at `db_path`: its absolute path, so each index has its own entry.
+#[must_use]
+pub fn keyring_account(db_path: &Path) -> String {
+    std::path::absolute(db_path)
+        .unwrap_or_else(|_| db_path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Save a passphrase that opened the index at `db_path` to `keys.store`, if any. A missing or
+/// failing store only logs a warning: the key still works for this run.
+pub fn remember_passphrase(db_path: &Path, keys: &KeySource<'_>, pass: &str) {
+    let Some(store) = keys.store else {
+        return;
+    };
+    let account = keyring_account(keys.store_path.unwrap_or(db_path));
+    match store.set(KEYRING_SERVICE, &account, pass) {
+        Ok(()) => debug!("Passphrase saved to keyring for {}", db_path.display()),
+        Err(e) => warn!("Could not save passphrase to keyring: {e:#}"),
+    }
+}
+
 fn try_env_then_dotenv(dir: &Path) -> Option<String> {
     if let Ok(s) = std::env::var(ENV_KEY) {
         let s = s.trim().to_string();
@@ -27,18 +126,79 @@ fn try_env_then_dotenv(dir: &Path) -> Option<String> {
     None
 }
 
-/// Read passphrase: env (`NEFAXER_DB_KEY`) → .env in `dir` → secure prompt.
+/// Read the passphrase from `path` (surrounding whitespace and the trailing newline trimmed).
+/// On Unix, warns when the file is readable by group or others.
+pub(crate) fn read_key_file(path: &Path) -> Result<String> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("read key file {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(meta) = std::fs::metadata(path)
+            && meta.permissions().mode() & 0o077 != 0
+        {
+            warn!(
+                "Key file {} is accessible by other users; consider chmod 600",
+                path.display()
+            );
+        }
+    }
+    let key = contents.trim();
+    if key.is_empty() {
+        anyhow::bail!("key file {} is empty", path.display());
+    }
+    Ok(key.to_string())
+}
+
+/// Read passphrase: `key_file` → env (`NEFAXER_DB_KEY`) → .env in `dir` → secure prompt.
 /// `is_new`: true for creating a new encrypted index (prompt says "New ...", reminds to note it down).
+/// A given `key_file` must be readable and non-empty; there is no fallback past it.
 ///
 /// # Errors
 ///
-/// Returns [`anyhow::Error`] when reading the password from the terminal fails.
-pub fn get_passphrase(dir: &Path, is_new: bool) -> Result<String> {
+/// Returns [`anyhow::Error`] when the key file cannot be read or is empty, or reading the password
+/// from the terminal fails.
+pub fn get_passphrase(dir: &Path, is_new: bool, key_file: Option<&Path>) -> Result<String> {
+    let keys = KeySource {
+        key_file,
+        ..KeySource::default()
+    };
+    get_passphrase_for_db(None, dir, is_new, &keys)
+}
+
+/// Like [`get_passphrase`], with `keys.store` consulted (for the index at `db_path`) before
+/// prompting. An unavailable store falls through to the prompt. Callers save a passphrase that
+/// turned out to open the index with [`remember_passphrase`].
+///
+/// # Errors
+///
+/// Same as [`get_passphrase`].
+pub fn get_passphrase_for_db(
+    db_path: Option<&Path>,
+    dir: &Path,
+    is_new: bool,
+    keys: &KeySource<'_>,
+) -> Result<String> {
     info!("Encryption mode (either flag was provided or encrypted index was detected)");
+    if let Some(path) = keys.key_file {
+        let key = read_key_file(path)?;
+        info!("Passphrase read from key file");
+        return Ok(key);
+    }
     if let Some(s) = try_env_then_dotenv(dir) {
         info!("Passphrase found in environment");
         return Ok(s);
     }
+    if let (Some(store), Some(db)) = (keys.store, keys.store_path.or(db_path)) {
+        match store.get(KEYRING_SERVICE, &keyring_account(db)) {
+            Ok(Some(s)) => {
+                info!("Passphrase found in keyring");
+                return Ok(s);
+            }
+            Ok(None) => debug!("No passphrase in keyring for {}", db.display()),
+            Err(e) => warn!("Keyring unavailable ({e:#}); falling back to prompt"),
+        }
+    }
     let label = format!("[{}]", env!("CARGO_PKG_NAME")).cyan().bold();
     let prompt = if is_new {
         "Create new passphrase: "
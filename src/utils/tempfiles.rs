@@ -1,9 +1,14 @@
 use anyhow::{Context, Result};
+use log::warn;
+use rusqlite::Connection;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use crate::utils::config::PackagePaths;
 
+/// First bytes of every plaintext `SQLite` database (`SQLCipher` files start with random salt).
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
 /// Get the temporary path for the index database.
 #[must_use]
 pub fn temp_path_for(db_path: &Path) -> PathBuf {
@@ -28,44 +33,144 @@ pub fn remove_temp_wal_and_shm(temp_path: &Path) {
     let _ = std::fs::remove_file(parent.join(format!("{file_name}-shm")));
 }
 
+/// Whether the file at `path` starts with the plaintext `SQLite` header.
+fn has_sqlite_header(path: &Path) -> bool {
+    let mut header = [0u8; SQLITE_HEADER.len()];
+    fs::File::open(path)
+        .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut header))
+        .is_ok_and(|()| &header == SQLITE_HEADER)
+}
+
+/// Remove a temp index and its WAL/SHM files. `Ok(false)` when permission is denied.
+fn discard_temp(temp_path: &Path) -> Result<bool> {
+    remove_temp_wal_and_shm(temp_path);
+    match fs::remove_file(temp_path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Ok(false),
+        Err(e) => {
+            Err(e).with_context(|| format!("remove stale temp index at {}", temp_path.display()))
+        }
+    }
+}
+
+/// Copy the existing index to the temp path. `Ok(false)` when permission is denied.
+fn copy_to_temp(db_path: &Path, temp_path: &Path) -> Result<bool> {
+    match fs::copy(db_path, temp_path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Ok(false),
+        Err(e) => Err(e).context(format!(
+            "copy existing index to temp ({} -> {})",
+            db_path.display(),
+            temp_path.display()
+        )),
+    }
+}
+
 /// Prepare work path for indexing: temp file and whether to use it (atomic rename).
 /// Removes stale temp and WAL/SHM; copies existing DB to temp when possible. On permission denied, falls back to writing directly to `db_path`.
 ///
+/// A temp left behind by a crashed run is never reused, intact or not: it holds part of a write
+/// that never finished, while `db_path` is the last complete index. It is removed together with its
+/// WAL/SHM files (without opening it, so nothing is replayed) before the fresh copy is made.
+///
 /// # Errors
 ///
 /// Returns [`anyhow::Error`] when removing a stale temp file or copying the existing index fails
 /// (other than permission-denied fallback cases).
 pub fn prepare_index_work_path(db_path: &Path) -> Result<(PathBuf, bool)> {
     let temp_path = temp_path_for(db_path);
-    let mut use_temp = true;
 
     if temp_path.exists() {
-        remove_temp_wal_and_shm(&temp_path);
-        if let Err(e) = std::fs::remove_file(&temp_path) {
-            if e.kind() == std::io::ErrorKind::PermissionDenied {
-                use_temp = false;
-            } else {
-                return Err(e).with_context(|| {
-                    format!("remove stale temp index at {}", temp_path.display())
-                });
-            }
+        warn!(
+            "Discarding temp index left by an interrupted run at {}",
+            temp_path.display()
+        );
+        if !discard_temp(&temp_path)? {
+            return Ok((temp_path, false));
         }
     }
-    if use_temp
-        && db_path.exists()
-        && let Err(e) = std::fs::copy(db_path, &temp_path)
-    {
-        if e.kind() == std::io::ErrorKind::PermissionDenied {
-            use_temp = false;
-        } else {
-            return Err(e).context(format!(
-                "copy existing index to temp ({} -> {})",
+    if !db_path.exists() {
+        return Ok((temp_path, true));
+    }
+    if !copy_to_temp(db_path, &temp_path)? {
+        return Ok((temp_path, false));
+    }
+    Ok((temp_path, true))
+}
+
+/// Throwaway copy of an index in [`std::env::temp_dir`], removed (with its WAL/SHM files) on drop.
+/// Used by preview runs, which go through the real writer without touching the index on disk.
+/// The file is created fresh with owner-only permissions, so nothing planted in the temp dir is
+/// written through.
+pub struct ThrowawayIndex(tempfile::TempPath);
+
+impl ThrowawayIndex {
+    /// Copy `db_path` (when it exists; otherwise the copy starts empty) to a new temp file.
+    /// Plaintext indexes are copied with the `SQLite` backup API, which includes writes still in the
+    /// WAL; encrypted ones (whose key is not known yet) are copied byte for byte together with their
+    /// WAL, which is replayed when the copy is opened.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`anyhow::Error`] when the temp file cannot be created or the index cannot be copied.
+    pub fn create(db_path: &Path) -> Result<Self> {
+        let mut file = tempfile::Builder::new()
+            .prefix("nefaxer-preview-")
+            .suffix(".db")
+            .tempfile()
+            .context("create temp file for preview")?;
+        let copy_path = file.path().to_path_buf();
+        let context = || {
+            format!(
+                "copy index for preview ({} -> {})",
                 db_path.display(),
-                temp_path.display()
-            ));
+                copy_path.display()
+            )
+        };
+        if has_sqlite_header(db_path) {
+            Connection::open(db_path)
+                .and_then(|src| src.backup(rusqlite::MAIN_DB, &copy_path, None))
+                .with_context(context)?;
+        } else if db_path.exists() {
+            fs::File::open(db_path)
+                .and_then(|mut src| std::io::copy(&mut src, file.as_file_mut()))
+                .with_context(context)?;
+            let wal = sidecar(db_path, "-wal");
+            if wal.exists() {
+                fs::File::open(&wal)
+                    .and_then(|mut src| {
+                        let mut dst = fs::File::options()
+                            .write(true)
+                            .create_new(true)
+                            .open(sidecar(&copy_path, "-wal"))?;
+                        std::io::copy(&mut src, &mut dst)
+                    })
+                    .with_context(context)?;
+            }
         }
+        Ok(Self(file.into_temp_path()))
     }
-    Ok((temp_path, use_temp))
+
+    /// Path of the copy.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ThrowawayIndex {
+    fn drop(&mut self) {
+        // The temp file itself is removed when `self.0` drops.
+        remove_temp_wal_and_shm(&self.0);
+    }
+}
+
+/// `path` with `suffix` appended to its file name (e.g. `-wal`).
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
 }
 
 /// Atomically replace the final index path with the temp file and remove WAL/SHM sidecars.
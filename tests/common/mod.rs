@@ -0,0 +1,111 @@
+//! Shared helpers for integration tests that run the full pipeline on a temp tree.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::sync::Once;
+
+use tempfile::TempDir;
+
+/// Create a temp directory to use as an index root.
+/// When tests run as root (e.g. in containers), hand the dir to `nobody` so the root-owned check
+/// in `check_root_and_canonicalize` does not reject it.
+pub fn temp_root() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    #[cfg(unix)]
+    if nefaxer::engine::running_as_root() {
+        std::os::unix::fs::chown(dir.path(), Some(65534), Some(65534)).unwrap();
+    }
+    dir
+}
+
+/// Write `contents` to `root/rel`, creating parent directories as needed.
+pub fn write_file(root: &Path, rel: &str, contents: &[u8]) {
+    let path = root.join(rel);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, contents).unwrap();
+}
+
+/// Persist a lib-built index to an index DB at `db` (same rows the CLI would write).
+pub fn save_index(db: &Path, nefax: &nefaxer::Nefax) {
+    let conn = nefaxer::engine::open_db(db, None).unwrap();
+    for (path, meta) in nefax {
+        conn.execute(
+            "INSERT OR REPLACE INTO paths (path, mtime_ns, size, hash) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![
+                nefaxer::engine::path_to_db_string(path),
+                meta.mtime_ns,
+                meta.size as i64,
+                meta.hash.map(|h| h.to_vec())
+            ],
+        )
+        .unwrap();
+    }
+}
+
+/// Recursively copy `src` into `dst`, preserving file and directory mtimes.
+pub fn copy_tree(src: &Path, dst: &Path) {
+    for entry in fs::read_dir(src).unwrap() {
+        let entry = entry.unwrap();
+        let target = dst.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            fs::create_dir_all(&target).unwrap();
+            copy_tree(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), &target).unwrap();
+        }
+        let mtime = entry.metadata().unwrap().modified().unwrap();
+        fs::File::open(&target)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+}
+
+thread_local! {
+    /// Messages logged on this thread while [`capture_logs`] runs.
+    static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Process-wide logger that keeps each message on the thread that logged it, so tests running in
+/// parallel only see their own lines.
+struct CaptureLogger;
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        CAPTURED.with(|c| {
+            if let Some(lines) = c.borrow_mut().as_mut() {
+                lines.push(record.args().to_string());
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Run `f` and return its result with the messages it logged on the calling thread (at any level,
+/// without the logger's prefix). Lines logged by other threads, e.g. pipeline workers, are not seen.
+/// Panics when another logger was installed in the test binary first, so tests that call
+/// `handle_run` (which installs the CLI logger) must run it through here.
+pub fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        // Another logger (e.g. `setup_logging`) set first would swallow every line and make the
+        // captured list silently empty; fail here instead.
+        log::set_logger(&CaptureLogger).expect(
+            "capture_logs: another logger is already installed in this test binary; do not call setup_logging in tests that share it",
+        );
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+    CAPTURED.with(|c| *c.borrow_mut() = Some(Vec::new()));
+    let out = f();
+    let lines = CAPTURED.with(|c| c.borrow_mut().take()).unwrap_or_default();
+    (out, lines)
+}
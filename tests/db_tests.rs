@@ -1,7 +1,27 @@
 //! DB tests: path_count_from_db, load_index round-trip, and file-DB fixture.
+// Encrypted-index tests (and their keyring mocks) only build with the `sqlcipher` feature.
+#![cfg_attr(not(feature = "sqlcipher"), allow(unused_imports, dead_code))]
 
-use nefaxer::engine::{load_index, open_db, open_db_in_memory, path_count_from_db};
-use std::path::PathBuf;
+use nefaxer::disk_detect::probe::{
+    DiskInfo, DiskTypeInfo, ProbeDirGuard, ProbeParams, ProbeThresholds, clear_disk_cache,
+    load_cache_from_db, measure_disk, probe_disk_type, prune_disk_cache, save_cache_to_db,
+};
+use nefaxer::engine::{
+    ApplyIndexDiffStreamingParams, HASH_ALGO, IndexMeta, WriteStats, apply_durability,
+    apply_index_diff_streaming, explain_db_locked, get_path_meta, index_file_digest,
+    index_meta_warnings, is_db_locked, list_index, load_index, load_index_meta, open_db,
+    open_db_exclusive, open_db_in_memory, open_db_or_detect_encrypted, path_count_from_db,
+    query_stale, store_index_meta,
+};
+use nefaxer::utils::{
+    KEYRING_SERVICE, KeySource, SecretStore, keyring_account, prepare_index_work_path,
+    temp_path_for,
+};
+use nefaxer::{DbDurability, Entry, Opts};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 const INSERT_PATH_SQL: &str =
     "INSERT OR REPLACE INTO paths (path, mtime_ns, size, hash) VALUES (?1, ?2, ?3, ?4)";
@@ -68,33 +88,71 @@ fn test_load_index_round_trip() {
     );
 }
 
-/// Uses tests/fixtures/.nefaxer_simple: create if missing (empty schema), then path_count → 0.
 #[test]
-fn test_path_count_from_db_file_fixture_simple() {
-    let fixtures_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+fn test_get_path_meta_present_and_absent() {
+    let conn = open_db_in_memory().unwrap();
+    conn.execute(
+        INSERT_PATH_SQL,
+        rusqlite::params!["dir/a.txt", 1000_i64, 100_i64, Some(vec![7u8; 32])],
+    )
+    .unwrap();
+
+    let meta = get_path_meta(&conn, Path::new("dir/a.txt"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(meta.mtime_ns, 1000);
+    assert_eq!(meta.size, 100);
+    assert_eq!(meta.hash, Some([7u8; 32]));
+    // Backslashes are looked up as the stored forward slashes.
+    assert!(
+        get_path_meta(&conn, Path::new("dir\\a.txt"))
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        get_path_meta(&conn, Path::new("dir/missing.txt"))
+            .unwrap()
+            .is_none()
+    );
+}
+
+/// Copy `tests/fixtures/<name>` into a fresh temp dir so opening it (which migrates the schema)
+/// never rewrites the checked-in fixture. `None` when the fixture is missing.
+fn fixture_copy(name: &str) -> Option<(tempfile::TempDir, PathBuf)> {
+    let src = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .join("tests")
-        .join("fixtures");
-    let db_path = fixtures_dir.join(".nefaxer_simple");
-    std::fs::create_dir_all(&fixtures_dir).unwrap();
+        .join("fixtures")
+        .join(name);
+    if !src.exists() {
+        eprintln!(
+            "skip: {} not found (copy a .nefaxer here and rename)",
+            src.display()
+        );
+        return None;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join(name);
+    std::fs::copy(&src, &db_path).unwrap();
+    Some((dir, db_path))
+}
+
+/// Uses a copy of tests/fixtures/.nefaxer_simple (an empty index), then path_count → 0.
+#[test]
+fn test_path_count_from_db_file_fixture_simple() {
+    let Some((_dir, db_path)) = fixture_copy(".nefaxer_simple") else {
+        return;
+    };
     let conn = open_db(&db_path, None).unwrap();
     assert_eq!(path_count_from_db(&conn), Some(0));
 }
 
-/// Uses tests/fixtures/.nefaxer_complex: real index of this repo (diskinfo wiped).
+/// Uses a copy of tests/fixtures/.nefaxer_complex: real index of this repo (diskinfo wiped).
 /// Guards path_count_from_db and load_index on a real-sized fixture.
 #[test]
 fn test_path_count_and_load_index_complex_fixture() {
-    let db_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("tests")
-        .join("fixtures")
-        .join(".nefaxer_complex");
-    if !db_path.exists() {
-        eprintln!(
-            "skip: {} not found (copy a .nefaxer here and rename)",
-            db_path.display()
-        );
+    let Some((_dir, db_path)) = fixture_copy(".nefaxer_complex") else {
         return;
-    }
+    };
     let conn = open_db(&db_path, None).unwrap();
     let count = path_count_from_db(&conn).expect("COUNT(*) should succeed");
     assert!(count > 0, "complex fixture should have at least one path");
@@ -112,3 +170,962 @@ fn test_path_count_and_load_index_complex_fixture() {
         );
     }
 }
+
+// --- apply_index_diff_streaming ---
+
+/// Entry for a file written under `root` (metadata only; hash filled by the writer).
+fn file_entry(root: &Path, rel: &str, contents: &[u8]) -> Entry {
+    let abs = root.join(rel);
+    std::fs::write(&abs, contents).unwrap();
+    let meta = std::fs::metadata(&abs).unwrap();
+    let mtime_ns = meta
+        .modified()
+        .unwrap()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as i64;
+    Entry {
+        path: PathBuf::from(rel),
+        mtime_ns,
+        size: meta.len(),
+        hash: None,
+        ..Default::default()
+    }
+}
+
+/// Run the streaming writer over `entries`, returning how many times the hashing callback fired.
+fn stream_entries_counting_hashes(
+    conn: &mut rusqlite::Connection,
+    root: &Path,
+    entries: Vec<Entry>,
+) -> usize {
+    let existing = load_index(conn).unwrap();
+    let (tx, rx) = crossbeam_channel::unbounded();
+    tx.send(entries).unwrap();
+    drop(tx);
+    let hashed = Arc::new(AtomicUsize::new(0));
+    let hashed_cb = Arc::clone(&hashed);
+    let mut params = ApplyIndexDiffStreamingParams {
+        existing: &existing,
+        existing_extras: None,
+        mtime_window_ns: 0,
+        mtime_granularity_ns: 0,
+        change_policy: nefaxer::ChangePolicy::MtimeSize,
+        on_batch_progress: None,
+        on_received_progress: None,
+        on_hash_progress: Some(Box::new(move |n| {
+            hashed_cb.fetch_add(n, Ordering::Relaxed);
+        })),
+        root: Some(root),
+        with_hash: true,
+        hash_small_files: false,
+        cancel_check: None,
+        deadline_check: None,
+        walk_capped: None,
+        diff: None,
+        result_map: None,
+        path_codec: None,
+        append_only: false,
+        run_started_at: 0,
+        hash_key: None,
+    };
+    apply_index_diff_streaming(conn, &rx, &mut params).unwrap();
+    hashed.load(Ordering::Relaxed)
+}
+
+#[test]
+fn test_hash_progress_counts_each_file_over_threshold_hashed_or_reused() {
+    let dir = tempfile::tempdir().unwrap();
+    let big = vec![7u8; 8 * 1024];
+    let entries = vec![
+        file_entry(dir.path(), "big_a.bin", &big),
+        file_entry(dir.path(), "big_b.bin", &big),
+        file_entry(dir.path(), "small.txt", b"tiny"),
+    ];
+    let mut conn = open_db_in_memory().unwrap();
+
+    // Small files are below the hashing threshold, so only the two big files are hashed.
+    assert_eq!(
+        stream_entries_counting_hashes(&mut conn, dir.path(), entries.clone()),
+        2
+    );
+
+    // Second run: mtime/size unchanged, so the stored hashes are reused; the bar still advances for
+    // each so it reaches the total taken from the previous index.
+    let map: HashMap<PathBuf, _> = load_index(&conn).unwrap();
+    assert!(map[&PathBuf::from("big_a.bin")].2.is_some());
+    assert_eq!(
+        stream_entries_counting_hashes(&mut conn, dir.path(), entries),
+        2
+    );
+}
+
+// --- meta table / option mismatch warnings ---
+
+#[test]
+fn test_deadline_stops_streaming_and_flushes_partial_index() {
+    let mut conn = open_db_in_memory().unwrap();
+    conn.execute(INSERT_PATH_SQL, ("old.txt", 1_i64, 1_i64, None::<Vec<u8>>))
+        .unwrap();
+    let existing = load_index(&conn).unwrap();
+
+    // Slow producer: one entry every 50 ms, far longer in total than the deadline.
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    let producer = std::thread::spawn(move || {
+        for i in 0..100 {
+            let entry = Entry {
+                path: PathBuf::from(format!("f{i}.txt")),
+                mtime_ns: 1,
+                size: 1,
+                ..Default::default()
+            };
+            if tx.send(vec![entry]).is_err() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+    });
+    let timer = nefaxer::engine::DeadlineTimer::start(std::time::Duration::from_millis(300));
+    let mut params = ApplyIndexDiffStreamingParams {
+        existing: &existing,
+        existing_extras: None,
+        mtime_window_ns: 0,
+        mtime_granularity_ns: 0,
+        change_policy: nefaxer::ChangePolicy::MtimeSize,
+        on_batch_progress: None,
+        on_received_progress: None,
+        on_hash_progress: None,
+        root: None,
+        with_hash: false,
+        hash_small_files: false,
+        cancel_check: None,
+        deadline_check: Some(timer.flag()),
+        walk_capped: None,
+        diff: None,
+        result_map: None,
+        path_codec: None,
+        append_only: false,
+        run_started_at: 0,
+        hash_key: None,
+    };
+    let started = std::time::Instant::now();
+    let written = apply_index_diff_streaming(&mut conn, &rx, &mut params)
+        .unwrap()
+        .inserted;
+    assert!(started.elapsed() < std::time::Duration::from_secs(3));
+    drop(rx);
+    producer.join().unwrap();
+
+    assert!(timer.fired());
+    assert!(written > 0 && written < 100, "wrote {written} entries");
+    let index = load_index(&conn).unwrap();
+    // Entries received before the deadline were flushed; the unvisited old row was not deleted.
+    assert_eq!(index.len(), written + 1);
+    assert!(index.contains_key(Path::new("old.txt")));
+}
+
+#[test]
+fn test_index_meta_round_trip() {
+    let conn = open_db_in_memory().unwrap();
+    assert_eq!(load_index_meta(&conn).unwrap(), IndexMeta::default());
+
+    let opts = Opts {
+        with_hash: true,
+        mtime_window_ns: 2_000_000_000,
+        ..Default::default()
+    };
+    store_index_meta(&conn, &opts).unwrap();
+    let meta = load_index_meta(&conn).unwrap();
+    assert_eq!(meta.with_hash, Some(true));
+    assert_eq!(meta.hash_algo.as_deref(), Some(HASH_ALGO));
+    assert_eq!(meta.mtime_window_ns, Some(2_000_000_000));
+    assert!(meta.updated_at.is_some());
+}
+
+#[test]
+fn test_index_meta_warns_when_with_hash_toggled() {
+    let conn = open_db_in_memory().unwrap();
+    let hashed = Opts {
+        with_hash: true,
+        ..Default::default()
+    };
+    store_index_meta(&conn, &hashed).unwrap();
+    let stored = load_index_meta(&conn).unwrap();
+
+    assert!(index_meta_warnings(&stored, &hashed).is_empty());
+    let warnings = index_meta_warnings(&stored, &Opts::default());
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("built with hashing"));
+
+    // Legacy index (no meta rows): nothing to compare against.
+    assert!(index_meta_warnings(&IndexMeta::default(), &Opts::default()).is_empty());
+}
+
+#[test]
+fn test_index_meta_warns_on_path_key_mismatch_and_lookup_follows_it() {
+    let conn = open_db_in_memory().unwrap();
+    let lowercase = Opts {
+        path_key: nefaxer::PathKeyPolicy::Lowercase,
+        ..Default::default()
+    };
+    store_index_meta(&conn, &lowercase).unwrap();
+    let stored = load_index_meta(&conn).unwrap();
+    assert_eq!(stored.path_key.as_deref(), Some("lowercase"));
+
+    assert!(index_meta_warnings(&stored, &lowercase).is_empty());
+    let warnings = index_meta_warnings(&stored, &Opts::default());
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("path keys use policy lowercase"));
+
+    conn.execute(
+        INSERT_PATH_SQL,
+        rusqlite::params!["docs/readme.md", 1000_i64, 10_i64, None::<Vec<u8>>],
+    )
+    .unwrap();
+    assert!(
+        get_path_meta(&conn, Path::new("Docs/README.md"))
+            .unwrap()
+            .is_some()
+    );
+}
+
+// --- diskinfo probe cache ---
+
+#[test]
+fn test_clear_disk_cache_removes_root_row() {
+    let conn = open_db_in_memory().unwrap();
+    let info = DiskInfo {
+        disk_type: DiskTypeInfo {
+            drive_type: "SSD".to_string(),
+            random_iops: 5000.0,
+            probe_files: 50,
+            tested_at: 1,
+        },
+        network: None,
+        recommended_workers: 8,
+    };
+    save_cache_to_db(&conn, "/mnt/share", &info).unwrap();
+    save_cache_to_db(&conn, "/mnt/other", &info).unwrap();
+    assert!(load_cache_from_db(&conn, "/mnt/share").unwrap().is_some());
+
+    clear_disk_cache(&conn, Path::new("/mnt/share")).unwrap();
+    assert!(load_cache_from_db(&conn, "/mnt/share").unwrap().is_none());
+    assert!(load_cache_from_db(&conn, "/mnt/other").unwrap().is_some());
+}
+
+#[test]
+fn test_prune_disk_cache_keeps_only_listed_roots() {
+    let conn = open_db_in_memory().unwrap();
+    let info = DiskInfo {
+        disk_type: DiskTypeInfo {
+            drive_type: "HDD".to_string(),
+            random_iops: 120.0,
+            probe_files: 50,
+            tested_at: 1,
+        },
+        network: None,
+        recommended_workers: 2,
+    };
+    for root in ["/data", "/mnt/old", "/mnt/gone", "/media/usb"] {
+        save_cache_to_db(&conn, root, &info).unwrap();
+    }
+
+    assert_eq!(prune_disk_cache(&conn, &[Path::new("/data")]).unwrap(), 3);
+    assert!(load_cache_from_db(&conn, "/data").unwrap().is_some());
+    for root in ["/mnt/old", "/mnt/gone", "/media/usb"] {
+        assert!(load_cache_from_db(&conn, root).unwrap().is_none());
+    }
+    // Nothing left to prune.
+    assert_eq!(prune_disk_cache(&conn, &[Path::new("/data")]).unwrap(), 0);
+}
+
+#[test]
+fn test_probe_file_count_is_configurable() {
+    let defaults = ProbeParams::default();
+    assert_eq!(ProbeParams::from_strs(None, None), defaults);
+
+    let params = ProbeParams::from_strs(Some(" 3 "), Some("1024"));
+    assert_eq!(params.num_files, 3);
+    assert_eq!(params.file_size, 1024);
+
+    // Unparsable values fall back to the defaults.
+    assert_eq!(ProbeParams::from_strs(Some("many"), Some("-1")), defaults);
+    assert_eq!(ProbeParams::from_strs(Some(""), Some("1.5")), defaults);
+
+    // Out-of-range values are clamped rather than rejected.
+    let clamped = ProbeParams::from_strs(Some("0"), Some("99999999999"));
+    assert_eq!(clamped.num_files, 1);
+    assert_eq!(clamped.file_size, 1024 * 1024);
+    assert_eq!(
+        ProbeParams::from_strs(Some("5000"), Some("0")).num_files,
+        1000
+    );
+    assert_eq!(ProbeParams::from_strs(None, Some("0")).file_size, 512);
+
+    let dir = tempfile::tempdir().unwrap();
+    let info = probe_disk_type(dir.path(), params, &ProbeThresholds::default()).unwrap();
+    assert_eq!(info.probe_files, 3);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_probe_thresholds_classify_with_custom_iops_cutoff() {
+    let defaults = ProbeThresholds::default();
+    assert_eq!(defaults.classify(100.0), "Network+HDD");
+    assert_eq!(defaults.classify(400.0), "Network+SSD");
+
+    // A cached-SSD NAS measuring 400 IOPS still counts as HDD under a stricter cutoff.
+    let strict = ProbeThresholds {
+        hdd_iops: 500.0,
+        ..Default::default()
+    };
+    assert_eq!(strict.classify(400.0), "Network+HDD");
+    assert_eq!(strict.classify(600.0), "Network+SSD");
+
+    // The probe itself classifies with the thresholds it is given.
+    let dir = tempfile::tempdir().unwrap();
+    let params = ProbeParams {
+        num_files: 2,
+        file_size: 512,
+    };
+    let always_hdd = ProbeThresholds {
+        hdd_iops: f64::MAX,
+        ..Default::default()
+    };
+    let info = probe_disk_type(dir.path(), params, &always_hdd).unwrap();
+    assert_eq!(info.drive_type, "Network+HDD");
+    let always_ssd = ProbeThresholds {
+        hdd_iops: f64::MIN_POSITIVE,
+        ..Default::default()
+    };
+    let info = probe_disk_type(dir.path(), params, &always_ssd).unwrap();
+    assert_eq!(info.drive_type, "Network+SSD");
+}
+
+#[test]
+fn test_measure_disk_on_local_dir_returns_finite_numbers() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let info = measure_disk(dir.path()).unwrap();
+
+    assert!(info.disk_type.random_iops.is_finite() && info.disk_type.random_iops > 0.0);
+    let network = info.network.expect("latency is always measured");
+    assert!(network.latency_ms.is_finite() && network.latency_ms >= 0.0);
+    assert!(info.recommended_workers > 0);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_probe_dir_removed_after_panic_mid_probe() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().to_path_buf();
+
+    let result = std::panic::catch_unwind(move || {
+        let guard = ProbeDirGuard::create(&base).unwrap();
+        std::fs::write(guard.path().join("test_0.dat"), [0u8; 16]).unwrap();
+        panic!("interrupted mid-probe");
+    });
+
+    assert!(result.is_err());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_probe_clears_stale_probe_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let stale = ProbeDirGuard::create(dir.path()).unwrap();
+    std::fs::write(stale.path().join("leftover.dat"), b"x").unwrap();
+    // Simulate a crashed run: the directory outlives its guard.
+    std::mem::forget(stale);
+
+    let guard = ProbeDirGuard::create(dir.path()).unwrap();
+    assert_eq!(std::fs::read_dir(guard.path()).unwrap().count(), 0);
+    drop(guard);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+// --- batched deletes ---
+
+#[test]
+fn test_large_removal_matches_per_row_delete() {
+    let conn = open_db_in_memory().unwrap();
+    let total = 5_003; // not a multiple of the delete chunk size
+    for i in 0..total {
+        conn.execute(
+            INSERT_PATH_SQL,
+            rusqlite::params![format!("dir/file_{i}"), 100_i64, 10_i64, None::<Vec<u8>>],
+        )
+        .unwrap();
+    }
+    let mut conn = conn;
+    let existing = load_index(&conn).unwrap();
+
+    // Keep every 7th path; everything else was removed from the tree.
+    let kept: Vec<PathBuf> = (0..total)
+        .step_by(7)
+        .map(|i| PathBuf::from(format!("dir/file_{i}")))
+        .collect();
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let batch = kept
+        .iter()
+        .map(|p| Entry {
+            path: p.clone(),
+            mtime_ns: 100,
+            size: 10,
+            ..Default::default()
+        })
+        .collect();
+    tx.send(batch).unwrap();
+    drop(tx);
+    let mut diff = nefaxer::Diff::default();
+    let mut params = ApplyIndexDiffStreamingParams {
+        existing: &existing,
+        existing_extras: None,
+        mtime_window_ns: 0,
+        mtime_granularity_ns: 0,
+        change_policy: nefaxer::ChangePolicy::MtimeSize,
+        on_batch_progress: None,
+        on_received_progress: None,
+        on_hash_progress: None,
+        root: None,
+        with_hash: false,
+        hash_small_files: false,
+        cancel_check: None,
+        deadline_check: None,
+        walk_capped: None,
+        diff: Some(&mut diff),
+        result_map: None,
+        path_codec: None,
+        append_only: false,
+        run_started_at: 0,
+        hash_key: None,
+    };
+    apply_index_diff_streaming(&mut conn, &rx, &mut params).unwrap();
+
+    let after = load_index(&conn).unwrap();
+    let mut remaining: Vec<PathBuf> = after.into_keys().collect();
+    remaining.sort();
+    let mut expected = kept.clone();
+    expected.sort();
+    assert_eq!(remaining, expected);
+    assert_eq!(diff.removed.len(), total - kept.len());
+}
+
+// --- last_seen ---
+
+/// Stream `paths` (fixed mtime/size) as one run started at `run_started_at`.
+fn run_at(
+    conn: &mut rusqlite::Connection,
+    paths: &[&str],
+    run_started_at: i64,
+    append_only: bool,
+) -> WriteStats {
+    let existing = load_index(conn).unwrap();
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let batch = paths
+        .iter()
+        .map(|p| Entry {
+            path: PathBuf::from(p),
+            mtime_ns: 100,
+            size: 10,
+            ..Default::default()
+        })
+        .collect();
+    tx.send(batch).unwrap();
+    drop(tx);
+    let mut params = ApplyIndexDiffStreamingParams {
+        existing: &existing,
+        existing_extras: None,
+        mtime_window_ns: 0,
+        mtime_granularity_ns: 0,
+        change_policy: nefaxer::ChangePolicy::MtimeSize,
+        on_batch_progress: None,
+        on_received_progress: None,
+        on_hash_progress: None,
+        root: None,
+        with_hash: false,
+        hash_small_files: false,
+        cancel_check: None,
+        deadline_check: None,
+        walk_capped: None,
+        diff: None,
+        result_map: None,
+        path_codec: None,
+        append_only,
+        run_started_at,
+        hash_key: None,
+    };
+    apply_index_diff_streaming(conn, &rx, &mut params).unwrap()
+}
+
+fn last_seen(conn: &rusqlite::Connection, path: &str) -> Option<i64> {
+    conn.query_row(
+        "SELECT last_seen FROM paths WHERE path = ?1",
+        [path],
+        |row| row.get(0),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_last_seen_advances_for_persisting_file() {
+    let mut conn = open_db_in_memory().unwrap();
+    run_at(&mut conn, &["keep.txt", "gone.txt"], 1_000, true);
+    assert_eq!(last_seen(&conn, "keep.txt"), Some(1_000));
+
+    // Second run: keep.txt unchanged (not rewritten) but seen; gone.txt kept by append-only.
+    run_at(&mut conn, &["keep.txt"], 2_000, true);
+    assert_eq!(last_seen(&conn, "keep.txt"), Some(2_000));
+    assert_eq!(last_seen(&conn, "gone.txt"), Some(1_000));
+    assert_eq!(
+        query_stale(&conn, 1_500).unwrap(),
+        vec![PathBuf::from("gone.txt")]
+    );
+
+    // Without append-only, unchanged rows advance too.
+    run_at(&mut conn, &["keep.txt"], 3_000, false);
+    assert_eq!(last_seen(&conn, "keep.txt"), Some(3_000));
+}
+
+#[test]
+fn test_query_stale_without_append_only_skips_present_files() {
+    let mut conn = open_db_in_memory().unwrap();
+    run_at(&mut conn, &["keep.txt", "gone.txt"], 1_000, false);
+    run_at(&mut conn, &["keep.txt"], 2_000, false);
+    // keep.txt is unchanged and still on disk, so it is not stale; gone.txt was deleted.
+    assert!(query_stale(&conn, 1_500).unwrap().is_empty());
+    assert_eq!(last_seen(&conn, "keep.txt"), Some(2_000));
+}
+
+#[test]
+fn test_write_stats_count_new_unchanged_and_removed_rows() {
+    let mut conn = open_db_in_memory().unwrap();
+    let first = run_at(&mut conn, &["a.txt", "b.txt", "c.txt"], 1_000, false);
+    assert_eq!(
+        first,
+        WriteStats {
+            inserted: 3,
+            deleted: 0,
+            unchanged: 0,
+            received: 3,
+            key_collisions: 0,
+        }
+    );
+
+    // a and b unchanged, d new, c removed.
+    let second = run_at(&mut conn, &["a.txt", "b.txt", "d.txt"], 2_000, false);
+    assert_eq!(
+        second,
+        WriteStats {
+            inserted: 1,
+            deleted: 1,
+            unchanged: 2,
+            received: 3,
+            key_collisions: 0,
+        }
+    );
+
+    // Append-only keeps the row of a path gone from disk.
+    let third = run_at(&mut conn, &["a.txt"], 3_000, true);
+    assert_eq!(third.deleted, 0);
+    assert_eq!(third.unchanged, 1);
+}
+
+#[test]
+fn test_list_index_prints_sorted_paths_with_optional_meta() {
+    let conn = open_db_in_memory().unwrap();
+    for (path, mtime, size) in [
+        ("src/b.rs", 300_i64, 30_i64),
+        ("a.txt", 100, 10),
+        ("src/a.rs", 200, 20),
+    ] {
+        conn.execute(
+            INSERT_PATH_SQL,
+            rusqlite::params![path, mtime, size, None::<Vec<u8>>],
+        )
+        .unwrap();
+    }
+
+    let mut plain = Vec::new();
+    assert_eq!(list_index(&conn, false, &mut plain).unwrap(), 3);
+    assert_eq!(
+        String::from_utf8(plain).unwrap(),
+        "a.txt\nsrc/a.rs\nsrc/b.rs\n"
+    );
+
+    let mut tsv = Vec::new();
+    list_index(&conn, true, &mut tsv).unwrap();
+    assert_eq!(
+        String::from_utf8(tsv).unwrap(),
+        "a.txt\t10\t100\nsrc/a.rs\t20\t200\nsrc/b.rs\t30\t300\n"
+    );
+}
+
+#[test]
+fn test_open_db_adds_last_seen_to_old_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("old.nefaxer");
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE paths (path TEXT PRIMARY KEY, mtime_ns INTEGER NOT NULL, size INTEGER NOT NULL, hash BLOB);
+         INSERT INTO paths VALUES ('a.txt', 1, 2, NULL);",
+    )
+    .unwrap();
+    drop(conn);
+    let conn = open_db(&db, None).unwrap();
+    assert_eq!(last_seen(&conn, "a.txt"), None);
+    assert_eq!(query_stale(&conn, 0).unwrap(), vec![PathBuf::from("a.txt")]);
+    assert_eq!(load_index(&conn).unwrap().len(), 1);
+}
+
+#[cfg(feature = "compress")]
+#[test]
+fn test_compressed_index_round_trips_and_is_smaller() {
+    use nefaxer::engine::{PATH_CODEC_ZSTD, compress_index_paths, load_path_codec};
+
+    let dir = tempfile::tempdir().unwrap();
+    let fill = |db: &Path| {
+        let mut conn = open_db(db, None).unwrap();
+        let tx = conn.transaction().unwrap();
+        for i in 0..3000 {
+            let path = format!(
+                "projects/client_{}/src/components/widgets/generated/module_{}/file_{i}.rs",
+                i % 5,
+                i % 20
+            );
+            tx.execute(
+                INSERT_PATH_SQL,
+                rusqlite::params![path, i64::from(i), 10_i64, vec![i as u8; 32]],
+            )
+            .unwrap();
+        }
+        tx.commit().unwrap();
+        conn
+    };
+    let plain_db = dir.path().join("plain.db");
+    let packed_db = dir.path().join("packed.db");
+    let plain = fill(&plain_db);
+    let mut packed = fill(&packed_db);
+    plain.execute_batch("VACUUM").unwrap();
+    plain
+        .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .unwrap();
+
+    compress_index_paths(&mut packed).unwrap();
+    assert_eq!(
+        load_index_meta(&packed).unwrap().path_codec.as_deref(),
+        Some(PATH_CODEC_ZSTD)
+    );
+    let expected = load_index(&plain).unwrap();
+    assert_eq!(load_index(&packed).unwrap(), expected);
+    // Listing sorts decoded paths the same way SQL sorts plain ones.
+    let (mut plain_list, mut packed_list) = (Vec::new(), Vec::new());
+    assert_eq!(list_index(&plain, true, &mut plain_list).unwrap(), 3000);
+    assert_eq!(list_index(&packed, true, &mut packed_list).unwrap(), 3000);
+    assert_eq!(packed_list, plain_list);
+    let plain_len = std::fs::metadata(&plain_db).unwrap().len();
+    let packed_len = std::fs::metadata(&packed_db).unwrap().len();
+    assert!(
+        packed_len < plain_len,
+        "compressed {packed_len} bytes, plain {plain_len} bytes"
+    );
+
+    // Later runs write and delete through the recorded codec.
+    let codec = load_path_codec(&packed).unwrap();
+    assert!(codec.is_some());
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let new_path = PathBuf::from("projects/client_0/src/new_file.rs");
+    tx.send(vec![Entry {
+        path: new_path.clone(),
+        mtime_ns: 1,
+        size: 1,
+        ..Default::default()
+    }])
+    .unwrap();
+    drop(tx);
+    let mut params = ApplyIndexDiffStreamingParams {
+        existing: &expected,
+        existing_extras: None,
+        mtime_window_ns: 0,
+        mtime_granularity_ns: 0,
+        change_policy: nefaxer::ChangePolicy::MtimeSize,
+        on_batch_progress: None,
+        on_received_progress: None,
+        on_hash_progress: None,
+        root: None,
+        with_hash: false,
+        hash_small_files: false,
+        cancel_check: None,
+        deadline_check: None,
+        walk_capped: None,
+        diff: None,
+        result_map: None,
+        path_codec: codec.as_ref(),
+        append_only: false,
+        run_started_at: 0,
+        hash_key: None,
+    };
+    apply_index_diff_streaming(&mut packed, &rx, &mut params).unwrap();
+    let after = load_index(&packed).unwrap();
+    assert_eq!(after.keys().collect::<Vec<_>>(), vec![&new_path]);
+}
+
+/// In-memory [`SecretStore`] standing in for the OS keyring.
+#[derive(Default)]
+struct MockKeyring(std::cell::RefCell<HashMap<(String, String), String>>);
+
+impl SecretStore for MockKeyring {
+    fn get(&self, service: &str, account: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .0
+            .borrow()
+            .get(&(service.to_string(), account.to_string()))
+            .cloned())
+    }
+
+    fn set(&self, service: &str, account: &str, secret: &str) -> anyhow::Result<()> {
+        self.0.borrow_mut().insert(
+            (service.to_string(), account.to_string()),
+            secret.to_string(),
+        );
+        Ok(())
+    }
+}
+
+/// Store whose backend is missing (e.g. no keyring daemon).
+struct UnavailableKeyring;
+
+impl SecretStore for UnavailableKeyring {
+    fn get(&self, _: &str, _: &str) -> anyhow::Result<Option<String>> {
+        anyhow::bail!("no keyring")
+    }
+
+    fn set(&self, _: &str, _: &str, _: &str) -> anyhow::Result<()> {
+        anyhow::bail!("no keyring")
+    }
+}
+
+#[cfg(feature = "sqlcipher")]
+#[test]
+fn test_keyring_stores_then_retrieves_passphrase() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("enc.db");
+    open_db(&db, Some("hunter2")).unwrap();
+    let key_file = dir.path().join("key");
+    std::fs::write(&key_file, "hunter2\n").unwrap();
+
+    // First open gets the key elsewhere (the key file stands in for the prompt) and stores it.
+    let store = MockKeyring::default();
+    let first = KeySource {
+        key_file: Some(&key_file),
+        store: Some(&store),
+        ..Default::default()
+    };
+    let (_, used) = open_db_or_detect_encrypted(&db, dir.path(), &first).unwrap();
+    assert_eq!(used.as_deref(), Some("hunter2"));
+    assert_eq!(
+        store.get(KEYRING_SERVICE, &keyring_account(&db)).unwrap(),
+        Some("hunter2".to_string())
+    );
+
+    // Later opens fetch it silently.
+    let later = KeySource {
+        store: Some(&store),
+        ..Default::default()
+    };
+    let (conn, _) = open_db_or_detect_encrypted(&db, dir.path(), &later).unwrap();
+    assert_eq!(path_count_from_db(&conn), Some(0));
+
+    // A missing keyring backend is not fatal.
+    let fallback = KeySource {
+        key_file: Some(&key_file),
+        store: Some(&UnavailableKeyring),
+        ..Default::default()
+    };
+    assert!(open_db_or_detect_encrypted(&db, dir.path(), &fallback).is_ok());
+}
+
+#[cfg(feature = "sqlcipher")]
+#[test]
+fn test_wrong_passphrase_fails_on_open() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("enc.db");
+    open_db(&db, Some("right")).unwrap();
+
+    let err = open_db(&db, Some("wrong")).unwrap_err().to_string();
+    assert!(err.contains("wrong passphrase"), "{err}");
+
+    let key_file = dir.path().join("key");
+    std::fs::write(&key_file, "wrong").unwrap();
+    let keys = KeySource {
+        key_file: Some(&key_file),
+        ..Default::default()
+    };
+    let err = open_db_or_detect_encrypted(&db, dir.path(), &keys)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("wrong passphrase"), "{err}");
+}
+
+#[cfg(feature = "sqlcipher")]
+#[test]
+fn test_sqlcipher_available_with_feature() {
+    assert!(nefaxer::engine::sqlcipher_available());
+}
+
+#[cfg(not(feature = "sqlcipher"))]
+#[test]
+fn test_encryption_without_sqlcipher_is_an_error() {
+    assert!(!nefaxer::engine::sqlcipher_available());
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("enc.db");
+    let err = format!("{:#}", open_db(&db, Some("secret")).unwrap_err());
+    assert!(err.contains("no SQLCipher support"), "{err}");
+    // Nothing was written in plaintext.
+    assert!(!db.exists());
+}
+
+#[test]
+fn test_durability_sets_synchronous_level() {
+    let dir = tempfile::tempdir().unwrap();
+    let conn = open_db(&dir.path().join("dur.db"), None).unwrap();
+    let level = |conn: &rusqlite::Connection| -> i64 {
+        conn.query_row("PRAGMA synchronous", [], |row| row.get(0))
+            .unwrap()
+    };
+    // 0 = OFF, 1 = NORMAL, 2 = FULL
+    assert_eq!(level(&conn), 1);
+    apply_durability(&conn, DbDurability::Fast).unwrap();
+    assert_eq!(level(&conn), 0);
+    apply_durability(&conn, DbDurability::Safe).unwrap();
+    assert_eq!(level(&conn), 2);
+    let checkpoint: i64 = conn
+        .query_row("PRAGMA wal_autocheckpoint", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(checkpoint, 1000);
+    assert_eq!("fast".parse::<DbDurability>(), Ok(DbDurability::Fast));
+    assert!("fastest".parse::<DbDurability>().is_err());
+}
+
+#[test]
+fn test_locked_index_waits_for_busy_timeout_then_explains() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("busy.db");
+    let holder = open_db(&db, None).unwrap();
+    let contender = open_db(&db, None).unwrap();
+
+    // Another process holds the write lock briefly: the default timeout waits it out.
+    holder.execute_batch("BEGIN IMMEDIATE").unwrap();
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        holder.execute_batch("COMMIT").unwrap();
+        holder
+    });
+    contender
+        .execute(INSERT_PATH_SQL, ("a.txt", 1_i64, 1_i64, None::<Vec<u8>>))
+        .unwrap();
+    let holder = release.join().unwrap();
+
+    // A lock held past the timeout fails with an explained error.
+    holder.execute_batch("BEGIN IMMEDIATE").unwrap();
+    let waited = std::time::Duration::from_millis(50);
+    contender.busy_timeout(waited).unwrap();
+    let err: anyhow::Error = contender
+        .execute(INSERT_PATH_SQL, ("b.txt", 1_i64, 1_i64, None::<Vec<u8>>))
+        .unwrap_err()
+        .into();
+    assert!(is_db_locked(&err), "{err:#}");
+    let msg = format!("{:#}", explain_db_locked(err, &db, waited));
+    assert!(msg.contains("locked by another process"), "{msg}");
+    holder.execute_batch("ROLLBACK").unwrap();
+}
+
+#[test]
+fn test_exclusive_open_fails_fast_while_a_writer_holds_the_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("maint.db");
+    let writer = open_db(&db, None).unwrap();
+    writer.execute_batch("BEGIN IMMEDIATE").unwrap();
+
+    let timeout = std::time::Duration::from_millis(100);
+    let started = std::time::Instant::now();
+    let err = open_db_exclusive(&db, None, timeout).unwrap_err();
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    assert!(is_db_locked(&err), "{err:#}");
+    assert!(
+        format!("{err:#}").contains("locked by another process"),
+        "{err:#}"
+    );
+
+    // Once the other connection is closed the lock is granted and kept until `maint` is dropped.
+    writer.execute_batch("COMMIT").unwrap();
+    drop(writer);
+    let maint = open_db_exclusive(&db, None, timeout).unwrap();
+    let other = rusqlite::Connection::open(&db).unwrap();
+    other.busy_timeout(timeout).unwrap();
+    assert!(
+        other
+            .query_row("SELECT count(*) FROM paths", [], |_| Ok(()))
+            .is_err()
+    );
+    maint
+        .execute(INSERT_PATH_SQL, ("b.txt", 1_i64, 1_i64, None::<Vec<u8>>))
+        .unwrap();
+}
+
+#[test]
+fn test_index_file_digest_is_stable_until_the_file_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join(".nefaxer");
+    let checkpoint = |conn: &rusqlite::Connection| {
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE)")
+            .unwrap();
+    };
+    let conn = open_db(&db, None).unwrap();
+    conn.execute(INSERT_PATH_SQL, ("a.txt", 1_i64, 1_i64, None::<Vec<u8>>))
+        .unwrap();
+    checkpoint(&conn);
+
+    let first = index_file_digest(&db).unwrap();
+    assert_eq!(index_file_digest(&db).unwrap(), first);
+
+    conn.execute(INSERT_PATH_SQL, ("b.txt", 2_i64, 2_i64, None::<Vec<u8>>))
+        .unwrap();
+    checkpoint(&conn);
+    assert_ne!(index_file_digest(&db).unwrap(), first);
+    assert!(index_file_digest(&dir.path().join("missing")).is_err());
+}
+
+#[test]
+fn test_leftover_temp_index_and_wal_are_discarded_for_a_fresh_copy() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join(".nefaxer");
+    {
+        let conn = open_db(&db_path, None).unwrap();
+        conn.execute(INSERT_PATH_SQL, ("kept.txt", 1_i64, 4_i64, None::<Vec<u8>>))
+            .unwrap();
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE)")
+            .unwrap();
+    }
+
+    // A crashed run's leftovers: a plaintext header followed by garbage pages, plus a junk WAL.
+    let temp_path = temp_path_for(&db_path);
+    let mut junk = b"SQLite format 3\0".to_vec();
+    junk.extend(std::iter::repeat_n(0xAB_u8, 8192));
+    std::fs::write(&temp_path, &junk).unwrap();
+    let wal_path = dir.path().join(".nefaxer.tmp-wal");
+    std::fs::write(&wal_path, vec![0xCD_u8; 4096]).unwrap();
+
+    let (work_path, use_temp) = prepare_index_work_path(&db_path).unwrap();
+    assert!(use_temp);
+    assert_eq!(work_path, temp_path);
+    assert!(
+        !wal_path.exists(),
+        "stale WAL must not survive next to the copy"
+    );
+
+    let conn = open_db(&work_path, None).unwrap();
+    let rows = load_index(&conn).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert!(rows.contains_key(&PathBuf::from("kept.txt")));
+}
@@ -0,0 +1,2997 @@
+//! End-to-end tests: run the walk + metadata pipeline on a temp tree via the lib API.
+
+mod common;
+
+use nefaxer::check::{check_dir, check_dirs};
+use nefaxer::index::nefax_dir_with_opts;
+use nefaxer::pipeline::{
+    EntryOptions, WorkerErrorState, create_pipeline_channels, spawn_metadata_workers,
+    spawn_walk_thread,
+};
+use nefaxer::utils::config::ENTRY_SEND_BATCH_SIZE;
+use nefaxer::{
+    ChangePolicy, CompletionStatus, ManifestFormat, NefaxOpts, Opts, Patch, PathKeyPolicy,
+    SkipReason, SkipReport, diff_to_patch, find_removed, nefax_dir, verify_manifest,
+};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::{capture_logs, copy_tree, save_index, temp_root, write_file};
+
+// --- skip_dotfiles ---
+
+#[test]
+fn test_skip_dotfiles_excludes_git_contents() {
+    let dir = temp_root();
+    write_file(dir.path(), ".git/HEAD", b"ref: refs/heads/main");
+    write_file(dir.path(), ".git/objects/ab/cdef", b"blob");
+    write_file(dir.path(), "src/main.rs", b"fn main() {}");
+    write_file(dir.path(), ".env", b"KEY=1");
+
+    let opts = NefaxOpts {
+        skip_dotfiles: true,
+        ..Default::default()
+    };
+    let (nefax, _) = nefax_dir(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+
+    assert!(
+        nefax.keys().all(|p| !p.to_string_lossy().starts_with('.')),
+        "no dot paths expected, got {:?}",
+        nefax.keys().collect::<Vec<_>>()
+    );
+    assert!(nefax.contains_key(&PathBuf::from("src/main.rs")));
+    assert!(nefax.contains_key(&PathBuf::from("src")));
+}
+
+#[test]
+fn test_skip_dotfiles_off_keeps_dotfiles() {
+    let dir = temp_root();
+    write_file(dir.path(), ".git/HEAD", b"ref: refs/heads/main");
+    write_file(dir.path(), "a.txt", b"a");
+
+    let (nefax, _) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+
+    assert!(nefax.contains_key(&PathBuf::from(".git/HEAD")));
+    assert!(nefax.contains_key(&PathBuf::from("a.txt")));
+}
+
+// --- exclude_vcs ---
+
+#[test]
+fn test_exclude_vcs_skips_git_and_build_dirs() {
+    let dir = temp_root();
+    write_file(dir.path(), ".git/HEAD", b"ref: refs/heads/main");
+    write_file(dir.path(), ".git/objects/ab/cdef", b"blob");
+    write_file(dir.path(), "web/node_modules/pkg/index.js", b"x");
+    write_file(dir.path(), "src/main.rs", b"fn main() {}");
+    write_file(dir.path(), ".env", b"KEY=1");
+    write_file(dir.path(), "docs/target", b"a file, not a build dir");
+
+    let opts = NefaxOpts {
+        exclude_vcs: true,
+        ..Default::default()
+    };
+    let (nefax, _) = nefax_dir(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+
+    assert!(
+        nefax
+            .keys()
+            .all(|p| !p.starts_with(".git") && !p.starts_with("web/node_modules")),
+        "VCS/build dirs expected to be excluded, got {:?}",
+        nefax.keys().collect::<Vec<_>>()
+    );
+    assert!(nefax.contains_key(&PathBuf::from("src/main.rs")));
+    assert!(nefax.contains_key(&PathBuf::from(".env")));
+    assert!(nefax.contains_key(&PathBuf::from("docs/target")));
+}
+
+// --- follow_depth ---
+
+#[cfg(unix)]
+#[test]
+fn test_follow_depth_follows_only_top_level_symlinks() {
+    use std::os::unix::fs::symlink;
+    let dir = temp_root();
+    let ext = tempfile::tempdir().unwrap();
+    write_file(ext.path(), "a/file_a.txt", b"a");
+    write_file(ext.path(), "b/file_b.txt", b"b");
+    // link_a (depth 1) -> ext/a, which holds inner (depth 2 via link_a) -> ext/b.
+    symlink(ext.path().join("a"), dir.path().join("link_a")).unwrap();
+    symlink(ext.path().join("b"), ext.path().join("a/inner")).unwrap();
+
+    let opts = Opts {
+        follow_depth: Some(1),
+        ..Default::default()
+    };
+    for parallel_walk in [false, true] {
+        let channels = create_pipeline_channels(dir.path(), &None, &None, &opts, 1024);
+        let walk = spawn_walk_thread(
+            channels.path_tx,
+            channels.path_count_tx,
+            channels.ctx,
+            parallel_walk,
+        );
+        let paths: Vec<PathBuf> = channels.path_rx.iter().collect();
+        walk.join().unwrap();
+
+        let has = |rel: &str| paths.contains(&dir.path().join(rel));
+        assert!(
+            has("link_a/file_a.txt"),
+            "parallel={parallel_walk}: {paths:?}"
+        );
+        assert!(has("link_a/inner"), "parallel={parallel_walk}: {paths:?}");
+        assert!(
+            !has("link_a/inner/file_b.txt"),
+            "parallel={parallel_walk}: {paths:?}"
+        );
+    }
+}
+
+// --- symlink targets ---
+
+#[cfg(unix)]
+#[test]
+fn test_repointed_symlink_reported_as_modified() {
+    use std::os::unix::fs::symlink;
+    let dir = temp_root();
+    write_file(dir.path(), "data/a.txt", b"same");
+    let link = dir.path().join("link");
+    symlink("data/a.txt", &link).unwrap();
+    let lib_opts = NefaxOpts {
+        follow_links: true,
+        ..Default::default()
+    };
+    let (before, _) = nefax_dir(dir.path(), &lib_opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert_eq!(
+        before[&PathBuf::from("link")].symlink_target,
+        Some(PathBuf::from("data/a.txt"))
+    );
+    assert_eq!(before[&PathBuf::from("data/a.txt")].symlink_target, None);
+    let opts = Opts {
+        write_to_db: true,
+        follow_links: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+
+    // Same file under another spelling: everything but the link target is unchanged.
+    std::fs::remove_file(&link).unwrap();
+    symlink("./data/a.txt", &link).unwrap();
+    let expected = vec![PathBuf::from("link")];
+
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &lib_opts,
+        Some(&before),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(diff.modified, expected);
+    let check_opts = Opts {
+        follow_links: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        check_dir(dir.path(), &check_opts).unwrap().modified,
+        expected
+    );
+    let (_, diff, _) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(diff.modified, expected);
+    // Without following links the target is neither recorded nor compared.
+    assert!(
+        check_dir(dir.path(), &Opts::default())
+            .unwrap()
+            .modified
+            .is_empty()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_target_backfilled_into_rows_from_before_following() {
+    use std::os::unix::fs::symlink;
+    let dir = temp_root();
+    write_file(dir.path(), "data/a.txt", b"same");
+    symlink("data/a.txt", dir.path().join("link")).unwrap();
+    let stored_targets = || {
+        let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+        nefaxer::engine::load_index_symlink_targets(&conn).unwrap()
+    };
+    let plain = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &plain, None).unwrap();
+    assert!(stored_targets().is_empty());
+
+    // The row is rewritten to record the target, but nothing is reported as changed.
+    let following = Opts {
+        follow_links: true,
+        ..plain
+    };
+    let (_, diff, report) = nefax_dir_with_opts(dir.path(), &following, None).unwrap();
+    assert!(diff.is_empty());
+    assert_eq!(report.write_stats.unwrap().inserted, 1);
+    assert_eq!(
+        stored_targets().get(&PathBuf::from("link")),
+        Some(&PathBuf::from("data/a.txt"))
+    );
+    let (_, _, again) = nefax_dir_with_opts(dir.path(), &following, None).unwrap();
+    assert_eq!(again.write_stats.unwrap().inserted, 0);
+}
+
+// --- NefaxOpts::auto_tune ---
+
+#[test]
+fn test_auto_tune_fills_tuning_fields() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+
+    let mut opts = NefaxOpts::default();
+    opts.auto_tune(dir.path());
+    assert!(opts.num_threads.is_some_and(|n| n >= 1));
+    assert!(opts.drive_type.is_some());
+    assert!(opts.use_parallel_walk.is_some());
+
+    // Tuned opts are reusable for a run.
+    let (nefax, _) = nefax_dir(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert!(nefax.contains_key(&PathBuf::from("a.txt")));
+}
+
+// --- metadata workers: vanished vs failed paths ---
+
+fn worker_errors(strict: bool) -> WorkerErrorState {
+    WorkerErrorState {
+        strict,
+        first_error: Arc::new(Mutex::new(None)),
+        skipped_paths: Arc::new(Mutex::new(Vec::new())),
+        vanished: Arc::new(AtomicUsize::new(0)),
+    }
+}
+
+/// Push `paths` through a single metadata worker and return the entries it produced.
+fn run_workers(
+    root: &std::path::Path,
+    paths: Vec<PathBuf>,
+    errors: &WorkerErrorState,
+) -> Vec<nefaxer::Entry> {
+    run_workers_batched(root, paths, errors, ENTRY_SEND_BATCH_SIZE, 1)
+}
+
+/// Like [`run_workers`] with `num_threads` workers sending batches of up to `batch_size`.
+fn run_workers_batched(
+    root: &std::path::Path,
+    paths: Vec<PathBuf>,
+    errors: &WorkerErrorState,
+    batch_size: usize,
+    num_threads: usize,
+) -> Vec<nefaxer::Entry> {
+    let (path_tx, path_rx) = crossbeam_channel::unbounded();
+    let (entry_tx, entry_rx) = crossbeam_channel::unbounded();
+    let handles = spawn_metadata_workers(
+        &path_rx,
+        &entry_tx,
+        root,
+        EntryOptions::default(),
+        batch_size,
+        num_threads,
+        errors,
+    );
+    drop(entry_tx);
+    for p in paths {
+        path_tx.send(p).unwrap();
+    }
+    drop(path_tx);
+    for h in handles {
+        h.join().unwrap();
+    }
+    entry_rx.iter().flatten().collect()
+}
+
+#[test]
+fn test_metadata_not_found_counts_as_vanished_not_error() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(dir.path(), "kept.txt", b"kept");
+    let errors = worker_errors(true);
+
+    let entries = run_workers(
+        dir.path(),
+        vec![dir.path().join("kept.txt"), dir.path().join("deleted.txt")],
+        &errors,
+    );
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(errors.vanished.load(Ordering::Relaxed), 1);
+    assert!(errors.skipped_paths.lock().unwrap().is_empty());
+    // Even in strict mode a vanished file is not a hard error.
+    assert!(errors.first_error.lock().unwrap().is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_metadata_other_error_is_recorded_as_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let errors = worker_errors(false);
+    // Interior NUL makes the metadata call fail with InvalidInput (not NotFound).
+    let bad = dir.path().join("bad\0name");
+
+    let entries = run_workers(dir.path(), vec![bad.clone()], &errors);
+
+    assert!(entries.is_empty());
+    assert_eq!(errors.vanished.load(Ordering::Relaxed), 0);
+    let skipped = errors.skipped_paths.lock().unwrap();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].0, bad);
+}
+
+#[test]
+fn test_metadata_error_reason_names_the_failed_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let errors = worker_errors(false);
+    let bad = dir.path().join("bad\0name");
+    let good = dir.path().join("good.txt");
+    std::fs::write(&good, b"ok").unwrap();
+
+    let entries = run_workers(dir.path(), vec![bad.clone(), good], &errors);
+
+    assert_eq!(entries.len(), 1);
+    let report = nefaxer::pipeline::check_for_initial_error_with_skip_report(
+        &Opts::default(),
+        &errors.first_error,
+        &errors.skipped_paths,
+    )
+    .unwrap();
+    assert_eq!(report.paths.len(), 1);
+    let (path, _, detail) = &report.paths[0];
+    assert_eq!(path, &bad);
+    assert!(detail.starts_with("read metadata: "), "{detail}");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_skip_report_groups_skipped_paths_by_reason() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(dir.path(), "file.txt", b"not a dir");
+    let errors = worker_errors(false);
+    // Two distinct non-NotFound failures (root bypasses permission bits, so use path shape errors):
+    // interior NUL → InvalidInput, a regular file used as a directory → NotADirectory.
+    let bad_paths = vec![
+        dir.path().join("a\0name"),
+        dir.path().join("b\0name"),
+        dir.path().join("file.txt/child"),
+    ];
+
+    run_workers(dir.path(), bad_paths, &errors);
+    let report = nefaxer::pipeline::check_for_initial_error_with_skip_report(
+        &Opts::default(),
+        &errors.first_error,
+        &errors.skipped_paths,
+    )
+    .unwrap();
+
+    assert_eq!(report.total, 3);
+    assert_eq!(report.paths.len(), 3);
+    let mut counts: Vec<usize> = report.by_reason.values().copied().collect();
+    counts.sort_unstable();
+    assert_eq!(counts, vec![1, 2]);
+    let not_dir = &report
+        .paths
+        .iter()
+        .find(|(p, _, _)| p.ends_with("file.txt/child"))
+        .unwrap()
+        .1;
+    assert_eq!(report.by_reason[not_dir], 1);
+}
+
+#[test]
+fn test_permission_errors_group_together_regardless_of_path() {
+    // Root ignores permission bits, so build the errors the way the workers see them.
+    let denied = |p: &str| {
+        let err = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+            .context(format!("read metadata of {p}"));
+        (
+            PathBuf::from(p),
+            SkipReason::from_error(&err),
+            format!("{err:#}"),
+        )
+    };
+    let gone = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::NotADirectory));
+    let skipped = vec![
+        denied("private/a"),
+        denied("other/secret/b"),
+        (
+            PathBuf::from("file.txt/child"),
+            SkipReason::from_error(&gone),
+            format!("{gone:#}"),
+        ),
+    ];
+
+    let report = SkipReport::from_skipped(&skipped);
+    assert_eq!(report.total, 3);
+    assert_eq!(report.by_reason.len(), 2);
+    assert_eq!(report.by_reason[&SkipReason::PermissionDenied], 2);
+    assert_ne!(report.paths[0].2, report.paths[1].2);
+    assert_eq!(SkipReason::PermissionDenied.as_str(), "permission denied");
+}
+
+#[test]
+fn test_batched_workers_yield_same_entries_as_per_item() {
+    let dir = tempfile::tempdir().unwrap();
+    // Not a multiple of the batch size, so the last batch is partial.
+    let paths: Vec<PathBuf> = (0..ENTRY_SEND_BATCH_SIZE * 3 + 7)
+        .map(|i| {
+            let rel = format!("d{}/f{i}.txt", i % 5);
+            write_file(dir.path(), &rel, i.to_string().as_bytes());
+            dir.path().join(rel)
+        })
+        .collect();
+    let summarize = |entries: Vec<nefaxer::Entry>| {
+        let mut rows: Vec<(PathBuf, u64, i64)> = entries
+            .into_iter()
+            .map(|e| (e.path, e.size, e.mtime_ns))
+            .collect();
+        rows.sort();
+        rows
+    };
+
+    let errors = worker_errors(true);
+    let per_item = summarize(run_workers_batched(
+        dir.path(),
+        paths.clone(),
+        &errors,
+        1,
+        4,
+    ));
+    let batched = summarize(run_workers_batched(
+        dir.path(),
+        paths.clone(),
+        &errors,
+        ENTRY_SEND_BATCH_SIZE,
+        4,
+    ));
+
+    assert_eq!(per_item.len(), paths.len());
+    assert_eq!(batched, per_item);
+    assert!(errors.first_error.lock().unwrap().is_none());
+}
+
+// --- stats::profile_dir ---
+
+#[test]
+fn test_profile_dir_counts_and_bytes() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", &[b'a'; 10]);
+    write_file(dir.path(), "b.bin", &[b'b'; 5000]);
+    write_file(dir.path(), "sub/c.txt", &[b'c'; 100]);
+
+    let profile = nefaxer::stats::profile_dir(dir.path(), &nefaxer::Opts::default()).unwrap();
+
+    assert_eq!(profile.files, 3);
+    assert_eq!(profile.dirs, 1);
+    assert_eq!(profile.total_bytes, 5110);
+    assert_eq!(profile.hash_candidates, 1);
+    assert_eq!(profile.below_hash_threshold, 2);
+    let histogram_files: usize = profile.size_histogram.iter().map(|b| b.count).sum();
+    let histogram_bytes: u64 = profile.size_histogram.iter().map(|b| b.bytes).sum();
+    assert_eq!(histogram_files, 3);
+    assert_eq!(histogram_bytes, 5110);
+    assert_eq!(profile.size_histogram[0].count, 2); // < 1 KiB
+}
+
+#[test]
+fn test_profile_dir_breaks_down_by_extension() {
+    use nefaxer::ExtensionStat;
+
+    let dir = temp_root();
+    write_file(dir.path(), "a.jpg", &[0; 800]);
+    write_file(dir.path(), "sub/B.JPG", &[0; 200]);
+    write_file(dir.path(), "notes.txt", &[0; 50]);
+    write_file(dir.path(), "Makefile", &[0; 30]);
+    write_file(dir.path(), "archive.tar.gz", &[0; 20]);
+
+    let profile = nefaxer::stats::profile_dir(dir.path(), &Opts::default()).unwrap();
+    let ext = |e: &str| profile.by_extension.get(e).copied().unwrap_or_default();
+    assert_eq!(
+        ext("jpg"),
+        ExtensionStat {
+            count: 2,
+            bytes: 1000
+        }
+    );
+    assert_eq!(
+        ext("txt"),
+        ExtensionStat {
+            count: 1,
+            bytes: 50
+        }
+    );
+    assert_eq!(
+        ext("<none>"),
+        ExtensionStat {
+            count: 1,
+            bytes: 30
+        }
+    );
+    assert_eq!(
+        ext("gz"),
+        ExtensionStat {
+            count: 1,
+            bytes: 20
+        }
+    );
+    assert_eq!(profile.by_extension.len(), 4);
+}
+
+// --- collect_entries_with_stats ---
+
+#[test]
+fn test_collect_entries_with_stats_totals_file_bytes() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"abc");
+    write_file(dir.path(), "sub/b.bin", &[1u8; 5000]);
+    write_file(dir.path(), "sub/deeper/c.txt", b"hello");
+    let conn = nefaxer::engine::open_db_in_memory().unwrap();
+
+    let stats = nefaxer::pipeline::collect_entries_with_stats(
+        dir.path(),
+        &Opts::default(),
+        None,
+        None,
+        &conn,
+    )
+    .unwrap();
+
+    assert_eq!(stats.total_bytes, 3 + 5000 + 5);
+    assert_eq!(stats.entries.len(), stats.path_count);
+    assert!(stats.walk_elapsed <= stats.metadata_elapsed);
+}
+
+// --- nested indexes ---
+
+#[test]
+fn test_nested_index_is_reported_and_pruned() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"abc");
+    write_file(dir.path(), "inner/b.txt", b"bb");
+    write_file(dir.path(), "inner/deep/c.txt", b"c");
+    write_file(dir.path(), "inner/.nefaxer", b"");
+    let inner = dir.path().canonicalize().unwrap().join("inner");
+    let conn = nefaxer::engine::open_db_in_memory().unwrap();
+
+    for parallel in [false, true] {
+        for skip_dotfiles in [false, true] {
+            let opts = Opts {
+                num_threads: Some(2),
+                drive_type: Some(nefaxer::disk_detect::DriveType::SSD),
+                use_parallel_walk: Some(parallel),
+                skip_dotfiles,
+                ..Default::default()
+            };
+            let stats =
+                nefaxer::pipeline::collect_entries_with_stats(dir.path(), &opts, None, None, &conn)
+                    .unwrap();
+            assert_eq!(stats.nested_indexes, vec![inner.clone()]);
+            assert!(
+                stats
+                    .entries
+                    .iter()
+                    .any(|e| e.path == std::path::Path::new("inner/deep/c.txt"))
+            );
+
+            let pruned = Opts {
+                prune_nested: true,
+                ..opts
+            };
+            let stats = nefaxer::pipeline::collect_entries_with_stats(
+                dir.path(),
+                &pruned,
+                None,
+                None,
+                &conn,
+            )
+            .unwrap();
+            assert_eq!(stats.nested_indexes, vec![inner.clone()]);
+            let mut paths: Vec<_> = stats.entries.iter().map(|e| e.path.clone()).collect();
+            paths.sort();
+            assert_eq!(
+                paths,
+                vec![PathBuf::from("a.txt"), PathBuf::from("inner")],
+                "parallel={parallel} skip_dotfiles={skip_dotfiles}"
+            );
+        }
+    }
+}
+
+// --- special files ---
+
+#[cfg(unix)]
+#[test]
+fn test_fifo_is_indexed_as_other_and_never_opened() {
+    let dir = temp_root();
+    write_file(dir.path(), "big.bin", &[3u8; 8 * 1024]);
+    let fifo = dir.path().join("pipe");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .expect("run mkfifo");
+    assert!(status.success());
+
+    // A blocking open of a FIFO with no writer would hang here.
+    assert_eq!(nefaxer::engine::hash_file(&fifo, 1 << 20).unwrap(), None);
+
+    let walked: Vec<nefaxer::Entry> = nefaxer::walk_entries(dir.path(), &NefaxOpts::default())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let pipe = walked
+        .iter()
+        .find(|e| e.path == std::path::Path::new("pipe"))
+        .expect("fifo entry");
+    assert_eq!(pipe.kind, nefaxer::FileKind::Other);
+
+    let hashed = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let (nefax, _) = nefax_dir(dir.path(), &hashed, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert!(nefax[&PathBuf::from("pipe")].hash.is_none());
+    assert!(nefax[&PathBuf::from("big.bin")].hash.is_some());
+}
+
+// --- parallel hashing in diff ---
+
+#[test]
+fn test_parallel_diff_hashing_matches_serial_hashes_and_diff() {
+    let dir = temp_root();
+    for i in 0..48u8 {
+        write_file(dir.path(), &format!("d{}/f{i}.bin", i % 4), &[i; 6 * 1024]);
+    }
+    let hashed = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(
+        dir.path(),
+        &Opts {
+            with_hash: true,
+            write_to_db: true,
+            ..Default::default()
+        },
+        None,
+    )
+    .unwrap();
+    let (before, _) = nefax_dir(dir.path(), &hashed, None, None::<fn(&nefaxer::Entry)>).unwrap();
+
+    for i in (0..48u8).step_by(7) {
+        write_file(
+            dir.path(),
+            &format!("d{}/f{i}.bin", i % 4),
+            &[255 - i; 6 * 1024],
+        );
+    }
+    write_file(dir.path(), "d0/new.bin", &[9u8; 5 * 1024]);
+
+    let (after, diff) = nefax_dir(
+        dir.path(),
+        &hashed,
+        Some(&before),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    for (path, meta) in after.iter().filter(|(_, m)| m.size >= 4096) {
+        let serial = nefaxer::engine::hash_file(&dir.path().join(path), meta.size).unwrap();
+        assert_eq!(meta.hash, serial, "{}", path.display());
+    }
+
+    let serial_diff = nefaxer::check::diff_nefax(&before, &after, 0);
+    let sorted = |v: &[PathBuf]| {
+        let mut v = v.to_vec();
+        v.sort();
+        v
+    };
+    assert_eq!(sorted(&diff.added), sorted(&serial_diff.added));
+    assert_eq!(sorted(&diff.modified), sorted(&serial_diff.modified));
+    // Seven rewritten files, plus `d0` whose mtime moved when `new.bin` was created.
+    assert_eq!(diff.modified.len(), 8);
+
+    let check = Opts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let checked = check_dir(dir.path(), &check).unwrap();
+    assert_eq!(sorted(&checked.added), sorted(&serial_diff.added));
+    assert_eq!(sorted(&checked.modified), sorted(&serial_diff.modified));
+}
+
+// --- walk_entries ---
+
+#[test]
+fn test_walk_entries_matches_collect_entries() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"abc");
+    write_file(dir.path(), "sub/b.bin", &[1u8; 5000]);
+    write_file(dir.path(), "sub/deeper/c.txt", b"hello");
+    let conn = nefaxer::engine::open_db_in_memory().unwrap();
+    let (collected, _) =
+        nefaxer::pipeline::collect_entries(dir.path(), &Opts::default(), None, None, &conn)
+            .unwrap();
+
+    let walked: Vec<nefaxer::Entry> = nefaxer::walk_entries(dir.path(), &NefaxOpts::default())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    let key = |e: &nefaxer::Entry| (e.path.clone(), e.size, e.mtime_ns);
+    let mut expected: Vec<_> = collected.iter().map(key).collect();
+    let mut actual: Vec<_> = walked.iter().map(key).collect();
+    expected.sort();
+    actual.sort();
+    assert_eq!(actual, expected);
+    assert!(walked.iter().all(|e| e.hash.is_none()));
+
+    // Dropping after one item stops the pipeline instead of hanging.
+    let mut iter = nefaxer::walk_entries(dir.path(), &NefaxOpts::default()).unwrap();
+    assert!(iter.next().unwrap().is_ok());
+    drop(iter);
+}
+
+// --- nefax_dir_with_report ---
+
+#[test]
+fn test_report_contains_local_tuning() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+
+    let (_, _, report) = nefaxer::nefax_dir_with_report(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+
+    assert!(!report.tuning.is_network_drive);
+    assert!(!report.tuning.drive_type.is_network());
+    assert!(report.tuning.num_threads >= 1);
+    assert!(report.tuning.channel_cap > 0);
+}
+
+#[test]
+fn test_zero_threads_falls_back_to_auto_detect() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+    write_file(dir.path(), "sub/b.txt", b"b");
+    let opts = NefaxOpts {
+        num_threads: Some(0),
+        ..Default::default()
+    };
+
+    let (nefax, _, report) =
+        nefaxer::nefax_dir_with_report(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>)
+            .unwrap();
+    assert!(report.tuning.num_threads >= 1);
+    assert_eq!(nefax.len(), 3);
+
+    let mut tuned = opts.clone();
+    tuned.auto_tune(dir.path());
+    assert!(tuned.num_threads.is_some_and(|n| n >= 1));
+}
+
+#[test]
+fn test_index_summary_reports_thread_count() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+    let opts = Opts {
+        num_threads: Some(3),
+        db_path: Some(dir.path().join("summary.nefaxer")),
+        ..Default::default()
+    };
+
+    let (_, _, report) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(report.tuning.num_threads, 3);
+    let summary = report.tuning.summary();
+    assert!(summary.starts_with("3 threads, "), "summary: {summary}");
+    assert!(summary.ends_with(&report.tuning.drive_type.to_string()));
+}
+
+#[test]
+fn test_empty_directory_gets_empty_index_message() {
+    let dir = temp_root();
+    let opts = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    let (result, logs) = capture_logs(|| nefax_dir_with_opts(dir.path(), &opts, None));
+    assert!(result.unwrap().1.is_empty());
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    assert_eq!(nefaxer::engine::path_count_from_db(&conn).unwrap(), 0);
+    assert!(
+        logs.iter()
+            .any(|l| l == "Directory is empty; empty index created."),
+        "logs: {logs:?}"
+    );
+    assert!(!logs.iter().any(|l| l == "New nefaxer index created."));
+
+    let full = temp_root();
+    write_file(full.path(), "a.txt", b"a");
+    let (result, logs) = capture_logs(|| nefax_dir_with_opts(full.path(), &opts, None));
+    result.unwrap();
+    assert!(
+        logs.iter().any(|l| l == "New nefaxer index created."),
+        "logs: {logs:?}"
+    );
+}
+
+// --- check_dir with index_from ---
+
+#[test]
+fn test_check_copy_against_original_index() {
+    let original = temp_root();
+    write_file(original.path(), "a.txt", b"alpha");
+    write_file(original.path(), "sub/b.txt", b"bravo");
+    write_file(original.path(), "sub/deeper/c.txt", b"charlie");
+    let (nefax, _) = nefax_dir(
+        original.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    let original_db = original.path().join(".nefaxer");
+    save_index(&original_db, &nefax);
+
+    let copy = temp_root();
+    copy_tree(original.path(), copy.path());
+
+    let opts = Opts {
+        index_from: Some(original_db.clone()),
+        ..Default::default()
+    };
+    let diff = check_dir(copy.path(), &opts).unwrap();
+    assert!(diff.added.is_empty(), "added: {:?}", diff.added);
+    assert!(diff.removed.is_empty(), "removed: {:?}", diff.removed);
+    assert!(diff.modified.is_empty(), "modified: {:?}", diff.modified);
+
+    write_file(copy.path(), "extra.txt", b"x");
+    let diff = check_dir(copy.path(), &opts).unwrap();
+    assert_eq!(diff.added, vec![PathBuf::from("extra.txt")]);
+}
+
+#[test]
+fn test_check_against_index_from_leaves_it_unmigrated() {
+    let original = temp_root();
+    write_file(original.path(), "a.txt", b"alpha");
+    write_file(original.path(), "sub/b.txt", b"bravo");
+    let (nefax, _) = nefax_dir(
+        original.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    let original_db = original.path().join(".nefaxer");
+    save_index(&original_db, &nefax);
+    // Turn it into an index from before `meta` and the later `paths` columns existed.
+    let conn = rusqlite::Connection::open(&original_db).unwrap();
+    conn.execute_batch(
+        "DROP TABLE meta;
+         ALTER TABLE paths DROP COLUMN last_seen;
+         ALTER TABLE paths DROP COLUMN btime_ns;
+         ALTER TABLE paths DROP COLUMN symlink_target;
+         ALTER TABLE paths DROP COLUMN xattr_hash;
+         ALTER TABLE paths DROP COLUMN disk_path;
+         PRAGMA wal_checkpoint(TRUNCATE);",
+    )
+    .unwrap();
+    drop(conn);
+    let before = std::fs::read(&original_db).unwrap();
+
+    let copy = temp_root();
+    copy_tree(original.path(), copy.path());
+    let opts = Opts {
+        index_from: Some(original_db.clone()),
+        ..Default::default()
+    };
+    let diff = check_dir(copy.path(), &opts).unwrap();
+    assert!(diff.added.is_empty(), "added: {:?}", diff.added);
+    assert!(diff.removed.is_empty(), "removed: {:?}", diff.removed);
+    assert!(diff.modified.is_empty(), "modified: {:?}", diff.modified);
+
+    assert_eq!(std::fs::read(&original_db).unwrap(), before);
+    let conn = rusqlite::Connection::open(&original_db).unwrap();
+    let columns: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('paths')",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(columns, 4);
+}
+
+#[test]
+fn test_find_removed_lists_only_deleted_paths() {
+    let root = temp_root();
+    write_file(root.path(), "keep.txt", b"keep");
+    write_file(root.path(), "thumbs/gone.jpg", b"gone");
+    write_file(root.path(), "thumbs/stay.jpg", b"stay");
+    let (nefax, _) = nefax_dir(
+        root.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    save_index(&root.path().join(".nefaxer"), &nefax);
+
+    std::fs::remove_file(root.path().join("thumbs/gone.jpg")).unwrap();
+    let removed = find_removed(root.path(), None, &NefaxOpts::default()).unwrap();
+    assert_eq!(removed, vec![PathBuf::from("thumbs/gone.jpg")]);
+
+    let missing = root.path().join("missing.db");
+    assert!(find_removed(root.path(), Some(missing.as_path()), &NefaxOpts::default()).is_err());
+}
+
+#[test]
+fn test_find_removed_reports_disk_names_and_leaves_the_index_alone() {
+    let root = temp_root();
+    write_file(root.path(), "Docs/Keep.TXT", b"keep");
+    write_file(root.path(), "Docs/Gone.TXT", b"gone");
+    let opts = Opts {
+        path_key: PathKeyPolicy::Lowercase,
+        write_to_db: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(root.path(), &opts, None).unwrap();
+    let db = root.path().join(".nefaxer");
+    let before = std::fs::read(&db).unwrap();
+
+    std::fs::remove_file(root.path().join("Docs/Gone.TXT")).unwrap();
+    let lib_opts = NefaxOpts {
+        path_key: PathKeyPolicy::Lowercase,
+        ..Default::default()
+    };
+    let removed = find_removed(root.path(), None, &lib_opts).unwrap();
+    assert_eq!(removed, vec![PathBuf::from("Docs/Gone.TXT")]);
+    assert_eq!(std::fs::read(&db).unwrap(), before);
+}
+
+#[test]
+fn test_verify_manifest_reports_tampered_file_as_modified() {
+    use sha2::{Digest, Sha256};
+    let root = temp_root();
+    let files: [(&str, &[u8]); 2] = [("a.txt", b"alpha"), ("sub/b.bin", b"beta")];
+    let mut manifest = String::from("# sha256sum output\n");
+    for (rel, data) in files {
+        write_file(root.path(), rel, data);
+        let hex: String = Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        manifest.push_str(&format!("{hex}  {rel}\n"));
+    }
+    let out = temp_root();
+    let manifest_path = out.path().join("SHA256SUMS");
+    std::fs::write(&manifest_path, manifest).unwrap();
+    let opts = NefaxOpts::default();
+
+    let diff = verify_manifest(root.path(), &manifest_path, ManifestFormat::Sha256, &opts).unwrap();
+    assert!(diff.is_empty(), "{:?}", diff.modified);
+
+    // Same size, different content: only the hash can tell.
+    write_file(root.path(), "a.txt", b"alphA");
+    let diff = verify_manifest(root.path(), &manifest_path, ManifestFormat::Sha256, &opts).unwrap();
+    assert_eq!(diff.modified, vec![PathBuf::from("a.txt")]);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+
+    std::fs::write(&manifest_path, "not-a-hash  a.txt\n").unwrap();
+    let Err(err) = verify_manifest(root.path(), &manifest_path, ManifestFormat::Sha256, &opts)
+    else {
+        panic!("malformed manifest should be rejected");
+    };
+    assert!(format!("{err:#}").contains("line 1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_verify_manifest_skips_itself_and_reports_unreadable_separately() {
+    use sha2::{Digest, Sha256};
+    use std::os::unix::fs::PermissionsExt;
+    let root = temp_root();
+    let mut manifest = String::new();
+    for (rel, data) in [("a.txt", b"alpha"), ("b.txt", b"bravo")] {
+        write_file(root.path(), rel, data);
+        let hex: String = Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        manifest.push_str(&format!("{hex}  {rel}\n"));
+    }
+    // The usual layout: SHA256SUMS next to the files it lists.
+    let manifest_path = root.path().join("SHA256SUMS");
+    std::fs::write(&manifest_path, manifest).unwrap();
+    let opts = NefaxOpts::default();
+
+    let diff = verify_manifest(root.path(), &manifest_path, ManifestFormat::Sha256, &opts).unwrap();
+    assert!(diff.is_empty(), "added: {:?}", diff.added);
+
+    if nefaxer::engine::running_as_root() {
+        // Root reads a file whatever its mode, so none can be made unreadable here.
+        return;
+    }
+    let locked = root.path().join("b.txt");
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    let diff = verify_manifest(root.path(), &manifest_path, ManifestFormat::Sha256, &opts).unwrap();
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert_eq!(diff.unreadable, vec![PathBuf::from("b.txt")]);
+    assert!(diff.modified.is_empty());
+    assert!(!diff.is_empty());
+}
+
+#[test]
+fn test_patch_from_diff_brings_copy_index_up_to_date() {
+    let root = temp_root();
+    write_file(root.path(), "a.txt", b"alpha");
+    write_file(root.path(), "sub/b.txt", b"bravo");
+    write_file(root.path(), "sub/c.txt", b"charlie");
+    let lib_opts = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let (old, _) = nefax_dir(root.path(), &lib_opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    let dbs = temp_root();
+    let copy_db = dbs.path().join("copy.nefaxer");
+    save_index(&copy_db, &old);
+
+    write_file(root.path(), "a.txt", b"alpha, longer now");
+    std::fs::remove_file(root.path().join("sub/c.txt")).unwrap();
+    write_file(root.path(), "new.txt", b"new");
+    let (new, diff) = nefax_dir(
+        root.path(),
+        &lib_opts,
+        Some(&old),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+
+    let patch = diff_to_patch(&diff, &new, PathKeyPolicy::AsIs);
+    assert_eq!(patch.remove, vec!["sub/c.txt".to_string()]);
+    let json = serde_json::to_string(&patch).unwrap();
+    let patch: Patch = serde_json::from_str(&json).unwrap();
+
+    let mut conn = nefaxer::engine::open_db(&copy_db, None).unwrap();
+    let stats = nefaxer::engine::apply_patch(&mut conn, &patch).unwrap();
+    assert_eq!(stats.deleted, 1);
+
+    let fresh_db = dbs.path().join("fresh.nefaxer");
+    save_index(&fresh_db, &new);
+    let fresh = nefaxer::engine::open_db(&fresh_db, None).unwrap();
+    assert_eq!(
+        nefaxer::engine::load_index(&conn).unwrap(),
+        nefaxer::engine::load_index(&fresh).unwrap()
+    );
+}
+
+/// Index `root` holding `kept` and `gone` under `policy`, edit `kept` and delete `gone`, and check the
+/// patch names both by their index keys and applies to a copy of the old index.
+fn assert_patch_uses_index_keys(policy: PathKeyPolicy, kept: &str, gone: &str) {
+    let root = temp_root();
+    write_file(root.path(), kept, b"alpha");
+    write_file(root.path(), gone, b"bravo");
+    let lib_opts = NefaxOpts {
+        path_key: policy,
+        ..Default::default()
+    };
+    let (old, _) = nefax_dir(root.path(), &lib_opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    let dbs = temp_root();
+    let copy_db = dbs.path().join("copy.nefaxer");
+    save_index(&copy_db, &old);
+
+    write_file(root.path(), kept, b"alpha, longer now");
+    std::fs::remove_file(root.path().join(gone)).unwrap();
+    let (new, diff) = nefax_dir(
+        root.path(),
+        &lib_opts,
+        Some(&old),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(diff.modified, vec![PathBuf::from(kept)]);
+
+    let patch = diff_to_patch(&diff, &new, policy);
+    let key = |p: &str| nefaxer::engine::path_to_db_key(std::path::Path::new(p), policy);
+    assert_eq!(
+        patch
+            .upsert
+            .iter()
+            .map(|e| e.path.clone())
+            .collect::<Vec<_>>(),
+        vec![key(kept)]
+    );
+    assert_eq!(patch.remove, vec![key(gone)]);
+
+    let mut conn = nefaxer::engine::open_db(&copy_db, None).unwrap();
+    let stats = nefaxer::engine::apply_patch(&mut conn, &patch).unwrap();
+    assert_eq!(stats.deleted, 1);
+    let index = nefaxer::engine::load_index(&conn).unwrap();
+    assert!(index.contains_key(&PathBuf::from(key(kept))));
+    assert!(!index.contains_key(&PathBuf::from(key(gone))));
+}
+
+#[test]
+fn test_patch_uses_lowercase_index_keys() {
+    assert_patch_uses_index_keys(PathKeyPolicy::Lowercase, "Docs/Readme.TXT", "Old.TXT");
+}
+
+#[test]
+fn test_patch_uses_nfc_index_keys() {
+    // Decomposed on disk (`e` + combining acute), composed in the index.
+    assert_patch_uses_index_keys(
+        PathKeyPolicy::Nfc,
+        "cafe\u{301}.txt",
+        "re\u{301}sume\u{301}.txt",
+    );
+}
+
+#[test]
+fn test_check_index_from_missing_db_errors() {
+    let dir = temp_root();
+    let opts = Opts {
+        index_from: Some(dir.path().join("missing.nefaxer")),
+        ..Default::default()
+    };
+    assert!(check_dir(dir.path(), &opts).is_err());
+}
+
+// --- check_dirs ---
+
+#[test]
+fn test_check_dirs_reports_each_root_independently() {
+    let dbs = temp_root();
+    let one = temp_root();
+    let two = temp_root();
+    write_file(one.path(), "a.txt", b"alpha");
+    write_file(two.path(), "b.txt", b"bravo");
+    write_file(two.path(), "gone.txt", b"soon removed");
+    let mut roots = Vec::new();
+    for (i, root) in [one.path(), two.path()].into_iter().enumerate() {
+        let (nefax, _) = nefax_dir(
+            root,
+            &NefaxOpts::default(),
+            None,
+            None::<fn(&nefaxer::Entry)>,
+        )
+        .unwrap();
+        let db = dbs.path().join(format!("{i}.nefaxer"));
+        save_index(&db, &nefax);
+        roots.push((root.to_path_buf(), db));
+    }
+    write_file(one.path(), "new.txt", b"new");
+    std::fs::remove_file(two.path().join("gone.txt")).unwrap();
+
+    let diffs = check_dirs(&roots, &Opts::default()).unwrap();
+
+    assert_eq!(diffs.len(), 2);
+    let d1 = &diffs[one.path()];
+    assert_eq!(d1.added, vec![PathBuf::from("new.txt")]);
+    assert!(d1.removed.is_empty() && d1.modified.is_empty());
+    let d2 = &diffs[two.path()];
+    assert_eq!(d2.removed, vec![PathBuf::from("gone.txt")]);
+    assert!(d2.added.is_empty() && d2.modified.is_empty());
+
+    // One report file cannot hold every root, and a root checked twice would collide in the map.
+    let shared_output = Opts {
+        output: Some(dbs.path().join("report.txt")),
+        ..Default::default()
+    };
+    assert!(check_dirs(&roots, &shared_output).is_err());
+    assert!(!dbs.path().join("report.txt").exists());
+    assert_eq!(check_dirs(&roots[..1], &shared_output).unwrap().len(), 1);
+    let twice = [roots[0].clone(), (one.path().join("."), roots[1].1.clone())];
+    let Err(err) = check_dirs(&twice, &Opts::default()) else {
+        panic!("a repeated root must be rejected");
+    };
+    assert!(err.to_string().contains("more than once"), "{err:#}");
+}
+
+// --- completion status ---
+
+#[test]
+fn test_deadline_reports_partial_index() {
+    let dir = temp_root();
+    for i in 0..50 {
+        write_file(dir.path(), &format!("f{i}.txt"), b"x");
+    }
+    let opts = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    let (_, _, report) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(report.status, CompletionStatus::Complete);
+
+    // A zero deadline is already set when the pipeline starts: nothing new is read, nothing is removed.
+    write_file(dir.path(), "late.txt", b"y");
+    let opts = Opts {
+        deadline: Some(std::time::Duration::ZERO),
+        ..opts
+    };
+    let (_, diff, report) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(report.status, CompletionStatus::Partial);
+    assert!(diff.removed.is_empty(), "removed: {:?}", diff.removed);
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    assert_eq!(nefaxer::engine::load_index(&conn).unwrap().len(), 50);
+    assert!(nefaxer::engine::DeadlineTimer::start(std::time::Duration::ZERO).fired());
+}
+
+#[test]
+fn test_stopped_run_keeps_the_index_meta_of_the_last_full_run() {
+    let dir = temp_root();
+    write_file(dir.path(), "big.bin", &[4u8; 8192]);
+    let opts = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    let meta = |dir: &std::path::Path| {
+        let conn = nefaxer::engine::open_db(&dir.join(".nefaxer"), None).unwrap();
+        nefaxer::engine::load_index_meta(&conn).unwrap()
+    };
+    let before = meta(dir.path());
+    assert_eq!(before.with_hash, Some(false));
+
+    let stopped = Opts {
+        with_hash: true,
+        deadline: Some(std::time::Duration::ZERO),
+        ..opts.clone()
+    };
+    let (_, _, report) = nefax_dir_with_opts(dir.path(), &stopped, None).unwrap();
+    assert_eq!(report.status, CompletionStatus::Partial);
+    assert_eq!(meta(dir.path()).with_hash, Some(false));
+
+    let hashed = Opts {
+        with_hash: true,
+        ..opts
+    };
+    nefax_dir_with_opts(dir.path(), &hashed, None).unwrap();
+    assert_eq!(meta(dir.path()).with_hash, Some(true));
+}
+
+#[test]
+fn test_max_entries_caps_walk_and_keeps_unreached_rows() {
+    let dir = temp_root();
+    for i in 0..20 {
+        write_file(dir.path(), &format!("f{i:02}.txt"), b"x");
+    }
+    let capped = NefaxOpts {
+        max_entries: Some(5),
+        ..Default::default()
+    };
+    let (nefax, _, report) =
+        nefaxer::nefax_dir_with_report(dir.path(), &capped, None, None::<fn(&nefaxer::Entry)>)
+            .unwrap();
+    assert!(nefax.len() <= 5, "{} entries", nefax.len());
+    assert_eq!(report.status, CompletionStatus::Partial);
+
+    // Against a full index: the capped run sees part of the tree and removes nothing.
+    let opts = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    let opts = Opts {
+        max_entries: Some(5),
+        ..opts
+    };
+    let (_, diff, report) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(report.status, CompletionStatus::Partial);
+    assert!(diff.removed.is_empty(), "removed: {:?}", diff.removed);
+    let write_stats = report.write_stats.unwrap();
+    assert!(write_stats.received <= 5, "{write_stats:?}");
+    assert_eq!(write_stats.deleted, 0);
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    assert_eq!(nefaxer::engine::load_index(&conn).unwrap().len(), 20);
+
+    let check = Opts {
+        max_entries: Some(5),
+        ..Default::default()
+    };
+    assert!(check_dir(dir.path(), &check).unwrap().removed.is_empty());
+}
+
+// --- max_path_len ---
+
+#[test]
+fn test_max_path_len_skips_and_lists_long_paths() {
+    let dir = temp_root();
+    write_file(dir.path(), "short.txt", b"s");
+    write_file(dir.path(), "deep/er/nested/dir/file.txt", b"d");
+    let opts = NefaxOpts {
+        max_path_len: Some(12),
+        ..Default::default()
+    };
+    let (nefax, _, report) =
+        nefaxer::nefax_dir_with_report(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>)
+            .unwrap();
+
+    let mut kept: Vec<_> = nefax.keys().cloned().collect();
+    kept.sort();
+    assert_eq!(
+        kept,
+        ["deep", "deep/er", "short.txt"].map(PathBuf::from).to_vec()
+    );
+    let too_long: Vec<_> = report
+        .skipped
+        .too_long
+        .iter()
+        .map(|p| p.strip_prefix(dir.path().canonicalize().unwrap()).unwrap())
+        .collect();
+    assert_eq!(
+        too_long,
+        [
+            "deep/er/nested",
+            "deep/er/nested/dir",
+            "deep/er/nested/dir/file.txt"
+        ]
+        .map(std::path::Path::new)
+        .to_vec()
+    );
+    // Kept apart from unreadable paths.
+    assert_eq!(report.skipped.total, 0);
+}
+
+// --- append_only ---
+
+#[test]
+fn test_append_only_keeps_rows_of_deleted_files() {
+    let dir = temp_root();
+    write_file(dir.path(), "keep.txt", b"keep");
+    write_file(dir.path(), "gone.txt", b"gone");
+    let opts = Opts {
+        write_to_db: true,
+        append_only: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+
+    std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+    let (_, diff, _) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(diff.removed, vec![PathBuf::from("gone.txt")]);
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let index = nefaxer::engine::load_index(&conn).unwrap();
+    assert!(index.contains_key(&PathBuf::from("gone.txt")), "{index:?}");
+    drop(conn);
+
+    // A normal run prunes it.
+    let opts = Opts {
+        append_only: false,
+        ..opts
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let index = nefaxer::engine::load_index(&conn).unwrap();
+    assert!(!index.contains_key(&PathBuf::from("gone.txt")), "{index:?}");
+}
+
+// --- fail_on_change ---
+
+#[test]
+fn test_fail_on_change_errors_only_when_drifted() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"abc");
+    let index = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &index, None).unwrap();
+
+    let check = Opts {
+        fail_on_change: true,
+        ..Default::default()
+    };
+    assert!(check_dir(dir.path(), &check).unwrap().is_empty());
+
+    write_file(dir.path(), "b.txt", b"new");
+    let Err(err) = check_dir(dir.path(), &check) else {
+        panic!("expected a drift error");
+    };
+    let changes = err
+        .downcast_ref::<nefaxer::check::ChangesDetected>()
+        .expect("drift error");
+    assert_eq!(changes.total, 1);
+}
+
+#[test]
+fn test_newly_empty_dirs_lists_dirs_whose_contents_were_removed() {
+    let dir = temp_root();
+    write_file(dir.path(), "logs/a.log", b"a");
+    write_file(dir.path(), "keep/b.txt", b"b");
+    write_file(dir.path(), "keep/c.txt", b"c");
+    write_file(dir.path(), "gone/d.txt", b"d");
+    let (before, _) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    let index = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &index, None).unwrap();
+
+    std::fs::remove_file(dir.path().join("logs/a.log")).unwrap();
+    std::fs::remove_file(dir.path().join("keep/c.txt")).unwrap();
+    std::fs::remove_dir_all(dir.path().join("gone")).unwrap();
+    let check = Opts {
+        prune_empty_dirs: true,
+        ..Default::default()
+    };
+    let diff = check_dir(dir.path(), &check).unwrap();
+
+    let emptied = nefaxer::check::newly_empty_dirs(&diff, before.keys());
+    assert_eq!(emptied, vec![PathBuf::from("logs")]);
+}
+
+// --- tree digest ---
+
+#[test]
+fn test_tree_digest_stable_on_noop_run_and_changes_on_edit() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"abc");
+    write_file(dir.path(), "sub/b.bin", &[1u8; 5000]);
+    let lib_opts = NefaxOpts {
+        tree_digest: true,
+        ..Default::default()
+    };
+    let (nefax, _, report) =
+        nefaxer::nefax_dir_with_report(dir.path(), &lib_opts, None, None::<fn(&nefaxer::Entry)>)
+            .unwrap();
+    assert_eq!(report.tree_digest, Some(nefaxer::tree_digest(&nefax)));
+
+    let opts = Opts {
+        write_to_db: true,
+        tree_digest: true,
+        ..Default::default()
+    };
+    let (_, _, first) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    // The DB-side digest covers the same entries as the in-memory snapshot.
+    assert_eq!(first.tree_digest, report.tree_digest);
+    let (_, _, again) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(again.tree_digest, first.tree_digest);
+
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let stored = nefaxer::engine::load_index_meta(&conn).unwrap().tree_digest;
+    assert_eq!(
+        stored,
+        first.tree_digest.map(|d| nefaxer::engine::hex_encode(&d))
+    );
+    drop(conn);
+
+    write_file(dir.path(), "a.txt", b"abcd");
+    let (_, _, edited) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert!(edited.tree_digest.is_some());
+    assert_ne!(edited.tree_digest, first.tree_digest);
+}
+
+// --- keyed hashing ---
+
+#[test]
+fn test_hash_key_records_keyed_algo_and_keyed_hashes() {
+    let dir = temp_root();
+    write_file(dir.path(), "big.bin", &[3u8; 8192]);
+    let key = nefaxer::engine::HashKey::derive("audit secret");
+    let opts = Opts {
+        write_to_db: true,
+        with_hash: true,
+        hash_key: Some(key),
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let meta = nefaxer::engine::load_index_meta(&conn).unwrap();
+    assert_eq!(
+        meta.hash_algo.as_deref(),
+        Some(nefaxer::engine::HASH_ALGO_KEYED)
+    );
+    assert_eq!(meta.hash_key_check, Some(key.check_value()));
+    let index = nefaxer::engine::load_index(&conn).unwrap();
+    let keyed =
+        nefaxer::engine::hash_file_keyed(&dir.path().join("big.bin"), 8192, Some(&key)).unwrap();
+    assert_eq!(
+        index[&PathBuf::from("big.bin")].2.as_deref(),
+        keyed.as_ref().map(|h| h.as_slice())
+    );
+    drop(conn);
+
+    // Verifying with the right key finds nothing; another key is refused instead of flagging corruption.
+    let verify = Opts {
+        verify_sample: Some(1.0),
+        ..opts
+    };
+    assert!(check_dir(dir.path(), &verify).unwrap().is_empty());
+    let other = Opts {
+        hash_key: Some(nefaxer::engine::HashKey::derive("forger")),
+        ..verify
+    };
+    let err = check_dir(dir.path(), &other).err().expect("key mismatch");
+    assert!(err.to_string().contains("different hash key"), "{err}");
+}
+
+#[test]
+fn test_seeded_run_refuses_to_change_the_hash_key() {
+    let dir = temp_root();
+    write_file(dir.path(), "a/big.bin", &[4u8; 8192]);
+    write_file(dir.path(), "b/big.bin", &[5u8; 8192]);
+    let opts = Opts {
+        write_to_db: true,
+        with_hash: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    let keyed_seeded = Opts {
+        hash_key: Some(nefaxer::engine::HashKey::derive("new secret")),
+        seeds: vec![PathBuf::from("a")],
+        ..opts
+    };
+    let Err(err) = nefax_dir_with_opts(dir.path(), &keyed_seeded, None) else {
+        panic!("seeded run under a new key should be rejected");
+    };
+    assert!(format!("{err:#}").contains("without seeds"), "{err:#}");
+
+    // The index is untouched: still plain blake3 throughout.
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let meta = nefaxer::engine::load_index_meta(&conn).unwrap();
+    assert_eq!(meta.hash_algo.as_deref(), Some(nefaxer::engine::HASH_ALGO));
+}
+
+#[test]
+fn test_reindex_under_new_hash_key_rehashes_with_it() {
+    let dir = temp_root();
+    write_file(dir.path(), "big.bin", &[4u8; 8192]);
+    let opts = Opts {
+        write_to_db: true,
+        with_hash: true,
+        hash_key: Some(nefaxer::engine::HashKey::derive("old secret")),
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    let new_key = nefaxer::engine::HashKey::derive("new secret");
+    let rotated = Opts {
+        hash_key: Some(new_key),
+        ..opts
+    };
+    nefax_dir_with_opts(dir.path(), &rotated, None).unwrap();
+
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let meta = nefaxer::engine::load_index_meta(&conn).unwrap();
+    assert_eq!(meta.hash_key_check, Some(new_key.check_value()));
+    let index = nefaxer::engine::load_index(&conn).unwrap();
+    let keyed = nefaxer::engine::hash_file_keyed(&dir.path().join("big.bin"), 8192, Some(&new_key))
+        .unwrap();
+    assert_eq!(
+        index[&PathBuf::from("big.bin")].2.as_deref(),
+        keyed.as_ref().map(|h| h.as_slice())
+    );
+}
+
+// --- compare_dirs ---
+
+fn write_backup_fixture(root: &std::path::Path) {
+    write_file(root, "docs/readme.md", b"# readme");
+    write_file(root, "docs/big.bin", &[7u8; 8192]);
+    write_file(root, "src/lib.rs", b"pub fn f() {}");
+}
+
+#[test]
+fn test_compare_dirs_identical_trees_with_hash() {
+    let a = temp_root();
+    let b = temp_root();
+    write_backup_fixture(a.path());
+    write_backup_fixture(b.path());
+
+    let opts = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let diff = nefaxer::compare_dirs(a.path(), b.path(), &opts).unwrap();
+    assert!(diff.added.is_empty(), "added: {:?}", diff.added);
+    assert!(diff.removed.is_empty(), "removed: {:?}", diff.removed);
+    assert!(diff.modified.is_empty(), "modified: {:?}", diff.modified);
+}
+
+#[test]
+fn test_compare_dirs_reports_extra_and_changed_files() {
+    let a = temp_root();
+    let b = temp_root();
+    write_backup_fixture(a.path());
+    copy_tree(a.path(), b.path());
+    write_file(b.path(), "docs/extra.txt", b"extra");
+
+    let diff = nefaxer::compare_dirs(a.path(), b.path(), &NefaxOpts::default()).unwrap();
+    assert_eq!(diff.added, vec![PathBuf::from("docs/extra.txt")]);
+    assert!(diff.removed.is_empty());
+
+    // Same size, different content: only caught in content (hash) mode.
+    write_file(b.path(), "src/lib.rs", b"pub fn g() {}");
+    let opts = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let diff = nefaxer::compare_dirs(a.path(), b.path(), &opts).unwrap();
+    assert_eq!(diff.modified, vec![PathBuf::from("src/lib.rs")]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_compare_dirs_reports_unreadable_file_as_modified() {
+    use std::os::unix::fs::PermissionsExt;
+    if nefaxer::engine::running_as_root() {
+        // Root reads a file whatever its mode, so none can be made unreadable here.
+        return;
+    }
+    let a = temp_root();
+    let b = temp_root();
+    write_backup_fixture(a.path());
+    write_backup_fixture(b.path());
+    for root in [a.path(), b.path()] {
+        let locked = root.join("src/lib.rs");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    }
+
+    let opts = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let diff = nefaxer::compare_dirs(a.path(), b.path(), &opts).unwrap();
+    assert_eq!(diff.modified, vec![PathBuf::from("src/lib.rs")]);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+}
+
+// --- index_self ---
+
+fn write_decoy_tree(root: &std::path::Path) {
+    write_file(root, ".nefaxer", b"not a database");
+    write_file(root, "nefaxer.results", b"user notes");
+    write_file(root, "data.txt", b"data");
+}
+
+#[test]
+fn test_index_self_includes_decoys_with_external_db() {
+    let dir = temp_root();
+    write_decoy_tree(dir.path());
+    let external = tempfile::tempdir().unwrap();
+    let external_db = external.path().join("tree.nefaxer");
+
+    let opts = Opts {
+        db_path: Some(external_db.clone()),
+        index_self: true,
+        ..Default::default()
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    let mut added = diff.added;
+    added.sort();
+    assert_eq!(
+        added,
+        vec![
+            PathBuf::from(".nefaxer"),
+            PathBuf::from("data.txt"),
+            PathBuf::from("nefaxer.results"),
+        ]
+    );
+
+    let opts = Opts {
+        index_self: false,
+        ..opts
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert!(!diff.added.contains(&PathBuf::from("nefaxer.results")));
+}
+
+#[test]
+fn test_index_self_ignored_when_db_in_tree() {
+    let dir = temp_root();
+    write_decoy_tree(dir.path());
+    let opts = Opts {
+        db_path: Some(dir.path().join("index.db")),
+        index_self: true,
+        ..Default::default()
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert_eq!(diff.added.len(), 2, "added: {:?}", diff.added);
+    assert!(!diff.added.contains(&PathBuf::from("nefaxer.results")));
+    assert!(!diff.added.contains(&PathBuf::from("index.db")));
+}
+
+// --- verify_sample ---
+
+#[test]
+fn test_verify_sample_flags_corruption_with_same_metadata() {
+    let dir = temp_root();
+    write_file(dir.path(), "data.bin", &[1u8; 16 * 1024]);
+    write_file(dir.path(), "ok.bin", &[2u8; 16 * 1024]);
+    let hashed = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let (nefax, _) = nefax_dir(dir.path(), &hashed, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert!(nefax[&PathBuf::from("data.bin")].hash.is_some());
+    save_index(&dir.path().join(".nefaxer"), &nefax);
+
+    // Flip content without changing size, then restore the original mtime.
+    let path = dir.path().join("data.bin");
+    let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+    let mut corrupt = vec![1u8; 16 * 1024];
+    corrupt[100] = 0xff;
+    std::fs::write(&path, corrupt).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let opts = Opts {
+        with_hash: true,
+        verify_sample: Some(1.0),
+        ..Default::default()
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert_eq!(diff.corrupted, vec![PathBuf::from("data.bin")]);
+    assert!(diff.modified.is_empty(), "modified: {:?}", diff.modified);
+
+    let opts = Opts {
+        verify_sample: None,
+        ..opts
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert!(diff.corrupted.is_empty());
+    assert!(diff.modified.is_empty());
+}
+
+// --- change policy ---
+
+fn set_mtime(path: &std::path::Path, mtime: std::time::SystemTime) {
+    std::fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+}
+
+#[test]
+fn test_size_only_policy_ignores_mtime_change() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"hello");
+    let (before, _) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+    set_mtime(&dir.path().join("a.txt"), later);
+
+    let size_only = NefaxOpts {
+        change_policy: ChangePolicy::SizeOnly,
+        ..Default::default()
+    };
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &size_only,
+        Some(&before),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert!(diff.modified.is_empty(), "modified: {:?}", diff.modified);
+
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        Some(&before),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(diff.modified, vec![PathBuf::from("a.txt")]);
+}
+
+#[test]
+fn test_hash_small_files_catches_small_edit_with_preserved_mtime() {
+    let dir = temp_root();
+    write_file(dir.path(), "app.cfg", b"port=8080\n");
+    let path = dir.path().join("app.cfg");
+    let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+    // Default change policy: the unchanged mtime and size must not let the stored hash be reused.
+    let large_only = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let small_hashed = NefaxOpts {
+        hash_small_files: true,
+        ..large_only.clone()
+    };
+    let run = |opts: &NefaxOpts, existing: Option<&nefaxer::Nefax>| {
+        nefax_dir(dir.path(), opts, existing, None::<fn(&nefaxer::Entry)>).unwrap()
+    };
+    let (before, _) = run(&small_hashed, None);
+    let (before_unhashed, _) = run(&large_only, None);
+    assert!(before[&PathBuf::from("app.cfg")].hash.is_some());
+    assert!(before_unhashed[&PathBuf::from("app.cfg")].hash.is_none());
+
+    write_file(dir.path(), "app.cfg", b"port=9090\n");
+    set_mtime(&path, mtime);
+
+    let (_, diff) = run(&small_hashed, Some(&before));
+    assert_eq!(diff.modified, vec![PathBuf::from("app.cfg")]);
+    let (_, diff) = run(&large_only, Some(&before_unhashed));
+    assert!(diff.modified.is_empty(), "modified: {:?}", diff.modified);
+}
+
+#[test]
+fn test_paranoid_without_hash_warns_and_compares_metadata() {
+    let paranoid = NefaxOpts {
+        paranoid: true,
+        ..Default::default()
+    };
+    let warnings = Opts::from(&paranoid).consistency_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("paranoid"), "{warnings:?}");
+    let hashed = NefaxOpts {
+        with_hash: true,
+        ..paranoid.clone()
+    };
+    assert!(Opts::from(&hashed).consistency_warnings().is_empty());
+
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"hello");
+    write_file(dir.path(), "b.txt", b"world");
+    let run = |opts: &NefaxOpts, existing: Option<&nefaxer::Nefax>| {
+        nefax_dir(dir.path(), opts, existing, None::<fn(&nefaxer::Entry)>).unwrap()
+    };
+    let (before, _) = run(&NefaxOpts::default(), None);
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+    set_mtime(&dir.path().join("a.txt"), later);
+
+    let (_, plain) = run(&NefaxOpts::default(), Some(&before));
+    let (_, with_paranoid) = run(&paranoid, Some(&before));
+    assert_eq!(with_paranoid.modified, plain.modified);
+    assert_eq!(with_paranoid.modified, vec![PathBuf::from("a.txt")]);
+}
+
+#[test]
+fn test_hash_only_policy_catches_edit_with_preserved_mtime() {
+    let dir = temp_root();
+    write_file(dir.path(), "data.bin", &[1u8; 16 * 1024]);
+    let hash_only = NefaxOpts {
+        change_policy: ChangePolicy::HashOnly,
+        ..Default::default()
+    };
+    let (before, _) = nefax_dir(dir.path(), &hash_only, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert!(before[&PathBuf::from("data.bin")].hash.is_some());
+
+    let path = dir.path().join("data.bin");
+    let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+    let mut edited = vec![1u8; 16 * 1024];
+    edited[0] = 2;
+    std::fs::write(&path, edited).unwrap();
+    set_mtime(&path, mtime);
+
+    // Default policy trusts the unchanged mtime/size and reuses the stored hash.
+    let hashed = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &hashed,
+        Some(&before),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert!(diff.modified.is_empty());
+
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &hash_only,
+        Some(&before),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(diff.modified, vec![PathBuf::from("data.bin")]);
+}
+
+// --- touched ---
+
+#[test]
+fn test_touched_file_with_same_content_reported_as_touched() {
+    use clap::Parser;
+
+    let dir = temp_root();
+    write_file(dir.path(), "same.bin", &[1u8; 16 * 1024]);
+    write_file(dir.path(), "edited.bin", &[2u8; 16 * 1024]);
+    let hashed = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let (before, _) = nefax_dir(dir.path(), &hashed, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    save_index(&dir.path().join(".nefaxer"), &before);
+
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+    set_mtime(&dir.path().join("same.bin"), later);
+    write_file(dir.path(), "edited.bin", &[3u8; 16 * 1024]);
+
+    let opts = Opts {
+        with_hash: true,
+        report_touched: true,
+        ..Default::default()
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert_eq!(diff.touched, vec![PathBuf::from("same.bin")]);
+    assert_eq!(diff.modified, vec![PathBuf::from("edited.bin")]);
+    assert_eq!(diff.total(), 1);
+    let paranoid = Opts {
+        paranoid: true,
+        ..opts
+    };
+    assert_eq!(
+        check_dir(dir.path(), &paranoid).unwrap().touched,
+        vec![PathBuf::from("same.bin")]
+    );
+
+    let lib_opts = NefaxOpts {
+        report_touched: true,
+        ..hashed
+    };
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &lib_opts,
+        Some(&before),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(diff.touched, vec![PathBuf::from("same.bin")]);
+    assert_eq!(diff.modified, vec![PathBuf::from("edited.bin")]);
+
+    // Off by default: the touch counts as a modification.
+    let plain = Opts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let mut modified = check_dir(dir.path(), &plain).unwrap().modified;
+    modified.sort();
+    assert_eq!(
+        modified,
+        vec![PathBuf::from("edited.bin"), PathBuf::from("same.bin")]
+    );
+
+    // Only dry runs report touched files; an index run would silently ignore the flag.
+    let parse = |extra: &[&str]| {
+        let args = ["nefaxer".as_ref(), dir.path().as_os_str()]
+            .into_iter()
+            .chain(extra.iter().map(std::ffi::OsStr::new));
+        nefaxer::engine::Cli::try_parse_from(args)
+    };
+    assert!(parse(&["--touched"]).is_err());
+    assert!(parse(&["--touched", "--dry-run"]).is_ok());
+
+    // The same holds for `touched = true` in .nefaxer.toml.
+    write_file(dir.path(), ".nefaxer.toml", b"[settings]\ntouched = true\n");
+    let setup = |extra: &[&str]| nefaxer::engine::cli::setup_opts(&parse(extra).unwrap()).unwrap();
+    assert!(!setup(&[]).report_touched);
+    assert!(setup(&["--dry-run"]).report_touched);
+}
+
+// --- strict-mode early exit with progress bars ---
+
+#[cfg(unix)]
+#[test]
+fn test_strict_error_with_progress_returns_error() {
+    use clap::Parser;
+
+    let dir = temp_root();
+    write_file(dir.path(), "sub/a.txt", b"a");
+    // Following this link loops back into the tree, which the walk reports as an error.
+    std::os::unix::fs::symlink(dir.path(), dir.path().join("sub/loop")).unwrap();
+
+    let cli = nefaxer::engine::Cli::parse_from([
+        "nefaxer".as_ref(),
+        dir.path().as_os_str(),
+        "--strict".as_ref(),
+        "--verbose".as_ref(),
+        "--follow-links".as_ref(),
+    ]);
+    let (result, _) = capture_logs(|| nefaxer::engine::handle_run(&cli));
+    let err = result.unwrap_err();
+    assert!(err.to_string().to_lowercase().contains("loop"), "{err}");
+    assert!(!dir.path().join(".nefaxer").exists());
+}
+
+// --- hard links in stats ---
+
+#[cfg(unix)]
+#[test]
+fn test_profile_counts_hardlinked_bytes_once() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.bin", &[0u8; 10_000]);
+    write_file(dir.path(), "b.txt", b"12345");
+    std::fs::hard_link(dir.path().join("a.bin"), dir.path().join("a_link.bin")).unwrap();
+
+    let profile = nefaxer::stats::profile_dir(dir.path(), &Opts::default()).unwrap();
+    assert_eq!(profile.files, 3);
+    assert_eq!(profile.hardlinks, 1);
+    assert_eq!(profile.total_bytes, 10_005);
+    let histogram_files: usize = profile.size_histogram.iter().map(|b| b.count).sum();
+    assert_eq!(histogram_files, 2);
+}
+
+// --- store_absolute ---
+
+#[test]
+fn test_store_absolute_round_trip_and_check() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+    write_file(dir.path(), "sub/b.txt", b"b");
+    let opts = NefaxOpts {
+        store_absolute: true,
+        ..Default::default()
+    };
+    let (nefax, _) = nefax_dir(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert!(nefax.keys().all(|p| p.is_absolute()), "{:?}", nefax.keys());
+    assert!(nefaxer::validate_nefax(&nefax).is_err());
+    nefaxer::validate_nefax_with_mode(&nefax, true).unwrap();
+
+    // Re-index against the absolute snapshot: nothing changed.
+    let (_, diff) =
+        nefax_dir(dir.path(), &opts, Some(&nefax), None::<fn(&nefaxer::Entry)>).unwrap();
+    assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.modified.is_empty());
+
+    // A DB recorded as absolute is checked in absolute mode even when the run did not ask for it.
+    let db = dir.path().join(".nefaxer");
+    save_index(&db, &nefax);
+    let conn = nefaxer::engine::open_db(&db, None).unwrap();
+    nefaxer::engine::write_meta(&conn, "store_absolute", "true").unwrap();
+    drop(conn);
+    write_file(dir.path(), "c.txt", b"c");
+    let diff = check_dir(dir.path(), &Opts::default()).unwrap();
+    assert_eq!(diff.added.len(), 1);
+    assert!(diff.added[0].is_absolute() && diff.added[0].ends_with("c.txt"));
+    assert!(diff.removed.is_empty() && diff.modified.is_empty());
+}
+
+// --- NEFAXER_THREADS ---
+
+#[test]
+fn test_threads_env_value_parses_to_num_threads() {
+    use nefaxer::engine::cli::threads_from_str;
+
+    assert_eq!(threads_from_str(Some("3")), Some(3));
+    assert_eq!(threads_from_str(Some(" 8\n")), Some(8));
+    assert_eq!(threads_from_str(Some("zero")), None);
+    assert_eq!(threads_from_str(Some("-2")), None);
+    assert_eq!(threads_from_str(Some("0")), None);
+    assert_eq!(threads_from_str(None), None);
+}
+
+// --- --exclude-from ---
+
+#[test]
+fn test_exclude_from_file_merges_patterns() {
+    use clap::Parser;
+
+    let dir = temp_root();
+    write_file(dir.path(), "src/main.rs", b"fn main() {}");
+    write_file(dir.path(), "debug.log", b"log");
+    write_file(dir.path(), "build/out.o", b"obj");
+    let patterns = temp_root();
+    write_file(
+        patterns.path(),
+        "excludes",
+        b"# build artifacts\n*.log\n\n  build/  \n",
+    );
+
+    let cli = nefaxer::engine::Cli::parse_from([
+        "nefaxer".as_ref(),
+        dir.path().as_os_str(),
+        "-e".as_ref(),
+        "*.tmp".as_ref(),
+        "--exclude-from".as_ref(),
+        patterns.path().join("excludes").as_os_str(),
+    ]);
+    let opts = nefaxer::engine::cli::setup_opts(&cli).unwrap();
+    assert_eq!(opts.exclude, ["*.tmp", "*.log", "build/"]);
+
+    let lib_opts = NefaxOpts {
+        exclude: opts.exclude,
+        ..Default::default()
+    };
+    let (nefax, _) = nefax_dir(dir.path(), &lib_opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert!(nefax.contains_key(std::path::Path::new("src/main.rs")));
+    assert!(!nefax.contains_key(std::path::Path::new("debug.log")));
+    assert!(!nefax.keys().any(|p| p.starts_with("build")));
+
+    let missing = nefaxer::engine::Cli::try_parse_from([
+        "nefaxer".as_ref(),
+        dir.path().as_os_str(),
+        "--exclude-from".as_ref(),
+        patterns.path().join("missing").as_os_str(),
+    ]);
+    assert!(missing.is_err());
+}
+
+// --- .nefaxer.toml profiles ---
+
+#[test]
+fn test_profile_overrides_base_settings() {
+    use clap::Parser;
+    use nefaxer::engine::cli::setup_opts;
+
+    let dir = temp_root();
+    write_file(
+        dir.path(),
+        ".nefaxer.toml",
+        b"[settings]\nhash = false\nexclude = [\"target\"]\nstrict = true\n\n\
+[profiles.quick]\nexclude = [\"target\", \"node_modules\"]\n\n\
+[profiles.deep]\nhash = true\nparanoid = true\n",
+    );
+    let parse = |extra: &[&str]| {
+        let mut args = vec!["nefaxer".as_ref(), dir.path().as_os_str()];
+        args.extend(extra.iter().map(std::ffi::OsStr::new));
+        nefaxer::engine::Cli::parse_from(args)
+    };
+
+    let base = setup_opts(&parse(&[])).unwrap();
+    assert!(!base.with_hash && !base.paranoid && base.strict);
+    assert_eq!(base.exclude, ["target"]);
+
+    let deep = setup_opts(&parse(&["--profile", "deep"])).unwrap();
+    assert!(deep.with_hash && deep.paranoid && deep.strict);
+    assert_eq!(deep.exclude, ["target"]);
+
+    let quick = setup_opts(&parse(&["--profile", "quick", "--check-hash"])).unwrap();
+    assert!(quick.with_hash, "CLI flags still win over the profile");
+    assert_eq!(quick.exclude, ["target", "node_modules"]);
+
+    let Err(err) = setup_opts(&parse(&["--profile", "full"])) else {
+        panic!("expected an unknown-profile error");
+    };
+    let msg = err.to_string();
+    assert!(
+        msg.contains("\"full\"") && msg.contains("deep, quick"),
+        "{msg}"
+    );
+}
+
+#[test]
+fn test_malformed_config_is_an_error_naming_the_file() {
+    use clap::Parser;
+    use nefaxer::engine::cli::setup_opts;
+
+    let dir = temp_root();
+    write_file(dir.path(), ".nefaxer.toml", b"[settings]\nhash = yes\n");
+    let cli = nefaxer::engine::Cli::parse_from(["nefaxer".as_ref(), dir.path().as_os_str()]);
+    let Err(err) = setup_opts(&cli) else {
+        panic!("expected a parse error, not a missing config");
+    };
+    let msg = format!("{err:#}");
+    assert!(msg.contains(".nefaxer.toml"), "{msg}");
+    assert!(msg.contains("hash"), "{msg}");
+
+    // Without a .nefaxer.toml the defaults apply.
+    std::fs::remove_file(dir.path().join(".nefaxer.toml")).unwrap();
+    assert!(setup_opts(&cli).is_ok());
+}
+
+// --- check output report ---
+
+#[test]
+fn test_check_output_writes_all_sections() {
+    let dir = temp_root();
+    write_file(dir.path(), "keep.txt", b"keep");
+    write_file(dir.path(), "gone.txt", b"gone");
+    write_file(dir.path(), "edit.txt", b"v1");
+    let (nefax, _) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    save_index(&dir.path().join(".nefaxer"), &nefax);
+
+    std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+    write_file(dir.path(), "edit.txt", b"version 2");
+    write_file(dir.path(), "new.txt", b"new");
+
+    let out_dir = tempfile::tempdir().unwrap();
+    let report = out_dir.path().join("diff.txt");
+    let opts = Opts {
+        output: Some(report.clone()),
+        ..Default::default()
+    };
+    check_dir(dir.path(), &opts).unwrap();
+
+    let text = std::fs::read_to_string(&report).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let section = |name: &str| lines.iter().position(|l| *l == name).unwrap();
+    let (added, removed, modified) = (
+        section("[added]"),
+        section("[removed]"),
+        section("[modified]"),
+    );
+    assert!(added < removed && removed < modified);
+    assert_eq!(lines[added + 1], "+ new.txt");
+    assert_eq!(lines[removed + 1], "- gone.txt");
+    assert!(lines[modified + 1..].contains(&"M edit.txt"));
+    assert!(!text.contains("keep.txt"));
+}
+
+// --- quiet ---
+
+#[test]
+fn test_quiet_no_change_run_prints_nothing() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_nefaxer"))
+            .arg(dir.path())
+            .args(args)
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap()
+    };
+    assert!(run(&[]).status.success());
+
+    let loud = run(&["--dry-run"]);
+    assert!(String::from_utf8_lossy(&loud.stderr).contains("No changes detected"));
+
+    let quiet = run(&["--dry-run", "--quiet"]);
+    assert!(quiet.status.success());
+    assert!(
+        quiet.stdout.is_empty() && quiet.stderr.is_empty(),
+        "stdout: {:?}, stderr: {:?}",
+        String::from_utf8_lossy(&quiet.stdout),
+        String::from_utf8_lossy(&quiet.stderr)
+    );
+}
+
+// --- key file ---
+
+#[cfg(feature = "sqlcipher")]
+#[test]
+fn test_key_file_used_instead_of_prompt() {
+    use clap::Parser;
+
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+    let keys = tempfile::tempdir().unwrap();
+    let key_file = keys.path().join("index.key");
+    std::fs::write(&key_file, "s3cret\n").unwrap();
+
+    // No env key and no terminal: only the key file can supply the passphrase.
+    let cli = nefaxer::engine::Cli::parse_from([
+        "nefaxer".as_ref(),
+        dir.path().as_os_str(),
+        "--encrypt".as_ref(),
+        "--key-file".as_ref(),
+        key_file.as_os_str(),
+    ]);
+    capture_logs(|| nefaxer::engine::handle_run(&cli))
+        .0
+        .unwrap();
+
+    let raw = rusqlite::Connection::open(dir.path().join(".nefaxer")).unwrap();
+    assert!(
+        raw.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+            .is_err()
+    );
+    assert_eq!(
+        nefaxer::utils::get_passphrase(dir.path(), false, Some(&key_file)).unwrap(),
+        "s3cret"
+    );
+
+    let opts = Opts {
+        key_file: Some(key_file),
+        ..Default::default()
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert!(diff.added.is_empty() && diff.modified.is_empty() && diff.removed.is_empty());
+}
+
+// --- focus ---
+
+#[test]
+fn test_check_focus_reports_only_matching_paths() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.rs", b"fn a() {}");
+    write_file(dir.path(), "notes.txt", b"notes");
+    write_file(dir.path(), "src/lib.rs", b"pub mod x;");
+    write_file(dir.path(), "src/data.txt", b"data");
+    let (nefax, _) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    save_index(&dir.path().join(".nefaxer"), &nefax);
+
+    write_file(dir.path(), "a.rs", b"fn a() { changed(); }");
+    write_file(dir.path(), "notes.txt", b"notes, longer now");
+    std::fs::remove_file(dir.path().join("src/lib.rs")).unwrap();
+    std::fs::remove_file(dir.path().join("src/data.txt")).unwrap();
+    write_file(dir.path(), "new.rs", b"fn new() {}");
+    write_file(dir.path(), "new.txt", b"new");
+
+    let opts = Opts {
+        focus: vec!["*.rs".to_string()],
+        ..Default::default()
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert_eq!(diff.added, vec![PathBuf::from("new.rs")]);
+    assert_eq!(diff.modified, vec![PathBuf::from("a.rs")]);
+    assert_eq!(diff.removed, vec![PathBuf::from("src/lib.rs")]);
+}
+
+// --- since ---
+
+#[test]
+fn test_check_since_reports_only_paths_newer_than_last_index() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    let dir = temp_root();
+    let long_ago = SystemTime::now() - Duration::from_secs(1000);
+    for name in ["old.txt", "edited.txt", "gone.txt"] {
+        write_file(dir.path(), name, b"v1");
+        set_mtime(&dir.path().join(name), long_ago);
+    }
+    let (nefax, _) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    let db = dir.path().join(".nefaxer");
+    save_index(&db, &nefax);
+    let updated_at = SystemTime::now() - Duration::from_secs(500);
+    let secs = updated_at.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let conn = nefaxer::engine::open_db(&db, None).unwrap();
+    nefaxer::engine::write_meta(&conn, "updated_at", &secs.to_string()).unwrap();
+    drop(conn);
+
+    write_file(dir.path(), "edited.txt", b"v2");
+    write_file(dir.path(), "new.txt", b"new");
+    // Copied in with an old mtime: invisible to --since by design.
+    write_file(dir.path(), "copied.txt", b"copied");
+    set_mtime(&dir.path().join("copied.txt"), long_ago);
+    std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+
+    let opts = Opts {
+        since_last_index: true,
+        ..Default::default()
+    };
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert_eq!(diff.added, vec![PathBuf::from("new.txt")]);
+    assert_eq!(diff.modified, vec![PathBuf::from("edited.txt")]);
+    assert_eq!(diff.removed, vec![PathBuf::from("gone.txt")]);
+
+    let diff = check_dir(dir.path(), &Opts::default()).unwrap();
+    assert!(diff.added.contains(&PathBuf::from("copied.txt")));
+}
+
+// --- case-only renames ---
+
+#[cfg(any(target_os = "macos", windows))]
+#[test]
+fn test_case_only_rename_reported_as_case_changed() {
+    let dir = temp_root();
+    write_file(dir.path(), "Readme.md", b"# readme");
+    write_file(dir.path(), "other.txt", b"other");
+    let (before, _) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    std::fs::rename(dir.path().join("Readme.md"), dir.path().join("README.md")).unwrap();
+
+    let expected = vec![(PathBuf::from("Readme.md"), PathBuf::from("README.md"))];
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        Some(&before),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(diff.case_changed, expected);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+
+    save_index(&dir.path().join(".nefaxer"), &before);
+    let diff = check_dir(dir.path(), &Opts::default()).unwrap();
+    assert_eq!(diff.case_changed, expected);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+}
+
+// --- jsonl export ---
+
+#[test]
+fn test_jsonl_export_writes_one_line_per_entry() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+    write_file(dir.path(), "src/lib.rs", b"pub fn f() {}");
+    write_file(dir.path(), "big.bin", &[7u8; 8 * 1024]);
+    let opts = NefaxOpts {
+        with_hash: true,
+        ..Default::default()
+    };
+    let (nefax, _) = nefax_dir(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+
+    let mut out = Vec::new();
+    let count = nefaxer::nefax_dir_jsonl(dir.path(), &opts, &mut out).unwrap();
+    let lines: Vec<serde_json::Value> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(count, nefax.len());
+    assert_eq!(lines.len(), nefax.len());
+
+    let big = lines.iter().find(|v| v["path"] == "big.bin").unwrap();
+    assert_eq!(big["size"], 8 * 1024);
+    let expected_hash: String = nefax[&PathBuf::from("big.bin")]
+        .hash
+        .unwrap()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    assert_eq!(big["hash"], expected_hash.as_str());
+    let small = lines.iter().find(|v| v["path"] == "a.txt").unwrap();
+    assert!(small["hash"].is_null());
+    assert!(small["mtime_ns"].is_i64());
+}
+
+#[test]
+fn test_unicode_normalize_matches_nfc_and_nfd_spellings() {
+    let nfd = temp_root();
+    let nfc = temp_root();
+    write_file(nfd.path(), "cafe\u{301}.txt", b"latte");
+    write_file(nfc.path(), "caf\u{e9}.txt", b"latte");
+    let mtime = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+    set_mtime(&nfd.path().join("cafe\u{301}.txt"), mtime);
+    set_mtime(&nfc.path().join("caf\u{e9}.txt"), mtime);
+
+    let normalized = NefaxOpts {
+        path_key: PathKeyPolicy::Nfc,
+        ..Default::default()
+    };
+    let (from_nfd, _) =
+        nefax_dir(nfd.path(), &normalized, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert!(from_nfd.contains_key(std::path::Path::new("caf\u{e9}.txt")));
+    let (_, diff) = nefax_dir(
+        nfc.path(),
+        &normalized,
+        Some(&from_nfd),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert!(
+        diff.is_empty(),
+        "added {:?} removed {:?}",
+        diff.added,
+        diff.removed
+    );
+
+    let (raw, _) = nefax_dir(
+        nfd.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    let (_, diff) = nefax_dir(
+        nfc.path(),
+        &NefaxOpts::default(),
+        Some(&raw),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(diff.added, vec![PathBuf::from("caf\u{e9}.txt")]);
+    assert_eq!(diff.removed, vec![PathBuf::from("cafe\u{301}.txt")]);
+}
+
+#[test]
+fn test_unicode_normalize_hashes_and_reports_files_by_on_disk_name() {
+    let dir = temp_root();
+    let disk_name = "cafe\u{301}.bin";
+    write_file(dir.path(), disk_name, &[9u8; 8192]);
+    let opts = Opts {
+        write_to_db: true,
+        with_hash: true,
+        path_key: PathKeyPolicy::Nfc,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+
+    // The row is keyed by the NFC spelling but hashed from the NFD file on disk.
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let index = nefaxer::engine::load_index(&conn).unwrap();
+    let expected = nefaxer::engine::hash_file(&dir.path().join(disk_name), 8192).unwrap();
+    assert!(expected.is_some());
+    assert_eq!(
+        index[&PathBuf::from("caf\u{e9}.bin")].2.as_deref(),
+        expected.as_ref().map(|h| h.as_slice())
+    );
+    drop(conn);
+
+    let verify = Opts {
+        paranoid: true,
+        verify_sample: Some(1.0),
+        ..opts.clone()
+    };
+    assert!(check_dir(dir.path(), &verify).unwrap().is_empty());
+
+    // Removals are reported (and scripted) under the name on disk, not the key.
+    std::fs::remove_file(dir.path().join(disk_name)).unwrap();
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert_eq!(diff.removed, vec![PathBuf::from(disk_name)]);
+    let script = diff.to_shell_script(nefaxer::ScriptStyle::Force);
+    assert!(script.contains(disk_name), "{script}");
+}
+
+#[test]
+fn test_path_key_policies_on_mixed_case_path() {
+    let dir = temp_root();
+    write_file(dir.path(), "Cafe\u{301}/ReadMe.TXT", b"hello");
+    let cases = [
+        (PathKeyPolicy::AsIs, "Cafe\u{301}/ReadMe.TXT"),
+        (PathKeyPolicy::Lowercase, "cafe\u{301}/readme.txt"),
+        (PathKeyPolicy::Nfc, "Caf\u{e9}/ReadMe.TXT"),
+        (PathKeyPolicy::Nfd, "Cafe\u{301}/ReadMe.TXT"),
+    ];
+    for (policy, expected) in cases {
+        let opts = NefaxOpts {
+            path_key: policy,
+            ..Default::default()
+        };
+        let (nefax, _) = nefax_dir(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+        assert!(
+            nefax.contains_key(std::path::Path::new(expected)),
+            "{policy:?}: {:?}",
+            nefax.keys().collect::<Vec<_>>()
+        );
+        // The same policy on both sides: nothing changed.
+        let (_, diff) =
+            nefax_dir(dir.path(), &opts, Some(&nefax), None::<fn(&nefaxer::Entry)>).unwrap();
+        assert!(diff.is_empty(), "{policy:?}");
+    }
+}
+
+#[test]
+fn test_path_key_lookup_follows_nfc_policy_and_collisions_are_counted() {
+    let dir = temp_root();
+    write_file(dir.path(), "cafe\u{301}.txt", b"latte");
+    let nfc = Opts {
+        write_to_db: true,
+        path_key: PathKeyPolicy::Nfc,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &nfc, None).unwrap();
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let found = nefaxer::engine::get_path_meta(&conn, std::path::Path::new("cafe\u{301}.txt"));
+    assert!(found.unwrap().is_some());
+    drop(conn);
+
+    // Names differing only by case are distinct files here but share one lowercased key.
+    let cased = temp_root();
+    write_file(cased.path(), "Notes.txt", b"upper");
+    write_file(cased.path(), "notes.txt", b"lower");
+    let lowercase = Opts {
+        write_to_db: true,
+        path_key: PathKeyPolicy::Lowercase,
+        ..Default::default()
+    };
+    let (_, _, report) = nefax_dir_with_opts(cased.path(), &lowercase, None).unwrap();
+    let stats = report.write_stats.unwrap();
+    assert_eq!(stats.key_collisions, 1);
+    assert_eq!(stats.received, 2);
+}
+
+// --- max_entries_in_memory ---
+
+#[test]
+fn test_store_spills_past_cap_with_same_diff_as_in_memory() {
+    use nefaxer::{IndexStore, NefaxStore, nefax_dir_store};
+
+    let dir = temp_root();
+    for i in 0..6 {
+        write_file(dir.path(), &format!("f{i}.txt"), b"x");
+    }
+    write_file(dir.path(), "sub/keep.bin", &[1u8; 5000]);
+    let opts = NefaxOpts {
+        with_hash: true,
+        tree_digest: true,
+        ..Default::default()
+    };
+    let (before, _) = nefax_dir(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+
+    write_file(dir.path(), "f0.txt", b"longer now");
+    std::fs::remove_file(dir.path().join("f1.txt")).unwrap();
+    write_file(dir.path(), "sub/new.txt", b"new");
+
+    let (memory, memory_diff, memory_report) =
+        nefax_dir_store(dir.path(), &opts, Some(&before as &dyn IndexStore)).unwrap();
+    assert!(!memory.is_spilled());
+    let capped = NefaxOpts {
+        max_entries_in_memory: Some(2),
+        ..opts.clone()
+    };
+    let (spilled, diff, report) =
+        nefax_dir_store(dir.path(), &capped, Some(&before as &dyn IndexStore)).unwrap();
+    assert!(spilled.is_spilled());
+
+    let sorted = |mut v: Vec<PathBuf>| {
+        v.sort();
+        v
+    };
+    assert_eq!(sorted(diff.added), sorted(memory_diff.added));
+    assert_eq!(sorted(diff.removed), sorted(memory_diff.removed));
+    assert_eq!(sorted(diff.modified), sorted(memory_diff.modified));
+    assert_eq!(report.tree_digest, memory_report.tree_digest);
+
+    assert_eq!(spilled.len(), memory.len());
+    for item in memory.iter() {
+        let (path, meta) = item.unwrap();
+        let stored = spilled.get(&path).unwrap().unwrap();
+        assert_eq!(
+            (stored.mtime_ns, stored.size, stored.hash),
+            (meta.mtime_ns, meta.size, meta.hash)
+        );
+    }
+    let paths: Vec<PathBuf> = spilled.iter().map(|r| r.unwrap().0).collect();
+    assert_eq!(
+        paths,
+        sorted(paths.clone()),
+        "spilled store iterates in path order"
+    );
+
+    // A spilled snapshot works as `existing` for the next run.
+    let (_, again, _) =
+        nefax_dir_store(dir.path(), &capped, Some(&spilled as &dyn IndexStore)).unwrap();
+    assert!(again.is_empty());
+
+    let NefaxStore::Spilled(spilled) = spilled else {
+        panic!("expected a spilled store");
+    };
+    let spill_path = spilled.path().to_path_buf();
+    assert!(spill_path.exists());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&spill_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o077, 0, "spill DB is private to its owner");
+    }
+    drop(spilled);
+    assert!(!spill_path.exists(), "spill DB is removed on drop");
+}
+
+#[test]
+fn test_rerun_tuning_carries_stored_path_count() {
+    let dir = temp_root();
+    write_file(dir.path(), "a.txt", b"a");
+    write_file(dir.path(), "sub/b.txt", b"b");
+    let opts = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    let (_, _, first) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(first.tuning.stored_path_count, None);
+    let (_, _, again) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    // a.txt, sub, sub/b.txt
+    assert_eq!(again.tuning.stored_path_count, Some(3));
+}
+
+// --- track_btime ---
+
+#[cfg(any(target_os = "macos", windows, target_os = "linux"))]
+#[test]
+fn test_track_btime_captures_creation_time() {
+    let dir = temp_root();
+    write_file(dir.path(), "new.txt", b"fresh");
+    let Ok(created) = std::fs::metadata(dir.path().join("new.txt")).and_then(|m| m.created())
+    else {
+        // Filesystem without birth times (e.g. some Linux mounts): nothing to capture.
+        return;
+    };
+    let created_ns = created
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as i64;
+
+    let (plain, _) = nefax_dir(
+        dir.path(),
+        &NefaxOpts::default(),
+        None,
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(plain[&PathBuf::from("new.txt")].btime_ns, None);
+
+    let opts = NefaxOpts {
+        track_btime: true,
+        ..Default::default()
+    };
+    let (tracked, _) = nefax_dir(dir.path(), &opts, None, None::<fn(&nefaxer::Entry)>).unwrap();
+    assert_eq!(
+        tracked[&PathBuf::from("new.txt")].btime_ns,
+        Some(created_ns)
+    );
+
+    // Same mtime/size/hash but a different stored creation time: only btime_is_change flags it.
+    let mut recreated = tracked.clone();
+    recreated
+        .get_mut(&PathBuf::from("new.txt"))
+        .unwrap()
+        .btime_ns = Some(created_ns - 1);
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &opts,
+        Some(&recreated),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert!(diff.modified.is_empty());
+    let opts = NefaxOpts {
+        btime_is_change: true,
+        ..Default::default()
+    };
+    let (_, diff) = nefax_dir(
+        dir.path(),
+        &opts,
+        Some(&recreated),
+        None::<fn(&nefaxer::Entry)>,
+    )
+    .unwrap();
+    assert_eq!(diff.modified, vec![PathBuf::from("new.txt")]);
+
+    let opts = Opts {
+        write_to_db: true,
+        track_btime: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+    let btimes = nefaxer::engine::load_index_btimes(&conn).unwrap();
+    assert_eq!(btimes.get(&PathBuf::from("new.txt")), Some(&created_ns));
+}
+
+// --- track_xattrs ---
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn test_track_xattrs_reports_attribute_change_as_modified() {
+    use rustix::fs::{XattrFlags, setxattr};
+    let dir = temp_root();
+    write_file(dir.path(), "tagged.txt", b"same content");
+    write_file(dir.path(), "plain.txt", b"untouched");
+    let stored_hashes = || {
+        let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+        nefaxer::engine::load_index_xattr_hashes(&conn).unwrap()
+    };
+    let opts = Opts {
+        write_to_db: true,
+        with_hash: true,
+        track_xattrs: true,
+        ..Default::default()
+    };
+    let untracked = Opts {
+        track_xattrs: false,
+        ..opts.clone()
+    };
+    nefax_dir_with_opts(dir.path(), &untracked, None).unwrap();
+    assert!(stored_hashes().is_empty());
+
+    // Rows written before tracking get their hash filled in without being reported as changed.
+    let (_, backfilled, _) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert!(backfilled.is_empty());
+    // An empty attribute set is stored too, so gaining the first attribute is a change.
+    assert!(stored_hashes().contains_key(&PathBuf::from("tagged.txt")));
+    let content = std::fs::read(dir.path().join("tagged.txt")).unwrap();
+
+    match setxattr(
+        dir.path().join("tagged.txt"),
+        "user.nefaxer.tag",
+        b"red",
+        XattrFlags::empty(),
+    ) {
+        Ok(()) => {}
+        Err(e) if e == rustix::io::Errno::NOTSUP || e == rustix::io::Errno::PERM => {
+            // Filesystem without user xattrs: the attribute set stays empty, and so does the diff.
+            assert!(check_dir(dir.path(), &opts).unwrap().is_empty());
+            return;
+        }
+        Err(e) => panic!("setxattr: {e}"),
+    }
+    assert_eq!(
+        std::fs::read(dir.path().join("tagged.txt")).unwrap(),
+        content
+    );
+
+    assert!(check_dir(dir.path(), &untracked).unwrap().is_empty());
+    let diff = check_dir(dir.path(), &opts).unwrap();
+    assert_eq!(diff.modified, vec![PathBuf::from("tagged.txt")]);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+
+    // The write stores the new hash, so the next run is clean.
+    let (_, written, _) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(written.modified, vec![PathBuf::from("tagged.txt")]);
+    assert!(check_dir(dir.path(), &opts).unwrap().is_empty());
+}
+
+// --- seeds ---
+
+#[test]
+fn test_seeds_index_only_their_subtrees_and_scope_removals() {
+    let dir = temp_root();
+    write_file(dir.path(), "a/one.txt", b"1");
+    write_file(dir.path(), "a/deep/two.txt", b"2");
+    write_file(dir.path(), "b/three.txt", b"3");
+    write_file(dir.path(), "c/four.txt", b"4");
+    write_file(dir.path(), "top.txt", b"t");
+    let seeded = Opts {
+        write_to_db: true,
+        seeds: vec![PathBuf::from("a"), PathBuf::from("./b/")],
+        ..Default::default()
+    };
+    let stored_keys = || {
+        let conn = nefaxer::engine::open_db(&dir.path().join(".nefaxer"), None).unwrap();
+        let mut keys: Vec<PathBuf> = nefaxer::engine::load_index(&conn)
+            .unwrap()
+            .into_keys()
+            .collect();
+        keys.sort();
+        keys
+    };
+
+    nefax_dir_with_opts(dir.path(), &seeded, None).unwrap();
+    let seeded_keys: Vec<PathBuf> = [
+        "a",
+        "a/deep",
+        "a/deep/two.txt",
+        "a/one.txt",
+        "b",
+        "b/three.txt",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(stored_keys(), seeded_keys);
+
+    // Rows outside the seeds (here from a full run) are neither reported removed nor deleted.
+    let full = Opts {
+        seeds: Vec::new(),
+        ..seeded.clone()
+    };
+    nefax_dir_with_opts(dir.path(), &full, None).unwrap();
+    std::fs::remove_file(dir.path().join("a/one.txt")).unwrap();
+    std::fs::remove_file(dir.path().join("c/four.txt")).unwrap();
+    let (_, diff, _) = nefax_dir_with_opts(dir.path(), &seeded, None).unwrap();
+    assert_eq!(diff.removed, vec![PathBuf::from("a/one.txt")]);
+    let keys = stored_keys();
+    assert!(keys.contains(&PathBuf::from("c/four.txt")));
+    assert!(keys.contains(&PathBuf::from("top.txt")));
+    assert!(!keys.contains(&PathBuf::from("a/one.txt")));
+
+    let outside = Opts {
+        seeds: vec![PathBuf::from("../elsewhere")],
+        ..seeded
+    };
+    assert!(nefax_dir_with_opts(dir.path(), &outside, None).is_err());
+}
+
+#[test]
+fn test_overlapping_seeds_walk_each_path_once() {
+    let dir = temp_root();
+    write_file(dir.path(), "a/x.txt", b"x");
+    write_file(dir.path(), "a/b/y.txt", b"y");
+    write_file(dir.path(), "c/skip.txt", b"s");
+    let seeded = Opts {
+        write_to_db: true,
+        seeds: vec![
+            PathBuf::from("a/b"),
+            PathBuf::from("a"),
+            PathBuf::from("./a/"),
+        ],
+        ..Default::default()
+    };
+    let (_, _, report) = nefax_dir_with_opts(dir.path(), &seeded, None).unwrap();
+    let stats = report.write_stats.unwrap();
+    // a, a/x.txt, a/b, a/b/y.txt
+    assert_eq!(stats.received, 4);
+    assert_eq!(stats.key_collisions, 0);
+
+    write_file(dir.path(), "a/b/z.txt", b"z");
+    let check = check_dir(dir.path(), &seeded).unwrap();
+    assert_eq!(check.added, vec![PathBuf::from("a/b/z.txt")]);
+    let (_, diff, _) = nefax_dir_with_opts(dir.path(), &seeded, None).unwrap();
+    assert_eq!(diff.added, vec![PathBuf::from("a/b/z.txt")]);
+}
+
+// --- preview ---
+
+#[test]
+fn test_preview_index_leaves_db_unchanged_and_matches_real_write() {
+    let dir = temp_root();
+    write_file(dir.path(), "keep.txt", b"keep");
+    write_file(dir.path(), "gone.txt", b"gone");
+    let opts = Opts {
+        write_to_db: true,
+        with_hash: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    write_file(dir.path(), "new.txt", b"new");
+    std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+    let db = dir.path().join(".nefaxer");
+    let before = std::fs::read(&db).unwrap();
+
+    let preview = Opts {
+        preview: true,
+        ..opts.clone()
+    };
+    let (_, previewed, report) = nefax_dir_with_opts(dir.path(), &preview, None).unwrap();
+    assert_eq!(report.status, CompletionStatus::Complete);
+    assert_eq!(previewed.added, vec![PathBuf::from("new.txt")]);
+    assert_eq!(previewed.removed, vec![PathBuf::from("gone.txt")]);
+    assert_eq!(std::fs::read(&db).unwrap(), before);
+    let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .filter(|n| n.to_string_lossy().starts_with(".nefaxer") && n != ".nefaxer")
+        .collect();
+    assert!(leftovers.is_empty(), "{leftovers:?}");
+
+    // The real write then reports the same changes.
+    let (_, written, _) = nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    assert_eq!(written.added, previewed.added);
+    assert_eq!(written.removed, previewed.removed);
+    assert_ne!(std::fs::read(&db).unwrap(), before);
+}
+
+#[test]
+fn test_preview_sees_writes_still_in_the_wal() {
+    let dir = temp_root();
+    write_file(dir.path(), "keep.txt", b"keep");
+    write_file(dir.path(), "gone.txt", b"gone");
+    let opts = Opts {
+        write_to_db: true,
+        ..Default::default()
+    };
+    nefax_dir_with_opts(dir.path(), &opts, None).unwrap();
+    std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+
+    // Another process has committed the removal but not checkpointed it: the main file still has the row.
+    let writer = rusqlite::Connection::open(dir.path().join(".nefaxer")).unwrap();
+    writer
+        .execute_batch("PRAGMA wal_autocheckpoint = 0; DELETE FROM paths WHERE path = 'gone.txt';")
+        .unwrap();
+    assert!(dir.path().join(".nefaxer-wal").exists());
+
+    let preview = Opts {
+        preview: true,
+        ..opts
+    };
+    let (_, previewed, _) = nefax_dir_with_opts(dir.path(), &preview, None).unwrap();
+    assert!(previewed.removed.is_empty(), "{:?}", previewed.removed);
+    assert!(previewed.is_empty());
+    drop(writer);
+}
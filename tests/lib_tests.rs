@@ -1,8 +1,11 @@
-use nefaxer::Entry;
+use nefaxer::disk_detect::network::{is_network_mount, strip_verbatim_prefix};
 use nefaxer::engine::{
-    StoredMeta, entry_needs_update, glob_match, hash_equals, mtime_changed, path_relative_to,
+    StoredMeta, effective_mtime_granularity_ns, entry_needs_update, glob_match, hash_equals,
+    is_under_dotfile, mtime_changed, mtime_changed_with_granularity, path_relative_to,
     path_to_db_string, should_include_in_walk,
 };
+use nefaxer::utils::workers_for_fd_limit;
+use nefaxer::{Entry, PathMeta};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
@@ -70,6 +73,7 @@ fn test_mtime_changed_same_mtime() {
 }
 
 #[test]
+#[allow(clippy::identity_op)] // `old + 0` lines up with the ± window cases below
 fn test_mtime_changed_within_window() {
     let old = 1_000_000_000i64;
     let window = 50i64;
@@ -94,6 +98,61 @@ fn test_mtime_changed_zero_tolerance() {
     assert!(mtime_changed(t - 1, t, 0));
 }
 
+#[test]
+fn test_mtime_granularity_absorbs_rounding_loss() {
+    let g = 2_000_000_000i64;
+    let t = 1_000_000_000_000i64;
+    assert!(!mtime_changed_with_granularity(t + 100_000_000, t, 0, g));
+    // Straddles a 2 s boundary (1.95 s vs 2.05 s after rounding) but is still under one granule.
+    assert!(!mtime_changed_with_granularity(
+        2_050_000_000,
+        1_950_000_000,
+        0,
+        g
+    ));
+    assert!(mtime_changed_with_granularity(t + 3_000_000_000, t, 0, g));
+    assert!(mtime_changed_with_granularity(t - 3_000_000_000, t, 0, g));
+    // Window still applies beyond the granularity.
+    assert!(!mtime_changed_with_granularity(
+        t + 3_000_000_000,
+        t,
+        3_000_000_000,
+        g
+    ));
+}
+
+#[test]
+fn test_effective_mtime_granularity_defaults_by_drive() {
+    use nefaxer::disk_detect::DriveType;
+    assert_eq!(
+        effective_mtime_granularity_ns(None, DriveType::Network),
+        2_000_000_000
+    );
+    assert_eq!(effective_mtime_granularity_ns(None, DriveType::SSD), 0);
+    assert_eq!(
+        effective_mtime_granularity_ns(Some(0), DriveType::Network),
+        0
+    );
+}
+
+#[test]
+fn test_force_parallel_walk_overrides_hdd_serial_walk() {
+    use nefaxer::disk_detect::{determine_threads_for_drive, hdd_parallel_walk};
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a.txt", "b.txt", "c"] {
+        std::fs::write(dir.path().join(name), b"x").unwrap();
+    }
+    // Three top-level entries: wide enough for a fanout of 3, not for 4 unless forced.
+    assert!(hdd_parallel_walk(dir.path(), false, 3));
+    assert!(!hdd_parallel_walk(dir.path(), false, 4));
+    assert!(hdd_parallel_walk(dir.path(), true, 4));
+    // A fanout of 0 disables the parallel walk instead of matching every directory.
+    assert!(!hdd_parallel_walk(dir.path(), false, 0));
+    assert!(hdd_parallel_walk(dir.path(), true, 0));
+    let (_, _, parallel_walk) = determine_threads_for_drive(dir.path(), None, 4, None, None, true);
+    assert!(parallel_walk);
+}
+
 // --- hash_equals ---
 
 #[test]
@@ -150,7 +209,14 @@ fn test_glob_match_negation_stripped() {
 #[test]
 fn test_should_include_root_excluded() {
     let root = PathBuf::from("/foo");
-    assert!(!should_include_in_walk(&root, &root, &None, &None, &[]));
+    assert!(!should_include_in_walk(
+        &root,
+        &root,
+        &None,
+        &None,
+        &[],
+        false
+    ));
 }
 
 #[test]
@@ -162,7 +228,8 @@ fn test_should_include_db_canonical_skipped() {
         &root,
         &Some(db.clone()),
         &None,
-        &[]
+        &[],
+        false
     ));
 }
 
@@ -175,7 +242,34 @@ fn test_should_include_temp_canonical_skipped() {
         &root,
         &None,
         &Some(temp.clone()),
-        &[]
+        &[],
+        false
+    ));
+}
+
+#[test]
+fn test_should_include_db_sidecars_skipped() {
+    let root = PathBuf::from("/foo");
+    let db = Some(PathBuf::from("/foo/.nefaxer"));
+    for name in [".nefaxer-wal", ".nefaxer-shm", ".nefaxer-journal"] {
+        let path = root.join(name);
+        assert!(!should_include_in_walk(
+            &path,
+            &root,
+            &db,
+            &None,
+            &[],
+            false
+        ));
+    }
+    let other = root.join(".nefaxer-notes");
+    assert!(should_include_in_walk(
+        &other,
+        &root,
+        &db,
+        &None,
+        &[],
+        false
     ));
 }
 
@@ -188,7 +282,8 @@ fn test_should_include_exclude_pattern_name() {
         &root,
         &None,
         &None,
-        &["node_modules".to_string()]
+        &["node_modules".to_string()],
+        false
     ));
 }
 
@@ -201,10 +296,29 @@ fn test_should_include_exclude_pattern_glob() {
         &root,
         &None,
         &None,
-        &["*.log".to_string()]
+        &["*.log".to_string()],
+        false
     ));
 }
 
+#[test]
+fn test_should_include_trailing_slash_matches_directories_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("a/logs")).unwrap();
+    std::fs::write(root.join("a/logs/x.txt"), b"x").unwrap();
+    std::fs::create_dir_all(root.join("b")).unwrap();
+    std::fs::write(root.join("b/logs"), b"not a dir").unwrap();
+    let patterns = ["logs/".to_string()];
+    let include =
+        |rel: &str| should_include_in_walk(&root.join(rel), root, &None, &None, &patterns, false);
+
+    assert!(!include("a/logs"));
+    assert!(!include("a/logs/x.txt"));
+    assert!(include("b/logs"));
+    assert!(include("b"));
+}
+
 #[test]
 fn test_should_include_not_excluded() {
     let root = PathBuf::from("/foo");
@@ -214,10 +328,69 @@ fn test_should_include_not_excluded() {
         &root,
         &None,
         &None,
-        &["*.log".to_string(), "node_modules".to_string()]
+        &["*.log".to_string(), "node_modules".to_string()],
+        false
     ));
 }
 
+#[test]
+fn test_should_include_skip_dotfiles_prunes_dot_dir_contents() {
+    let root = PathBuf::from("/foo");
+    let git_dir = PathBuf::from("/foo/.git");
+    let git_obj = PathBuf::from("/foo/.git/objects/ab");
+    assert!(!should_include_in_walk(
+        &git_dir,
+        &root,
+        &None,
+        &None,
+        &[],
+        true
+    ));
+    assert!(!should_include_in_walk(
+        &git_obj,
+        &root,
+        &None,
+        &None,
+        &[],
+        true
+    ));
+    // Without the flag, dotfiles are indexed as usual.
+    assert!(should_include_in_walk(
+        &git_obj,
+        &root,
+        &None,
+        &None,
+        &[],
+        false
+    ));
+}
+
+#[test]
+fn test_should_include_skip_dotfiles_keeps_normal_files() {
+    let root = PathBuf::from("/home/user/.config");
+    let path = PathBuf::from("/home/user/.config/app/settings.toml");
+    // Root itself is a dot-directory: only components below root are considered.
+    assert!(should_include_in_walk(
+        &path,
+        &root,
+        &None,
+        &None,
+        &[],
+        true
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_skip_dotfiles_matches_non_utf8_dot_names() {
+    use std::os::unix::ffi::OsStrExt;
+    let root = PathBuf::from("/foo");
+    let hidden = PathBuf::from(std::ffi::OsStr::from_bytes(b"/foo/.cache\xff/x"));
+    let plain = PathBuf::from(std::ffi::OsStr::from_bytes(b"/foo/cache\xff/x"));
+    assert!(is_under_dotfile(&hidden, &root));
+    assert!(!is_under_dotfile(&plain, &root));
+}
+
 // --- entry_needs_update ---
 
 fn entry(path: &str, mtime_ns: i64, size: u64, hash: Option<[u8; 32]>) -> Entry {
@@ -226,6 +399,7 @@ fn entry(path: &str, mtime_ns: i64, size: u64, hash: Option<[u8; 32]>) -> Entry
         mtime_ns,
         size,
         hash,
+        ..Default::default()
     }
 }
 
@@ -310,3 +484,653 @@ fn test_entry_needs_update_outside_tolerance() {
         50
     ));
 }
+
+// --- PathMeta::effectively_equal ---
+
+fn path_meta(mtime_ns: i64, size: u64, hash: Option<[u8; 32]>) -> PathMeta {
+    PathMeta {
+        mtime_ns,
+        size,
+        hash,
+        btime_ns: None,
+        symlink_target: None,
+        xattr_hash: None,
+    }
+}
+
+#[test]
+fn test_effectively_equal_respects_mtime_window() {
+    let old = path_meta(100, 10, Some([1; 32]));
+    assert!(path_meta(150, 10, Some([1; 32])).effectively_equal(&old, 50));
+    assert!(path_meta(50, 10, Some([1; 32])).effectively_equal(&old, 50));
+    assert!(!path_meta(151, 10, Some([1; 32])).effectively_equal(&old, 50));
+    assert!(!path_meta(101, 10, Some([1; 32])).effectively_equal(&old, 0));
+}
+
+#[test]
+fn test_effectively_equal_hash_and_size_mismatch() {
+    let old = path_meta(100, 10, Some([1; 32]));
+    let mut h = [1u8; 32];
+    h[31] = 2;
+    assert!(!path_meta(100, 10, Some(h)).effectively_equal(&old, i64::MAX));
+    assert!(!path_meta(100, 10, None).effectively_equal(&old, i64::MAX));
+    assert!(!path_meta(100, 11, Some([1; 32])).effectively_equal(&old, i64::MAX));
+}
+
+#[test]
+fn test_effectively_equal_matches_entry_needs_update() {
+    let mut existing = HashMap::new();
+    existing.insert(PathBuf::from("a/b"), meta(100, 10, Some(vec![1; 32])));
+    for e in [
+        entry("a/b", 130, 10, Some([1; 32])),
+        entry("a/b", 200, 10, Some([1; 32])),
+        entry("a/b", 100, 10, Some([2; 32])),
+    ] {
+        let stored = PathMeta::from(&existing[&e.path]);
+        assert_eq!(
+            entry_needs_update(&e, &existing, 50),
+            !PathMeta::from(&e).effectively_equal(&stored, 50)
+        );
+    }
+}
+
+// --- FD limit worker cap ---
+
+#[test]
+fn test_workers_for_fd_limit_scales_inversely_with_budget() {
+    let limit = 1000_u64; // 800 usable at 80%
+    assert_eq!(workers_for_fd_limit(limit, 10), 80);
+    assert_eq!(workers_for_fd_limit(limit, 20), 40);
+    assert_eq!(workers_for_fd_limit(limit, 5), 160);
+}
+
+#[test]
+fn test_workers_for_fd_limit_guards_zero_and_tiny_limits() {
+    // Zero budget falls back to the default estimate instead of dividing by zero.
+    assert_eq!(
+        workers_for_fd_limit(1000, 0),
+        workers_for_fd_limit(1000, 10)
+    );
+    // Budget larger than the usable limit still allows one worker.
+    assert_eq!(workers_for_fd_limit(10, 100), 1);
+}
+
+// --- UNC / extended-length paths ---
+
+#[test]
+fn test_is_network_mount_unc_forms() {
+    assert!(is_network_mount(r"\\server\share"));
+    assert!(is_network_mount("//server/share"));
+    assert!(is_network_mount(r"\\?\UNC\server\share\dir"));
+    assert!(!is_network_mount(r"\\?\C:\Users\me"));
+    assert!(!is_network_mount(r"\\.\PhysicalDrive0"));
+    assert!(!is_network_mount(r"C:\"));
+    assert!(!is_network_mount("/mnt/data"));
+}
+
+#[test]
+fn test_strip_verbatim_prefix() {
+    assert_eq!(
+        strip_verbatim_prefix(r"\\?\UNC\server\share"),
+        r"\\server\share"
+    );
+    assert_eq!(strip_verbatim_prefix(r"\\?\C:\dir"), r"C:\dir");
+    assert_eq!(strip_verbatim_prefix("/home/me"), "/home/me");
+}
+
+#[cfg(windows)]
+#[test]
+fn test_unc_root_classified_as_network() {
+    use nefaxer::disk_detect::{DriveType, drive_type_for_path};
+    let root = std::path::Path::new(r"\\?\UNC\server\share");
+    assert_eq!(drive_type_for_path(root), DriveType::Network);
+}
+
+#[cfg(windows)]
+#[test]
+fn test_index_path_longer_than_max_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let long_rel: PathBuf = (0..30).map(|i| format!("segment_{i:02}")).collect();
+    let long_abs = nefaxer::engine::path_to_long_path(&dir.path().join(&long_rel));
+    std::fs::create_dir_all(&long_abs).unwrap();
+    std::fs::write(long_abs.join("leaf.txt"), b"leaf").unwrap();
+    assert!(long_abs.as_os_str().len() > 260);
+
+    let (nefax, _) = nefaxer::nefax_dir(
+        dir.path(),
+        &nefaxer::NefaxOpts::default(),
+        None,
+        None::<fn(&Entry)>,
+    )
+    .unwrap();
+    let expected = PathBuf::from(path_to_db_string(&long_rel.join("leaf.txt")));
+    assert!(nefax.contains_key(&expected));
+    assert!(!expected.to_string_lossy().contains('\\'));
+}
+
+// --- matches_focus ---
+
+#[test]
+fn test_matches_focus_whole_path_or_component() {
+    use nefaxer::engine::matches_focus;
+    let focus = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert!(matches_focus(&PathBuf::from("any/thing"), &[]));
+    assert!(matches_focus(
+        &PathBuf::from("src/a/b.rs"),
+        &focus(&["*.rs"])
+    ));
+    assert!(matches_focus(
+        &PathBuf::from("src/a/b.txt"),
+        &focus(&["src/"])
+    ));
+    assert!(matches_focus(
+        &PathBuf::from("src/a/b.txt"),
+        &focus(&["src/*"])
+    ));
+    assert!(!matches_focus(
+        &PathBuf::from("lib/b.txt"),
+        &focus(&["src", "*.rs"])
+    ));
+}
+
+// --- Diff helpers ---
+
+#[test]
+fn test_diff_total_and_is_empty() {
+    let empty = nefaxer::Diff::default();
+    assert!(empty.is_empty());
+    assert_eq!(empty.total(), 0);
+
+    let diff = nefaxer::Diff {
+        added: vec![PathBuf::from("a"), PathBuf::from("b")],
+        removed: vec![PathBuf::from("c")],
+        modified: vec![PathBuf::from("d")],
+        corrupted: vec![PathBuf::from("e")],
+        case_changed: vec![(PathBuf::from("f"), PathBuf::from("F"))],
+        touched: vec![PathBuf::from("g")],
+        unreadable: vec![PathBuf::from("h")],
+    };
+    assert!(!diff.is_empty());
+    // Touched paths are not changes.
+    assert_eq!(diff.total(), 7);
+}
+
+// --- write_changed_paths_nul ---
+
+#[test]
+fn test_changed_paths_nul_separated_and_dirs_optional() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("newdir")).unwrap();
+    std::fs::write(dir.path().join("a b.txt"), b"a").unwrap();
+    std::fs::write(dir.path().join("m.txt"), b"m").unwrap();
+    let diff = nefaxer::Diff {
+        added: vec![PathBuf::from("a b.txt"), PathBuf::from("newdir")],
+        removed: vec![PathBuf::from("gone.txt")],
+        modified: vec![PathBuf::from("m.txt")],
+        ..Default::default()
+    };
+
+    let mut out = Vec::new();
+    let n =
+        nefaxer::engine::write_changed_paths_nul(&mut out, &diff, dir.path(), false, true, None)
+            .unwrap();
+    assert_eq!(n, 3);
+    assert_eq!(out, b"a b.txt\0newdir\0m.txt\0");
+
+    let mut out = Vec::new();
+    nefaxer::engine::write_changed_paths_nul(&mut out, &diff, dir.path(), false, false, None)
+        .unwrap();
+    assert_eq!(out, b"a b.txt\0m.txt\0");
+
+    let mut out = Vec::new();
+    nefaxer::engine::write_changed_paths_nul(&mut out, &diff, dir.path(), true, false, None)
+        .unwrap();
+    let paths: Vec<PathBuf> = out
+        .split(|&b| b == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| PathBuf::from(std::str::from_utf8(s).unwrap()))
+        .collect();
+    assert_eq!(
+        paths,
+        vec![dir.path().join("a b.txt"), dir.path().join("m.txt")]
+    );
+}
+
+// --- Diff::to_shell_script ---
+
+#[test]
+fn test_shell_script_quotes_spaces_and_quotes() {
+    let diff = nefaxer::Diff {
+        added: vec![PathBuf::from("new $HOME.txt")],
+        removed: vec![PathBuf::from("my file.txt"), PathBuf::from("it's.txt")],
+        ..Default::default()
+    };
+    let script = diff.to_shell_script(nefaxer::ScriptStyle::default());
+    assert!(script.starts_with("#!/bin/sh\n"));
+    assert!(script.contains(r#"else rm -i -- "$1"; fi; }"#), "{script}");
+    assert!(script.contains("remove 'my file.txt'\n"), "{script}");
+    assert!(script.contains(r"remove 'it'\''s.txt'"), "{script}");
+    assert!(
+        script.contains("printf 'added: %s\\n' 'new $HOME.txt'\n"),
+        "{script}"
+    );
+
+    let log_only = diff.to_shell_script(nefaxer::ScriptStyle::LogOnly);
+    assert!(
+        log_only
+            .lines()
+            .filter(|l| l.contains("rm ") || l.contains("remove "))
+            .all(|l| l.starts_with('#'))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_shell_script_removes_dirs_and_non_utf8_names() {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = tempfile::tempdir().unwrap();
+    let raw = std::ffi::OsStr::from_bytes(b"caf\xe9 'x'.txt");
+    std::fs::create_dir_all(dir.path().join("gone/deeper")).unwrap();
+    std::fs::write(dir.path().join("gone/deeper/f.txt"), b"x").unwrap();
+    std::fs::write(dir.path().join(raw), b"x").unwrap();
+    std::fs::write(dir.path().join("keep.txt"), b"x").unwrap();
+    let diff = nefaxer::Diff {
+        removed: vec![
+            PathBuf::from("gone"),
+            PathBuf::from("gone/deeper"),
+            PathBuf::from("gone/deeper/f.txt"),
+            PathBuf::from(raw),
+        ],
+        ..Default::default()
+    };
+    let script = diff.to_shell_script(nefaxer::ScriptStyle::Force);
+    assert!(script.starts_with("#!/usr/bin/env bash\n"), "{script}");
+    assert!(script.contains(r"remove $'caf\xe9 \'x\'.txt'"), "{script}");
+    let status = std::process::Command::new("bash")
+        .arg("-c")
+        .arg(&script)
+        .current_dir(dir.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let left: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(left, vec![std::ffi::OsString::from("keep.txt")]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_shell_script_runs_and_removes_exact_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["my file.txt", "it's.txt", "keep.txt"] {
+        std::fs::write(dir.path().join(name), b"x").unwrap();
+    }
+    let diff = nefaxer::Diff {
+        removed: vec![PathBuf::from("my file.txt"), PathBuf::from("it's.txt")],
+        ..Default::default()
+    };
+    let script = diff.to_shell_script(nefaxer::ScriptStyle::Force);
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(&script)
+        .current_dir(dir.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let mut left: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    left.sort();
+    assert_eq!(left, vec![std::ffi::OsString::from("keep.txt")]);
+}
+
+// --- keyed hashing ---
+
+#[test]
+fn test_keyed_hash_differs_per_key() {
+    use nefaxer::engine::{HashKey, hash_file, hash_file_keyed};
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+    std::fs::write(&path, [5u8; 8192]).unwrap();
+
+    let key_a = HashKey::derive("secret a");
+    let key_b = HashKey::derive("secret b");
+    let plain = hash_file(&path, 8192).unwrap();
+    let under_a = hash_file_keyed(&path, 8192, Some(&key_a)).unwrap();
+    let under_b = hash_file_keyed(&path, 8192, Some(&key_b)).unwrap();
+    assert_ne!(under_a, under_b);
+    assert_ne!(under_a, plain);
+    assert_eq!(
+        under_a,
+        hash_file_keyed(&path, 8192, Some(&HashKey::derive("secret a"))).unwrap()
+    );
+    assert_eq!(format!("{key_a:?}"), "HashKey(..)");
+}
+
+// --- tree_digest ---
+
+#[test]
+fn test_tree_digest_changes_with_any_single_field() {
+    let mut nefax: nefaxer::Nefax = HashMap::new();
+    nefax.insert(PathBuf::from("a.txt"), path_meta(1_000, 3, None));
+    nefax.insert(
+        PathBuf::from("dir/b.bin"),
+        path_meta(2_000, 9000, Some([7u8; 32])),
+    );
+    let base = nefaxer::tree_digest(&nefax);
+
+    // Deterministic regardless of map iteration order.
+    let mut rows: Vec<_> = nefax.clone().into_iter().collect();
+    rows.reverse();
+    let rebuilt: nefaxer::Nefax = rows.into_iter().collect();
+    assert_eq!(nefaxer::tree_digest(&rebuilt), base);
+
+    let edits: [fn(&mut PathMeta); 4] = [
+        |m| m.mtime_ns += 1,
+        |m| m.size += 1,
+        |m| m.hash = Some([8u8; 32]),
+        |m| m.hash = None,
+    ];
+    for edit in edits {
+        let mut changed = nefax.clone();
+        edit(changed.get_mut(&PathBuf::from("dir/b.bin")).unwrap());
+        assert_ne!(nefaxer::tree_digest(&changed), base);
+    }
+    let mut renamed = nefax.clone();
+    let meta = renamed.remove(&PathBuf::from("a.txt")).unwrap();
+    renamed.insert(PathBuf::from("a2.txt"), meta);
+    assert_ne!(nefaxer::tree_digest(&renamed), base);
+
+    // The DB-row form of the same entries folds to the same digest.
+    let stored: HashMap<PathBuf, nefaxer::engine::StoredMeta> = nefax
+        .iter()
+        .map(|(p, m)| (p.clone(), (m.mtime_ns, m.size, m.hash.map(|h| h.to_vec()))))
+        .collect();
+    assert_eq!(nefaxer::engine::index_tree_digest(&stored), base);
+}
+
+// --- print_diff list threshold ---
+
+#[test]
+fn test_list_threshold_extremes_pick_file_or_stdout() {
+    let diff = nefaxer::Diff {
+        added: vec![PathBuf::from("a.txt")],
+        modified: vec![PathBuf::from("b.txt")],
+        ..Default::default()
+    };
+    let results = nefaxer::utils::config::PackagePaths::get()
+        .results_filename()
+        .to_string();
+
+    let dir = tempfile::tempdir().unwrap();
+    nefaxer::engine::print_diff_with_threshold(&diff, true, true, dir.path(), Some(0), None);
+    let listed = std::fs::read_to_string(dir.path().join(&results)).unwrap();
+    assert!(
+        listed.contains("a.txt") && listed.contains("b.txt"),
+        "{listed}"
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    nefaxer::engine::print_diff_with_threshold(
+        &diff,
+        true,
+        true,
+        dir.path(),
+        Some(usize::MAX),
+        None,
+    );
+    assert!(!dir.path().join(&results).exists());
+}
+
+#[test]
+fn test_display_rebasing_rewrites_listed_paths_only() {
+    use nefaxer::engine::rebase_for_display;
+    use std::path::Path;
+    let root = Path::new("/data/tree");
+    let key = Path::new("sub/a.txt");
+    assert_eq!(rebase_for_display(key, root, root), Path::new("sub/a.txt"));
+    assert_eq!(
+        rebase_for_display(key, root, Path::new("/data")),
+        Path::new("tree/sub/a.txt")
+    );
+    assert_eq!(
+        rebase_for_display(key, root, Path::new("/data/tree/sub/deep")),
+        Path::new("../a.txt")
+    );
+    assert_eq!(
+        rebase_for_display(key, root, Path::new("/data/other")),
+        Path::new("../tree/sub/a.txt")
+    );
+    assert_eq!(
+        rebase_for_display(Path::new("sub"), root, Path::new("/data/tree/sub")),
+        Path::new(".")
+    );
+
+    let diff = nefaxer::Diff {
+        added: vec![PathBuf::from("deep/new.txt")],
+        removed: vec![PathBuf::from("old.txt")],
+        ..Default::default()
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    std::fs::create_dir_all(root.join("deep")).unwrap();
+    nefaxer::engine::print_diff_with_threshold(
+        &diff,
+        true,
+        true,
+        &root,
+        Some(0),
+        Some(&root.join("deep")),
+    );
+    let results = nefaxer::utils::config::PackagePaths::get().results_filename();
+    let listed = std::fs::read_to_string(root.join(results)).unwrap();
+    assert_eq!(listed, "+ new.txt\n- ../old.txt\n");
+    assert_eq!(diff.added, vec![PathBuf::from("deep/new.txt")]);
+    assert_eq!(diff.removed, vec![PathBuf::from("old.txt")]);
+
+    // --output and --print0 show the same rebased paths.
+    let dirs = nefaxer::engine::display_rebase_dirs(&root, Some(&root.join("deep"))).unwrap();
+    let rebase = Some((dirs.0.as_path(), dirs.1.as_path()));
+    let report = dir.path().join("report.txt");
+    nefaxer::engine::write_diff_report(&diff, &report, rebase).unwrap();
+    let report = std::fs::read_to_string(report).unwrap();
+    assert!(report.contains("[added]\n+ new.txt\n"), "{report}");
+    assert!(report.contains("[removed]\n- ../old.txt\n"), "{report}");
+    let mut out = Vec::new();
+    nefaxer::engine::write_changed_paths_nul(&mut out, &diff, &root, false, true, rebase).unwrap();
+    assert_eq!(out, b"new.txt\0");
+    assert!(nefaxer::engine::display_rebase_dirs(&root, None).is_none());
+}
+
+// --- log format ---
+
+#[test]
+fn test_json_log_record_is_one_parseable_object() {
+    let line = nefaxer::utils::format_log_record(
+        &log::Record::builder()
+            .args(format_args!("Nefaxing \"index\" results:"))
+            .level(log::Level::Warn)
+            .target("nefaxer::check")
+            .build(),
+        nefaxer::LogFormat::Json,
+    );
+    assert!(!line.contains('\n') && !line.contains('\u{1b}'), "{line:?}");
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["level"], "WARN");
+    assert_eq!(value["target"], "nefaxer::check");
+    assert_eq!(value["msg"], "Nefaxing \"index\" results:");
+    assert!(value["ts"].as_u64().is_some_and(|ts| ts > 0));
+}
+
+#[test]
+fn test_json_logging_turns_off_colors_in_summary() {
+    /// Drops the color override however the test ends.
+    struct ResetColors;
+    impl Drop for ResetColors {
+        fn drop(&mut self) {
+            colored::control::unset_override();
+        }
+    }
+    let _reset = ResetColors;
+    // As on a color terminal, until JSON logging is set up.
+    colored::control::set_override(true);
+    let diff = nefaxer::Diff {
+        added: vec![PathBuf::from("new.txt")],
+        ..Default::default()
+    };
+    assert!(nefaxer::engine::diff_counts_line(&diff).contains('\u{1b}'));
+
+    // The color half of `setup_logging`, without installing a process-wide logger.
+    nefaxer::utils::set_colors_for_format(nefaxer::LogFormat::Json);
+    let summary = nefaxer::engine::diff_counts_line(&diff);
+    let line = nefaxer::utils::format_log_record(
+        &log::Record::builder()
+            .args(format_args!("{summary}"))
+            .level(log::Level::Info)
+            .target("nefaxer::engine::tools")
+            .build(),
+        nefaxer::LogFormat::Json,
+    );
+    assert!(!line.contains('\u{1b}'), "{line:?}");
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["msg"], "Added: 1 | Removed: 0 | Modified: 0");
+}
+
+// --- snapshot reader / writer ---
+
+#[test]
+fn test_nefax_round_trips_through_gzip_stream() {
+    use std::io::Write;
+
+    let mut nefax = nefaxer::Nefax::new();
+    nefax.insert(
+        PathBuf::from("src/main.rs"),
+        PathMeta {
+            mtime_ns: 1_700_000_000_123_456_789,
+            size: 42,
+            hash: Some([0xab; 32]),
+            btime_ns: Some(1_690_000_000_000_000_000),
+            symlink_target: None,
+            xattr_hash: Some([0xcd; 32]),
+        },
+    );
+    nefax.insert(
+        PathBuf::from("src"),
+        PathMeta {
+            mtime_ns: -5,
+            size: 0,
+            hash: None,
+            btime_ns: None,
+            symlink_target: None,
+            xattr_hash: None,
+        },
+    );
+
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    assert_eq!(nefaxer::nefax_to_writer(&nefax, &mut gz).unwrap(), 2);
+    gz.flush().unwrap();
+    let compressed = gz.finish().unwrap();
+
+    let reader = std::io::BufReader::new(flate2::read::GzDecoder::new(compressed.as_slice()));
+    let loaded = nefaxer::nefax_from_reader(reader).unwrap();
+    assert_eq!(loaded.len(), 2);
+    for (path, meta) in &nefax {
+        let got = &loaded[path];
+        assert_eq!(
+            (got.mtime_ns, got.size, got.hash, got.xattr_hash),
+            (meta.mtime_ns, meta.size, meta.hash, meta.xattr_hash)
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.jsonl.gz");
+        nefaxer::save_nefax_gz(&nefax, &path).unwrap();
+        assert_eq!(nefaxer::load_nefax_gz(&path).unwrap().len(), 2);
+    }
+
+    let bad = nefaxer::nefax_from_reader(
+        &b"{\"path\":\"a\",\"size\":1,\"mtime_ns\":0,\"hash\":\"zz\"}\n"[..],
+    );
+    assert!(bad.unwrap_err().to_string().contains("line 1"));
+}
+
+// --- Config (.nefaxer.toml) ---
+
+#[test]
+fn test_config_round_trips_through_toml_into_opts() {
+    use nefaxer::utils::{Config, Settings};
+
+    let config = Config {
+        settings: Settings {
+            hash: Some(true),
+            exclude: Some(vec!["target/".into(), "*.log".into()]),
+            mtime_window: Some(2),
+            change_policy: Some("size-only".into()),
+            durability: Some("safe".into()),
+            busy_timeout: Some(30),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let text = config.to_toml_string().unwrap();
+    assert!(text.contains("[settings]"), "{text}");
+    assert!(
+        !text.contains("verbose"),
+        "unset fields are omitted: {text}"
+    );
+    assert_eq!(Config::from_toml_str(&text).unwrap(), config);
+
+    let opts = config.to_opts().unwrap();
+    assert!(opts.with_hash);
+    assert_eq!(opts.exclude, ["target/", "*.log"]);
+    assert_eq!(opts.mtime_window_ns, 2_000_000_000);
+    assert!(matches!(
+        opts.change_policy,
+        nefaxer::ChangePolicy::SizeOnly
+    ));
+    assert_eq!(opts.durability, nefaxer::DbDurability::Safe);
+    assert_eq!(opts.busy_timeout, Some(std::time::Duration::from_secs(30)));
+    assert!(!opts.verbose);
+
+    // Opts -> Config -> TOML -> Config -> Opts keeps every file-configurable field.
+    let again = Config::from_toml_str(&Config::from_opts(&opts).to_toml_string().unwrap())
+        .unwrap()
+        .to_opts()
+        .unwrap();
+    assert_eq!(Config::from_opts(&again), Config::from_opts(&opts));
+}
+
+#[test]
+fn test_config_rejects_unknown_change_policy() {
+    use nefaxer::utils::Config;
+
+    let config = Config::from_toml_str("[settings]\nchange_policy = \"size_only\"\n").unwrap();
+    let err = config.to_opts().unwrap_err().to_string();
+    assert!(err.contains("change_policy"), "{err}");
+    assert!(err.contains("size_only"), "{err}");
+
+    let config = Config::from_toml_str("[profiles.fast]\ndurability = \"reckless\"\n").unwrap();
+    let mut opts = nefaxer::Opts::default();
+    let err = config.apply_profile_to("fast", &mut opts).unwrap_err();
+    assert!(format!("{err:#}").contains("durability"), "{err:#}");
+}
+
+// --- progress bars ---
+
+#[test]
+fn test_network_bar_uses_stored_path_count_as_total() {
+    use nefaxer::engine::create_nefaxing_bar;
+
+    let estimated = create_nefaxing_bar("Nefaxing", true, Some(42));
+    assert_eq!(estimated.lock().unwrap().total, 42);
+    let counter = create_nefaxing_bar("Nefaxing", true, None);
+    assert_eq!(counter.lock().unwrap().total, 0);
+    // Local drives wait for the walk's exact count.
+    let local = create_nefaxing_bar("Nefaxing", false, Some(42));
+    assert_eq!(local.lock().unwrap().total, 1);
+}